@@ -0,0 +1,19 @@
+-- Adds the upload_variants table for image renditions generated after completion,
+-- indexed by key so new uploads cannot claim a variant's key.
+CREATE TABLE IF NOT EXISTS upload_variants (
+    upload_id TEXT NOT NULL,
+    variant_name TEXT NOT NULL,
+    
+    -- Variant object information
+    r2_key TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    size INTEGER NOT NULL,
+    created_at TEXT NOT NULL,
+    
+    PRIMARY KEY (upload_id, variant_name),
+    FOREIGN KEY (upload_id) REFERENCES uploads(upload_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_upload_variants_r2_key ON upload_variants(r2_key);
@@ -0,0 +1,2 @@
+-- Adds the media_info column for parsed media metadata.
+ALTER TABLE uploads ADD COLUMN media_info TEXT;
@@ -0,0 +1,2 @@
+-- Adds the metadata_stripped flag for images whose metadata was removed.
+ALTER TABLE uploads ADD COLUMN metadata_stripped INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,2 @@
+-- Adds the index used to count a user's active uploads at initialization.
+CREATE INDEX IF NOT EXISTS idx_uploads_user_status ON uploads(user_id, status, created_at);
@@ -0,0 +1,89 @@
+-- Adds the `completing` upload status and replaces the CHECK constraint on
+-- uploads.status with a foreign key to the upload_statuses lookup table, so
+-- later states can be added with an INSERT.
+--
+-- SQLite cannot alter a CHECK constraint, so uploads is rebuilt:
+-- - dropping the old table cascades to upload_chunks and upload_variants, so
+--   their rows are copied aside first and restored afterwards;
+-- - the views are dropped and recreated because they would block the rename.
+
+PRAGMA defer_foreign_keys = on;
+
+CREATE TABLE IF NOT EXISTS upload_statuses (
+    status TEXT PRIMARY KEY
+);
+
+INSERT OR IGNORE INTO upload_statuses (status) VALUES
+    ('initiated'), ('in_progress'), ('completing'), ('completed'), ('cancelled');
+
+DROP VIEW IF EXISTS upload_stats;
+DROP VIEW IF EXISTS active_uploads;
+
+CREATE TABLE uploads_new (
+    upload_id TEXT PRIMARY KEY,
+    file_name TEXT NOT NULL,
+    total_size INTEGER NOT NULL,
+    content_type TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    user_role TEXT NOT NULL CHECK (user_role IN ('creator', 'member', 'subscriber')),
+    r2_key TEXT NOT NULL,
+    r2_upload_id TEXT NOT NULL,
+    status TEXT NOT NULL REFERENCES upload_statuses(status),
+    media_info TEXT,
+    metadata_stripped INTEGER NOT NULL DEFAULT 0,
+    created_at TEXT NOT NULL,
+    updated_at TEXT NOT NULL
+);
+
+INSERT INTO uploads_new (
+    upload_id, file_name, total_size, content_type, user_id, user_role, r2_key,
+    r2_upload_id, status, media_info, metadata_stripped, created_at, updated_at
+)
+SELECT
+    upload_id, file_name, total_size, content_type, user_id, user_role, r2_key,
+    r2_upload_id, status, media_info, metadata_stripped, created_at, updated_at
+FROM uploads;
+
+CREATE TABLE upload_chunks_backup AS SELECT * FROM upload_chunks;
+CREATE TABLE upload_variants_backup AS SELECT * FROM upload_variants;
+
+DROP TABLE uploads;
+ALTER TABLE uploads_new RENAME TO uploads;
+
+INSERT INTO upload_chunks SELECT * FROM upload_chunks_backup;
+INSERT INTO upload_variants SELECT * FROM upload_variants_backup;
+DROP TABLE upload_chunks_backup;
+DROP TABLE upload_variants_backup;
+
+CREATE INDEX IF NOT EXISTS idx_uploads_user_id ON uploads(user_id);
+CREATE INDEX IF NOT EXISTS idx_uploads_status ON uploads(status);
+CREATE INDEX IF NOT EXISTS idx_uploads_created_at ON uploads(created_at);
+CREATE INDEX IF NOT EXISTS idx_uploads_user_role ON uploads(user_role);
+CREATE INDEX IF NOT EXISTS idx_uploads_user_status ON uploads(user_id, status, created_at);
+
+CREATE VIEW IF NOT EXISTS upload_stats AS
+SELECT
+    user_role,
+    status,
+    COUNT(*) as upload_count,
+    SUM(total_size) as total_bytes,
+    AVG(total_size) as avg_file_size,
+    MIN(created_at) as earliest_upload,
+    MAX(created_at) as latest_upload
+FROM uploads
+GROUP BY user_role, status;
+
+CREATE VIEW IF NOT EXISTS active_uploads AS
+SELECT
+    upload_id,
+    file_name,
+    user_id,
+    user_role,
+    total_size,
+    status,
+    (SELECT COUNT(*) FROM upload_chunks WHERE upload_chunks.upload_id = uploads.upload_id) as chunks_uploaded,
+    created_at,
+    updated_at
+FROM uploads
+WHERE status IN ('initiated', 'in_progress', 'completing')
+ORDER BY created_at DESC;
@@ -0,0 +1,18 @@
+-- Adds the expired, failed and deleted statuses and the upload_events history
+-- table for the explicit upload state machine.
+
+INSERT OR IGNORE INTO upload_statuses (status) VALUES
+    ('expired'), ('failed'), ('deleted');
+
+CREATE TABLE IF NOT EXISTS upload_events (
+    event_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    upload_id TEXT NOT NULL,
+    event TEXT NOT NULL,
+    from_status TEXT NOT NULL REFERENCES upload_statuses(status),
+    to_status TEXT NOT NULL REFERENCES upload_statuses(status),
+    actor TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    FOREIGN KEY (upload_id) REFERENCES uploads(upload_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_upload_events_upload_id ON upload_events(upload_id, event_id);
@@ -0,0 +1,27 @@
+-- Adds the append-only audit_log table.
+CREATE TABLE IF NOT EXISTS audit_log (
+    audit_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    action TEXT NOT NULL,
+    upload_id TEXT,
+    actor TEXT,
+    ip TEXT,
+    user_agent TEXT,
+    request_id TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+
+CREATE TRIGGER IF NOT EXISTS audit_log_no_update
+BEFORE UPDATE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_log_no_delete
+BEFORE DELETE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
+
+CREATE INDEX IF NOT EXISTS idx_audit_log_upload_id ON audit_log(upload_id, audit_id);
+CREATE INDEX IF NOT EXISTS idx_audit_log_actor ON audit_log(actor, audit_id);
+CREATE INDEX IF NOT EXISTS idx_audit_log_created_at ON audit_log(created_at);
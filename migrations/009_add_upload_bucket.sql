@@ -0,0 +1,4 @@
+-- Records which R2 bucket binding holds each upload, so routing rules can
+-- change without stranding uploads already in flight. Rows created before
+-- bucket routing all live in the original STORAGE_BUCKET binding.
+ALTER TABLE uploads ADD COLUMN bucket TEXT NOT NULL DEFAULT 'STORAGE_BUCKET';
@@ -0,0 +1,3 @@
+-- Adds the index used to check whether an R2 key is already claimed when an
+-- upload is initialized.
+CREATE INDEX IF NOT EXISTS idx_uploads_bucket_key ON uploads(bucket, r2_key, status);
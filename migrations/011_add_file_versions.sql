@@ -0,0 +1,20 @@
+-- Adds object versioning. Uploads initialized with key_collision = 'version'
+-- record the logical key they are a version of, and each completion appends a
+-- file_versions row pointing at the upload's own R2 key.
+ALTER TABLE uploads ADD COLUMN logical_key TEXT;
+
+CREATE TABLE IF NOT EXISTS file_versions (
+    bucket TEXT NOT NULL,
+    logical_key TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    upload_id TEXT NOT NULL,
+    r2_key TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    content_type TEXT NOT NULL,
+    is_current INTEGER NOT NULL DEFAULT 0,
+    created_at TEXT NOT NULL,
+    PRIMARY KEY (bucket, logical_key, version),
+    FOREIGN KEY (upload_id) REFERENCES uploads(upload_id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_file_versions_upload_id ON file_versions(upload_id);
@@ -0,0 +1,8 @@
+-- Adds encryption at rest. Encrypted uploads record how their data key is
+-- protected and the key itself wrapped under the customer or managed key;
+-- each chunk records its nonce prefix and the ciphertext size stored in R2.
+ALTER TABLE uploads ADD COLUMN encryption TEXT NOT NULL DEFAULT 'none';
+ALTER TABLE uploads ADD COLUMN wrapped_key TEXT;
+
+ALTER TABLE upload_chunks ADD COLUMN nonce TEXT;
+ALTER TABLE upload_chunks ADD COLUMN stored_size INTEGER;
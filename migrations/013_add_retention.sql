@@ -0,0 +1,4 @@
+-- Adds retention holds. An upload whose retain_until lies in the future, or
+-- that is under legal hold, cannot be deleted.
+ALTER TABLE uploads ADD COLUMN retain_until TEXT;
+ALTER TABLE uploads ADD COLUMN legal_hold INTEGER NOT NULL DEFAULT 0;
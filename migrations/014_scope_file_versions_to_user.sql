@@ -0,0 +1,53 @@
+-- Scopes version history to the uploading user. Key templates without
+-- {user_id} render the same logical key for different users, who must not see
+-- or restore each other's versions.
+--
+-- SQLite cannot change a primary key, so file_versions is rebuilt with
+-- user_id copied from each version's upload. A user left without a current
+-- version of a key gets their newest one.
+
+CREATE TABLE file_versions_new (
+    bucket TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    logical_key TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    upload_id TEXT NOT NULL,
+    r2_key TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    content_type TEXT NOT NULL,
+    is_current INTEGER NOT NULL DEFAULT 0,
+    created_at TEXT NOT NULL,
+    PRIMARY KEY (bucket, user_id, logical_key, version),
+    FOREIGN KEY (upload_id) REFERENCES uploads(upload_id) ON DELETE CASCADE
+);
+
+INSERT INTO file_versions_new (
+    bucket, user_id, logical_key, version, upload_id, r2_key, size, content_type,
+    is_current, created_at
+)
+SELECT
+    v.bucket, u.user_id, v.logical_key, v.version, v.upload_id, v.r2_key, v.size,
+    v.content_type, v.is_current, v.created_at
+FROM file_versions v
+JOIN uploads u ON u.upload_id = v.upload_id;
+
+DROP TABLE file_versions;
+ALTER TABLE file_versions_new RENAME TO file_versions;
+
+UPDATE file_versions
+SET is_current = 1
+WHERE version = (
+        SELECT MAX(version) FROM file_versions AS other
+        WHERE other.bucket = file_versions.bucket
+          AND other.user_id = file_versions.user_id
+          AND other.logical_key = file_versions.logical_key
+    )
+  AND NOT EXISTS (
+        SELECT 1 FROM file_versions AS other
+        WHERE other.bucket = file_versions.bucket
+          AND other.user_id = file_versions.user_id
+          AND other.logical_key = file_versions.logical_key
+          AND other.is_current = 1
+    );
+
+CREATE INDEX IF NOT EXISTS idx_file_versions_upload_id ON file_versions(upload_id);
@@ -0,0 +1,4 @@
+-- Adds the time a completion request claimed an upload. A `completing` upload
+-- whose claim is older than the completion lease, or was never stamped, can be
+-- taken over by a retried completion.
+ALTER TABLE uploads ADD COLUMN completion_claimed_at TEXT;
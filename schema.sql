@@ -2,6 +2,17 @@
 -- This schema replaces Durable Objects with D1 database storage
 -- Created: 2025-08-17
 
+-- Upload status lookup table
+-- uploads.status references this table, so adding a lifecycle state is an INSERT
+-- rather than a rebuild of the uploads table
+CREATE TABLE IF NOT EXISTS upload_statuses (
+    status TEXT PRIMARY KEY
+);
+
+INSERT OR IGNORE INTO upload_statuses (status) VALUES
+    ('initiated'), ('in_progress'), ('completing'), ('completed'), ('cancelled'),
+    ('expired'), ('failed'), ('deleted');
+
 -- Upload metadata table
 -- Stores comprehensive information about file uploads
 CREATE TABLE IF NOT EXISTS uploads (
@@ -20,13 +31,38 @@ CREATE TABLE IF NOT EXISTS uploads (
     -- Storage information
     r2_key TEXT NOT NULL,
     r2_upload_id TEXT NOT NULL,
+    -- R2 bucket binding holding the object, chosen by Config::bucket_routing
+    bucket TEXT NOT NULL DEFAULT 'STORAGE_BUCKET',
     
     -- Status tracking
-    status TEXT NOT NULL CHECK (status IN ('initiated', 'in_progress', 'completed', 'cancelled')),
+    status TEXT NOT NULL REFERENCES upload_statuses(status),
+    
+    -- Media metadata parsed from the first chunk (JSON, nullable)
+    media_info TEXT,
+    
+    -- Set to 1 when EXIF/XMP/GPS metadata was stripped before storage
+    metadata_stripped INTEGER NOT NULL DEFAULT 0,
     
     -- Timestamp tracking (ISO 8601 format)
     created_at TEXT NOT NULL,
-    updated_at TEXT NOT NULL
+    updated_at TEXT NOT NULL,
+    
+    -- Key this upload is a version of, when initialized in versioning mode
+    logical_key TEXT,
+    
+    -- Encryption at rest: 'none', 'customer' (X-Encryption-Key) or 'managed'
+    encryption TEXT NOT NULL DEFAULT 'none',
+    -- Per-upload data key wrapped under the customer or managed key (base64)
+    wrapped_key TEXT,
+    
+    -- Retention holds: no delete before retain_until (ISO 8601),
+    -- nor at all while legal_hold is 1
+    retain_until TEXT,
+    legal_hold INTEGER NOT NULL DEFAULT 0,
+    
+    -- When a completion request last claimed the upload (ISO 8601); a stale
+    -- claim can be taken over by a retried completion
+    completion_claimed_at TEXT
 );
 
 -- Upload chunks table
@@ -41,16 +77,138 @@ CREATE TABLE IF NOT EXISTS upload_chunks (
     etag TEXT,  -- R2 ETag for the chunk
     uploaded_at TEXT NOT NULL,
     
+    -- Encrypted uploads only: AES-GCM nonce prefix (hex) and ciphertext bytes in R2
+    nonce TEXT,
+    stored_size INTEGER,
+    
     PRIMARY KEY (upload_id, chunk_index),
     FOREIGN KEY (upload_id) REFERENCES uploads(upload_id) ON DELETE CASCADE
 );
 
+-- Upload variants table
+-- Derived renditions (thumbnails) generated after an image upload completes
+CREATE TABLE IF NOT EXISTS upload_variants (
+    upload_id TEXT NOT NULL,
+    variant_name TEXT NOT NULL,
+    
+    -- Variant object information
+    r2_key TEXT NOT NULL,
+    content_type TEXT NOT NULL,
+    width INTEGER NOT NULL,
+    height INTEGER NOT NULL,
+    size INTEGER NOT NULL,
+    created_at TEXT NOT NULL,
+    
+    PRIMARY KEY (upload_id, variant_name),
+    FOREIGN KEY (upload_id) REFERENCES uploads(upload_id) ON DELETE CASCADE
+);
+
+-- Upload events table
+-- History of status transitions, one row per applied event
+CREATE TABLE IF NOT EXISTS upload_events (
+    event_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    upload_id TEXT NOT NULL,
+    
+    -- Event name (e.g. 'completion_started') and the statuses it moved between
+    event TEXT NOT NULL,
+    from_status TEXT NOT NULL REFERENCES upload_statuses(status),
+    to_status TEXT NOT NULL REFERENCES upload_statuses(status),
+    
+    -- User ID the transition was applied for
+    actor TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    
+    FOREIGN KEY (upload_id) REFERENCES uploads(upload_id) ON DELETE CASCADE
+);
+
+-- File versions table
+-- One row per completed upload of a versioned logical key; each user has their
+-- own history of a key, with exactly one row current
+CREATE TABLE IF NOT EXISTS file_versions (
+    bucket TEXT NOT NULL,
+    user_id TEXT NOT NULL,
+    logical_key TEXT NOT NULL,
+    version INTEGER NOT NULL,
+    
+    -- Upload that produced the version and the R2 object holding it
+    upload_id TEXT NOT NULL,
+    r2_key TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    content_type TEXT NOT NULL,
+    
+    -- 1 for the version served as current
+    is_current INTEGER NOT NULL DEFAULT 0,
+    created_at TEXT NOT NULL,
+    
+    PRIMARY KEY (bucket, user_id, logical_key, version),
+    FOREIGN KEY (upload_id) REFERENCES uploads(upload_id) ON DELETE CASCADE
+);
+
+-- Audit log table
+-- Append-only record of who did what to which upload; no foreign key, so
+-- entries outlive the uploads they describe
+CREATE TABLE IF NOT EXISTS audit_log (
+    audit_id INTEGER PRIMARY KEY AUTOINCREMENT,
+    action TEXT NOT NULL,  -- init, chunk, complete, cancel, download, restore, delete, share, hold or release
+    
+    -- Upload acted on and the user the action was performed for
+    upload_id TEXT,
+    actor TEXT,
+    
+    -- Where the request came from
+    ip TEXT,
+    user_agent TEXT,
+    request_id TEXT NOT NULL,
+    
+    created_at TEXT NOT NULL
+);
+
+CREATE TRIGGER IF NOT EXISTS audit_log_no_update
+BEFORE UPDATE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
+
+CREATE TRIGGER IF NOT EXISTS audit_log_no_delete
+BEFORE DELETE ON audit_log
+BEGIN
+    SELECT RAISE(ABORT, 'audit_log is append-only');
+END;
+
+-- Idempotency keys table
+-- Responses to requests sent with an Idempotency-Key header, replayed on retry
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    route TEXT NOT NULL,
+    idempotency_key TEXT NOT NULL,
+    
+    -- SHA-256 of the request body; a retry with a different body is rejected
+    request_hash TEXT NOT NULL,
+    
+    -- Stored response (NULL while the original request is in progress)
+    status_code INTEGER,
+    response_body TEXT,
+    
+    created_at TEXT NOT NULL,
+    expires_at TEXT NOT NULL,
+    
+    PRIMARY KEY (route, idempotency_key)
+);
+
 -- Create indexes for performance optimization
 CREATE INDEX IF NOT EXISTS idx_uploads_user_id ON uploads(user_id);
 CREATE INDEX IF NOT EXISTS idx_uploads_status ON uploads(status);
 CREATE INDEX IF NOT EXISTS idx_uploads_created_at ON uploads(created_at);
 CREATE INDEX IF NOT EXISTS idx_uploads_user_role ON uploads(user_role);
+CREATE INDEX IF NOT EXISTS idx_uploads_user_status ON uploads(user_id, status, created_at);
+CREATE INDEX IF NOT EXISTS idx_uploads_bucket_key ON uploads(bucket, r2_key, status);
 CREATE INDEX IF NOT EXISTS idx_upload_chunks_upload_id ON upload_chunks(upload_id);
+CREATE INDEX IF NOT EXISTS idx_upload_variants_r2_key ON upload_variants(r2_key);
+CREATE INDEX IF NOT EXISTS idx_upload_events_upload_id ON upload_events(upload_id, event_id);
+CREATE INDEX IF NOT EXISTS idx_file_versions_upload_id ON file_versions(upload_id);
+CREATE INDEX IF NOT EXISTS idx_audit_log_upload_id ON audit_log(upload_id, audit_id);
+CREATE INDEX IF NOT EXISTS idx_audit_log_actor ON audit_log(actor, audit_id);
+CREATE INDEX IF NOT EXISTS idx_audit_log_created_at ON audit_log(created_at);
+CREATE INDEX IF NOT EXISTS idx_idempotency_keys_expires_at ON idempotency_keys(expires_at);
 
 -- Upload statistics view
 -- Provides aggregated statistics for monitoring
@@ -80,5 +238,32 @@ SELECT
     created_at,
     updated_at
 FROM uploads 
-WHERE status IN ('initiated', 'in_progress')
-ORDER BY created_at DESC;
\ No newline at end of file
+WHERE status IN ('initiated', 'in_progress', 'completing')
+ORDER BY created_at DESC;
+-- Schema migrations table
+-- Versions from migrations/ that have been applied. This file already contains
+-- every migration, so a database created from it records them all up front
+-- and the worker's startup check finds nothing pending.
+CREATE TABLE IF NOT EXISTS schema_migrations (
+    version INTEGER PRIMARY KEY,
+    name TEXT NOT NULL,
+    applied_at TEXT NOT NULL
+);
+
+INSERT OR IGNORE INTO schema_migrations (version, name, applied_at) VALUES
+    (0, '000_initial_schema', strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    (1, '001_add_upload_variants', strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    (2, '002_add_media_info', strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    (3, '003_add_metadata_stripped', strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    (4, '004_add_user_status_index', strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    (5, '005_add_idempotency_keys', strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    (6, '006_add_completing_status', strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    (7, '007_add_upload_events', strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    (8, '008_add_audit_log', strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    (9, '009_add_upload_bucket', strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    (10, '010_add_upload_key_index', strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    (11, '011_add_file_versions', strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    (12, '012_add_encryption', strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    (13, '013_add_retention', strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    (14, '014_scope_file_versions_to_user', strftime('%Y-%m-%dT%H:%M:%SZ', 'now')),
+    (15, '015_add_completion_claim', strftime('%Y-%m-%dT%H:%M:%SZ', 'now'));
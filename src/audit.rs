@@ -0,0 +1,136 @@
+//! # Audit Log
+//!
+//! Every successful upload lifecycle request, delete, download, version restore
+//! and retention hold change appends one row to the append-only D1 `audit_log`
+//! table: the action, the upload and user it acted on, and the client IP, user
+//! agent and request ID it came from. Rows are read back through
+//! `GET /api/admin/audit`.
+//!
+//! Recording happens once per request in the upload and admin route
+//! dispatchers, from the fields handlers already record on the
+//! [`RequestContext`]. Chunk uploads are the exception: their entry is written
+//! in the same D1 batch as the chunk row, so they are not mapped here.
+//! Replayed idempotent responses are not recorded again.
+//! A failed write is logged at error level but never fails a request whose
+//! action already took effect.
+
+use chrono::Utc;
+use worker::{Env, Response};
+
+use crate::config::Config;
+use crate::constants::HEADER_IDEMPOTENT_REPLAYED;
+use crate::database::{DatabaseService, NewAuditEntry};
+use crate::logging::{LogLevel, RequestContext};
+use crate::models::AuditAction;
+
+/// Maps a route pattern to the lifecycle action it performs.
+pub fn action_for_route(route: &str) -> Option<AuditAction> {
+    match route {
+        "POST /api/upload/init" => Some(AuditAction::Init),
+        "POST /api/upload/complete" => Some(AuditAction::Complete),
+        "POST /api/upload/cancel" => Some(AuditAction::Cancel),
+        "DELETE /api/upload/{id}" => Some(AuditAction::Delete),
+        "GET /api/upload/{id}/download"
+        | "GET /api/upload/{id}/variants/{name}"
+        | "GET /api/upload/{id}/versions/{version}" => Some(AuditAction::Download),
+        "POST /api/upload/{id}/versions/{version}/restore" => Some(AuditAction::Restore),
+        "PUT /api/admin/uploads/{id}/retention" | "PUT /api/admin/uploads/{id}/legal-hold" => {
+            Some(AuditAction::Hold)
+        }
+        "DELETE /api/admin/uploads/{id}/retention"
+        | "DELETE /api/admin/uploads/{id}/legal-hold" => Some(AuditAction::Release),
+        _ => None,
+    }
+}
+
+/// Appends the audit entry for a successful lifecycle response.
+pub async fn record(env: &Env, config: &Config, ctx: &RequestContext, response: &Response) {
+    let Some(action) = action_for_route(ctx.route()) else {
+        return;
+    };
+    let replayed = response
+        .headers()
+        .get(HEADER_IDEMPOTENT_REPLAYED)
+        .ok()
+        .flatten()
+        .is_some();
+    if !(200..300).contains(&response.status_code()) || replayed {
+        return;
+    }
+
+    let upload_id = ctx.upload_id();
+    let actor = ctx.user_id();
+    let entry = NewAuditEntry {
+        action,
+        upload_id: upload_id.as_deref(),
+        actor: actor.as_deref(),
+        ip: ctx.client_ip(),
+        user_agent: ctx.user_agent(),
+        request_id: ctx.request_id(),
+        created_at: Utc::now(),
+    };
+
+    let result = match DatabaseService::new(env, &config.database_name) {
+        Ok(database) => database.record_audit(&entry).await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = result {
+        ctx.log(
+            LogLevel::Error,
+            &format!("failed to record audit entry: {err}"),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn action_for_route_covers_mutating_upload_routes() {
+        assert_eq!(
+            action_for_route("POST /api/upload/init"),
+            Some(AuditAction::Init)
+        );
+        assert_eq!(
+            action_for_route("POST /api/upload/complete"),
+            Some(AuditAction::Complete)
+        );
+        assert_eq!(
+            action_for_route("POST /api/upload/cancel"),
+            Some(AuditAction::Cancel)
+        );
+        assert_eq!(
+            action_for_route("DELETE /api/upload/{id}"),
+            Some(AuditAction::Delete)
+        );
+        assert_eq!(
+            action_for_route("GET /api/upload/{id}/download"),
+            Some(AuditAction::Download)
+        );
+        assert_eq!(
+            action_for_route("GET /api/upload/{id}/variants/{name}"),
+            Some(AuditAction::Download)
+        );
+        assert_eq!(
+            action_for_route("GET /api/upload/{id}/versions/{version}"),
+            Some(AuditAction::Download)
+        );
+        assert_eq!(
+            action_for_route("POST /api/upload/{id}/versions/{version}/restore"),
+            Some(AuditAction::Restore)
+        );
+        assert_eq!(
+            action_for_route("PUT /api/admin/uploads/{id}/legal-hold"),
+            Some(AuditAction::Hold)
+        );
+        assert_eq!(
+            action_for_route("DELETE /api/admin/uploads/{id}/retention"),
+            Some(AuditAction::Release)
+        );
+        assert_eq!(action_for_route("PUT /api/upload/chunk"), None);
+        assert_eq!(action_for_route("GET /api/upload/{id}/status"), None);
+        assert_eq!(action_for_route("GET /api/upload/{id}/versions"), None);
+        assert_eq!(action_for_route("GET /api/admin/audit"), None);
+    }
+}
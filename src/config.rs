@@ -2,26 +2,89 @@
 //!
 //! Configuration is read from the `STORAGE_CONFIG` KV namespace under the
 //! `"config"` key when present, otherwise the service falls back to
-//! [`Config::default`].
+//! [`Config::default`]. The KV value may be partial: every missing field,
+//! including fields of nested sections such as `cors` or `rate_limits`, keeps
+//! its default. The result must pass [`Config::validate`]; a rejected value is
+//! logged and reported by `/health`, and never replaces a working configuration.
+//!
+//! ## Sources
+//!
+//! A configuration is built from layers, each overriding the ones before it:
+//!
+//! 1. [`Config::default`].
+//! 2. The KV `config` value, merged over the defaults field by field.
+//! 3. The `CONFIG_OVERRIDES` Worker var, a JSON Merge Patch (RFC 7386) applied
+//!    to the KV value; set it per environment under `[env.<name>.vars]` in
+//!    `wrangler.toml`, as a JSON string or a table.
+//! 4. Worker secrets, which fill the [`SecretString`] fields listed in
+//!    [`SECRET_FIELDS`]. Those fields cannot be set by KV or vars, and are
+//!    redacted by `Debug` and serialization so they never reach logs or API
+//!    responses.
+//!
+//! Validation runs on the merged result, before secrets are applied.
+//!
+//! ## Refresh
+//!
+//! Each worker isolate caches the configuration and re-reads KV once the
+//! cached copy is older than `config_refresh_secs`, so edits take effect
+//! within that window on every isolate. A configuration is identified by its
+//! fingerprint, a hash of the KV JSON and vars: when a refresh finds the same
+//! fingerprint the cached `Arc<Config>` is kept. If a refresh fails (KV
+//! unavailable, invalid JSON or a validation error) the last good
+//! configuration keeps serving and the error is reported by `/health` until a
+//! later refresh succeeds.
 //!
 //! ## Fields
 //!
+//! - `config_version`: operator-maintained revision number, reported by `/health` (default: 0).
 //! - `database_name`: D1 database binding name used by `DatabaseService`.
 //! - `max_file_size`: hard cap on `total_size` accepted at upload init (default: 10 GB).
 //! - `chunk_size`: recommended chunk size returned to clients (default: 95 MiB, kept under the Workers request body cap).
+//! - `image_variants`: thumbnail sizes and formats generated after an `image/*` upload completes.
+//! - `image_metadata_policy`: per-role choice to keep or strip EXIF/XMP/GPS metadata from images.
+//! - `encryption_policy`: per-role encryption at rest: none, managed keys, or client keys required.
+//! - `cors`: allowed browser origins, per-origin methods/headers, and credential support.
+//! - `rate_limits`: token buckets per route, keyed by client IP and by user role.
+//! - `bucket_routing`: which R2 bucket binding new uploads go to, by role and content category.
+//! - `r2_key_template`: layout of new uploads' object keys (default: `{role}/{user_id}/{yyyy}{mm}{dd}/{category}/{filename}`).
+//! - `key_collision`: what init does when the rendered key is already taken (default: `append_upload_id`).
+//! - `max_active_uploads`: per-role cap on uploads a user may have `initiated` or `in_progress`.
+//! - `idempotency_ttl_secs`: how long `Idempotency-Key` responses are replayed (default: 24 hours).
+//! - `auto_migrate`: apply pending D1 schema migrations on the first request (default: true).
+//! - `config_refresh_secs`: how long an isolate serves its cached configuration before re-reading KV (default: 60).
+//! - `admin_token`: bearer token for `/api/admin`, from the `ADMIN_TOKEN` secret only.
+//! - `encryption_master_key`: base64 256-bit key for managed encryption, from the
+//!   `ENCRYPTION_MASTER_KEY` secret only.
 //!
 //! ## Example
 //!
 //! ```rust
-//! let kv = env.kv("STORAGE_CONFIG")?;
-//! let config = Config::load(&kv).await?;
+//! let config = config::current(&env).await?;
 //! println!("Max file size: {} bytes", config.max_file_size);
 //! ```
 
-use crate::constants::{DEFAULT_CHUNK_SIZE, DEFAULT_MAX_FILE_SIZE, UPLOAD_DB_NAME};
-use serde::{Deserialize, Serialize};
-use worker::kv::KvStore;
-use worker::{console_log, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use chrono::{DateTime, Duration, Utc};
+use sha2::{Digest, Sha256};
+
+use crate::constants::{
+    ADMIN_TOKEN_SECRET_NAME, CONFIG_KV_KEY, CONFIG_OVERRIDES_VAR_NAME, DEFAULT_CHUNK_SIZE,
+    DEFAULT_CONFIG_REFRESH_SECS, DEFAULT_CORS_ALLOWED_HEADERS, DEFAULT_CORS_ALLOWED_METHODS,
+    DEFAULT_CORS_ALLOWED_ORIGIN, DEFAULT_CORS_EXPOSE_HEADERS, DEFAULT_CORS_MAX_AGE,
+    DEFAULT_IDEMPOTENCY_TTL_SECS, DEFAULT_MAX_FILE_SIZE, DEFAULT_MAX_VARIANT_SOURCE_SIZE,
+    DEFAULT_R2_KEY_TEMPLATE, ENCRYPTION_MASTER_KEY_SECRET_NAME, MAX_CHUNK_SIZE,
+    MAX_CONFIG_REFRESH_SECS, MAX_PART_NUMBER, MAX_VARIANT_DIMENSION, MIN_CHUNK_SIZE,
+    STORAGE_BUCKET_NAME, STORAGE_CONFIG_KV_NAME, UPLOAD_DB_NAME,
+};
+use crate::crypto::Key;
+use crate::logging::{log, LogLevel};
+use crate::models::UserRole;
+use crate::utils::{categorize_content_type, KeyTemplate, CONTENT_CATEGORIES};
+use serde::{Deserialize, Serialize, Serializer};
+use worker::{Env, Result};
 
 /// Configuration structure for the file storage service.
 ///
@@ -29,7 +92,12 @@ use worker::{console_log, Result};
 /// including upload limits, chunk sizes, and database settings.
 /// All fields are public to allow easy access throughout the application.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
+    /// Revision of this configuration, reported by `/health` so operators can
+    /// tell which edit each isolate is serving.
+    pub config_version: u64,
+
     /// Name of the D1 database binding used for upload state tracking.
     /// Must match the binding name in wrangler.toml.
     pub database_name: String,
@@ -41,6 +109,492 @@ pub struct Config {
     /// Size of individual upload chunks in bytes.
     /// Larger chunks reduce the number of requests but increase memory usage.
     pub chunk_size: usize,
+
+    /// Post-completion image variant generation settings.
+    pub image_variants: ImageVariantConfig,
+
+    /// Image metadata handling per uploader role.
+    /// Roles without an entry keep their metadata.
+    pub image_metadata_policy: HashMap<UserRole, MetadataPolicy>,
+
+    /// Encryption at rest per uploader role.
+    /// Roles without an entry are stored unencrypted unless the client sends a key.
+    pub encryption_policy: HashMap<UserRole, EncryptionPolicy>,
+
+    /// Cross-origin policy for browser clients.
+    /// Defaults to allowing any origin without credentials.
+    pub cors: CorsConfig,
+
+    /// Request rate limits per route.
+    /// Only enforced when the `RATE_LIMITER` Durable Object or `RATE_LIMIT` KV
+    /// binding exists.
+    pub rate_limits: RateLimitConfig,
+
+    /// R2 bucket selection for new uploads.
+    /// The chosen binding is stored on the upload and used for its whole lifetime.
+    pub bucket_routing: BucketRouting,
+
+    /// Template for new uploads' R2 object keys; see [`KeyTemplate`] for placeholders.
+    /// Changing it only affects uploads initialized afterwards.
+    pub r2_key_template: String,
+
+    /// What init does when the rendered key is held by another upload or object.
+    pub key_collision: KeyCollisionStrategy,
+
+    /// Maximum uploads a user may have `initiated` or `in_progress` at once, per role.
+    /// Roles without an entry are not limited.
+    pub max_active_uploads: HashMap<UserRole, u32>,
+
+    /// Seconds a stored `Idempotency-Key` response is replayed before the key may be reused.
+    pub idempotency_ttl_secs: u64,
+
+    /// Apply pending schema migrations on the first request each isolate serves.
+    /// When off, requests fail with `SCHEMA_MISMATCH` until an operator runs them.
+    pub auto_migrate: bool,
+
+    /// Seconds a worker isolate serves its cached configuration before re-reading KV.
+    /// `0` re-reads on every request.
+    pub config_refresh_secs: u64,
+
+    /// Bearer token required by `/api/admin`; the admin API is off without it.
+    /// Only read from the `ADMIN_TOKEN` secret.
+    #[serde(skip_deserializing)]
+    pub admin_token: Option<SecretString>,
+
+    /// Base64 256-bit master key that per-user managed encryption keys are derived from.
+    /// Only read from the `ENCRYPTION_MASTER_KEY` secret.
+    #[serde(skip_deserializing)]
+    pub encryption_master_key: Option<SecretString>,
+}
+
+/// Text that must not leave the worker: `Debug` and serialization print
+/// `[redacted]` instead of the value.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SecretString(String);
+
+impl SecretString {
+    pub fn new(value: impl Into<String>) -> Self {
+        Self(value.into())
+    }
+
+    /// The secret itself, for the code that needs to use it.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+const REDACTED: &str = "[redacted]";
+
+impl fmt::Debug for SecretString {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl Serialize for SecretString {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        serializer.serialize_str(REDACTED)
+    }
+}
+
+/// Configuration fields filled from Worker secrets, with the secret each reads.
+pub const SECRET_FIELDS: &[(&str, &str)] = &[
+    ("admin_token", ADMIN_TOKEN_SECRET_NAME),
+    ("encryption_master_key", ENCRYPTION_MASTER_KEY_SECRET_NAME),
+];
+
+/// Creators may run 50 uploads in parallel, members 20, subscribers 5.
+fn default_max_active_uploads() -> HashMap<UserRole, u32> {
+    HashMap::from([
+        (UserRole::Creator, 50),
+        (UserRole::Member, 20),
+        (UserRole::Subscriber, 5),
+    ])
+}
+
+/// Chooses the R2 bucket binding for a new upload.
+///
+/// Rules are checked in order; the first whose `role` and `category` both
+/// match (an omitted one matches anything) picks the bucket. Uploads no rule
+/// matches go to `default_bucket`. Every named binding must exist in
+/// `wrangler.toml`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BucketRouting {
+    /// Binding used when no rule matches.
+    pub default_bucket: String,
+
+    /// Routing rules, first match wins.
+    pub rules: Vec<BucketRule>,
+}
+
+/// Sends uploads matching a role and/or content category to a bucket.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BucketRule {
+    /// Uploader role to match; any role when omitted.
+    #[serde(default)]
+    pub role: Option<UserRole>,
+
+    /// Content category (`image`, `video`, `audio`, `document`, `other`) to
+    /// match; any category when omitted.
+    #[serde(default)]
+    pub category: Option<String>,
+
+    /// R2 bucket binding name.
+    pub bucket: String,
+}
+
+impl Default for BucketRouting {
+    fn default() -> Self {
+        Self {
+            default_bucket: STORAGE_BUCKET_NAME.to_string(),
+            rules: Vec::new(),
+        }
+    }
+}
+
+impl BucketRouting {
+    /// Returns the bucket binding for an upload by `role` of `content_type`.
+    pub fn bucket_for(&self, role: &UserRole, content_type: &str) -> &str {
+        let category = categorize_content_type(content_type);
+        self.rules
+            .iter()
+            .find(|rule| {
+                rule.role.as_ref().is_none_or(|rule_role| rule_role == role)
+                    && rule
+                        .category
+                        .as_deref()
+                        .is_none_or(|rule_category| rule_category == category)
+            })
+            .map_or(&self.default_bucket, |rule| &rule.bucket)
+    }
+}
+
+/// Whether `name` can be a Worker binding name: an ASCII identifier.
+fn is_binding_name(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Rate limits keyed by route pattern, as recorded in the request log
+/// (e.g. `POST /api/upload/init`). Routes without an entry are not limited.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Whether any limit is enforced.
+    pub enabled: bool,
+
+    /// Limits per route pattern.
+    pub routes: HashMap<String, RouteRateLimit>,
+}
+
+/// Limits for one route.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RouteRateLimit {
+    /// Bucket per client IP (`CF-Connecting-IP`), applied before the handler runs.
+    #[serde(default)]
+    pub per_ip: Option<TokenBucketSpec>,
+
+    /// Bucket per user ID, sized by the user's role.
+    /// Roles without an entry are not limited per user.
+    #[serde(default)]
+    pub per_user: HashMap<UserRole, TokenBucketSpec>,
+}
+
+/// Token bucket size: bursts up to `capacity`, sustained `refill_per_second`.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TokenBucketSpec {
+    /// Maximum burst of requests.
+    pub capacity: u32,
+
+    /// Tokens added per second.
+    pub refill_per_second: f64,
+}
+
+impl RateLimitConfig {
+    /// Per-IP limit for `route`, if enforced.
+    pub fn ip_limit(&self, route: &str) -> Option<&TokenBucketSpec> {
+        self.route(route)?.per_ip.as_ref()
+    }
+
+    /// Per-user limit for `route` and `role`, if enforced.
+    pub fn user_limit(&self, route: &str, role: &UserRole) -> Option<&TokenBucketSpec> {
+        self.route(route)?.per_user.get(role)
+    }
+
+    fn route(&self, route: &str) -> Option<&RouteRateLimit> {
+        self.routes.get(route).filter(|_| self.enabled)
+    }
+}
+
+impl Default for RateLimitConfig {
+    /// Limits upload initialization and chunk uploads; other routes are unlimited.
+    fn default() -> Self {
+        let bucket = |capacity, refill_per_second| TokenBucketSpec {
+            capacity,
+            refill_per_second,
+        };
+
+        let init = RouteRateLimit {
+            per_ip: Some(bucket(60, 1.0)),
+            per_user: HashMap::from([
+                (UserRole::Creator, bucket(60, 1.0)),
+                (UserRole::Member, bucket(20, 0.2)),
+                (UserRole::Subscriber, bucket(10, 0.1)),
+            ]),
+        };
+        let chunk = RouteRateLimit {
+            per_ip: Some(bucket(600, 20.0)),
+            per_user: HashMap::from([
+                (UserRole::Creator, bucket(600, 20.0)),
+                (UserRole::Member, bucket(200, 5.0)),
+                (UserRole::Subscriber, bucket(100, 2.0)),
+            ]),
+        };
+
+        Self {
+            enabled: true,
+            routes: HashMap::from([
+                ("POST /api/upload/init".to_string(), init),
+                ("PUT /api/upload/chunk".to_string(), chunk),
+            ]),
+        }
+    }
+}
+
+/// Cross-origin resource sharing policy.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CorsConfig {
+    /// Origins allowed to call the API, checked in order; the first match wins.
+    pub allowed_origins: Vec<CorsOriginRule>,
+
+    /// Sends `Access-Control-Allow-Credentials: true` so browsers include cookies.
+    /// When enabled the matched origin is always echoed, never `*`.
+    pub allow_credentials: bool,
+
+    /// Response headers browser scripts may read (`Access-Control-Expose-Headers`).
+    pub expose_headers: Vec<String>,
+
+    /// Preflight cache lifetime in seconds (`Access-Control-Max-Age`).
+    pub max_age: u32,
+}
+
+/// An allowed origin pattern and what requests from it may use.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct CorsOriginRule {
+    /// `*` for any origin, an exact origin (`https://app.example.com`), or a
+    /// wildcard subdomain pattern (`https://*.example.com`, which does not
+    /// match `https://example.com` itself).
+    pub origin: String,
+
+    /// Methods allowed for this origin.
+    #[serde(default = "default_cors_allowed_methods")]
+    pub allowed_methods: Vec<String>,
+
+    /// Request headers allowed for this origin.
+    #[serde(default = "default_cors_allowed_headers")]
+    pub allowed_headers: Vec<String>,
+}
+
+impl CorsOriginRule {
+    /// Returns whether `origin` matches this rule's pattern (ASCII case-insensitive).
+    pub fn matches(&self, origin: &str) -> bool {
+        let pattern = self.origin.as_str();
+        if pattern == "*" {
+            return true;
+        }
+
+        match pattern.split_once("://*.") {
+            Some((scheme, domain)) => match origin.split_once("://") {
+                Some((origin_scheme, host)) if origin_scheme.eq_ignore_ascii_case(scheme) => {
+                    let host = host.to_ascii_lowercase();
+                    let suffix = format!(".{}", domain.to_ascii_lowercase());
+                    host.len() > suffix.len() && host.ends_with(&suffix)
+                }
+                _ => false,
+            },
+            None => pattern.eq_ignore_ascii_case(origin),
+        }
+    }
+
+    /// Returns whether `method` is in this rule's allowed methods.
+    pub fn allows_method(&self, method: &str) -> bool {
+        self.allowed_methods
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(method))
+    }
+}
+
+impl CorsConfig {
+    /// Returns the first rule whose pattern matches `origin`.
+    pub fn rule_for(&self, origin: &str) -> Option<&CorsOriginRule> {
+        self.allowed_origins
+            .iter()
+            .find(|rule| rule.matches(origin))
+    }
+}
+
+impl Default for CorsConfig {
+    /// Allows any origin with the default methods and headers, without credentials.
+    fn default() -> Self {
+        Self {
+            allowed_origins: vec![CorsOriginRule {
+                origin: DEFAULT_CORS_ALLOWED_ORIGIN.to_string(),
+                allowed_methods: default_cors_allowed_methods(),
+                allowed_headers: default_cors_allowed_headers(),
+            }],
+            allow_credentials: false,
+            expose_headers: default_cors_expose_headers(),
+            max_age: DEFAULT_CORS_MAX_AGE,
+        }
+    }
+}
+
+fn to_strings(values: &[&str]) -> Vec<String> {
+    values.iter().map(|value| value.to_string()).collect()
+}
+
+fn default_cors_allowed_methods() -> Vec<String> {
+    to_strings(DEFAULT_CORS_ALLOWED_METHODS)
+}
+
+fn default_cors_allowed_headers() -> Vec<String> {
+    to_strings(DEFAULT_CORS_ALLOWED_HEADERS)
+}
+
+fn default_cors_expose_headers() -> Vec<String> {
+    to_strings(DEFAULT_CORS_EXPOSE_HEADERS)
+}
+
+/// How EXIF, XMP, and GPS metadata embedded in JPEG/PNG/WebP uploads is handled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetadataPolicy {
+    /// Store the image exactly as uploaded.
+    #[default]
+    Keep,
+    /// Blank metadata segments before the bytes reach R2.
+    Strip,
+}
+
+/// Whether uploads are encrypted before they reach R2.
+///
+/// A client that sends `X-Encryption-Key` always gets its upload encrypted
+/// with that key, whatever the policy.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EncryptionPolicy {
+    /// Store uploads as sent.
+    #[default]
+    None,
+    /// Encrypt with a key derived from `ENCRYPTION_MASTER_KEY`.
+    Managed,
+    /// Reject uploads that do not send `X-Encryption-Key`.
+    Customer,
+}
+
+/// How upload init resolves an R2 key that is already taken.
+///
+/// A key is taken when an upload in flight or completed holds it in the same
+/// bucket, or when an object already exists there.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyCollisionStrategy {
+    /// Insert the upload ID before the extension: `video-<upload_id>.mp4`.
+    #[default]
+    AppendUploadId,
+    /// Insert the first free number: `video-1.mp4`, `video-2.mp4`, ...
+    NumericSuffix,
+    /// Fail init with `409 R2_KEY_CONFLICT`.
+    Reject,
+    /// Keep the key as a logical key with version history: every upload is
+    /// stored under its own key and each completion becomes the current version.
+    Version,
+}
+
+/// Strips metadata for members and subscribers; creators keep theirs.
+fn default_image_metadata_policy() -> HashMap<UserRole, MetadataPolicy> {
+    HashMap::from([
+        (UserRole::Creator, MetadataPolicy::Keep),
+        (UserRole::Member, MetadataPolicy::Strip),
+        (UserRole::Subscriber, MetadataPolicy::Strip),
+    ])
+}
+
+/// Settings for the image thumbnail pipeline run after an `image/*` upload completes.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImageVariantConfig {
+    /// Whether variants are generated at all.
+    pub enabled: bool,
+
+    /// Largest source object, in bytes, that will be decoded inside the worker.
+    /// Decoding happens fully in memory, so this guards the isolate memory limit.
+    pub max_source_size: u64,
+
+    /// Variants to produce for every eligible image.
+    pub variants: Vec<ImageVariantSpec>,
+}
+
+/// A single configured image variant.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ImageVariantSpec {
+    /// Stable variant name, used in the R2 key and the `upload_variants` table.
+    pub name: String,
+
+    /// Longest edge of the generated image in pixels; aspect ratio is preserved.
+    pub max_dimension: u32,
+
+    /// Output encoding.
+    pub format: VariantFormat,
+}
+
+/// Output encoding for generated image variants.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum VariantFormat {
+    /// Lossless WebP.
+    Webp,
+    /// Baseline JPEG (alpha is flattened).
+    Jpeg,
+}
+
+impl VariantFormat {
+    /// File extension used for variant object keys.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            VariantFormat::Webp => "webp",
+            VariantFormat::Jpeg => "jpg",
+        }
+    }
+
+    /// MIME type stored on the variant object.
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            VariantFormat::Webp => "image/webp",
+            VariantFormat::Jpeg => "image/jpeg",
+        }
+    }
+}
+
+impl Default for ImageVariantConfig {
+    /// Generates 128, 512, and 1024 px WebP variants for images up to 50 MiB.
+    fn default() -> Self {
+        let variant = |size: u32| ImageVariantSpec {
+            name: format!("w{size}"),
+            max_dimension: size,
+            format: VariantFormat::Webp,
+        };
+
+        Self {
+            enabled: true,
+            max_source_size: DEFAULT_MAX_VARIANT_SOURCE_SIZE,
+            variants: vec![variant(128), variant(512), variant(1024)],
+        }
+    }
 }
 
 impl Default for Config {
@@ -52,39 +606,1060 @@ impl Default for Config {
     /// - Standard D1 database binding name
     fn default() -> Self {
         Self {
+            config_version: 0,
             database_name: UPLOAD_DB_NAME.to_string(),
             max_file_size: DEFAULT_MAX_FILE_SIZE,
             chunk_size: DEFAULT_CHUNK_SIZE as usize,
+            image_variants: ImageVariantConfig::default(),
+            image_metadata_policy: default_image_metadata_policy(),
+            encryption_policy: HashMap::new(),
+            cors: CorsConfig::default(),
+            rate_limits: RateLimitConfig::default(),
+            bucket_routing: BucketRouting::default(),
+            r2_key_template: DEFAULT_R2_KEY_TEMPLATE.to_string(),
+            key_collision: KeyCollisionStrategy::default(),
+            max_active_uploads: default_max_active_uploads(),
+            idempotency_ttl_secs: DEFAULT_IDEMPOTENCY_TTL_SECS,
+            auto_migrate: true,
+            config_refresh_secs: DEFAULT_CONFIG_REFRESH_SECS,
+            admin_token: None,
+            encryption_master_key: None,
         }
     }
 }
 
 impl Config {
+    /// Returns the image metadata policy for `role`, defaulting to [`MetadataPolicy::Keep`].
+    pub fn metadata_policy_for(&self, role: &UserRole) -> MetadataPolicy {
+        self.image_metadata_policy
+            .get(role)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Returns the encryption policy for `role`, defaulting to [`EncryptionPolicy::None`].
+    pub fn encryption_policy_for(&self, role: &UserRole) -> EncryptionPolicy {
+        self.encryption_policy
+            .get(role)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Decodes the managed encryption master key, or `None` when the secret is unset.
+    pub fn master_key(&self) -> Option<Key> {
+        self.encryption_master_key
+            .as_ref()
+            .and_then(|secret| Key::from_base64("encryption_master_key", secret.expose()).ok())
+    }
+
+    /// Returns the active upload cap for `role`, or `None` when unlimited.
+    pub fn active_upload_limit(&self, role: &UserRole) -> Option<u32> {
+        self.max_active_uploads.get(role).copied()
+    }
+
     /// Loads configuration from KV storage with fallback to defaults.
     ///
     /// Reads the `"config"` key from KV. Returns [`Config::default`] when the
-    /// key is absent. KV access errors and JSON deserialization failures are
-    /// propagated.
+    /// key is absent. Fields missing from the KV value keep their defaults.
+    /// KV access errors, JSON deserialization failures and
+    /// [`Config::validate`] problems are propagated. Requests go through the per-isolate cache in [`current`]
+    /// instead of calling this directly.
     ///
     /// Expected KV value:
     ///
     /// ```json
     /// {
+    ///   "config_version": 3,
     ///   "database_name": "UPLOAD_DB",
     ///   "max_file_size": 10737418240,
-    ///   "chunk_size": 99614720
+    ///   "chunk_size": 99614720,
+    ///   "image_variants": {
+    ///     "enabled": true,
+    ///     "max_source_size": 52428800,
+    ///     "variants": [{ "name": "w128", "max_dimension": 128, "format": "webp" }]
+    ///   },
+    ///   "image_metadata_policy": { "creator": "keep", "member": "strip", "subscriber": "strip" },
+    ///   "cors": {
+    ///     "allowed_origins": [
+    ///       { "origin": "https://app.example.com" },
+    ///       { "origin": "https://*.example.com", "allowed_methods": ["GET"] }
+    ///     ],
+    ///     "allow_credentials": true
+    ///   },
+    ///   "max_active_uploads": { "creator": 50, "member": 20, "subscriber": 5 },
+    ///   "idempotency_ttl_secs": 86400,
+    ///   "auto_migrate": true,
+    ///   "config_refresh_secs": 60,
+    ///   "rate_limits": {
+    ///     "enabled": true,
+    ///     "routes": {
+    ///       "POST /api/upload/init": {
+    ///         "per_ip": { "capacity": 60, "refill_per_second": 1.0 },
+    ///         "per_user": { "subscriber": { "capacity": 10, "refill_per_second": 0.1 } }
+    ///       }
+    ///     }
+    ///   }
     /// }
     /// ```
-    pub async fn load(kv: &KvStore) -> Result<Self> {
-        match kv.get("config").json().await? {
-            Some(config) => {
-                console_log!("Configuration loaded from KV storage");
-                Ok(config)
+    pub async fn load(env: &Env) -> Result<LoadedConfig> {
+        let kv = env.kv(STORAGE_CONFIG_KV_NAME)?;
+        let sources = ConfigSources {
+            kv: kv.get(CONFIG_KV_KEY).text().await?,
+            ..ConfigSources::overlays(env)
+        };
+        LoadedConfig::resolve(&sources).map_err(|problems| {
+            worker::Error::RustError(format!("Configuration rejected: {}", problems.join("; ")))
+        })
+    }
+
+    /// Builds a configuration from a KV value: missing fields keep their
+    /// defaults and the result must pass [`Config::validate`].
+    ///
+    /// Secret fields are never taken from `value`; one that is set to anything
+    /// but `null` or the `[redacted]` placeholder is reported as a problem.
+    pub fn from_json(value: serde_json::Value) -> std::result::Result<Self, Vec<String>> {
+        let mut problems = unknown_role_keys(&value);
+        for (field, secret) in SECRET_FIELDS {
+            if value
+                .get(field)
+                .is_some_and(|value| !value.is_null() && value != REDACTED)
+            {
+                problems.push(format!(
+                    "{field}: secrets cannot be set in KV or vars; use `wrangler secret put {secret}`"
+                ));
+            }
+        }
+        if !problems.is_empty() {
+            return Err(problems);
+        }
+        let config: Config = serde_json::from_value(value).map_err(|err| vec![err.to_string()])?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Fills the field named in [`SECRET_FIELDS`].
+    fn set_secret(&mut self, field: &str, value: SecretString) {
+        match field {
+            "admin_token" => self.admin_token = Some(value),
+            "encryption_master_key" => self.encryption_master_key = Some(value),
+            _ => unreachable!("{field} is not a secret field"),
+        }
+    }
+
+    /// Checks the secrets [`LoadedConfig::resolve`] filled in against the
+    /// settings that need them.
+    fn validate_secrets(&self) -> std::result::Result<(), Vec<String>> {
+        let managed = self
+            .encryption_policy
+            .values()
+            .any(|policy| *policy == EncryptionPolicy::Managed);
+        match &self.encryption_master_key {
+            Some(secret) => Key::from_base64("encryption_master_key", secret.expose())
+                .map(|_| ())
+                .map_err(|_| {
+                    vec!["encryption_master_key: must be a base64-encoded 256-bit key".to_string()]
+                }),
+            None if managed => Err(vec![format!(
+                "encryption_policy: managed encryption needs the {ENCRYPTION_MASTER_KEY_SECRET_NAME} secret"
+            )]),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks limits against R2 and Workers constraints and for internal
+    /// consistency, returning every problem found as `field: reason`.
+    pub fn validate(&self) -> std::result::Result<(), Vec<String>> {
+        let mut problems = Vec::new();
+        let mut check = |ok: bool, field: &str, reason: String| {
+            if !ok {
+                problems.push(format!("{field}: {reason}"));
+            }
+        };
+
+        check(
+            !self.database_name.trim().is_empty(),
+            "database_name",
+            "must not be empty".to_string(),
+        );
+        check(
+            (MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&(self.chunk_size as u64)),
+            "chunk_size",
+            format!("must be between {MIN_CHUNK_SIZE} and {MAX_CHUNK_SIZE} bytes"),
+        );
+        let max_upload = (self.chunk_size as u64).saturating_mul(u64::from(MAX_PART_NUMBER));
+        check(
+            self.max_file_size > 0 && self.max_file_size <= max_upload,
+            "max_file_size",
+            format!("must be between 1 and {max_upload} bytes ({MAX_PART_NUMBER} chunks)"),
+        );
+        check(
+            self.idempotency_ttl_secs > 0,
+            "idempotency_ttl_secs",
+            "must be at least 1".to_string(),
+        );
+        check(
+            self.config_refresh_secs <= MAX_CONFIG_REFRESH_SECS,
+            "config_refresh_secs",
+            format!("must be at most {MAX_CONFIG_REFRESH_SECS}"),
+        );
+
+        let routing = &self.bucket_routing;
+        check(
+            is_binding_name(&routing.default_bucket),
+            "bucket_routing.default_bucket",
+            "must be an R2 binding name".to_string(),
+        );
+        for (index, rule) in routing.rules.iter().enumerate() {
+            let field = format!("bucket_routing.rules[{index}]");
+            check(
+                is_binding_name(&rule.bucket),
+                &format!("{field}.bucket"),
+                "must be an R2 binding name".to_string(),
+            );
+            check(
+                rule.category
+                    .as_deref()
+                    .is_none_or(|category| CONTENT_CATEGORIES.contains(&category)),
+                &format!("{field}.category"),
+                format!("must be one of {}", CONTENT_CATEGORIES.join(", ")),
+            );
+        }
+
+        if let Err(reason) = KeyTemplate::parse(&self.r2_key_template) {
+            check(false, "r2_key_template", reason);
+        }
+
+        for (role, limit) in &self.max_active_uploads {
+            check(
+                *limit > 0,
+                &format!("max_active_uploads.{}", role.as_str()),
+                "must be at least 1".to_string(),
+            );
+        }
+
+        let variants = &self.image_variants;
+        if variants.enabled {
+            check(
+                variants.max_source_size > 0,
+                "image_variants.max_source_size",
+                "must be at least 1".to_string(),
+            );
+        }
+        for (index, variant) in variants.variants.iter().enumerate() {
+            let field = format!("image_variants.variants[{index}]");
+            check(
+                !variant.name.is_empty()
+                    && variant
+                        .name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+                &format!("{field}.name"),
+                "must be ASCII letters, digits, '-' or '_'".to_string(),
+            );
+            check(
+                variants.variants[..index]
+                    .iter()
+                    .all(|other| other.name != variant.name),
+                &format!("{field}.name"),
+                format!("duplicate variant name '{}'", variant.name),
+            );
+            check(
+                (1..=MAX_VARIANT_DIMENSION).contains(&variant.max_dimension),
+                &format!("{field}.max_dimension"),
+                format!("must be between 1 and {MAX_VARIANT_DIMENSION}"),
+            );
+        }
+
+        for (index, rule) in self.cors.allowed_origins.iter().enumerate() {
+            let field = format!("cors.allowed_origins[{index}].origin");
+            check(
+                !rule.origin.trim().is_empty(),
+                &field,
+                "must not be empty".to_string(),
+            );
+            check(
+                !(self.cors.allow_credentials && rule.origin == "*"),
+                &field,
+                "'*' cannot be combined with allow_credentials".to_string(),
+            );
+        }
+
+        for (route, limits) in &self.rate_limits.routes {
+            let field = format!("rate_limits.routes.{route}");
+            check(
+                RATE_LIMITED_ROUTES.contains(&route.as_str()),
+                &field,
+                format!(
+                    "unknown route; expected one of {}",
+                    RATE_LIMITED_ROUTES.join(", ")
+                ),
+            );
+            let buckets = limits
+                .per_ip
+                .iter()
+                .map(|spec| ("per_ip".to_string(), spec))
+                .chain(
+                    limits
+                        .per_user
+                        .iter()
+                        .map(|(role, spec)| (format!("per_user.{}", role.as_str()), spec)),
+                );
+            for (bucket, spec) in buckets {
+                check(
+                    spec.capacity > 0
+                        && spec.refill_per_second.is_finite()
+                        && spec.refill_per_second > 0.0,
+                    &format!("{field}.{bucket}"),
+                    "capacity and refill_per_second must be positive".to_string(),
+                );
             }
-            None => {
-                console_log!("Config not found in KV, using default");
-                Ok(Self::default())
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems)
+        }
+    }
+}
+
+/// Route patterns whose requests pass through the rate limiter.
+const RATE_LIMITED_ROUTES: &[&str] = &[
+    "POST /api/upload/init",
+    "PUT /api/upload/chunk",
+    "POST /api/upload/complete",
+    "POST /api/upload/cancel",
+    "DELETE /api/upload/{id}",
+    "GET /api/upload/{id}/status",
+    "GET /api/upload/{id}/download",
+    "GET /api/upload/{id}/variants/{name}",
+    "GET /api/upload/{id}/versions",
+    "GET /api/upload/{id}/versions/{version}",
+    "POST /api/upload/{id}/versions/{version}/restore",
+];
+
+/// Applies a JSON Merge Patch (RFC 7386) to `target`: objects merge
+/// recursively, `null` removes a field so it falls back to its default, and any
+/// other value replaces what was there.
+pub fn merge_patch(target: &mut serde_json::Value, patch: serde_json::Value) {
+    let serde_json::Value::Object(patch) = patch else {
+        *target = patch;
+        return;
+    };
+    if !target.is_object() {
+        *target = serde_json::Value::Object(serde_json::Map::new());
+    }
+    if let serde_json::Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(&key);
+            } else {
+                merge_patch(target.entry(key).or_insert(serde_json::Value::Null), value);
             }
         }
     }
 }
+
+/// Sections of the configuration keyed by user role.
+const ROLE_KEYED_SECTIONS: &[&str] = &[
+    "image_metadata_policy",
+    "encryption_policy",
+    "max_active_uploads",
+];
+
+/// Names every key of a role-keyed map that is not a known [`UserRole`].
+///
+/// Checked on the raw JSON so the message names the offending map instead of
+/// serde's bare "unknown variant".
+fn unknown_role_keys(json: &serde_json::Value) -> Vec<String> {
+    let mut maps: Vec<(String, &serde_json::Value)> = ROLE_KEYED_SECTIONS
+        .iter()
+        .filter_map(|section| json.get(section).map(|map| (section.to_string(), map)))
+        .collect();
+    if let Some(routes) = json
+        .pointer("/rate_limits/routes")
+        .and_then(|routes| routes.as_object())
+    {
+        maps.extend(routes.iter().filter_map(|(route, limits)| {
+            limits
+                .get("per_user")
+                .map(|map| (format!("rate_limits.routes.{route}.per_user"), map))
+        }));
+    }
+
+    let mut problems = Vec::new();
+    for (field, map) in maps {
+        for key in map.as_object().into_iter().flat_map(|map| map.keys()) {
+            if serde_json::from_value::<UserRole>(serde_json::Value::String(key.clone())).is_err() {
+                problems.push(format!(
+                    "{field}.{key}: unknown role; expected creator, member or subscriber"
+                ));
+            }
+        }
+    }
+    problems
+}
+
+/// A layer that contributed to the active configuration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConfigSource {
+    /// [`Config::default`], always the base layer.
+    Default,
+    /// The `config` key in the `STORAGE_CONFIG` KV namespace.
+    Kv,
+    /// The `CONFIG_OVERRIDES` Worker var.
+    Vars,
+    /// Worker secrets named in [`SECRET_FIELDS`].
+    Secrets,
+}
+
+/// The raw inputs layered into a configuration.
+#[derive(Clone, Debug, Default)]
+pub struct ConfigSources {
+    /// The KV `config` value, if the key exists.
+    pub kv: Option<String>,
+    /// The `CONFIG_OVERRIDES` Worker var, if set.
+    pub vars: Option<String>,
+    /// Secret values by field name, for the secrets that are set.
+    pub secrets: Vec<(&'static str, SecretString)>,
+}
+
+impl ConfigSources {
+    /// Reads the layers that come from the Worker environment rather than KV.
+    /// They only change on deploy.
+    pub fn overlays(env: &Env) -> Self {
+        // The var may be a JSON string or, when written as a table in
+        // `wrangler.toml`, an object.
+        let vars = env
+            .var(CONFIG_OVERRIDES_VAR_NAME)
+            .map(|var| var.to_string())
+            .or_else(|_| {
+                env.object_var::<serde_json::Value>(CONFIG_OVERRIDES_VAR_NAME)
+                    .map(|value| value.to_string())
+            })
+            .ok();
+        let secrets = SECRET_FIELDS
+            .iter()
+            .filter_map(|(field, secret)| {
+                env.secret(secret)
+                    .ok()
+                    .map(|value| (*field, SecretString::new(value.to_string())))
+            })
+            .collect();
+
+        Self {
+            kv: None,
+            vars,
+            secrets,
+        }
+    }
+}
+
+/// A validated configuration together with the fingerprint identifying it.
+#[derive(Clone, Debug)]
+pub struct LoadedConfig {
+    pub config: Arc<Config>,
+    /// First 16 hex digits of the SHA-256 of the KV JSON and vars, or
+    /// `"default"` when neither is set. Secrets are left out so the
+    /// fingerprint reveals nothing about them.
+    pub fingerprint: String,
+    pub sources: Vec<ConfigSource>,
+}
+
+impl LoadedConfig {
+    /// Layers `sources` over the defaults and validates the result. Returns
+    /// every problem found.
+    pub fn resolve(sources: &ConfigSources) -> std::result::Result<Self, Vec<String>> {
+        let mut layers = vec![ConfigSource::Default];
+        let mut value = serde_json::Value::Object(serde_json::Map::new());
+
+        if let Some(json) = &sources.kv {
+            value =
+                serde_json::from_str(json).map_err(|err| vec![format!("invalid JSON: {err}")])?;
+            layers.push(ConfigSource::Kv);
+        }
+        if let Some(json) = &sources.vars {
+            let patch: serde_json::Value = serde_json::from_str(json)
+                .ok()
+                .filter(serde_json::Value::is_object)
+                .ok_or_else(|| {
+                    vec![format!(
+                        "{CONFIG_OVERRIDES_VAR_NAME}: must be a JSON object"
+                    )]
+                })?;
+            merge_patch(&mut value, patch);
+            layers.push(ConfigSource::Vars);
+        }
+
+        let mut config = Config::from_json(value)?;
+        for (field, secret) in &sources.secrets {
+            config.set_secret(field, secret.clone());
+        }
+        config.validate_secrets()?;
+        if !sources.secrets.is_empty() {
+            layers.push(ConfigSource::Secrets);
+        }
+
+        let fingerprint = match (&sources.kv, &sources.vars) {
+            (None, None) => "default".to_string(),
+            (kv, vars) => {
+                let mut hasher = Sha256::new();
+                hasher.update(kv.as_deref().unwrap_or_default());
+                hasher.update([0]);
+                hasher.update(vars.as_deref().unwrap_or_default());
+                hasher
+                    .finalize()
+                    .iter()
+                    .take(8)
+                    .map(|byte| format!("{byte:02x}"))
+                    .collect()
+            }
+        };
+
+        Ok(Self {
+            config: Arc::new(config),
+            fingerprint,
+            sources: layers,
+        })
+    }
+}
+
+/// What `/health` reports about the active configuration.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ConfigStatus {
+    /// `config_version` of the active configuration.
+    pub version: u64,
+    /// Hash of the KV JSON and vars; changes on every edit, even without a version bump.
+    pub fingerprint: String,
+    /// Layers applied, lowest precedence first.
+    pub sources: Vec<ConfigSource>,
+    /// When this configuration was first loaded by the isolate.
+    pub loaded_at: DateTime<Utc>,
+    /// When KV was last read, successfully or not.
+    pub checked_at: DateTime<Utc>,
+    /// Why the last refresh failed, while the previous configuration is still serving.
+    pub last_error: Option<String>,
+}
+
+/// The configuration an isolate is serving and when it last checked KV.
+#[derive(Clone, Debug)]
+struct CachedConfig {
+    loaded: LoadedConfig,
+    loaded_at: DateTime<Utc>,
+    checked_at: DateTime<Utc>,
+    last_error: Option<String>,
+}
+
+impl CachedConfig {
+    fn is_fresh(&self, now: DateTime<Utc>) -> bool {
+        let ttl = i64::try_from(self.loaded.config.config_refresh_secs).unwrap_or(i64::MAX);
+        now.signed_duration_since(self.checked_at) < Duration::seconds(ttl)
+    }
+
+    fn status(&self) -> ConfigStatus {
+        ConfigStatus {
+            version: self.loaded.config.config_version,
+            fingerprint: self.loaded.fingerprint.clone(),
+            sources: self.loaded.sources.clone(),
+            loaded_at: self.loaded_at,
+            checked_at: self.checked_at,
+            last_error: self.last_error.clone(),
+        }
+    }
+}
+
+static CONFIG_CACHE: Mutex<Option<CachedConfig>> = Mutex::new(None);
+
+/// Returns the isolate's configuration, re-reading KV when the cached copy is
+/// older than its `config_refresh_secs`.
+///
+/// Fails only when no configuration has been loaded yet and KV cannot provide
+/// one; afterwards refresh failures keep the last good configuration.
+pub async fn current(env: &Env) -> Result<Arc<Config>> {
+    let cached = lock_cache().clone();
+    if let Some(cached) = cached.as_ref().filter(|cached| cached.is_fresh(Utc::now())) {
+        return Ok(cached.loaded.config.clone());
+    }
+
+    let fetched = Config::load(env).await.map_err(|err| err.to_string());
+    let refreshed = refresh(cached, fetched, Utc::now());
+    let config = refreshed
+        .as_ref()
+        .map(|cached| cached.loaded.config.clone())
+        .map_err(|err| worker::Error::RustError(err.clone()));
+    if let Ok(cached) = refreshed {
+        *lock_cache() = Some(cached);
+    }
+    config
+}
+
+/// Makes this isolate serve `sources` immediately, as after an admin update,
+/// instead of waiting for its next refresh from KV.
+pub fn install(sources: &ConfigSources) -> std::result::Result<(), Vec<String>> {
+    let loaded = LoadedConfig::resolve(sources)?;
+    let mut cache = lock_cache();
+    let cached = cache.take();
+    *cache = refresh(cached, Ok(loaded), Utc::now()).ok();
+    Ok(())
+}
+
+/// Status of the isolate's configuration, or `None` before the first load.
+pub fn status() -> Option<ConfigStatus> {
+    lock_cache().as_ref().map(CachedConfig::status)
+}
+
+fn lock_cache() -> std::sync::MutexGuard<'static, Option<CachedConfig>> {
+    CONFIG_CACHE.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Folds the result of a KV read into the cache.
+///
+/// An unchanged fingerprint keeps the cached `Arc<Config>`; a failed read keeps
+/// the previous configuration (when there is one) and records the error.
+fn refresh(
+    cached: Option<CachedConfig>,
+    fetched: std::result::Result<LoadedConfig, String>,
+    now: DateTime<Utc>,
+) -> std::result::Result<CachedConfig, String> {
+    match (cached, fetched) {
+        (Some(cached), Ok(loaded)) if cached.loaded.fingerprint == loaded.fingerprint => {
+            Ok(CachedConfig {
+                checked_at: now,
+                last_error: None,
+                ..cached
+            })
+        }
+        (_, Ok(loaded)) => {
+            log(
+                LogLevel::Info,
+                "configuration loaded",
+                serde_json::json!({
+                    "version": loaded.config.config_version,
+                    "fingerprint": loaded.fingerprint,
+                    "sources": loaded.sources,
+                }),
+            );
+            Ok(CachedConfig {
+                loaded,
+                loaded_at: now,
+                checked_at: now,
+                last_error: None,
+            })
+        }
+        (Some(cached), Err(error)) => {
+            log(
+                LogLevel::Error,
+                "configuration refresh failed, serving last good configuration",
+                serde_json::json!({
+                    "version": cached.loaded.config.config_version,
+                    "fingerprint": cached.loaded.fingerprint,
+                    "error": error,
+                }),
+            );
+            Ok(CachedConfig {
+                checked_at: now,
+                last_error: Some(error),
+                ..cached
+            })
+        }
+        (None, Err(error)) => Err(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(json: Option<&str>) -> std::result::Result<LoadedConfig, Vec<String>> {
+        LoadedConfig::resolve(&ConfigSources {
+            kv: json.map(str::to_string),
+            ..ConfigSources::default()
+        })
+    }
+
+    fn loaded(json: &str) -> LoadedConfig {
+        parse(Some(json)).unwrap()
+    }
+
+    fn cached(json: &str, at: DateTime<Utc>) -> CachedConfig {
+        refresh(None, Ok(loaded(json)), at).unwrap()
+    }
+
+    const KV_JSON: &str =
+        r#"{"config_version":2,"max_file_size":1000000000,"config_refresh_secs":30}"#;
+
+    #[test]
+    fn loaded_config_versions_by_content() {
+        let first = loaded(KV_JSON);
+        assert_eq!(first.sources, [ConfigSource::Default, ConfigSource::Kv]);
+        assert_eq!(first.fingerprint.len(), 16);
+        assert_eq!(first.fingerprint, loaded(KV_JSON).fingerprint);
+        assert_ne!(
+            first.fingerprint,
+            loaded(&KV_JSON.replace("1000000000", "2000000000")).fingerprint
+        );
+
+        let default = parse(None).unwrap();
+        assert_eq!(default.sources, [ConfigSource::Default]);
+        assert_eq!(default.fingerprint, "default");
+    }
+
+    #[test]
+    fn partial_config_keeps_defaults_for_missing_fields() {
+        let config =
+            loaded(r#"{"config_version":2,"cors":{"max_age":60},"rate_limits":{"enabled":false}}"#)
+                .config;
+        let defaults = Config::default();
+        assert_eq!(config.config_version, 2);
+        assert_eq!(config.chunk_size, defaults.chunk_size);
+        assert_eq!(config.cors.max_age, 60);
+        assert_eq!(config.cors.allowed_origins, defaults.cors.allowed_origins);
+        assert!(!config.rate_limits.enabled);
+        assert_eq!(config.rate_limits.routes, defaults.rate_limits.routes);
+        assert_eq!(config.max_active_uploads, defaults.max_active_uploads);
+    }
+
+    #[test]
+    fn merge_patch_merges_objects_and_removes_nulls() {
+        let mut stored = serde_json::json!({
+            "config_version": 4,
+            "max_file_size": 1000,
+            "cors": { "max_age": 60, "allow_credentials": true },
+        });
+        merge_patch(
+            &mut stored,
+            serde_json::json!({ "max_file_size": null, "cors": { "max_age": 120 }, "auto_migrate": false }),
+        );
+        assert_eq!(
+            stored,
+            serde_json::json!({
+                "config_version": 4,
+                "cors": { "max_age": 120, "allow_credentials": true },
+                "auto_migrate": false,
+            })
+        );
+    }
+
+    #[test]
+    fn vars_override_kv_and_secrets_fill_secret_fields() {
+        let layered = LoadedConfig::resolve(&ConfigSources {
+            kv: Some(KV_JSON.to_string()),
+            vars: Some(r#"{"max_file_size":2000000000,"cors":{"max_age":5}}"#.to_string()),
+            secrets: vec![("admin_token", SecretString::new("hunter2"))],
+        })
+        .unwrap();
+        assert_eq!(layered.config.config_version, 2);
+        assert_eq!(layered.config.max_file_size, 2_000_000_000);
+        assert_eq!(layered.config.cors.max_age, 5);
+        assert_eq!(
+            layered
+                .config
+                .admin_token
+                .as_ref()
+                .map(SecretString::expose),
+            Some("hunter2")
+        );
+        assert_eq!(
+            layered.sources,
+            [
+                ConfigSource::Default,
+                ConfigSource::Kv,
+                ConfigSource::Vars,
+                ConfigSource::Secrets
+            ]
+        );
+        assert_ne!(
+            layered.fingerprint,
+            super::tests::loaded(KV_JSON).fingerprint
+        );
+    }
+
+    #[test]
+    fn secrets_are_redacted_and_rejected_outside_secret_store() {
+        let config = Config {
+            admin_token: Some(SecretString::new("hunter2")),
+            ..Config::default()
+        };
+        assert!(!format!("{config:?}").contains("hunter2"));
+        let json = serde_json::to_string(&config).unwrap();
+        assert!(!json.contains("hunter2"));
+        assert!(json.contains(r#""admin_token":"[redacted]""#));
+
+        // A serialized configuration can be written back unchanged.
+        assert_eq!(
+            Config::from_json(serde_json::from_str(&json).unwrap())
+                .unwrap()
+                .admin_token,
+            None
+        );
+        let problems = parse(Some(r#"{"admin_token":"hunter2"}"#)).unwrap_err();
+        assert!(problems[0].starts_with("admin_token: "), "{problems:?}");
+        assert!(!problems[0].contains("hunter2"));
+    }
+
+    #[test]
+    fn managed_encryption_requires_a_valid_master_key() {
+        let resolve = |secret: Option<&str>| {
+            LoadedConfig::resolve(&ConfigSources {
+                kv: Some(r#"{"encryption_policy":{"member":"managed"}}"#.to_string()),
+                vars: None,
+                secrets: secret
+                    .map(|value| ("encryption_master_key", SecretString::new(value)))
+                    .into_iter()
+                    .collect(),
+            })
+        };
+
+        let problems = resolve(None).unwrap_err();
+        assert!(
+            problems[0].starts_with("encryption_policy: "),
+            "{problems:?}"
+        );
+        let problems = resolve(Some("too-short")).unwrap_err();
+        assert!(
+            problems[0].starts_with("encryption_master_key: "),
+            "{problems:?}"
+        );
+        assert!(!problems[0].contains("too-short"));
+
+        let config = resolve(Some(&format!("{}=", "A".repeat(43))))
+            .unwrap()
+            .config;
+        assert_eq!(
+            config.encryption_policy_for(&UserRole::Member),
+            EncryptionPolicy::Managed
+        );
+        assert_eq!(
+            config.encryption_policy_for(&UserRole::Creator),
+            EncryptionPolicy::None
+        );
+        assert!(config.master_key().is_some());
+    }
+
+    #[test]
+    fn bucket_routing_uses_first_matching_rule() {
+        let routing: BucketRouting = serde_json::from_value(serde_json::json!({
+            "default_bucket": "PUBLIC_BUCKET",
+            "rules": [
+                { "category": "video", "bucket": "VIDEO_BUCKET" },
+                { "role": "member", "bucket": "MEMBER_BUCKET" },
+            ],
+        }))
+        .unwrap();
+        assert_eq!(
+            routing.bucket_for(&UserRole::Member, "video/mp4"),
+            "VIDEO_BUCKET"
+        );
+        assert_eq!(
+            routing.bucket_for(&UserRole::Member, "image/png"),
+            "MEMBER_BUCKET"
+        );
+        assert_eq!(
+            routing.bucket_for(&UserRole::Creator, "image/png"),
+            "PUBLIC_BUCKET"
+        );
+    }
+
+    #[test]
+    fn validate_rejects_bad_bucket_rules() {
+        let mut config = Config::default();
+        config.bucket_routing.rules.push(BucketRule {
+            role: None,
+            category: Some("movies".to_string()),
+            bucket: "not a binding".to_string(),
+        });
+        let problems = config.validate().unwrap_err();
+        assert_eq!(problems.len(), 2, "{problems:?}");
+        assert!(problems[0].starts_with("bucket_routing.rules[0].bucket: "));
+        assert!(problems[1].starts_with("bucket_routing.rules[0].category: "));
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        assert_eq!(Config::default().validate(), Ok(()));
+    }
+
+    #[test]
+    fn validate_rejects_bad_key_template() {
+        let mut config = Config::default();
+        assert!(config.validate().is_ok());
+        config.r2_key_template = "{role}/{yyyy}".to_string();
+        let problems = config.validate().unwrap_err();
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with("r2_key_template: must include"));
+    }
+
+    #[test]
+    fn validate_reports_every_problem() {
+        let mut config = Config {
+            chunk_size: 1,
+            max_file_size: 0,
+            ..Config::default()
+        };
+        config.cors.allow_credentials = true;
+        config.rate_limits.routes.insert(
+            "GET /nowhere".to_string(),
+            RouteRateLimit {
+                per_ip: Some(TokenBucketSpec {
+                    capacity: 0,
+                    refill_per_second: 1.0,
+                }),
+                per_user: HashMap::new(),
+            },
+        );
+
+        let problems = config.validate().unwrap_err();
+        let fields: Vec<&str> = problems
+            .iter()
+            .map(|problem| problem.split(": ").next().unwrap())
+            .collect();
+        for field in [
+            "chunk_size",
+            "max_file_size",
+            "cors.allowed_origins[0].origin",
+            "rate_limits.routes.GET /nowhere",
+            "rate_limits.routes.GET /nowhere.per_ip",
+        ] {
+            assert!(fields.contains(&field), "{field} missing from {problems:?}");
+        }
+    }
+
+    #[test]
+    fn validate_caps_max_file_size_by_part_count() {
+        let config = Config {
+            chunk_size: MIN_CHUNK_SIZE as usize,
+            max_file_size: MIN_CHUNK_SIZE * u64::from(MAX_PART_NUMBER) + 1,
+            ..Config::default()
+        };
+        assert!(config.validate().unwrap_err()[0].starts_with("max_file_size"));
+    }
+
+    #[test]
+    fn parse_names_unknown_role_keys() {
+        let problems = parse(Some(
+            r#"{"max_active_uploads":{"admin":5},"rate_limits":{"routes":{"PUT /api/upload/chunk":{"per_user":{"guest":{"capacity":1,"refill_per_second":1}}}}}}"#,
+        ))
+        .unwrap_err();
+        assert_eq!(problems.len(), 2, "{problems:?}");
+        assert!(problems[0].starts_with("max_active_uploads.admin: "));
+        assert!(
+            problems[1].starts_with("rate_limits.routes.PUT /api/upload/chunk.per_user.guest: ")
+        );
+    }
+
+    #[test]
+    fn parse_rejects_invalid_json_and_invalid_values() {
+        assert!(parse(Some("{not json")).unwrap_err()[0].starts_with("invalid JSON"));
+        assert!(parse(Some(r#"{"chunk_size":1}"#)).unwrap_err()[0].starts_with("chunk_size"));
+    }
+
+    #[test]
+    fn cached_config_expires_after_refresh_secs() {
+        let now = Utc::now();
+        let cached = cached(KV_JSON, now);
+        assert!(cached.is_fresh(now + Duration::seconds(29)));
+        assert!(!cached.is_fresh(now + Duration::seconds(30)));
+    }
+
+    #[test]
+    fn refresh_keeps_the_same_arc_when_fingerprint_is_unchanged() {
+        let start = Utc::now();
+        let previous = cached(KV_JSON, start);
+        let later = start + Duration::seconds(60);
+
+        let refreshed = refresh(Some(previous.clone()), Ok(loaded(KV_JSON)), later).unwrap();
+        assert!(Arc::ptr_eq(
+            &refreshed.loaded.config,
+            &previous.loaded.config
+        ));
+        assert_eq!(refreshed.loaded_at, start);
+        assert_eq!(refreshed.checked_at, later);
+
+        let changed = KV_JSON.replace("1000000000", "2000000000");
+        let refreshed = refresh(Some(previous), Ok(loaded(&changed)), later).unwrap();
+        assert_eq!(refreshed.loaded.config.max_file_size, 2_000_000_000);
+        assert_eq!(refreshed.loaded_at, later);
+    }
+
+    #[test]
+    fn refresh_failure_serves_last_good_config() {
+        let start = Utc::now();
+        let previous = cached(KV_JSON, start);
+        let later = start + Duration::seconds(60);
+
+        let refreshed = refresh(Some(previous.clone()), Err("KV down".to_string()), later).unwrap();
+        assert!(Arc::ptr_eq(
+            &refreshed.loaded.config,
+            &previous.loaded.config
+        ));
+        assert_eq!(refreshed.status().last_error.as_deref(), Some("KV down"));
+        assert!(!refreshed.is_fresh(later + Duration::seconds(30)));
+
+        let recovered = refresh(Some(refreshed), Ok(loaded(KV_JSON)), later).unwrap();
+        assert_eq!(recovered.last_error, None);
+
+        assert!(refresh(None, Err("KV down".to_string()), later).is_err());
+    }
+
+    fn rule(origin: &str) -> CorsOriginRule {
+        CorsOriginRule {
+            origin: origin.to_string(),
+            allowed_methods: default_cors_allowed_methods(),
+            allowed_headers: default_cors_allowed_headers(),
+        }
+    }
+
+    #[test]
+    fn exact_origin_matches_case_insensitively() {
+        let rule = rule("https://app.example.com");
+        assert!(rule.matches("https://APP.example.com"));
+        assert!(!rule.matches("https://app.example.com:8443"));
+        assert!(!rule.matches("http://app.example.com"));
+    }
+
+    #[test]
+    fn wildcard_origin_matches_subdomains_only() {
+        let rule = rule("https://*.example.com");
+        assert!(rule.matches("https://app.example.com"));
+        assert!(rule.matches("https://a.b.example.com"));
+        assert!(!rule.matches("https://example.com"));
+        assert!(!rule.matches("https://evilexample.com"));
+        assert!(!rule.matches("https://app.example.com.evil.io"));
+        assert!(!rule.matches("http://app.example.com"));
+    }
+
+    #[test]
+    fn cors_config_omitted_fields_use_defaults() {
+        let cors: CorsConfig = serde_json::from_value(serde_json::json!({
+            "allowed_origins": [{ "origin": "https://app.example.com" }]
+        }))
+        .unwrap();
+
+        assert!(!cors.allow_credentials);
+        assert_eq!(cors.max_age, DEFAULT_CORS_MAX_AGE);
+        assert_eq!(cors.expose_headers, default_cors_expose_headers());
+        assert!(cors.allowed_origins[0].allows_method("put"));
+    }
+
+    #[test]
+    fn rate_limits_resolve_per_route_and_role() {
+        let limits = RateLimitConfig::default();
+        let route = "POST /api/upload/init";
+
+        assert!(limits.ip_limit(route).is_some());
+        assert_eq!(
+            limits
+                .user_limit(route, &UserRole::Subscriber)
+                .map(|spec| spec.capacity),
+            Some(10)
+        );
+        assert!(limits.ip_limit("GET /health").is_none());
+
+        let disabled = RateLimitConfig {
+            enabled: false,
+            ..limits
+        };
+        assert!(disabled.ip_limit(route).is_none());
+    }
+}
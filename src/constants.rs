@@ -22,9 +22,47 @@ pub const STORAGE_CONFIG_KV_NAME: &str = "STORAGE_CONFIG";
 /// Standard R2 bucket binding name
 pub const STORAGE_BUCKET_NAME: &str = "STORAGE_BUCKET";
 
+/// Default R2 key template: `{role}/{user_id}/{YYYYMMDD}/{category}/{filename}`
+pub const DEFAULT_R2_KEY_TEMPLATE: &str = "{role}/{user_id}/{yyyy}{mm}{dd}/{category}/{filename}";
+
+/// Longest object key R2 accepts, in bytes
+pub const MAX_R2_KEY_LENGTH: usize = 1024;
+
+/// Numeric suffixes tried for a colliding key before falling back to the upload ID
+pub const MAX_KEY_SUFFIX_ATTEMPTS: u32 = 20;
+
 /// Standard D1 database binding name for upload tracking
 pub const UPLOAD_DB_NAME: &str = "UPLOAD_DB";
 
+/// Workers Analytics Engine dataset binding name for metrics (optional)
+pub const METRICS_DATASET_NAME: &str = "UPLOAD_METRICS";
+
+/// Durable Object binding name for rate limit token buckets (optional, preferred over KV)
+pub const RATE_LIMIT_DO_NAME: &str = "RATE_LIMITER";
+
+/// KV binding name holding rate limit token buckets (optional)
+pub const RATE_LIMIT_KV_NAME: &str = "RATE_LIMIT";
+
+/// Prefix for rate limit bucket keys in KV
+pub const RATE_LIMIT_KEY_PREFIX: &str = "ratelimit:";
+
+/// HTTP header carrying the client IP, set by Cloudflare
+pub const HEADER_CONNECTING_IP: &str = "CF-Connecting-IP";
+
+/// Secret holding the bearer token for `/api/admin` endpoints (optional; admin API is
+/// disabled without it)
+pub const ADMIN_TOKEN_SECRET_NAME: &str = "ADMIN_TOKEN";
+
+/// Secret holding the base64 256-bit master key for managed encryption (optional;
+/// required when any role's `encryption_policy` is `managed`)
+pub const ENCRYPTION_MASTER_KEY_SECRET_NAME: &str = "ENCRYPTION_MASTER_KEY";
+
+/// Plaintext bytes per AES-GCM segment of an encrypted chunk (64 KiB)
+///
+/// Each segment carries its own 16-byte tag, so a byte range is decrypted by
+/// reading only the segments that cover it.
+pub const ENCRYPTION_SEGMENT_SIZE: usize = 64 * 1024;
+
 /// Default maximum file size (10GB)
 pub const DEFAULT_MAX_FILE_SIZE: u64 = 10_737_418_240;
 
@@ -34,26 +72,121 @@ pub const DEFAULT_MAX_FILE_SIZE: u64 = 10_737_418_240;
 /// default leaves ample headroom while staying within Workers' Free/Paid plan limits.
 pub const DEFAULT_CHUNK_SIZE: u64 = 95 * 1024 * 1024;
 
+/// Default ceiling on source image size for variant generation (50 MiB).
+///
+/// Variants are produced by decoding the whole object in the worker, which must fit
+/// comfortably inside the 128 MB isolate memory limit alongside the decoded bitmap.
+pub const DEFAULT_MAX_VARIANT_SOURCE_SIZE: u64 = 50 * 1024 * 1024;
+
 /// Maximum R2/S3 multipart part number per upload (1-based).
 ///
 /// Chunk indices are 0-based and map to part numbers via `part_number = chunk_index + 1`,
 /// so the highest accepted chunk index is `MAX_PART_NUMBER - 1`.
 pub const MAX_PART_NUMBER: u16 = 10_000;
 
+/// Smallest accepted `chunk_size`: R2's minimum size for every part except the last (5 MiB)
+pub const MIN_CHUNK_SIZE: u64 = 5 * 1024 * 1024;
+
+/// Largest accepted `chunk_size`: the Workers request body cap (100 MB)
+pub const MAX_CHUNK_SIZE: u64 = 100_000_000;
+
+/// Longest accepted `max_dimension` for an image variant, in pixels
+pub const MAX_VARIANT_DIMENSION: u32 = 8_192;
+
+/// Longest accepted `config_refresh_secs` (one day)
+pub const MAX_CONFIG_REFRESH_SECS: u64 = 86_400;
+
+/// Number of oldest active upload IDs listed when the active upload limit is hit
+pub const MAX_LISTED_ACTIVE_UPLOADS: u32 = 5;
+
 /// HTTP header for upload session ID
 pub const HEADER_UPLOAD_ID: &str = "X-Upload-Id";
 
 /// HTTP header for chunk index
 pub const HEADER_CHUNK_INDEX: &str = "X-Chunk-Index";
 
-/// CORS header for allowed origins
-pub const CORS_ALLOW_ORIGIN: &str = "*";
+/// HTTP header carrying the per-request correlation ID (propagated or generated)
+pub const HEADER_REQUEST_ID: &str = "X-Request-Id";
+
+/// HTTP header carrying a client-chosen key that makes a request safe to retry
+pub const HEADER_IDEMPOTENCY_KEY: &str = "Idempotency-Key";
+
+/// HTTP response header set when a stored idempotent response is replayed
+pub const HEADER_IDEMPOTENT_REPLAYED: &str = "Idempotent-Replayed";
+
+/// HTTP header carrying a client-provided base64 AES-256 key (SSE-C style)
+pub const HEADER_ENCRYPTION_KEY: &str = "X-Encryption-Key";
+
+/// HTTP header carrying the base64 MD5 digest of `X-Encryption-Key`
+pub const HEADER_ENCRYPTION_KEY_MD5: &str = "X-Encryption-Key-MD5";
+
+/// Longest accepted `Idempotency-Key` value
+pub const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 255;
+
+/// KV key in `STORAGE_CONFIG` holding the configuration JSON
+pub const CONFIG_KV_KEY: &str = "config";
+
+/// Worker var holding a JSON Merge Patch layered over the KV configuration,
+/// set per environment in `wrangler.toml`
+pub const CONFIG_OVERRIDES_VAR_NAME: &str = "CONFIG_OVERRIDES";
+
+/// Prefix of the KV keys in `STORAGE_CONFIG` holding replaced configurations,
+/// followed by the `config_version` they had (e.g. `config:v3`)
+pub const CONFIG_BACKUP_KV_PREFIX: &str = "config:v";
+
+/// Default seconds a worker isolate serves its cached configuration before re-reading KV
+pub const DEFAULT_CONFIG_REFRESH_SECS: u64 = 60;
+
+/// Default lifetime of stored idempotent responses in seconds (24 hours)
+pub const DEFAULT_IDEMPOTENCY_TTL_SECS: u64 = 86_400;
+
+/// Seconds after which an idempotency key whose request never finished may be reclaimed
+pub const IDEMPOTENCY_PENDING_LEASE_SECS: i64 = 300;
+
+/// Seconds after which a `completing` upload's claim may be taken over by a retried completion
+pub const COMPLETION_CLAIM_LEASE_SECS: i64 = 300;
+
+/// Seconds without activity after which an open upload is expired (7 days, R2's default
+/// lifetime for unfinished multipart uploads)
+pub const UPLOAD_EXPIRY_SECS: i64 = 7 * 86_400;
+
+/// Most uploads one expiry sweep expires
+pub const EXPIRY_BATCH_SIZE: u32 = 100;
+
+/// Default page size for the audit log endpoint
+pub const DEFAULT_AUDIT_PAGE_SIZE: u32 = 100;
+
+/// Largest page size accepted by the audit log endpoint
+pub const MAX_AUDIT_PAGE_SIZE: u32 = 1_000;
+
+/// Default CORS origin pattern (any origin, without credentials)
+pub const DEFAULT_CORS_ALLOWED_ORIGIN: &str = "*";
+
+/// Default CORS allowed methods
+pub const DEFAULT_CORS_ALLOWED_METHODS: &[&str] = &["GET", "POST", "PUT", "DELETE", "OPTIONS"];
 
-/// CORS header for allowed methods
-pub const CORS_ALLOW_METHODS: &str = "GET, POST, PUT, DELETE, OPTIONS";
+/// Default CORS allowed request headers
+pub const DEFAULT_CORS_ALLOWED_HEADERS: &[&str] = &[
+    "Content-Type",
+    "X-Upload-Id",
+    "X-Chunk-Index",
+    "X-Request-Id",
+    "Idempotency-Key",
+    "X-Encryption-Key",
+    "X-Encryption-Key-MD5",
+    "Range",
+];
 
-/// CORS header for allowed headers
-pub const CORS_ALLOW_HEADERS: &str = "Content-Type, X-Upload-Id, X-Chunk-Index";
+/// Default response headers readable by browser scripts
+pub const DEFAULT_CORS_EXPOSE_HEADERS: &[&str] = &[
+    "ETag",
+    "X-Upload-Id",
+    "X-Request-Id",
+    "Retry-After",
+    "Idempotent-Replayed",
+    "Content-Range",
+    "X-Version",
+];
 
-/// CORS preflight cache lifetime in seconds (24 hours).
-pub const CORS_MAX_AGE: &str = "86400";
+/// Default CORS preflight cache lifetime in seconds (24 hours).
+pub const DEFAULT_CORS_MAX_AGE: u32 = 86_400;
@@ -0,0 +1,613 @@
+//! # Encryption at Rest
+//!
+//! AES-256-GCM encryption of upload chunks before they reach R2, and
+//! decryption of byte ranges on download.
+//!
+//! ## Keys
+//!
+//! Every encrypted upload gets its own random data key. The data key is
+//! wrapped (encrypted) under a key-encryption key and stored in D1:
+//!
+//! - **customer**: the client's `X-Encryption-Key`, which is never stored;
+//!   every chunk and download request must send it again.
+//! - **managed**: a per-user key derived with HKDF-SHA256 from the
+//!   `ENCRYPTION_MASTER_KEY` secret.
+//!
+//! ## Chunk Format
+//!
+//! A chunk is sealed in segments of [`ENCRYPTION_SEGMENT_SIZE`] plaintext
+//! bytes, each stored as its ciphertext followed by its 16-byte tag. The nonce
+//! is the chunk's random 8-byte prefix followed by the segment number, and the
+//! associated data binds the upload ID, the chunk index and whether the
+//! segment ends the chunk, so segments cannot be reordered, moved between
+//! chunks or uploads, or dropped from the end of a chunk unnoticed.
+//!
+//! A plaintext byte range maps onto the whole segments covering it; those are
+//! read with one R2 range request and decrypted as they stream
+//! ([`plan_range`]).
+
+use std::fmt;
+
+use aes_gcm::aead::{Aead, AeadInPlace, KeyInit, Nonce, Payload};
+use aes_gcm::{Aes256Gcm, Tag};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hkdf::Hkdf;
+use md5::{Digest, Md5};
+use sha2::Sha256;
+
+use crate::constants::{ENCRYPTION_SEGMENT_SIZE, HEADER_ENCRYPTION_KEY, HEADER_ENCRYPTION_KEY_MD5};
+use crate::errors::{AppError, AppResult};
+
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+const NONCE_PREFIX_LEN: usize = 8;
+const TAG_LEN: usize = 16;
+
+/// Plaintext and stored bytes per full segment.
+const SEGMENT: u64 = ENCRYPTION_SEGMENT_SIZE as u64;
+const STORED_SEGMENT: u64 = SEGMENT + TAG_LEN as u64;
+
+/// HKDF `info` prefix for per-user managed keys; the user ID is appended.
+const USER_KEY_INFO: &str = "memenow-storage/user-key/v1/";
+
+/// A 256-bit AES key. `Debug` never prints the key material.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Key([u8; KEY_LEN]);
+
+impl fmt::Debug for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Key([redacted])")
+    }
+}
+
+impl Key {
+    /// A fresh random data key.
+    pub fn generate() -> AppResult<Self> {
+        let mut key = [0; KEY_LEN];
+        fill_random(&mut key)?;
+        Ok(Self(key))
+    }
+
+    /// Decodes a base64 256-bit key; `field` names its source in errors.
+    pub fn from_base64(field: &str, value: &str) -> AppResult<Self> {
+        STANDARD
+            .decode(value.trim())
+            .ok()
+            .and_then(|bytes| <[u8; KEY_LEN]>::try_from(bytes).ok())
+            .map(Self)
+            .ok_or_else(|| AppError::InvalidField {
+                field: field.to_string(),
+                reason: "Must be a base64-encoded 256-bit key".to_string(),
+            })
+    }
+
+    /// Reads a client-provided key, checking it against the base64 MD5 digest
+    /// the client sent alongside it to catch keys damaged in transit.
+    pub fn from_customer_headers(key: &str, key_md5: Option<&str>) -> AppResult<Self> {
+        let parsed = Self::from_base64(HEADER_ENCRYPTION_KEY, key)?;
+        let Some(key_md5) = key_md5 else {
+            return Err(AppError::MissingField {
+                field: format!("{HEADER_ENCRYPTION_KEY_MD5} header"),
+            });
+        };
+        if STANDARD.encode(Md5::digest(parsed.0)) != key_md5.trim() {
+            return Err(AppError::InvalidField {
+                field: HEADER_ENCRYPTION_KEY_MD5.to_string(),
+                reason: format!("Does not match the MD5 digest of {HEADER_ENCRYPTION_KEY}"),
+            });
+        }
+        Ok(parsed)
+    }
+
+    /// Derives the managed key-encryption key of `user_id` from this master key.
+    pub fn derive_user_key(&self, user_id: &str) -> Self {
+        let mut key = [0; KEY_LEN];
+        Hkdf::<Sha256>::new(None, &self.0)
+            .expand(format!("{USER_KEY_INFO}{user_id}").as_bytes(), &mut key)
+            .expect("32 bytes is a valid HKDF-SHA256 output length");
+        Self(key)
+    }
+
+    fn cipher(&self) -> Aes256Gcm {
+        Aes256Gcm::new(&self.0.into())
+    }
+}
+
+fn fill_random(buffer: &mut [u8]) -> AppResult<()> {
+    getrandom::fill(buffer).map_err(|err| AppError::InternalError {
+        message: format!("Failed to generate random bytes: {err}"),
+    })
+}
+
+/// Encrypts `data_key` under `kek` for storage, bound to `upload_id`.
+///
+/// The result is base64 of the 12-byte nonce followed by the sealed key.
+pub fn wrap_key(kek: &Key, data_key: &Key, upload_id: &str) -> AppResult<String> {
+    let mut nonce = [0; NONCE_LEN];
+    fill_random(&mut nonce)?;
+    let sealed = kek
+        .cipher()
+        .encrypt(
+            Nonce::<Aes256Gcm>::from_slice(&nonce),
+            Payload {
+                msg: &data_key.0,
+                aad: upload_id.as_bytes(),
+            },
+        )
+        .map_err(|_| AppError::InternalError {
+            message: "Failed to wrap data key".to_string(),
+        })?;
+    let mut wrapped = nonce.to_vec();
+    wrapped.extend(sealed);
+    Ok(STANDARD.encode(wrapped))
+}
+
+/// Recovers the data key [`wrap_key`] stored for `upload_id`.
+///
+/// Fails with [`AppError::EncryptionKeyMismatch`] when `kek` is not the key
+/// the data key was wrapped under.
+pub fn unwrap_key(kek: &Key, wrapped: &str, upload_id: &str) -> AppResult<Key> {
+    let bytes = STANDARD
+        .decode(wrapped)
+        .ok()
+        .filter(|bytes| bytes.len() == NONCE_LEN + KEY_LEN + TAG_LEN)
+        .ok_or_else(|| AppError::DatabaseError {
+            message: format!("Invalid wrapped key stored for upload {upload_id}"),
+        })?;
+    let (nonce, sealed) = bytes.split_at(NONCE_LEN);
+    kek.cipher()
+        .decrypt(
+            Nonce::<Aes256Gcm>::from_slice(nonce),
+            Payload {
+                msg: sealed,
+                aad: upload_id.as_bytes(),
+            },
+        )
+        .ok()
+        .and_then(|key| <[u8; KEY_LEN]>::try_from(key).ok())
+        .map(Key)
+        .ok_or_else(|| AppError::EncryptionKeyMismatch {
+            upload_id: upload_id.to_string(),
+        })
+}
+
+/// A chunk sealed by [`encrypt_chunk`].
+#[derive(Debug)]
+pub struct EncryptedChunk {
+    /// Bytes to store in R2.
+    pub bytes: Vec<u8>,
+    /// Hex nonce prefix to record with the chunk.
+    pub nonce: String,
+}
+
+/// Encrypts one chunk of `upload_id` in place.
+///
+/// The buffer only grows by the segment tags, so a chunk never needs a
+/// second full-size copy in memory.
+pub fn encrypt_chunk(
+    key: &Key,
+    upload_id: &str,
+    chunk_index: u16,
+    mut data: Vec<u8>,
+) -> AppResult<EncryptedChunk> {
+    let mut prefix = [0; NONCE_PREFIX_LEN];
+    fill_random(&mut prefix)?;
+
+    let plain_len = data.len() as u64;
+    let segments = segment_count(plain_len);
+    let stored_len = usize::try_from(encrypted_len(plain_len)).expect("chunk fits in memory");
+    data.reserve_exact(stored_len - data.len());
+    data.resize(stored_len, 0);
+
+    // Each segment moves forward by the tags before it, so sealing from the
+    // last segment back never overwrites plaintext that is still needed.
+    let cipher = key.cipher();
+    for segment in (0..segments).rev() {
+        let plain_start = (segment * SEGMENT) as usize;
+        let plain_end = plain_len.min((segment + 1) * SEGMENT) as usize;
+        let stored_start = (segment * STORED_SEGMENT) as usize;
+        let stored_end = stored_start + (plain_end - plain_start);
+        data.copy_within(plain_start..plain_end, stored_start);
+
+        let tag = cipher
+            .encrypt_in_place_detached(
+                &segment_nonce(&prefix, segment),
+                &segment_aad(upload_id, chunk_index, segment + 1 == segments),
+                &mut data[stored_start..stored_end],
+            )
+            .map_err(|_| AppError::InternalError {
+                message: format!("Failed to encrypt chunk {chunk_index}"),
+            })?;
+        data[stored_end..stored_end + TAG_LEN].copy_from_slice(&tag);
+    }
+
+    Ok(EncryptedChunk {
+        bytes: data,
+        nonce: prefix.iter().map(|byte| format!("{byte:02x}")).collect(),
+    })
+}
+
+/// Bytes stored for a chunk of `plain_len` plaintext bytes.
+pub fn encrypted_len(plain_len: u64) -> u64 {
+    plain_len + segment_count(plain_len) * TAG_LEN as u64
+}
+
+/// Plaintext bytes in a chunk stored as `stored_len` bytes, or `None` when no
+/// chunk encrypts to that length.
+pub fn plaintext_len(stored_len: u64) -> Option<u64> {
+    let plain_len = stored_len.checked_sub(stored_len.div_ceil(STORED_SEGMENT) * TAG_LEN as u64)?;
+    (encrypted_len(plain_len) == stored_len).then_some(plain_len)
+}
+
+/// Segments in a chunk; an empty chunk still carries one tag.
+fn segment_count(plain_len: u64) -> u64 {
+    plain_len.div_ceil(SEGMENT).max(1)
+}
+
+fn segment_nonce(prefix: &[u8; NONCE_PREFIX_LEN], segment: u64) -> Nonce<Aes256Gcm> {
+    let mut nonce = [0; NONCE_LEN];
+    nonce[..NONCE_PREFIX_LEN].copy_from_slice(prefix);
+    nonce[NONCE_PREFIX_LEN..].copy_from_slice(&(segment as u32).to_be_bytes());
+    nonce.into()
+}
+
+fn segment_aad(upload_id: &str, chunk_index: u16, is_last: bool) -> Vec<u8> {
+    let mut aad = upload_id.as_bytes().to_vec();
+    aad.extend(chunk_index.to_be_bytes());
+    aad.push(u8::from(is_last));
+    aad
+}
+
+/// An encrypted chunk as recorded in D1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredChunk {
+    pub chunk_index: u16,
+    /// Bytes the chunk occupies in R2.
+    pub stored_size: u64,
+    /// Plaintext bytes the chunk decrypts to.
+    pub plain_size: u64,
+    nonce_prefix: [u8; NONCE_PREFIX_LEN],
+}
+
+impl StoredChunk {
+    /// Checks a chunk row of an encrypted upload.
+    pub fn new(chunk_index: u16, stored_size: u64, nonce: &str) -> AppResult<Self> {
+        let invalid = || AppError::DatabaseError {
+            message: format!("Invalid encryption metadata for chunk {chunk_index}"),
+        };
+        let nonce_prefix = decode_hex(nonce).ok_or_else(invalid)?;
+        let plain_size = plaintext_len(stored_size).ok_or_else(invalid)?;
+        Ok(Self {
+            chunk_index,
+            stored_size,
+            plain_size,
+            nonce_prefix,
+        })
+    }
+
+    fn segments(&self) -> u64 {
+        segment_count(self.plain_size)
+    }
+
+    /// Stored bytes of `segment`, tag included.
+    fn stored_segment_len(&self, segment: u64) -> usize {
+        (self.stored_size - segment * STORED_SEGMENT).min(STORED_SEGMENT) as usize
+    }
+}
+
+fn decode_hex(hex: &str) -> Option<[u8; NONCE_PREFIX_LEN]> {
+    if hex.len() != NONCE_PREFIX_LEN * 2 {
+        return None;
+    }
+    let mut bytes = [0; NONCE_PREFIX_LEN];
+    for (index, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(index * 2..index * 2 + 2)?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+/// The stored bytes covering a plaintext range, and the decryptor for them.
+#[derive(Debug)]
+pub struct RangePlan {
+    /// Offset of the first stored byte to read.
+    pub stored_offset: u64,
+    /// Number of stored bytes to read.
+    pub stored_length: u64,
+    pub decryptor: RangeDecryptor,
+}
+
+/// Maps the plaintext bytes `start..=end` of an object stored as `chunks`
+/// (ordered by index) onto the segments that hold them.
+///
+/// The range must lie within the plaintext size, the sum of `plain_size`.
+pub fn plan_range(
+    data_key: Key,
+    upload_id: &str,
+    chunks: Vec<StoredChunk>,
+    start: u64,
+    end: u64,
+) -> RangePlan {
+    let mut plain_offset = 0;
+    let mut stored_offset = 0;
+    let mut first = None;
+    let mut stored_end = 0;
+    for (position, chunk) in chunks.iter().enumerate() {
+        let plain_end = plain_offset + chunk.plain_size;
+        if first.is_none() && start < plain_end {
+            let segment = (start - plain_offset) / SEGMENT;
+            first = Some((
+                position,
+                segment,
+                stored_offset + segment * STORED_SEGMENT,
+                start - plain_offset - segment * SEGMENT,
+            ));
+        }
+        if end < plain_end {
+            let segment = (end - plain_offset) / SEGMENT;
+            stored_end = stored_offset + chunk.stored_size.min((segment + 1) * STORED_SEGMENT);
+            break;
+        }
+        plain_offset = plain_end;
+        stored_offset += chunk.stored_size;
+    }
+
+    let (position, segment, range_offset, skip) = first.unwrap_or((chunks.len(), 0, 0, 0));
+    RangePlan {
+        stored_offset: range_offset,
+        stored_length: stored_end.saturating_sub(range_offset),
+        decryptor: RangeDecryptor {
+            cipher: data_key.cipher(),
+            upload_id: upload_id.to_string(),
+            chunks,
+            position,
+            segment,
+            skip,
+            remaining: (end + 1).saturating_sub(start),
+            buffer: Vec::new(),
+        },
+    }
+}
+
+/// Decrypts the stored bytes of a [`RangePlan`] as they arrive.
+pub struct RangeDecryptor {
+    cipher: Aes256Gcm,
+    upload_id: String,
+    chunks: Vec<StoredChunk>,
+    /// Chunk and segment the buffer starts at.
+    position: usize,
+    segment: u64,
+    /// Plaintext bytes to drop from the next segment.
+    skip: u64,
+    /// Plaintext bytes still to emit.
+    remaining: u64,
+    buffer: Vec<u8>,
+}
+
+impl fmt::Debug for RangeDecryptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RangeDecryptor")
+            .field("upload_id", &self.upload_id)
+            .field("position", &self.position)
+            .field("segment", &self.segment)
+            .field("remaining", &self.remaining)
+            .finish_non_exhaustive()
+    }
+}
+
+impl RangeDecryptor {
+    /// Takes the next stored bytes and returns the plaintext of every segment
+    /// they complete, trimmed to the requested range.
+    pub fn push(&mut self, bytes: &[u8]) -> AppResult<Vec<u8>> {
+        self.buffer.extend_from_slice(bytes);
+        let mut plaintext = Vec::new();
+        let mut consumed = 0;
+
+        while self.remaining > 0 {
+            let Some(chunk) = self.chunks.get(self.position) else {
+                break;
+            };
+            let stored_len = chunk.stored_segment_len(self.segment);
+            if self.buffer.len() - consumed < stored_len {
+                break;
+            }
+
+            let (sealed, tag) =
+                self.buffer[consumed..consumed + stored_len].split_at_mut(stored_len - TAG_LEN);
+            let is_last = self.segment + 1 == chunk.segments();
+            self.cipher
+                .decrypt_in_place_detached(
+                    &segment_nonce(&chunk.nonce_prefix, self.segment),
+                    &segment_aad(&self.upload_id, chunk.chunk_index, is_last),
+                    sealed,
+                    Tag::from_slice(tag),
+                )
+                .map_err(|_| AppError::InternalError {
+                    message: format!(
+                        "Failed to decrypt chunk {} of upload {}",
+                        chunk.chunk_index, self.upload_id
+                    ),
+                })?;
+
+            let skip = (self.skip as usize).min(sealed.len());
+            let take = (sealed.len() - skip).min(self.remaining as usize);
+            plaintext.extend_from_slice(&sealed[skip..skip + take]);
+            self.skip = 0;
+            self.remaining -= take as u64;
+            consumed += stored_len;
+
+            if is_last {
+                self.position += 1;
+                self.segment = 0;
+            } else {
+                self.segment += 1;
+            }
+        }
+
+        self.buffer.drain(..consumed);
+        Ok(plaintext)
+    }
+
+    /// Fails when the stored bytes ended before the whole range was decrypted.
+    pub fn finish(&self) -> AppResult<()> {
+        if self.remaining > 0 {
+            return Err(AppError::R2Error {
+                message: format!(
+                    "Encrypted object for upload {} ended {} bytes early",
+                    self.upload_id, self.remaining
+                ),
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UPLOAD: &str = "upload-1";
+
+    fn sample(len: usize, seed: u8) -> Vec<u8> {
+        (0..len)
+            .map(|index| (index as u8).wrapping_mul(31).wrapping_add(seed))
+            .collect()
+    }
+
+    /// Encrypts `sizes` as consecutive chunks, returning the stored object,
+    /// its chunk records and the plaintext.
+    fn encrypt_object(key: &Key, sizes: &[usize]) -> (Vec<u8>, Vec<StoredChunk>, Vec<u8>) {
+        let (mut stored, mut chunks, mut plain) = (Vec::new(), Vec::new(), Vec::new());
+        for (index, size) in sizes.iter().enumerate() {
+            let data = sample(*size, index as u8);
+            plain.extend_from_slice(&data);
+            let sealed = encrypt_chunk(key, UPLOAD, index as u16, data).unwrap();
+            assert_eq!(sealed.bytes.len() as u64, encrypted_len(*size as u64));
+            chunks.push(
+                StoredChunk::new(index as u16, sealed.bytes.len() as u64, &sealed.nonce).unwrap(),
+            );
+            stored.extend(sealed.bytes);
+        }
+        (stored, chunks, plain)
+    }
+
+    /// Decrypts `start..=end` feeding the planned bytes in `piece`-sized pieces.
+    fn read_range(
+        key: &Key,
+        stored: &[u8],
+        chunks: &[StoredChunk],
+        start: u64,
+        end: u64,
+        piece: usize,
+    ) -> AppResult<Vec<u8>> {
+        let mut plan = plan_range(key.clone(), UPLOAD, chunks.to_vec(), start, end);
+        let offset = plan.stored_offset as usize;
+        let range = &stored[offset..offset + plan.stored_length as usize];
+        let mut plain = Vec::new();
+        for bytes in range.chunks(piece) {
+            plain.extend(plan.decryptor.push(bytes)?);
+        }
+        plan.decryptor.finish()?;
+        Ok(plain)
+    }
+
+    #[test]
+    fn ranges_decrypt_across_segment_and_chunk_boundaries() {
+        let key = Key::generate().unwrap();
+        let segment = ENCRYPTION_SEGMENT_SIZE;
+        let (stored, chunks, plain) = encrypt_object(&key, &[2 * segment + 100, segment, 7]);
+        let total = plain.len() as u64;
+
+        for (start, end) in [
+            (0, total - 1),
+            (5, 10),
+            (segment as u64 - 3, segment as u64 + 3),
+            (2 * segment as u64 + 50, 3 * segment as u64 + 102),
+            (total - 7, total - 1),
+            (total - 1, total - 1),
+        ] {
+            for piece in [1, 1000, stored.len()] {
+                let range = read_range(&key, &stored, &chunks, start, end, piece).unwrap();
+                assert_eq!(
+                    range,
+                    &plain[start as usize..=end as usize],
+                    "{start}-{end}/{piece}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn tampered_or_truncated_objects_fail_to_decrypt() {
+        let key = Key::generate().unwrap();
+        let (mut stored, chunks, _) = encrypt_object(&key, &[100, 50]);
+        let total = 150;
+
+        let mut plan = plan_range(key.clone(), UPLOAD, chunks.clone(), 0, total - 1);
+        assert_eq!(plan.stored_length as usize, stored.len());
+        let plain = plan.decryptor.push(&stored[..stored.len() - 1]).unwrap();
+        assert_eq!(plain.len(), 100);
+        assert!(matches!(
+            plan.decryptor.finish(),
+            Err(AppError::R2Error { .. })
+        ));
+
+        stored[3] ^= 1;
+        assert!(matches!(
+            read_range(&key, &stored, &chunks, 0, total - 1, 64),
+            Err(AppError::InternalError { .. })
+        ));
+    }
+
+    #[test]
+    fn wrapped_keys_only_open_with_their_kek_and_upload() {
+        let master = Key::generate().unwrap();
+        let kek = master.derive_user_key("user-1");
+        assert_eq!(kek, master.derive_user_key("user-1"));
+        assert_ne!(kek, master.derive_user_key("user-2"));
+
+        let data_key = Key::generate().unwrap();
+        let wrapped = wrap_key(&kek, &data_key, UPLOAD).unwrap();
+        assert_eq!(unwrap_key(&kek, &wrapped, UPLOAD).unwrap(), data_key);
+        assert!(matches!(
+            unwrap_key(&master.derive_user_key("user-2"), &wrapped, UPLOAD),
+            Err(AppError::EncryptionKeyMismatch { .. })
+        ));
+        assert!(matches!(
+            unwrap_key(&kek, &wrapped, "upload-2"),
+            Err(AppError::EncryptionKeyMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn customer_key_requires_matching_md5() {
+        let key = [7u8; KEY_LEN];
+        let encoded = STANDARD.encode(key);
+        let digest = STANDARD.encode(Md5::digest(key));
+
+        assert_eq!(
+            Key::from_customer_headers(&encoded, Some(&digest)).unwrap(),
+            Key(key)
+        );
+        assert!(matches!(
+            Key::from_customer_headers(&encoded, None),
+            Err(AppError::MissingField { .. })
+        ));
+        assert!(matches!(
+            Key::from_customer_headers(&encoded, Some(&STANDARD.encode([0u8; 16]))),
+            Err(AppError::InvalidField { .. })
+        ));
+        assert!(matches!(
+            Key::from_customer_headers("c2hvcnQ=", Some(&digest)),
+            Err(AppError::InvalidField { .. })
+        ));
+    }
+
+    #[test]
+    fn plaintext_len_inverts_encrypted_len() {
+        for plain in [1, SEGMENT - 1, SEGMENT, SEGMENT + 1, 5 * SEGMENT] {
+            assert_eq!(plaintext_len(encrypted_len(plain)), Some(plain));
+        }
+        assert_eq!(plaintext_len(TAG_LEN as u64 - 1), None);
+        assert_eq!(plaintext_len(STORED_SEGMENT + 3), None);
+    }
+}
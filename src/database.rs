@@ -7,15 +7,44 @@
 //!
 //! - **Upload Metadata Management**: Create, read, update upload records
 //! - **Chunk Tracking**: Record individual chunk uploads and progress
-//! - **Status Management**: Track upload lifecycle states
+//! - **Status Management**: Apply validated lifecycle transitions and record their history
+//! - **Variant Tracking**: Record generated image variants per upload
+//! - **Retention Holds**: Set retention periods and legal holds, and refuse to delete held uploads
+//! - **Idempotency Keys**: Claim keys and store responses for safe client retries
+//! - **Audit Log**: Append lifecycle actions and query them with keyset pagination
+//! - **Schema Migrations**: Track applied versions and apply the embedded migrations
 //! - **Query Operations**: Support for analytics and dashboards built on top of D1
+//!
+//! ## Integers
+//!
+//! D1 passes numbers through JavaScript doubles, which are exact only up to
+//! 2^53. Integer parameters are therefore bound as decimal text
+//! ([`int_param`], [`uint_param`]) and integer columns that can grow past
+//! 2^53 are selected with `CAST(... AS TEXT)` and decoded by [`SqlInt`],
+//! which rejects any value that would have been rounded.
 
-use chrono::{DateTime, Utc};
-use serde::Deserialize;
-use worker::{d1::D1Database, wasm_bindgen::JsValue, Env};
+use chrono::{DateTime, Duration, Utc};
+use std::fmt;
 
+use serde::{de, Deserialize, Deserializer};
+use worker::{
+    d1::{D1Database, D1PreparedStatement},
+    wasm_bindgen::JsValue,
+    Env,
+};
+
+use crate::constants::UPLOAD_EXPIRY_SECS;
 use crate::errors::{AppError, AppResult};
-use crate::models::{UploadMetadata, UploadStatus, UserRole};
+use crate::migrations::{
+    pending_after, resolve_start, split_statements, Migration, MigrationStatus, MIGRATIONS,
+};
+use crate::models::{
+    transition, AuditAction, AuditEntry, EncryptionMode, FileVersion, MediaInfo, UploadEvent,
+    UploadMetadata, UploadStatus, UploadVariant, UserRole,
+};
+
+/// Statuses in which an upload still accepts chunks.
+const OPEN_STATUSES: &str = "('initiated', 'in_progress')";
 
 /// Lightweight representation of a stored chunk used when finalizing uploads.
 #[derive(Debug, Clone)]
@@ -23,6 +52,134 @@ pub struct UploadChunkRecord {
     pub chunk_index: u16,
     pub chunk_size: u64,
     pub etag: Option<String>,
+    /// Hex AES-GCM nonce prefix; encrypted uploads only.
+    pub nonce: Option<String>,
+    /// Ciphertext bytes stored in R2; encrypted uploads only.
+    pub stored_size: Option<u64>,
+}
+
+/// Whether [`DatabaseService::get_upload`] loads the upload's chunk indices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChunkList {
+    /// Fill `UploadMetadata::chunks` (one extra query).
+    Include,
+    /// Leave `UploadMetadata::chunks` empty; for callers that only need the upload row.
+    Skip,
+}
+
+/// Which version of a logical key to read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VersionRef {
+    /// The version marked current.
+    Current,
+    /// A specific version number.
+    Number(u32),
+}
+
+/// One version history: a user's versioned uploads of a logical key in a bucket.
+///
+/// Histories are per user because a key template without `{user_id}` renders
+/// the same logical key for everyone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionScope<'a> {
+    pub bucket: &'a str,
+    pub user_id: &'a str,
+    pub logical_key: &'a str,
+}
+
+impl<'a> VersionScope<'a> {
+    /// The history `metadata` belongs to, or `None` for an unversioned upload.
+    pub fn of(metadata: &'a UploadMetadata) -> Option<Self> {
+        Some(Self {
+            bucket: &metadata.bucket,
+            user_id: &metadata.user_id,
+            logical_key: metadata.logical_key.as_deref()?,
+        })
+    }
+
+    fn params(&self) -> Vec<JsValue> {
+        vec![
+            JsValue::from_str(self.bucket),
+            JsValue::from_str(self.user_id),
+            JsValue::from_str(self.logical_key),
+        ]
+    }
+}
+
+/// A change to an upload's retention hold, applied by [`DatabaseService::update_hold`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HoldUpdate {
+    /// Set (`Some`) or clear (`None`) the end of the retention period.
+    RetainUntil(Option<DateTime<Utc>>),
+    /// Set or clear the legal hold.
+    LegalHold(bool),
+}
+
+/// An uploaded chunk and the upload row changes recorded with it.
+#[derive(Debug, Clone)]
+pub struct ChunkWrite<'a> {
+    pub upload_id: &'a str,
+    pub chunk_index: u16,
+    pub chunk_size: u64,
+    pub etag: Option<&'a str>,
+    /// Hex AES-GCM nonce prefix; encrypted uploads only.
+    pub nonce: Option<&'a str>,
+    /// Ciphertext bytes stored in R2; encrypted uploads only.
+    pub stored_size: Option<u64>,
+    /// Media metadata parsed from the chunk, stored on the upload row.
+    pub media_info: Option<&'a MediaInfo>,
+    /// Whether image metadata was stripped from the chunk.
+    pub metadata_stripped: bool,
+    /// Lifecycle event to apply; `None` only refreshes `updated_at`.
+    pub event: Option<UploadEvent>,
+    /// User the event is recorded for.
+    pub actor: &'a str,
+    /// Audit entry appended in the same batch as the chunk row.
+    pub audit: &'a NewAuditEntry<'a>,
+}
+
+/// A user's uploads that are still `initiated`, `in_progress` or `completing`.
+#[derive(Debug, Clone, Default)]
+pub struct ActiveUploads {
+    /// Total number of active uploads.
+    pub count: u64,
+    /// IDs of the oldest active uploads, oldest first.
+    pub oldest_upload_ids: Vec<String>,
+}
+
+/// A stored `Idempotency-Key` entry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdempotencyRecord {
+    /// SHA-256 of the original request body, hex encoded.
+    pub request_hash: String,
+    /// Status of the stored response; `None` while the original request is running.
+    pub status_code: Option<u16>,
+    /// Stored response body.
+    pub response_body: Option<String>,
+}
+
+/// An action to append to the audit log.
+#[derive(Debug, Clone)]
+pub struct NewAuditEntry<'a> {
+    pub action: AuditAction,
+    pub upload_id: Option<&'a str>,
+    pub actor: Option<&'a str>,
+    pub ip: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+    pub request_id: &'a str,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Filters for reading the audit log; unset fields match every entry.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AuditQuery {
+    pub upload_id: Option<String>,
+    pub user_id: Option<String>,
+    /// Only entries created at or after this time.
+    pub since: Option<DateTime<Utc>>,
+    /// Only entries with an `audit_id` above this cursor.
+    pub after: Option<u64>,
+    pub limit: u32,
 }
 
 /// D1-backed persistence layer for uploads and chunk metadata.
@@ -41,7 +198,12 @@ impl DatabaseService {
     }
 
     /// Persist a fresh upload record.
-    pub async fn create_upload(&self, metadata: &UploadMetadata) -> AppResult<()> {
+    ///
+    /// The row is only inserted if no other upload or variant holds the same
+    /// bucket and key (see [`DatabaseService::key_in_use`]); the check and insert are one
+    /// statement, so concurrent inits cannot both claim a key. Returns `false`
+    /// when the key was taken.
+    pub async fn create_upload(&self, metadata: &UploadMetadata) -> AppResult<bool> {
         let statement = self.db.prepare(
             "INSERT INTO uploads (
                 upload_id,
@@ -52,40 +214,235 @@ impl DatabaseService {
                 user_role,
                 r2_key,
                 r2_upload_id,
+                bucket,
                 status,
                 created_at,
-                updated_at
-            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11)",
+                updated_at,
+                logical_key,
+                encryption,
+                wrapped_key
+            )
+            SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15
+            WHERE NOT EXISTS (
+                SELECT 1 FROM uploads
+                WHERE bucket = ?9 AND r2_key = ?7
+                  AND status IN ('initiated', 'in_progress', 'completing', 'completed')
+            )
+            AND NOT EXISTS (
+                SELECT 1 FROM upload_variants v
+                JOIN uploads u ON u.upload_id = v.upload_id
+                WHERE u.bucket = ?9 AND v.r2_key = ?7
+            )",
         );
 
         let statement = statement
             .bind(&[
                 JsValue::from_str(&metadata.upload_id),
                 JsValue::from_str(&metadata.file_name),
-                JsValue::from_f64(metadata.total_size as f64),
+                uint_param(metadata.total_size, "total_size")?,
                 JsValue::from_str(&metadata.content_type),
                 JsValue::from_str(&metadata.user_id),
                 JsValue::from_str(metadata.user_role.as_str()),
                 JsValue::from_str(&metadata.r2_key),
                 JsValue::from_str(&metadata.r2_upload_id),
+                JsValue::from_str(&metadata.bucket),
                 JsValue::from_str(metadata.status.as_str()),
                 JsValue::from_str(&metadata.created_at.to_rfc3339()),
                 JsValue::from_str(&metadata.updated_at.to_rfc3339()),
+                metadata
+                    .logical_key
+                    .as_deref()
+                    .map_or(JsValue::NULL, JsValue::from_str),
+                JsValue::from_str(metadata.encryption.as_str()),
+                metadata
+                    .wrapped_key
+                    .as_deref()
+                    .map_or(JsValue::NULL, JsValue::from_str),
             ])
             .map_err(map_d1_error("bind insert upload"))?;
 
-        statement
+        let result = statement
             .run()
             .await
-            .map(|_| ())
-            .map_err(map_d1_error("insert upload"))
+            .map_err(map_d1_error("insert upload"))?;
+        let inserted = result
+            .meta()
+            .map_err(map_d1_error("read insert upload result"))?
+            .and_then(|meta| meta.changes)
+            .unwrap_or(0);
+        Ok(inserted > 0)
+    }
+
+    /// Whether an upload that is in flight or completed, or a generated variant,
+    /// already holds `r2_key` in `bucket`.
+    ///
+    /// Cancelled, expired, failed and deleted uploads release their key.
+    pub async fn key_in_use(&self, bucket: &str, r2_key: &str) -> AppResult<bool> {
+        let statement = self.db.prepare(
+            "SELECT upload_id FROM uploads
+             WHERE bucket = ?1 AND r2_key = ?2
+               AND status IN ('initiated', 'in_progress', 'completing', 'completed')
+             UNION ALL
+             SELECT v.upload_id FROM upload_variants v
+             JOIN uploads u ON u.upload_id = v.upload_id
+             WHERE u.bucket = ?1 AND v.r2_key = ?2
+             LIMIT 1",
+        );
+
+        let statement = statement
+            .bind(&[JsValue::from_str(bucket), JsValue::from_str(r2_key)])
+            .map_err(map_d1_error("bind key lookup"))?;
+        let holder: Option<String> = statement
+            .first(Some("upload_id"))
+            .await
+            .map_err(map_d1_error("look up key"))?;
+
+        Ok(holder.is_some())
+    }
+
+    /// Every version in `scope`, newest first.
+    pub async fn list_versions(&self, scope: VersionScope<'_>) -> AppResult<Vec<FileVersion>> {
+        let statement = self.db.prepare(format!(
+            "{VERSION_SELECT}
+             WHERE v.bucket = ?1 AND v.user_id = ?2 AND v.logical_key = ?3
+             ORDER BY v.version DESC"
+        ));
+        let statement = statement
+            .bind(&scope.params())
+            .map_err(map_d1_error("bind list versions"))?;
+        let result = statement
+            .all()
+            .await
+            .map_err(map_d1_error("list versions"))?;
+
+        let rows: Vec<VersionRow> = result
+            .results()
+            .map_err(map_d1_error("deserialize versions"))?;
+        rows.into_iter().map(VersionRow::try_into_version).collect()
+    }
+
+    /// One version in `scope`.
+    pub async fn get_version(
+        &self,
+        scope: VersionScope<'_>,
+        version: VersionRef,
+    ) -> AppResult<Option<FileVersion>> {
+        let mut params = scope.params();
+        let filter = match version {
+            VersionRef::Current => "v.is_current = 1",
+            VersionRef::Number(number) => {
+                params.push(int_param(number));
+                "v.version = ?4"
+            }
+        };
+        let statement = self.db.prepare(format!(
+            "{VERSION_SELECT}
+             WHERE v.bucket = ?1 AND v.user_id = ?2 AND v.logical_key = ?3 AND {filter}"
+        ));
+        let statement = statement
+            .bind(&params)
+            .map_err(map_d1_error("bind load version"))?;
+        let row: Option<VersionRow> = statement
+            .first(None)
+            .await
+            .map_err(map_d1_error("load version"))?;
+
+        row.map(VersionRow::try_into_version).transpose()
+    }
+
+    /// The version number `upload_id` was recorded as, if it completed as a version.
+    pub async fn version_of_upload(&self, upload_id: &str) -> AppResult<Option<u32>> {
+        let statement = self.db.prepare(
+            "SELECT CAST(version AS TEXT) AS version FROM file_versions WHERE upload_id = ?1",
+        );
+        let statement = statement
+            .bind(&[JsValue::from_str(upload_id)])
+            .map_err(map_d1_error("bind load upload version"))?;
+        let version: Option<SqlInt> = statement
+            .first(Some("version"))
+            .await
+            .map_err(map_d1_error("load upload version"))?;
+
+        version.map(|version| version.get("version")).transpose()
+    }
+
+    /// Marks `version` as the current version in `scope`, returning `false`
+    /// when no such version exists.
+    ///
+    /// One statement flips every row, so readers never see zero or two
+    /// current versions.
+    pub async fn restore_version(&self, scope: VersionScope<'_>, version: u32) -> AppResult<bool> {
+        let statement = self.db.prepare(
+            "UPDATE file_versions
+             SET is_current = (version = CAST(?4 AS INTEGER))
+             WHERE bucket = ?1 AND user_id = ?2 AND logical_key = ?3
+               AND EXISTS (
+                   SELECT 1 FROM file_versions
+                   WHERE bucket = ?1 AND user_id = ?2 AND logical_key = ?3
+                     AND version = CAST(?4 AS INTEGER)
+               )",
+        );
+        let mut params = scope.params();
+        params.push(int_param(version));
+        let statement = statement
+            .bind(&params)
+            .map_err(map_d1_error("bind restore version"))?;
+        let result = statement
+            .run()
+            .await
+            .map_err(map_d1_error("restore version"))?;
+        let changes = result
+            .meta()
+            .map_err(map_d1_error("read restore version result"))?
+            .and_then(|meta| meta.changes)
+            .unwrap_or(0);
+
+        Ok(changes > 0)
+    }
+
+    /// Applies `update` to a completed upload's retention hold, returning
+    /// `false` when the upload does not exist or is not `completed`.
+    pub async fn update_hold(&self, upload_id: &str, update: HoldUpdate) -> AppResult<bool> {
+        let (column, value) = match update {
+            HoldUpdate::RetainUntil(until) => (
+                "retain_until",
+                until.map_or(JsValue::NULL, |until| {
+                    JsValue::from_str(&until.to_rfc3339())
+                }),
+            ),
+            HoldUpdate::LegalHold(held) => ("legal_hold", int_param(held)),
+        };
+        let statement = self.db.prepare(format!(
+            "UPDATE uploads
+             SET {column} = ?2, updated_at = ?3
+             WHERE upload_id = ?1 AND status = 'completed'"
+        ));
+        let statement = statement
+            .bind(&[
+                JsValue::from_str(upload_id),
+                value,
+                JsValue::from_str(&Utc::now().to_rfc3339()),
+            ])
+            .map_err(map_d1_error("bind update hold"))?;
+        let result = statement.run().await.map_err(map_d1_error("update hold"))?;
+        let changes = result
+            .meta()
+            .map_err(map_d1_error("read update hold result"))?
+            .and_then(|meta| meta.changes)
+            .unwrap_or(0);
+
+        Ok(changes > 0)
     }
 
-    /// Fetch upload metadata including associated chunk indices.
-    pub async fn get_upload(&self, upload_id: &str) -> AppResult<Option<UploadMetadata>> {
+    /// Fetch upload metadata, with its chunk indices unless `chunks` is [`ChunkList::Skip`].
+    pub async fn get_upload(
+        &self,
+        upload_id: &str,
+        chunks: ChunkList,
+    ) -> AppResult<Option<UploadMetadata>> {
         let statement = self
             .db
-            .prepare("SELECT * FROM uploads WHERE upload_id = ?1");
+            .prepare(format!("{UPLOAD_SELECT} WHERE upload_id = ?1"));
         let statement = statement
             .bind(&[JsValue::from_str(upload_id)])
             .map_err(map_d1_error("bind load upload"))?;
@@ -98,109 +455,974 @@ impl DatabaseService {
             return Ok(None);
         };
 
-        let chunks = self.fetch_chunks(upload_id).await?;
+        let chunks = match chunks {
+            ChunkList::Include => self.fetch_chunks(upload_id).await?,
+            ChunkList::Skip => Vec::new(),
+        };
         let metadata = row.try_into_metadata(chunks)?;
 
         Ok(Some(metadata))
     }
 
-    /// Update the upload status and timestamp.
-    pub async fn update_upload_status(
+    /// The `limit` least recently updated open uploads with no activity since
+    /// `idle_before`, without their chunk indices.
+    pub async fn list_idle_uploads(
+        &self,
+        idle_before: DateTime<Utc>,
+        limit: u32,
+    ) -> AppResult<Vec<UploadMetadata>> {
+        let statement = self.db.prepare(format!(
+            "{UPLOAD_SELECT}
+             WHERE status IN {OPEN_STATUSES} AND updated_at <= ?1
+             ORDER BY updated_at ASC
+             LIMIT CAST(?2 AS INTEGER)"
+        ));
+        let statement = statement
+            .bind(&[
+                JsValue::from_str(&idle_before.to_rfc3339()),
+                int_param(limit),
+            ])
+            .map_err(map_d1_error("bind list idle uploads"))?;
+        let result = statement
+            .all()
+            .await
+            .map_err(map_d1_error("list idle uploads"))?;
+
+        let rows: Vec<UploadRow> = result
+            .results()
+            .map_err(map_d1_error("deserialize idle uploads"))?;
+        rows.into_iter()
+            .map(|row| row.try_into_metadata(Vec::new()))
+            .collect()
+    }
+
+    /// Count the user's active uploads and return the IDs of the oldest `limit` of them.
+    pub async fn list_active_uploads(&self, user_id: &str, limit: u32) -> AppResult<ActiveUploads> {
+        let statement = self.db.prepare(
+            "SELECT upload_id, COUNT(*) OVER () AS active_count
+             FROM uploads
+             WHERE user_id = ?1 AND status IN ('initiated', 'in_progress', 'completing')
+             ORDER BY created_at ASC
+             LIMIT CAST(?2 AS INTEGER)",
+        );
+
+        let statement = statement
+            .bind(&[JsValue::from_str(user_id), int_param(limit)])
+            .map_err(map_d1_error("bind list active uploads"))?;
+        let result = statement
+            .all()
+            .await
+            .map_err(map_d1_error("list active uploads"))?;
+
+        let rows: Vec<ActiveUploadRow> = result
+            .results()
+            .map_err(map_d1_error("deserialize active uploads"))?;
+
+        let count = match rows.first() {
+            Some(row) => row.active_count.get("active_count")?,
+            None => 0,
+        };
+
+        Ok(ActiveUploads {
+            count,
+            oldest_upload_ids: rows.into_iter().map(|row| row.upload_id).collect(),
+        })
+    }
+
+    /// Apply `event` to the upload and record it in `upload_events`.
+    ///
+    /// The update only happens if [`transition`] allows the event from the
+    /// current status and the event actually changes it, so concurrent callers
+    /// racing on the same event see exactly one winner. Returns `false` when
+    /// the row was not in such a state (or does not exist), for
+    /// [`UploadEvent::Deleted`] also while the upload is held, and for
+    /// [`UploadEvent::Expired`] also when the upload was updated within
+    /// [`UPLOAD_EXPIRY_SECS`]. The history row
+    /// and the status change are written in one batch, i.e. one transaction.
+    pub async fn apply_event(
         &self,
         upload_id: &str,
-        status: UploadStatus,
-    ) -> AppResult<()> {
+        event: UploadEvent,
+        actor: &str,
+    ) -> AppResult<bool> {
+        let Some(statements) = self.event_statements(upload_id, event, actor)? else {
+            return Ok(false);
+        };
+        self.run_event_batch(statements.into()).await
+    }
+
+    /// Apply `CompletionSucceeded` to a versioned upload and, in the same
+    /// transaction, record it as the new current version of its logical key.
+    ///
+    /// Versions are numbered from 1 in completion order. Returns `false`, and
+    /// records nothing, when the upload was not `completing`.
+    pub async fn complete_version(
+        &self,
+        metadata: &UploadMetadata,
+        logical_key: &str,
+        actor: &str,
+    ) -> AppResult<bool> {
+        let Some(event) =
+            self.event_statements(&metadata.upload_id, UploadEvent::CompletionSucceeded, actor)?
+        else {
+            return Ok(false);
+        };
+
+        let params = [
+            JsValue::from_str(&metadata.bucket),
+            JsValue::from_str(logical_key),
+            JsValue::from_str(&metadata.upload_id),
+            JsValue::from_str(&metadata.r2_key),
+            uint_param(metadata.total_size, "size")?,
+            JsValue::from_str(&metadata.content_type),
+            JsValue::from_str(&Utc::now().to_rfc3339()),
+            JsValue::from_str(&metadata.user_id),
+        ];
+        // Both statements only act while the upload is still `completing`, so a
+        // lost race leaves the version history untouched.
+        let retire = self
+            .db
+            .prepare(
+                "UPDATE file_versions SET is_current = 0
+                 WHERE bucket = ?1 AND user_id = ?8 AND logical_key = ?2 AND is_current = 1
+                   AND EXISTS (
+                       SELECT 1 FROM uploads WHERE upload_id = ?3 AND status = 'completing'
+                   )",
+            )
+            .bind(&params)
+            .map_err(map_d1_error("bind retire current version"))?;
+        let record = self
+            .db
+            .prepare(
+                "INSERT INTO file_versions (
+                    bucket, user_id, logical_key, version, upload_id, r2_key, size,
+                    content_type, is_current, created_at
+                 )
+                 SELECT ?1, ?8, ?2, next_version, ?3, ?4, ?5, ?6, 1, ?7
+                 FROM (
+                     SELECT COALESCE(MAX(version), 0) + 1 AS next_version
+                     FROM file_versions
+                     WHERE bucket = ?1 AND user_id = ?8 AND logical_key = ?2
+                 )
+                 WHERE EXISTS (
+                     SELECT 1 FROM uploads WHERE upload_id = ?3 AND status = 'completing'
+                 )",
+            )
+            .bind(&params)
+            .map_err(map_d1_error("bind record version"))?;
+
+        let mut statements = vec![retire, record];
+        statements.extend(event);
+        self.run_event_batch(statements).await
+    }
+
+    /// Mark a completed upload deleted and drop its version and variant rows, in
+    /// one transaction.
+    ///
+    /// The status update carries the hold check, so an upload that is under a
+    /// legal hold or inside its retention period is never marked deleted, however
+    /// the caller checked. When the deleted upload was the current version of its
+    /// logical key, the newest remaining version becomes current. Returns `false`,
+    /// and changes nothing, when the upload was not `completed` or is held.
+    pub async fn delete_upload(&self, upload_id: &str, actor: &str) -> AppResult<bool> {
+        let Some(event) = self.event_statements(upload_id, UploadEvent::Deleted, actor)? else {
+            return Ok(false);
+        };
+
+        let params = [JsValue::from_str(upload_id)];
+        // These only act once the status update has run, so a refused delete
+        // leaves versions and variants untouched.
+        let drop_version = self
+            .db
+            .prepare(
+                "DELETE FROM file_versions
+                 WHERE upload_id = ?1
+                   AND EXISTS (SELECT 1 FROM uploads WHERE upload_id = ?1 AND status = 'deleted')",
+            )
+            .bind(&params)
+            .map_err(map_d1_error("bind drop version"))?;
+        let promote = self
+            .db
+            .prepare(
+                "UPDATE file_versions
+                 SET is_current = 1
+                 WHERE (bucket, user_id, logical_key) IN (
+                       SELECT bucket, user_id, logical_key FROM uploads
+                       WHERE upload_id = ?1 AND status = 'deleted' AND logical_key IS NOT NULL
+                   )
+                   AND version = (
+                       SELECT MAX(version) FROM file_versions AS other
+                       WHERE other.bucket = file_versions.bucket
+                         AND other.user_id = file_versions.user_id
+                         AND other.logical_key = file_versions.logical_key
+                   )
+                   AND NOT EXISTS (
+                       SELECT 1 FROM file_versions AS other
+                       WHERE other.bucket = file_versions.bucket
+                         AND other.user_id = file_versions.user_id
+                         AND other.logical_key = file_versions.logical_key
+                         AND other.is_current = 1
+                   )",
+            )
+            .bind(&params)
+            .map_err(map_d1_error("bind promote version"))?;
+        let drop_variants = self
+            .db
+            .prepare(
+                "DELETE FROM upload_variants
+                 WHERE upload_id = ?1
+                   AND EXISTS (SELECT 1 FROM uploads WHERE upload_id = ?1 AND status = 'deleted')",
+            )
+            .bind(&params)
+            .map_err(map_d1_error("bind drop variants"))?;
+
+        let mut statements = Vec::from(event);
+        statements.extend([drop_version, promote, drop_variants]);
+        let results = self
+            .db
+            .batch(statements)
+            .await
+            .map_err(map_d1_error("delete upload"))?;
+        // The status update is the second statement (see `event_statements`).
+        let deleted = match results.get(1) {
+            Some(result) => result
+                .meta()
+                .map_err(map_d1_error("read delete upload result"))?
+                .and_then(|meta| meta.changes)
+                .unwrap_or(0),
+            None => 0,
+        };
+
+        Ok(deleted > 0)
+    }
+
+    /// Take over the claim on a `completing` upload whose claim was made
+    /// before `stale_before`, or never stamped, restamping it for `actor`.
+    ///
+    /// The takeover is recorded as a `completion_started` event that leaves the
+    /// status unchanged. Returns `false` when the upload is no longer
+    /// `completing` or another request holds a fresh claim.
+    pub async fn take_over_completion(
+        &self,
+        upload_id: &str,
+        actor: &str,
+        stale_before: DateTime<Utc>,
+    ) -> AppResult<bool> {
+        let params = [
+            JsValue::from_str(upload_id),
+            JsValue::from_str(UploadEvent::CompletionStarted.as_str()),
+            JsValue::from_str(actor),
+            JsValue::from_str(&Utc::now().to_rfc3339()),
+            JsValue::from_str(&stale_before.to_rfc3339()),
+        ];
+        let stale = "upload_id = ?1 AND status = 'completing'
+                 AND (completion_claimed_at IS NULL OR completion_claimed_at <= ?5)";
+
+        let record = self
+            .db
+            .prepare(format!(
+                "INSERT INTO upload_events (upload_id, event, from_status, to_status, actor, created_at)
+                 SELECT upload_id, ?2, status, status, ?3, ?4
+                 FROM uploads
+                 WHERE {stale}"
+            ))
+            .bind(&params)
+            .map_err(map_d1_error("bind record completion takeover"))?;
+        let update = self
+            .db
+            .prepare(format!(
+                "UPDATE uploads
+                 SET completion_claimed_at = ?4, updated_at = ?4
+                 WHERE {stale}"
+            ))
+            .bind(&params)
+            .map_err(map_d1_error("bind take over completion"))?;
+
+        self.run_event_batch(vec![record, update]).await
+    }
+
+    /// Runs a batch whose last statement is an upload status update, returning
+    /// whether that update changed the row.
+    async fn run_event_batch(&self, statements: Vec<D1PreparedStatement>) -> AppResult<bool> {
+        let results = self
+            .db
+            .batch(statements)
+            .await
+            .map_err(map_d1_error("transition upload status"))?;
+        let changes = match results.last() {
+            Some(result) => result
+                .meta()
+                .map_err(map_d1_error("read transition status result"))?
+                .and_then(|meta| meta.changes)
+                .unwrap_or(0),
+            None => 0,
+        };
+
+        Ok(changes > 0)
+    }
+
+    /// Statements that record `event` in `upload_events` and apply it to the
+    /// upload, history row first so it can read the status being left. `None`
+    /// when the event never changes a status.
+    fn event_statements(
+        &self,
+        upload_id: &str,
+        event: UploadEvent,
+        actor: &str,
+    ) -> AppResult<Option<[D1PreparedStatement; 2]>> {
+        let moves: Vec<(UploadStatus, UploadStatus)> = UploadStatus::ALL
+            .into_iter()
+            .filter_map(|from| {
+                transition(from, event)
+                    .ok()
+                    .filter(|to| *to != from)
+                    .map(|to| (from, to))
+            })
+            .collect();
+        // Each event leads to a single status (see `TRANSITIONS`).
+        let Some(&(_, target)) = moves.first() else {
+            return Ok(None);
+        };
+        let sources: Vec<UploadStatus> = moves.iter().map(|(from, _)| *from).collect();
+        let placeholders = (0..sources.len())
+            .map(|index| format!("?{}", index + 6))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let now = Utc::now().to_rfc3339();
+        let mut extra_params = Vec::new();
+        let guard = match event {
+            // However the caller checked, a held upload is never marked deleted.
+            UploadEvent::Deleted => {
+                " AND legal_hold = 0 AND (retain_until IS NULL OR retain_until <= ?3)".to_string()
+            }
+            // Nor is an upload that saw activity within the expiry period expired.
+            UploadEvent::Expired => {
+                let idle_before = Utc::now() - Duration::seconds(UPLOAD_EXPIRY_SECS);
+                extra_params.push(JsValue::from_str(&idle_before.to_rfc3339()));
+                format!(" AND updated_at <= ?{}", sources.len() + 6)
+            }
+            _ => String::new(),
+        };
+        // Claims are stamped so a stale one can be taken over.
+        let claim = if event == UploadEvent::CompletionStarted {
+            ", completion_claimed_at = ?3"
+        } else {
+            ""
+        };
+
+        let mut params = vec![
+            JsValue::from_str(upload_id),
+            JsValue::from_str(target.as_str()),
+            JsValue::from_str(&now),
+            JsValue::from_str(event.as_str()),
+            JsValue::from_str(actor),
+        ];
+        params.extend(
+            sources
+                .iter()
+                .map(|status| JsValue::from_str(status.as_str())),
+        );
+        params.extend(extra_params);
+
+        let record = self
+            .db
+            .prepare(format!(
+                "INSERT INTO upload_events (upload_id, event, from_status, to_status, actor, created_at)
+                 SELECT upload_id, ?4, status, ?2, ?5, ?3
+                 FROM uploads
+                 WHERE upload_id = ?1 AND status IN ({placeholders}){guard}"
+            ))
+            .bind(&params)
+            .map_err(map_d1_error("bind record upload event"))?;
+        let update = self
+            .db
+            .prepare(format!(
+                "UPDATE uploads
+                 SET status = ?2, updated_at = ?3{claim}
+                 WHERE upload_id = ?1 AND status IN ({placeholders}){guard}"
+            ))
+            .bind(&params)
+            .map_err(map_d1_error("bind transition status"))?;
+
+        Ok(Some([record, update]))
+    }
+
+    /// Read only the current status of an upload.
+    pub async fn current_status(&self, upload_id: &str) -> AppResult<Option<UploadStatus>> {
+        let statement = self
+            .db
+            .prepare("SELECT status FROM uploads WHERE upload_id = ?1");
+        let statement = statement
+            .bind(&[JsValue::from_str(upload_id)])
+            .map_err(map_d1_error("bind load status"))?;
+        let status: Option<String> = statement
+            .first(Some("status"))
+            .await
+            .map_err(map_d1_error("load status"))?;
+
+        status
+            .map(|status| {
+                status
+                    .parse::<UploadStatus>()
+                    .map_err(|err| AppError::DatabaseError {
+                        message: format!("Invalid upload status in database: {err}"),
+                    })
+            })
+            .transpose()
+    }
+
+    /// Record an uploaded chunk together with the upload row changes it
+    /// causes, in one batch (one round trip and one transaction).
+    ///
+    /// The chunk row is upserted, parsed media info and the metadata-stripped
+    /// flag are stored when present, and then `chunk.event` is applied as in
+    /// [`Self::apply_event`] or, without an event, only `updated_at` is
+    /// refreshed, and `chunk.audit` is appended to the audit log. Losing the
+    /// event to another chunk is not an error, but nothing is recorded once a
+    /// completion or cancellation has claimed the upload; `false` is returned
+    /// then.
+    pub async fn record_chunk_upload(&self, chunk: &ChunkWrite<'_>) -> AppResult<bool> {
+        let now = Utc::now().to_rfc3339();
+        let mut statements = vec![self
+            .db
+            .prepare(format!(
+                "INSERT INTO upload_chunks (
+                    upload_id,
+                    chunk_index,
+                    chunk_size,
+                    etag,
+                    uploaded_at,
+                    nonce,
+                    stored_size
+                )
+                SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7
+                WHERE EXISTS (
+                    SELECT 1 FROM uploads
+                    WHERE upload_id = ?1 AND status IN {OPEN_STATUSES}
+                )
+                ON CONFLICT(upload_id, chunk_index) DO UPDATE SET
+                    chunk_size = excluded.chunk_size,
+                    etag = excluded.etag,
+                    uploaded_at = excluded.uploaded_at,
+                    nonce = excluded.nonce,
+                    stored_size = excluded.stored_size"
+            ))
+            .bind(&[
+                JsValue::from_str(chunk.upload_id),
+                int_param(chunk.chunk_index),
+                uint_param(chunk.chunk_size, "chunk_size")?,
+                chunk.etag.map_or(JsValue::NULL, JsValue::from_str),
+                JsValue::from_str(&now),
+                chunk.nonce.map_or(JsValue::NULL, JsValue::from_str),
+                chunk
+                    .stored_size
+                    .map(|size| uint_param(size, "stored_size"))
+                    .transpose()?
+                    .unwrap_or(JsValue::NULL),
+            ])
+            .map_err(map_d1_error("bind record chunk"))?];
+
+        if let Some(media_info) = chunk.media_info {
+            let encoded =
+                serde_json::to_string(media_info).map_err(|err| AppError::InternalError {
+                    message: format!("Failed to serialize media info: {err}"),
+                })?;
+            statements.push(
+                self.db
+                    .prepare(format!(
+                        "UPDATE uploads
+                         SET media_info = ?1
+                         WHERE upload_id = ?2 AND status IN {OPEN_STATUSES}"
+                    ))
+                    .bind(&[
+                        JsValue::from_str(&encoded),
+                        JsValue::from_str(chunk.upload_id),
+                    ])
+                    .map_err(map_d1_error("bind update media info"))?,
+            );
+        }
+
+        if chunk.metadata_stripped {
+            statements.push(
+                self.db
+                    .prepare(format!(
+                        "UPDATE uploads
+                         SET metadata_stripped = 1
+                         WHERE upload_id = ?1 AND status IN {OPEN_STATUSES}"
+                    ))
+                    .bind(&[JsValue::from_str(chunk.upload_id)])
+                    .map_err(map_d1_error("bind mark metadata stripped"))?,
+            );
+        }
+
+        let event_statements = match chunk.event {
+            Some(event) => self.event_statements(chunk.upload_id, event, chunk.actor)?,
+            None => None,
+        };
+        match event_statements {
+            Some(event_statements) => statements.extend(event_statements),
+            None => statements.push(
+                self.db
+                    .prepare(format!(
+                        "UPDATE uploads
+                         SET updated_at = ?1
+                         WHERE upload_id = ?2 AND status IN {OPEN_STATUSES}"
+                    ))
+                    .bind(&[JsValue::from_str(&now), JsValue::from_str(chunk.upload_id)])
+                    .map_err(map_d1_error("bind touch upload"))?,
+            ),
+        }
+        statements.push(self.audit_statement(
+            chunk.audit,
+            &format!(
+                "WHERE EXISTS (
+                    SELECT 1 FROM uploads
+                    WHERE upload_id = ?8 AND status IN {OPEN_STATUSES}
+                )"
+            ),
+            Some(chunk.upload_id),
+        )?);
+
+        let results = self
+            .db
+            .batch(statements)
+            .await
+            .map_err(map_d1_error("record chunk upload"))?;
+        let recorded = match results.first() {
+            Some(result) => result
+                .meta()
+                .map_err(map_d1_error("read record chunk result"))?
+                .and_then(|meta| meta.changes)
+                .unwrap_or(0),
+            None => 0,
+        };
+
+        Ok(recorded > 0)
+    }
+
+    /// Retrieve chunk metadata for an upload, ordered by index.
+    pub async fn get_upload_chunks(&self, upload_id: &str) -> AppResult<Vec<UploadChunkRecord>> {
+        self.fetch_chunks(upload_id).await
+    }
+
+    /// Record or replace a generated variant for an upload.
+    pub async fn record_variant(&self, upload_id: &str, variant: &UploadVariant) -> AppResult<()> {
         let statement = self.db.prepare(
-            "UPDATE uploads
-             SET status = ?1, updated_at = ?2
-             WHERE upload_id = ?3",
+            "INSERT INTO upload_variants (
+                upload_id,
+                variant_name,
+                r2_key,
+                content_type,
+                width,
+                height,
+                size,
+                created_at
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+            ON CONFLICT(upload_id, variant_name) DO UPDATE SET
+                r2_key = excluded.r2_key,
+                content_type = excluded.content_type,
+                width = excluded.width,
+                height = excluded.height,
+                size = excluded.size,
+                created_at = excluded.created_at",
         );
 
         let statement = statement
             .bind(&[
-                JsValue::from_str(status.as_str()),
-                JsValue::from_str(&Utc::now().to_rfc3339()),
                 JsValue::from_str(upload_id),
+                JsValue::from_str(&variant.name),
+                JsValue::from_str(&variant.r2_key),
+                JsValue::from_str(&variant.content_type),
+                int_param(variant.width),
+                int_param(variant.height),
+                uint_param(variant.size, "size")?,
+                JsValue::from_str(&Utc::now().to_rfc3339()),
             ])
-            .map_err(map_d1_error("bind update status"))?;
+            .map_err(map_d1_error("bind record variant"))?;
 
         statement
             .run()
             .await
             .map(|_| ())
-            .map_err(map_d1_error("update upload status"))
+            .map_err(map_d1_error("record variant"))
     }
 
-    /// Update the last modified timestamp without changing status.
-    pub async fn touch_upload(&self, upload_id: &str) -> AppResult<()> {
+    /// Retrieve generated variants for an upload, ordered by name.
+    pub async fn get_upload_variants(&self, upload_id: &str) -> AppResult<Vec<UploadVariant>> {
         let statement = self.db.prepare(
-            "UPDATE uploads
-             SET updated_at = ?1
-             WHERE upload_id = ?2",
+            "SELECT variant_name, r2_key, content_type, width, height,
+                    CAST(size AS TEXT) AS size
+             FROM upload_variants
+             WHERE upload_id = ?1
+             ORDER BY variant_name ASC",
+        );
+
+        let statement = statement
+            .bind(&[JsValue::from_str(upload_id)])
+            .map_err(map_d1_error("bind list variants"))?;
+        let result = statement
+            .all()
+            .await
+            .map_err(map_d1_error("list variants"))?;
+
+        let rows: Vec<VariantRow> = result
+            .results()
+            .map_err(map_d1_error("deserialize variants"))?;
+
+        rows.into_iter()
+            .map(|row| {
+                Ok(UploadVariant {
+                    name: row.variant_name,
+                    r2_key: row.r2_key,
+                    content_type: row.content_type,
+                    width: row.width.get("width")?,
+                    height: row.height.get("height")?,
+                    size: row.size.get("size")?,
+                })
+            })
+            .collect()
+    }
+
+    /// Claims `key` for `route`, returning `false` when another live entry holds it.
+    ///
+    /// An existing entry is taken over when it has expired, or when its request
+    /// never stored a response and was claimed before `stale_before`.
+    pub async fn claim_idempotency_key(
+        &self,
+        route: &str,
+        key: &str,
+        request_hash: &str,
+        now: DateTime<Utc>,
+        expires_at: DateTime<Utc>,
+        stale_before: DateTime<Utc>,
+    ) -> AppResult<bool> {
+        let statement = self.db.prepare(
+            "INSERT INTO idempotency_keys (
+                route, idempotency_key, request_hash, status_code, response_body, created_at, expires_at
+            ) VALUES (?1, ?2, ?3, NULL, NULL, ?4, ?5)
+            ON CONFLICT (route, idempotency_key) DO UPDATE SET
+                request_hash = excluded.request_hash,
+                status_code = NULL,
+                response_body = NULL,
+                created_at = excluded.created_at,
+                expires_at = excluded.expires_at
+            WHERE idempotency_keys.expires_at <= excluded.created_at
+               OR (idempotency_keys.status_code IS NULL AND idempotency_keys.created_at <= ?6)",
         );
 
         let statement = statement
             .bind(&[
-                JsValue::from_str(&Utc::now().to_rfc3339()),
-                JsValue::from_str(upload_id),
+                JsValue::from_str(route),
+                JsValue::from_str(key),
+                JsValue::from_str(request_hash),
+                JsValue::from_str(&now.to_rfc3339()),
+                JsValue::from_str(&expires_at.to_rfc3339()),
+                JsValue::from_str(&stale_before.to_rfc3339()),
             ])
-            .map_err(map_d1_error("bind touch upload"))?;
+            .map_err(map_d1_error("bind claim idempotency key"))?;
 
-        statement
+        let result = statement
             .run()
             .await
-            .map(|_| ())
-            .map_err(map_d1_error("touch upload"))
+            .map_err(map_d1_error("claim idempotency key"))?;
+        let changes = result
+            .meta()
+            .map_err(map_d1_error("read claim idempotency key result"))?
+            .and_then(|meta| meta.changes)
+            .unwrap_or(0);
+
+        Ok(changes > 0)
     }
 
-    /// Record or update a chunk row for a multipart upload.
-    pub async fn record_chunk(
+    /// Fetch the entry for `key` on `route`, if any.
+    pub async fn get_idempotency_record(
         &self,
-        upload_id: &str,
-        chunk_index: u16,
-        chunk_size: u64,
-        etag: Option<&str>,
+        route: &str,
+        key: &str,
+    ) -> AppResult<Option<IdempotencyRecord>> {
+        let statement = self.db.prepare(
+            "SELECT request_hash, status_code, response_body
+             FROM idempotency_keys
+             WHERE route = ?1 AND idempotency_key = ?2",
+        );
+
+        let statement = statement
+            .bind(&[JsValue::from_str(route), JsValue::from_str(key)])
+            .map_err(map_d1_error("bind load idempotency key"))?;
+        let row: Option<IdempotencyRow> = statement
+            .first(None)
+            .await
+            .map_err(map_d1_error("load idempotency key"))?;
+
+        row.map(|row| {
+            Ok(IdempotencyRecord {
+                request_hash: row.request_hash,
+                status_code: row
+                    .status_code
+                    .map(|status| status.get("status_code"))
+                    .transpose()?,
+                response_body: row.response_body,
+            })
+        })
+        .transpose()
+    }
+
+    /// Store the response produced for a claimed key.
+    pub async fn save_idempotent_response(
+        &self,
+        route: &str,
+        key: &str,
+        status_code: u16,
+        response_body: &str,
     ) -> AppResult<()> {
         let statement = self.db.prepare(
-            "INSERT INTO upload_chunks (
-                upload_id,
-                chunk_index,
-                chunk_size,
-                etag,
-                uploaded_at
-            ) VALUES (?1, ?2, ?3, ?4, ?5)
-            ON CONFLICT(upload_id, chunk_index) DO UPDATE SET
-                chunk_size = excluded.chunk_size,
-                etag = excluded.etag,
-                uploaded_at = excluded.uploaded_at",
+            "UPDATE idempotency_keys
+             SET status_code = ?1, response_body = ?2
+             WHERE route = ?3 AND idempotency_key = ?4",
         );
 
         let statement = statement
             .bind(&[
-                JsValue::from_str(upload_id),
-                JsValue::from_f64(chunk_index as f64),
-                JsValue::from_f64(chunk_size as f64),
-                etag.map_or(JsValue::NULL, JsValue::from_str),
-                JsValue::from_str(&Utc::now().to_rfc3339()),
+                int_param(status_code),
+                JsValue::from_str(response_body),
+                JsValue::from_str(route),
+                JsValue::from_str(key),
             ])
-            .map_err(map_d1_error("bind record chunk"))?;
+            .map_err(map_d1_error("bind save idempotent response"))?;
 
         statement
             .run()
             .await
             .map(|_| ())
-            .map_err(map_d1_error("record chunk"))
+            .map_err(map_d1_error("save idempotent response"))
     }
 
-    /// Retrieve chunk metadata for an upload, ordered by index.
-    pub async fn get_upload_chunks(&self, upload_id: &str) -> AppResult<Vec<UploadChunkRecord>> {
-        self.fetch_chunks(upload_id).await
+    /// Delete a claimed key so the request can be retried from scratch.
+    pub async fn release_idempotency_key(&self, route: &str, key: &str) -> AppResult<()> {
+        let statement = self.db.prepare(
+            "DELETE FROM idempotency_keys
+             WHERE route = ?1 AND idempotency_key = ?2",
+        );
+
+        let statement = statement
+            .bind(&[JsValue::from_str(route), JsValue::from_str(key)])
+            .map_err(map_d1_error("bind release idempotency key"))?;
+
+        statement
+            .run()
+            .await
+            .map(|_| ())
+            .map_err(map_d1_error("release idempotency key"))
+    }
+
+    /// Append an entry to the audit log.
+    pub async fn record_audit(&self, entry: &NewAuditEntry<'_>) -> AppResult<()> {
+        self.audit_statement(entry, "", None)?
+            .run()
+            .await
+            .map(|_| ())
+            .map_err(map_d1_error("record audit"))
+    }
+
+    /// Builds the audit log insert for `entry`, written only when `condition`
+    /// holds; `guard_upload_id` is bound as `?8` for the condition to use.
+    fn audit_statement(
+        &self,
+        entry: &NewAuditEntry<'_>,
+        condition: &str,
+        guard_upload_id: Option<&str>,
+    ) -> AppResult<D1PreparedStatement> {
+        let optional = |value: Option<&str>| value.map_or(JsValue::NULL, JsValue::from_str);
+        let mut params = vec![
+            JsValue::from_str(entry.action.as_str()),
+            optional(entry.upload_id),
+            optional(entry.actor),
+            optional(entry.ip),
+            optional(entry.user_agent),
+            JsValue::from_str(entry.request_id),
+            JsValue::from_str(&entry.created_at.to_rfc3339()),
+        ];
+        if let Some(upload_id) = guard_upload_id {
+            params.push(JsValue::from_str(upload_id));
+        }
+
+        self.db
+            .prepare(format!(
+                "INSERT INTO audit_log (
+                    action, upload_id, actor, ip, user_agent, request_id, created_at
+                )
+                SELECT ?1, ?2, ?3, ?4, ?5, ?6, ?7
+                {condition}"
+            ))
+            .bind(&params)
+            .map_err(map_d1_error("bind record audit"))
+    }
+
+    /// List audit entries matching `query`, oldest first.
+    pub async fn list_audit(&self, query: &AuditQuery) -> AppResult<Vec<AuditEntry>> {
+        let statement = self.db.prepare(
+            "SELECT CAST(audit_id AS TEXT) AS audit_id, action, upload_id, actor, ip,
+                    user_agent, request_id, created_at
+             FROM audit_log
+             WHERE (?1 IS NULL OR upload_id = ?1)
+               AND (?2 IS NULL OR actor = ?2)
+               AND (?3 IS NULL OR created_at >= ?3)
+               AND audit_id > ?4
+             ORDER BY audit_id ASC
+             LIMIT CAST(?5 AS INTEGER)",
+        );
+
+        let statement = statement
+            .bind(&[
+                query
+                    .upload_id
+                    .as_deref()
+                    .map_or(JsValue::NULL, JsValue::from_str),
+                query
+                    .user_id
+                    .as_deref()
+                    .map_or(JsValue::NULL, JsValue::from_str),
+                query.since.map_or(JsValue::NULL, |since| {
+                    JsValue::from_str(&since.to_rfc3339())
+                }),
+                uint_param(query.after.unwrap_or(0), "cursor")?,
+                int_param(query.limit),
+            ])
+            .map_err(map_d1_error("bind list audit"))?;
+        let result = statement.all().await.map_err(map_d1_error("list audit"))?;
+
+        let rows: Vec<AuditRow> = result
+            .results()
+            .map_err(map_d1_error("deserialize audit entries"))?;
+
+        rows.into_iter().map(AuditRow::try_into_entry).collect()
+    }
+
+    /// Report the applied schema version and what this build would still apply.
+    pub async fn migration_status(&self) -> AppResult<MigrationStatus> {
+        let (recorded, has_tables) = self.schema_state().await?;
+        let version = resolve_start(recorded, has_tables, None)?;
+        Ok(MigrationStatus::new(
+            version,
+            Vec::new(),
+            pending_after(version)?,
+        ))
+    }
+
+    /// Apply pending migrations in order, each in its own batch together with
+    /// its `schema_migrations` row.
+    ///
+    /// `baseline` records every migration up to that version as applied
+    /// without running it, for databases created before migrations were
+    /// tracked. A migration that fails because another isolate applied it
+    /// first is skipped; any other failure stops the run.
+    pub async fn run_migrations(&self, baseline: Option<u32>) -> AppResult<MigrationStatus> {
+        let (recorded, has_tables) = self.schema_state().await?;
+        let mut version = resolve_start(recorded, has_tables, baseline)?;
+
+        if baseline.is_some() {
+            let records = MIGRATIONS
+                .iter()
+                .filter(|migration| Some(migration.version) <= version)
+                .map(|migration| self.migration_record(migration))
+                .collect::<AppResult<Vec<_>>>()?;
+            self.db
+                .batch(records)
+                .await
+                .map_err(map_d1_error("baseline schema migrations"))?;
+        }
+
+        let mut applied = Vec::new();
+        for migration in pending_after(version)? {
+            if let Err(err) = self.apply_migration(migration).await {
+                let (recorded, _) = self.schema_state().await?;
+                if recorded < Some(migration.version) {
+                    return Err(err);
+                }
+            } else {
+                applied.push(migration.name);
+            }
+            version = Some(migration.version);
+        }
+
+        Ok(MigrationStatus::new(version, applied, &[]))
+    }
+
+    /// Run one migration's statements and record it, in a single transaction.
+    async fn apply_migration(&self, migration: &Migration) -> AppResult<()> {
+        let mut statements = vec![self.migration_record(migration)?];
+        statements.extend(
+            split_statements(migration.sql)
+                .into_iter()
+                .map(|sql| self.db.prepare(sql)),
+        );
+
+        self.db
+            .batch(statements)
+            .await
+            .map(|_| ())
+            .map_err(|err| AppError::DatabaseError {
+                message: format!("Failed to apply migration {}: {err}", migration.name),
+            })
+    }
+
+    fn migration_record(&self, migration: &Migration) -> AppResult<D1PreparedStatement> {
+        self.db
+            .prepare(
+                "INSERT INTO schema_migrations (version, name, applied_at)
+                 VALUES (?1, ?2, ?3)",
+            )
+            .bind(&[
+                int_param(migration.version),
+                JsValue::from_str(migration.name),
+                JsValue::from_str(&Utc::now().to_rfc3339()),
+            ])
+            .map_err(map_d1_error("bind record schema migration"))
+    }
+
+    /// Highest recorded migration version, and whether the `uploads` table
+    /// exists. Creates `schema_migrations` on first use.
+    async fn schema_state(&self) -> AppResult<(Option<u32>, bool)> {
+        self.db
+            .prepare(
+                "CREATE TABLE IF NOT EXISTS schema_migrations (
+                    version INTEGER PRIMARY KEY,
+                    name TEXT NOT NULL,
+                    applied_at TEXT NOT NULL
+                )",
+            )
+            .run()
+            .await
+            .map_err(map_d1_error("create schema_migrations"))?;
+
+        let row = self
+            .db
+            .prepare(
+                "SELECT
+                    (SELECT MAX(version) FROM schema_migrations) AS version,
+                    (SELECT COUNT(*) FROM sqlite_master
+                     WHERE type = 'table' AND name = 'uploads') AS upload_tables",
+            )
+            .first::<SchemaStateRow>(None)
+            .await
+            .map_err(map_d1_error("read schema version"))?;
+
+        let Some(row) = row else {
+            return Ok((None, false));
+        };
+        let version = row
+            .version
+            .map(|version| version.get("version"))
+            .transpose()?;
+
+        Ok((version, row.upload_tables.0 > 0))
     }
 
     /// Queries all chunks for an upload, ordered by index.
     async fn fetch_chunks(&self, upload_id: &str) -> AppResult<Vec<UploadChunkRecord>> {
         let statement = self.db.prepare(
-            "SELECT chunk_index, chunk_size, etag
+            "SELECT chunk_index, CAST(chunk_size AS TEXT) AS chunk_size, etag, nonce,
+                    CAST(stored_size AS TEXT) AS stored_size
              FROM upload_chunks
              WHERE upload_id = ?1
              ORDER BY chunk_index ASC",
@@ -215,14 +1437,75 @@ impl DatabaseService {
             .results()
             .map_err(map_d1_error("deserialize chunks"))?;
 
-        Ok(rows
-            .into_iter()
-            .map(|row| UploadChunkRecord {
-                chunk_index: row.chunk_index as u16,
-                chunk_size: row.chunk_size as u64,
-                etag: row.etag,
+        rows.into_iter()
+            .map(|row| {
+                Ok(UploadChunkRecord {
+                    chunk_index: row.chunk_index.get("chunk_index")?,
+                    chunk_size: row.chunk_size.get("chunk_size")?,
+                    etag: row.etag,
+                    nonce: row.nonce,
+                    stored_size: row
+                        .stored_size
+                        .map(|size| size.get("stored_size"))
+                        .transpose()?,
+                })
             })
-            .collect())
+            .collect()
+    }
+}
+
+/// Result of the schema version probe.
+#[derive(Debug, Deserialize)]
+struct SchemaStateRow {
+    version: Option<SqlInt>,
+    upload_tables: SqlInt,
+}
+
+/// Columns of an [`UploadMetadata`], deserialized as [`UploadRow`].
+const UPLOAD_SELECT: &str = "SELECT upload_id, file_name, CAST(total_size AS TEXT) AS total_size,
+        content_type, user_id, user_role, r2_key, r2_upload_id, bucket, status, created_at,
+        updated_at, media_info, metadata_stripped, logical_key, encryption, wrapped_key,
+        retain_until, legal_hold, completion_claimed_at
+ FROM uploads";
+
+/// Columns of a [`FileVersion`], joined with its upload for the file name.
+const VERSION_SELECT: &str = "SELECT CAST(v.version AS TEXT) AS version, v.upload_id, u.file_name,
+        v.r2_key, CAST(v.size AS TEXT) AS size, v.content_type,
+        CAST(v.is_current AS TEXT) AS is_current, v.created_at
+ FROM file_versions v
+ JOIN uploads u ON u.upload_id = v.upload_id";
+
+/// Raw row deserialized from [`VERSION_SELECT`].
+#[derive(Debug, Deserialize)]
+struct VersionRow {
+    version: SqlInt,
+    upload_id: String,
+    file_name: String,
+    r2_key: String,
+    size: SqlInt,
+    content_type: String,
+    is_current: SqlInt,
+    created_at: String,
+}
+
+impl VersionRow {
+    fn try_into_version(self) -> AppResult<FileVersion> {
+        let created_at = DateTime::parse_from_rfc3339(&self.created_at)
+            .map_err(|err| AppError::DatabaseError {
+                message: format!("Invalid version created_at timestamp: {err}"),
+            })?
+            .with_timezone(&Utc);
+
+        Ok(FileVersion {
+            version: self.version.get("version")?,
+            upload_id: self.upload_id,
+            file_name: self.file_name,
+            r2_key: self.r2_key,
+            size: self.size.get("size")?,
+            content_type: self.content_type,
+            is_current: self.is_current.0 != 0,
+            created_at,
+        })
     }
 }
 
@@ -231,23 +1514,110 @@ impl DatabaseService {
 struct UploadRow {
     upload_id: String,
     file_name: String,
-    total_size: f64,
+    total_size: SqlInt,
     content_type: String,
     user_id: String,
     user_role: String,
     r2_key: String,
     r2_upload_id: String,
+    bucket: String,
     status: String,
     created_at: String,
     updated_at: String,
+    #[serde(default)]
+    media_info: Option<String>,
+    #[serde(default)]
+    metadata_stripped: Option<SqlInt>,
+    #[serde(default)]
+    logical_key: Option<String>,
+    #[serde(default)]
+    encryption: Option<String>,
+    #[serde(default)]
+    wrapped_key: Option<String>,
+    #[serde(default)]
+    retain_until: Option<String>,
+    #[serde(default)]
+    legal_hold: Option<SqlInt>,
+    #[serde(default)]
+    completion_claimed_at: Option<String>,
 }
 
 /// Raw row deserialized from the D1 `upload_chunks` table.
 #[derive(Debug, Deserialize)]
 struct ChunkRow {
-    chunk_index: f64,
-    chunk_size: f64,
+    chunk_index: SqlInt,
+    chunk_size: SqlInt,
     etag: Option<String>,
+    #[serde(default)]
+    nonce: Option<String>,
+    #[serde(default)]
+    stored_size: Option<SqlInt>,
+}
+
+/// Raw row from the active upload listing; `active_count` is the window total.
+#[derive(Debug, Deserialize)]
+struct ActiveUploadRow {
+    upload_id: String,
+    active_count: SqlInt,
+}
+
+/// Raw row deserialized from the D1 `idempotency_keys` table.
+#[derive(Debug, Deserialize)]
+struct IdempotencyRow {
+    request_hash: String,
+    status_code: Option<SqlInt>,
+    response_body: Option<String>,
+}
+
+/// Raw row deserialized from the D1 `audit_log` table.
+#[derive(Debug, Deserialize)]
+struct AuditRow {
+    audit_id: SqlInt,
+    action: String,
+    upload_id: Option<String>,
+    actor: Option<String>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+    request_id: String,
+    created_at: String,
+}
+
+impl AuditRow {
+    fn try_into_entry(self) -> AppResult<AuditEntry> {
+        let action = self
+            .action
+            .parse::<AuditAction>()
+            .map_err(|err| AppError::DatabaseError {
+                message: format!("Invalid audit action in database: {err}"),
+            })?;
+        let created_at = DateTime::parse_from_rfc3339(&self.created_at)
+            .map_err(|err| AppError::DatabaseError {
+                message: format!("Invalid audit created_at timestamp: {err}"),
+            })?
+            .with_timezone(&Utc);
+
+        Ok(AuditEntry {
+            audit_id: self.audit_id.get("audit_id")?,
+            action,
+            upload_id: self.upload_id,
+            actor: self.actor,
+            ip: self.ip,
+            user_agent: self.user_agent,
+            request_id: self.request_id,
+            created_at,
+        })
+    }
+}
+
+/// Raw row deserialized from the D1 `upload_variants` table.
+#[derive(Debug, Deserialize)]
+struct VariantRow {
+    variant_name: String,
+    r2_key: String,
+    content_type: String,
+    width: SqlInt,
+    height: SqlInt,
+    size: SqlInt,
 }
 
 impl UploadRow {
@@ -278,12 +1648,51 @@ impl UploadRow {
                     message: format!("Invalid upload status in database: {err}"),
                 })?;
 
+        let media_info = self
+            .media_info
+            .as_deref()
+            .map(serde_json::from_str::<MediaInfo>)
+            .transpose()
+            .map_err(|err| AppError::DatabaseError {
+                message: format!("Invalid media_info JSON in database: {err}"),
+            })?;
+
+        let encryption = self
+            .encryption
+            .as_deref()
+            .map(str::parse::<EncryptionMode>)
+            .transpose()
+            .map_err(|err| AppError::DatabaseError {
+                message: format!("Invalid encryption in database: {err}"),
+            })?
+            .unwrap_or_default();
+
+        let retain_until = self
+            .retain_until
+            .as_deref()
+            .map(DateTime::parse_from_rfc3339)
+            .transpose()
+            .map_err(|err| AppError::DatabaseError {
+                message: format!("Invalid retain_until timestamp: {err}"),
+            })?
+            .map(|until| until.with_timezone(&Utc));
+
+        let completion_claimed_at = self
+            .completion_claimed_at
+            .as_deref()
+            .map(DateTime::parse_from_rfc3339)
+            .transpose()
+            .map_err(|err| AppError::DatabaseError {
+                message: format!("Invalid completion_claimed_at timestamp: {err}"),
+            })?
+            .map(|claimed_at| claimed_at.with_timezone(&Utc));
+
         let chunk_indices = chunks.iter().map(|chunk| chunk.chunk_index).collect();
 
         Ok(UploadMetadata {
             upload_id: self.upload_id,
             file_name: self.file_name,
-            total_size: self.total_size as u64,
+            total_size: self.total_size.get("total_size")?,
             created_at,
             updated_at,
             user_role,
@@ -291,15 +1700,171 @@ impl UploadRow {
             status,
             chunks: chunk_indices,
             r2_key: self.r2_key,
+            bucket: self.bucket,
             user_id: self.user_id,
             r2_upload_id: self.r2_upload_id,
+            media_info,
+            metadata_stripped: self.metadata_stripped.is_some_and(|flag| flag.0 != 0),
+            logical_key: self.logical_key,
+            encryption,
+            wrapped_key: self.wrapped_key,
+            retain_until,
+            legal_hold: self.legal_hold.is_some_and(|flag| flag.0 != 0),
+            completion_claimed_at,
         })
     }
 }
 
+/// Binds an integer parameter exactly.
+///
+/// D1 turns JS numbers into doubles and does not accept `BigInt`, so integers
+/// are sent as decimal text. SQLite converts the text back when it is stored
+/// in or compared with an INTEGER column; anywhere else (such as `LIMIT`) the
+/// placeholder must be wrapped in `CAST(?N AS INTEGER)`.
+fn int_param(value: impl Into<i64>) -> JsValue {
+    JsValue::from_str(&value.into().to_string())
+}
+
+/// [`int_param`] for a `u64`, which must fit SQLite's signed 64-bit INTEGER.
+fn uint_param(value: u64, column: &'static str) -> AppResult<JsValue> {
+    checked_i64(value, column).map(int_param)
+}
+
+fn checked_i64(value: u64, column: &'static str) -> AppResult<i64> {
+    i64::try_from(value).map_err(|_| AppError::DatabaseError {
+        message: format!("{column} value {value} exceeds the SQLite INTEGER range"),
+    })
+}
+
+/// An INTEGER column value, decoded without going through `f64`.
+///
+/// Accepts decimal text, which is how queries should select columns that can
+/// exceed 2^53 (`CAST(column AS TEXT) AS column`), and JS numbers that are
+/// whole and within ±(2^53 − 1). Anything else fails to deserialize instead of
+/// being rounded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct SqlInt(i64);
+
+impl SqlInt {
+    /// Converts to the field's type, failing when the value does not fit.
+    fn get<T: TryFrom<i64>>(self, column: &'static str) -> AppResult<T> {
+        T::try_from(self.0).map_err(|_| AppError::DatabaseError {
+            message: format!("{column} value {} is out of range", self.0),
+        })
+    }
+}
+
+impl<'de> Deserialize<'de> for SqlInt {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SqlIntVisitor;
+
+        impl de::Visitor<'_> for SqlIntVisitor {
+            type Value = SqlInt;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("an integer or a decimal integer string")
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<SqlInt, E> {
+                Ok(SqlInt(value))
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<SqlInt, E> {
+                i64::try_from(value)
+                    .map(SqlInt)
+                    .map_err(|_| E::custom(format!("integer {value} exceeds i64")))
+            }
+
+            fn visit_f64<E: de::Error>(self, value: f64) -> Result<SqlInt, E> {
+                if value.fract() == 0.0 && value.abs() <= MAX_SAFE_INTEGER {
+                    Ok(SqlInt(value as i64))
+                } else {
+                    Err(E::custom(format!(
+                        "number {value} is not an exactly representable integer; \
+                         select the column as text"
+                    )))
+                }
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<SqlInt, E> {
+                value
+                    .parse::<i64>()
+                    .map(SqlInt)
+                    .map_err(|_| E::custom(format!("invalid integer text {value:?}")))
+            }
+        }
+
+        deserializer.deserialize_any(SqlIntVisitor)
+    }
+}
+
+/// Largest integer a JS number holds exactly (2^53 − 1).
+const MAX_SAFE_INTEGER: f64 = 9_007_199_254_740_991.0;
+
 /// Returns a closure mapping `worker::Error` to `AppError::DatabaseError` tagged with the operation name.
 fn map_d1_error(operation: &'static str) -> impl Fn(worker::Error) -> AppError {
     move |err| AppError::DatabaseError {
         message: format!("{operation} failed: {err}"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(value: serde_json::Value) -> Result<SqlInt, serde_json::Error> {
+        serde_json::from_value(value)
+    }
+
+    #[test]
+    fn sql_int_decodes_text_beyond_f64_precision() {
+        let value = decode(serde_json::json!("9007199254740993")).unwrap();
+        assert_eq!(
+            value.get::<u64>("total_size").unwrap(),
+            9_007_199_254_740_993
+        );
+        assert_eq!(
+            decode(serde_json::json!("9223372036854775807")).unwrap(),
+            SqlInt(i64::MAX)
+        );
+    }
+
+    #[test]
+    fn sql_int_accepts_exact_numbers() {
+        assert_eq!(decode(serde_json::json!(42)).unwrap(), SqlInt(42));
+        assert_eq!(decode(serde_json::json!(-7)).unwrap(), SqlInt(-7));
+        assert_eq!(decode(serde_json::json!(1024.0)).unwrap(), SqlInt(1024));
+    }
+
+    #[test]
+    fn sql_int_rejects_lossy_values() {
+        for value in [
+            serde_json::json!(1.5),
+            serde_json::json!(9007199254740992.0),
+            serde_json::json!(u64::MAX),
+            serde_json::json!("12abc"),
+            serde_json::json!("1e3"),
+        ] {
+            assert!(decode(value.clone()).is_err(), "{value}");
+        }
+    }
+
+    #[test]
+    fn sql_int_get_checks_the_target_range() {
+        assert_eq!(SqlInt(65_535).get::<u16>("chunk_index").unwrap(), 65_535);
+        assert!(matches!(
+            SqlInt(65_536).get::<u16>("chunk_index"),
+            Err(AppError::DatabaseError { .. })
+        ));
+        assert!(SqlInt(-1).get::<u64>("chunk_size").is_err());
+    }
+
+    #[test]
+    fn checked_i64_rejects_values_above_sqlite_range() {
+        assert_eq!(checked_i64(i64::MAX as u64, "size").unwrap(), i64::MAX);
+        assert!(matches!(
+            checked_i64(i64::MAX as u64 + 1, "size"),
+            Err(AppError::DatabaseError { .. })
+        ));
+    }
+}
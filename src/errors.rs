@@ -14,9 +14,11 @@
 //!
 //! ## Error Categories
 //!
-//! - **Client Errors (4xx)**: Missing fields, invalid input, file size limits
+//! - **Client Errors (4xx)**: Missing fields, invalid input, admin credentials, encryption
+//!   keys, retention holds, file size limits, unsatisfiable ranges, rate limits
 //! - **Server Errors (500)**: Database failures and internal errors
 //! - **Upstream Errors (502)**: External service failures (R2)
+//! - **Unavailable (503)**: Database schema out of step with the deployed code
 //!
 //! ## Example Error Response
 //!
@@ -25,11 +27,13 @@
 //!   "error": {
 //!     "code": "FILE_TOO_LARGE",
 //!     "message": "File size 11000000000 exceeds maximum allowed 10737418240",
-//!     "timestamp": "2024-01-15T10:30:00Z"
+//!     "timestamp": "2024-01-15T10:30:00Z",
+//!     "request_id": "3f0c0d6e-7d8f-4b59-9a53-0a5d3c1b2e4f"
 //!   }
 //! }
 //! ```
 
+use chrono::{DateTime, Utc};
 use serde_json::json;
 use thiserror::Error;
 use worker::{Response, Result};
@@ -87,6 +91,24 @@ pub enum AppError {
         upload_id: String,
     },
 
+    /// The requested version of a logical key does not exist.
+    #[error("Version {version} of {logical_key} not found")]
+    VersionNotFound {
+        /// Logical key the version was looked up under
+        logical_key: String,
+        /// Requested version number, or `current`
+        version: String,
+    },
+
+    /// The upload has no generated variant with the requested name.
+    #[error("Variant {variant} of upload {upload_id} not found")]
+    VariantNotFound {
+        /// Upload the variant was looked up under
+        upload_id: String,
+        /// Requested variant name
+        variant: String,
+    },
+
     /// Attempt to modify an upload that has already been completed.
     #[error("Upload already completed: {upload_id}")]
     UploadAlreadyCompleted {
@@ -101,6 +123,48 @@ pub enum AppError {
         upload_id: String,
     },
 
+    /// The upload is in a state that does not allow the requested operation,
+    /// typically because a concurrent request changed it first.
+    #[error("Upload {upload_id} is {status}")]
+    UploadStateConflict {
+        /// Upload identifier
+        upload_id: String,
+        /// Current upload status
+        status: String,
+    },
+
+    /// The upload's R2 key is already taken and the collision strategy is `reject`.
+    #[error("R2 key already in use: {r2_key}")]
+    R2KeyConflict {
+        /// The key another upload or object already holds
+        r2_key: String,
+    },
+
+    /// A retention period or legal hold protects the upload from being deleted.
+    #[error("Upload {upload_id} is under a retention hold")]
+    UploadRetained {
+        /// Upload the hold protects
+        upload_id: String,
+        /// Whether a legal hold is set
+        legal_hold: bool,
+        /// End of the retention period, if one is set
+        retain_until: Option<DateTime<Utc>>,
+    },
+
+    /// The encryption key sent for an upload does not unwrap its data key.
+    #[error("Encryption key mismatch for upload {upload_id}")]
+    EncryptionKeyMismatch {
+        /// Upload whose key was rejected
+        upload_id: String,
+    },
+
+    /// A `Range` header asks for bytes outside the object.
+    #[error("Range not satisfiable for object of {size} bytes")]
+    RangeNotSatisfiable {
+        /// Object size in bytes, sent as `Content-Range: bytes */{size}`
+        size: u64,
+    },
+
     /// Chunk index is invalid or out of sequence.
     #[error("Invalid chunk index: {index}")]
     InvalidChunkIndex {
@@ -108,6 +172,63 @@ pub enum AppError {
         index: u16,
     },
 
+    /// The user already has the maximum number of uploads in flight.
+    #[error("Too many active uploads: {active} of {limit}")]
+    TooManyActiveUploads {
+        /// Uploads currently `initiated` or `in_progress`
+        active: u64,
+        /// Configured cap for the user's role
+        limit: u32,
+        /// Oldest active upload IDs, so the client can resume or cancel them
+        oldest_upload_ids: Vec<String>,
+    },
+
+    /// An `Idempotency-Key` was reused with a different request body.
+    #[error("Idempotency key reused with a different payload: {key}")]
+    IdempotencyKeyMismatch {
+        /// The reused key
+        key: String,
+    },
+
+    /// The original request for an `Idempotency-Key` is still being processed.
+    #[error("Idempotency key in use: {key}")]
+    IdempotencyKeyInUse {
+        /// The key whose request has not finished
+        key: String,
+    },
+
+    /// Too many requests for a route from one client IP or user.
+    #[error("Rate limit exceeded for {scope}, retry after {retry_after_secs}s")]
+    RateLimited {
+        /// Which bucket ran out: `ip` or `user`
+        scope: String,
+        /// Seconds until a request will be accepted, sent as `Retry-After`
+        retry_after_secs: u64,
+    },
+
+    /// Missing or invalid credentials for an admin endpoint.
+    #[error("Unauthorized: {message}")]
+    Unauthorized {
+        /// Why the request was rejected
+        message: String,
+    },
+
+    /// A configuration update was based on a version that is no longer stored.
+    #[error("Configuration version conflict: expected {expected}, stored {current}")]
+    ConfigVersionConflict {
+        /// `config_version` the update was written against
+        expected: u64,
+        /// `config_version` currently stored in KV
+        current: u64,
+    },
+
+    /// The D1 schema does not match the migrations this build was compiled with.
+    #[error("Schema mismatch: {message}")]
+    SchemaMismatch {
+        /// What differs and how to resolve it
+        message: String,
+    },
+
     /// R2 storage operation failure.
     #[error("R2 storage error: {message}")]
     R2Error {
@@ -135,8 +256,8 @@ impl AppError {
     ///
     /// This method maps each error variant to an appropriate HTTP status code
     /// and creates a structured JSON response with error details. The response
-    /// includes a machine-readable error code, human-readable message, and
-    /// timestamp for debugging purposes.
+    /// includes a machine-readable error code, human-readable message,
+    /// timestamp, and the request ID so clients can quote it in bug reports.
     ///
     /// # Returns
     ///
@@ -149,7 +270,8 @@ impl AppError {
     ///   "error": {
     ///     "code": "ERROR_CODE",
     ///     "message": "Human-readable error description",
-    ///     "timestamp": "2024-01-15T10:30:00Z"
+    ///     "timestamp": "2024-01-15T10:30:00Z",
+    ///     "request_id": "3f0c0d6e-7d8f-4b59-9a53-0a5d3c1b2e4f"
     ///   }
     /// }
     /// ```
@@ -157,23 +279,49 @@ impl AppError {
     /// # Status Code Mapping
     ///
     /// - **400**: Client errors (missing/invalid fields, invalid chunk index, validation)
+    /// - **401**: Missing or invalid admin credentials
+    /// - **403**: Encryption key does not match the upload
     /// - **404**: Resource not found (upload not found)
-    /// - **409**: Conflict errors (upload already completed/cancelled)
+    /// - **409**: Conflict errors (upload already completed/cancelled/completing, R2 key taken, retention
+    ///   hold, too many active uploads, idempotency key reuse, stale configuration version)
     /// - **413**: Payload too large (file size exceeded)
+    /// - **416**: Range not satisfiable (with `Content-Range: bytes */{size}`)
+    /// - **429**: Too many requests (rate limited, with `Retry-After`)
     /// - **500**: Internal server errors (database, internal)
     /// - **502**: Upstream service errors (R2)
-    pub fn to_response(&self) -> Result<Response> {
+    /// - **503**: Database schema does not match this build
+    pub fn to_response(&self, request_id: &str) -> Result<Response> {
         let (status, error_code, message) = self.response_parts();
 
         let error_response = json!({
             "error": {
                 "code": error_code,
                 "message": message,
-                "timestamp": chrono::Utc::now().to_rfc3339()
+                "timestamp": Utc::now().to_rfc3339(),
+                "request_id": request_id
             }
         });
 
-        Ok(Response::from_json(&error_response)?.with_status(status))
+        let mut response = Response::from_json(&error_response)?.with_status(status);
+        if let AppError::RateLimited {
+            retry_after_secs, ..
+        } = self
+        {
+            response
+                .headers_mut()
+                .set("Retry-After", &retry_after_secs.to_string())?;
+        }
+        if let AppError::RangeNotSatisfiable { size } = self {
+            response
+                .headers_mut()
+                .set("Content-Range", &format!("bytes */{size}"))?;
+        }
+        Ok(response)
+    }
+
+    /// Machine-readable error code, as returned in the response body.
+    pub fn code(&self) -> &'static str {
+        self.response_parts().1
     }
 
     fn response_parts(&self) -> (u16, &'static str, String) {
@@ -199,6 +347,19 @@ impl AppError {
                 "UPLOAD_NOT_FOUND",
                 format!("Upload not found: {}", upload_id),
             ),
+            AppError::VersionNotFound {
+                logical_key,
+                version,
+            } => (
+                404,
+                "VERSION_NOT_FOUND",
+                format!("Version {} of '{}' not found", version, logical_key),
+            ),
+            AppError::VariantNotFound { upload_id, variant } => (
+                404,
+                "VARIANT_NOT_FOUND",
+                format!("Variant '{}' of upload {} not found", variant, upload_id),
+            ),
             AppError::UploadAlreadyCompleted { upload_id } => (
                 409,
                 "UPLOAD_COMPLETED",
@@ -209,11 +370,105 @@ impl AppError {
                 "UPLOAD_CANCELLED",
                 format!("Upload cancelled: {}", upload_id),
             ),
+            AppError::UploadStateConflict { upload_id, status } => (
+                409,
+                "UPLOAD_STATE_CONFLICT",
+                format!("Upload {} is {}", upload_id, status),
+            ),
+            AppError::R2KeyConflict { r2_key } => (
+                409,
+                "R2_KEY_CONFLICT",
+                format!(
+                    "An object already exists or is being uploaded at '{}'",
+                    r2_key
+                ),
+            ),
+            AppError::UploadRetained {
+                upload_id,
+                legal_hold,
+                retain_until,
+            } => (
+                409,
+                "UPLOAD_RETAINED",
+                match (legal_hold, retain_until) {
+                    (true, _) => format!("Upload {} is under legal hold", upload_id),
+                    (false, Some(until)) => format!(
+                        "Upload {} is retained until {}",
+                        upload_id,
+                        until.to_rfc3339()
+                    ),
+                    (false, None) => format!("Upload {} is under a retention hold", upload_id),
+                },
+            ),
+            AppError::EncryptionKeyMismatch { upload_id } => (
+                403,
+                "ENCRYPTION_KEY_MISMATCH",
+                format!(
+                    "The encryption key does not match the one upload {} was stored with",
+                    upload_id
+                ),
+            ),
+            AppError::RangeNotSatisfiable { size } => (
+                416,
+                "RANGE_NOT_SATISFIABLE",
+                format!("Requested range lies outside the {} byte object", size),
+            ),
             AppError::InvalidChunkIndex { index } => (
                 400,
                 "INVALID_CHUNK_INDEX",
                 format!("Invalid chunk index: {}", index),
             ),
+            AppError::TooManyActiveUploads {
+                active,
+                limit,
+                oldest_upload_ids,
+            } => (
+                409,
+                "TOO_MANY_ACTIVE_UPLOADS",
+                format!(
+                    "{} active uploads reached the limit of {}; resume or cancel the oldest: {}",
+                    active,
+                    limit,
+                    oldest_upload_ids.join(", ")
+                ),
+            ),
+            AppError::IdempotencyKeyMismatch { key } => (
+                409,
+                "IDEMPOTENCY_KEY_MISMATCH",
+                format!(
+                    "Idempotency key '{}' was already used with a different request",
+                    key
+                ),
+            ),
+            AppError::IdempotencyKeyInUse { key } => (
+                409,
+                "IDEMPOTENCY_KEY_IN_USE",
+                format!(
+                    "A request with idempotency key '{}' is still in progress",
+                    key
+                ),
+            ),
+            AppError::RateLimited {
+                scope,
+                retry_after_secs,
+            } => (
+                429,
+                "RATE_LIMITED",
+                format!(
+                    "Rate limit exceeded for {}, retry after {} seconds",
+                    scope, retry_after_secs
+                ),
+            ),
+            AppError::Unauthorized { message } => (401, "UNAUTHORIZED", message.clone()),
+            AppError::ConfigVersionConflict { expected, current } => (
+                409,
+                "CONFIG_VERSION_CONFLICT",
+                format!(
+                    "Configuration is at version {}, not {}; re-read it and retry",
+                    current, expected
+                ),
+            ),
+            AppError::SchemaMismatch { message } => (503, "SCHEMA_MISMATCH", message.clone()),
             AppError::R2Error { message } => {
                 (502, "R2_ERROR", format!("Storage error: {}", message))
             }
@@ -266,4 +521,39 @@ mod tests {
         assert_eq!(code, "FILE_TOO_LARGE");
         assert!(message.contains("20"));
     }
+
+    #[test]
+    fn upload_retained_names_the_hold() {
+        let until = DateTime::parse_from_rfc3339("2030-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let retained = |legal_hold| AppError::UploadRetained {
+            upload_id: "u1".into(),
+            legal_hold,
+            retain_until: Some(until),
+        };
+
+        let (status, code, message) = retained(false).response_parts();
+        assert_eq!(status, 409);
+        assert_eq!(code, "UPLOAD_RETAINED");
+        assert_eq!(
+            message,
+            "Upload u1 is retained until 2030-01-01T00:00:00+00:00"
+        );
+        let (_, _, message) = retained(true).response_parts();
+        assert_eq!(message, "Upload u1 is under legal hold");
+    }
+
+    #[test]
+    fn rate_limited_converts_to_429_response() {
+        let error = AppError::RateLimited {
+            scope: "user".into(),
+            retry_after_secs: 30,
+        };
+
+        let (status, code, message) = error.response_parts();
+        assert_eq!(status, 429);
+        assert_eq!(code, "RATE_LIMITED");
+        assert!(message.contains("30 seconds"));
+    }
 }
@@ -0,0 +1,70 @@
+//! # Upload Expiry
+//!
+//! An hourly Cron Trigger expires abandoned uploads: `initiated` and
+//! `in_progress` uploads with no activity for [`UPLOAD_EXPIRY_SECS`] (seven
+//! days, R2's default lifetime for unfinished multipart uploads) move to
+//! `expired`, which frees their slot under `max_active_uploads`, and their
+//! multipart sessions are aborted. Each run expires at most
+//! [`EXPIRY_BATCH_SIZE`] uploads, least recently updated first; the rest wait
+//! for the next run.
+//!
+//! The status change is conditional on the upload still being idle, so a
+//! chunk that arrives during the sweep keeps its upload open. Aborting is
+//! best-effort, as for cancellation: R2 drops the session itself once its
+//! lifetime runs out.
+
+use chrono::{Duration, Utc};
+use serde_json::json;
+use worker::Env;
+
+use crate::config::Config;
+use crate::constants::{EXPIRY_BATCH_SIZE, UPLOAD_EXPIRY_SECS};
+use crate::database::DatabaseService;
+use crate::errors::{AppError, AppResult};
+use crate::logging::{log, LogLevel};
+use crate::migrations::ensure_current;
+use crate::models::{UploadEvent, UploadMetadata};
+
+/// Expires one batch of abandoned uploads, returning how many were expired.
+pub async fn expire_abandoned_uploads(env: &Env, config: &Config) -> AppResult<u32> {
+    ensure_current(env, config).await?;
+
+    let database = DatabaseService::new(env, &config.database_name)?;
+    let idle_before = Utc::now() - Duration::seconds(UPLOAD_EXPIRY_SECS);
+    let uploads = database
+        .list_idle_uploads(idle_before, EXPIRY_BATCH_SIZE)
+        .await?;
+
+    let mut expired = 0;
+    for upload in &uploads {
+        if !database
+            .apply_event(&upload.upload_id, UploadEvent::Expired, &upload.user_id)
+            .await?
+        {
+            continue;
+        }
+        expired += 1;
+
+        if let Err(err) = abort_session(env, upload).await {
+            log(
+                LogLevel::Warn,
+                &format!("failed to abort expired multipart upload: {err}"),
+                json!({ "upload_id": upload.upload_id }),
+            );
+        }
+    }
+    Ok(expired)
+}
+
+async fn abort_session(env: &Env, upload: &UploadMetadata) -> AppResult<()> {
+    let r2_error = |err: worker::Error| AppError::R2Error {
+        message: err.to_string(),
+    };
+    env.bucket(&upload.bucket)
+        .map_err(r2_error)?
+        .resume_multipart_upload(upload.r2_key.clone(), upload.r2_upload_id.clone())
+        .map_err(r2_error)?
+        .abort()
+        .await
+        .map_err(r2_error)
+}
@@ -0,0 +1,712 @@
+//! # Admin Handlers
+//!
+//! Operator endpoints under `/api/admin`. The dispatcher authorizes every
+//! request with [`AdminAuthMiddleware`](crate::middleware::AdminAuthMiddleware)
+//! before these handlers run.
+//!
+//! Retention holds are set and cleared here: `PUT` and `DELETE` on
+//! `/api/admin/uploads/{id}/retention` and `/api/admin/uploads/{id}/legal-hold`.
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use worker::kv::KvStore;
+use worker::*;
+
+use crate::config::{self, Config, ConfigSources, LoadedConfig};
+use crate::constants::{
+    CONFIG_BACKUP_KV_PREFIX, CONFIG_KV_KEY, DEFAULT_AUDIT_PAGE_SIZE, MAX_AUDIT_PAGE_SIZE,
+    STORAGE_CONFIG_KV_NAME,
+};
+use crate::database::{AuditQuery, ChunkList, DatabaseService, HoldUpdate};
+use crate::errors::{AppError, AppResult};
+use crate::logging::{LogLevel, RequestContext};
+
+/// List audit log entries, oldest first.
+///
+/// Query parameters: `upload_id`, `user_id`, `since` (RFC 3339), `limit`
+/// (1–1000, default 100) and `cursor` (the previous page's `next_cursor`).
+pub async fn list_audit_log(
+    req: Request,
+    env: &Env,
+    config: &Config,
+    _ctx: &RequestContext,
+) -> AppResult<Response> {
+    let url = req.url().map_err(|err| AppError::InternalError {
+        message: format!("Failed to parse request URL: {err}"),
+    })?;
+    let query = parse_audit_query(url.query_pairs())?;
+
+    let database = DatabaseService::new(env, &config.database_name)?;
+    let entries = database.list_audit(&query).await?;
+
+    // A full page may have more entries behind it; a short page is the last one.
+    let next_cursor = if entries.len() == query.limit as usize {
+        entries.last().map(|entry| entry.audit_id.to_string())
+    } else {
+        None
+    };
+
+    let body = serde_json::json!({
+        "entries": entries,
+        "next_cursor": next_cursor,
+    });
+
+    Response::from_json(&body).map_err(|_| AppError::InternalError {
+        message: "Failed to serialize audit log response".to_string(),
+    })
+}
+
+/// Report the applied schema version and pending migrations.
+pub async fn migration_status(
+    _req: Request,
+    env: &Env,
+    config: &Config,
+    _ctx: &RequestContext,
+) -> AppResult<Response> {
+    let database = DatabaseService::new(env, &config.database_name)?;
+    let status = database.migration_status().await?;
+
+    Response::from_json(&status).map_err(|_| AppError::InternalError {
+        message: "Failed to serialize migration status".to_string(),
+    })
+}
+
+/// Apply pending schema migrations.
+///
+/// `?baseline=N` first records migrations up to version `N` as applied without
+/// running them, for a database created by hand before migrations were tracked.
+pub async fn run_migrations(
+    req: Request,
+    env: &Env,
+    config: &Config,
+    ctx: &RequestContext,
+) -> AppResult<Response> {
+    let url = req.url().map_err(|err| AppError::InternalError {
+        message: format!("Failed to parse request URL: {err}"),
+    })?;
+    let baseline = parse_baseline(url.query_pairs())?;
+
+    let database = DatabaseService::new(env, &config.database_name)?;
+    let status = database.run_migrations(baseline).await?;
+    ctx.log(
+        LogLevel::Info,
+        &format!(
+            "schema migrations run: {} applied, now at version {:?}",
+            status.applied.len(),
+            status.database_version
+        ),
+    );
+
+    Response::from_json(&status).map_err(|_| AppError::InternalError {
+        message: "Failed to serialize migration status".to_string(),
+    })
+}
+
+/// The hold a `/api/admin/uploads/{id}/...` path addresses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hold {
+    /// `/retention`: the retention period ending at `retain_until`.
+    Retention,
+    /// `/legal-hold`: the legal hold.
+    Legal,
+}
+
+impl Hold {
+    /// Splits a hold path into the upload ID and the hold, or `None` for any
+    /// other path.
+    pub fn parse(path: &str) -> Option<(&str, Hold)> {
+        let (upload_id, hold) = path.strip_prefix("/api/admin/uploads/")?.split_once('/')?;
+        let hold = match hold {
+            "retention" => Hold::Retention,
+            "legal-hold" => Hold::Legal,
+            _ => return None,
+        };
+        (!upload_id.is_empty() && !upload_id.contains('/')).then_some((upload_id, hold))
+    }
+}
+
+/// Whether a hold request places or removes the hold.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HoldChange {
+    /// `PUT`: place the hold.
+    Set,
+    /// `DELETE`: remove the hold.
+    Clear,
+}
+
+/// Set or clear a completed upload's retention period or legal hold.
+///
+/// Setting a retention period takes `{"retain_until": "<RFC 3339>"}`, which
+/// must lie in the future and replaces any earlier one; the other requests
+/// take no body. Responds with the upload's holds as they now stand.
+pub async fn update_hold(
+    mut req: Request,
+    env: &Env,
+    config: &Config,
+    ctx: &RequestContext,
+    change: HoldChange,
+) -> AppResult<Response> {
+    let url = req.url().map_err(|err| AppError::InternalError {
+        message: format!("Failed to parse request URL: {err}"),
+    })?;
+    let (upload_id, hold) = Hold::parse(url.path()).ok_or_else(|| AppError::ValidationError {
+        message: "Upload ID missing from path".to_string(),
+    })?;
+    let upload_id = upload_id.to_string();
+    ctx.set_upload_id(&upload_id);
+
+    let now = Utc::now();
+    let update = match (hold, change) {
+        (Hold::Retention, HoldChange::Set) => {
+            let body: Value = req.json().await.map_err(|_| AppError::ValidationError {
+                message: "Invalid JSON payload".to_string(),
+            })?;
+            HoldUpdate::RetainUntil(Some(parse_retain_until(&body, now)?))
+        }
+        (Hold::Retention, HoldChange::Clear) => HoldUpdate::RetainUntil(None),
+        (Hold::Legal, change) => HoldUpdate::LegalHold(change == HoldChange::Set),
+    };
+
+    let database = DatabaseService::new(env, &config.database_name)?;
+    if !database.update_hold(&upload_id, update).await? {
+        return Err(match database.current_status(&upload_id).await? {
+            Some(status) => AppError::UploadStateConflict {
+                upload_id,
+                status: status.as_str().to_string(),
+            },
+            None => AppError::UploadNotFound { upload_id },
+        });
+    }
+    let Some(metadata) = database.get_upload(&upload_id, ChunkList::Skip).await? else {
+        return Err(AppError::UploadNotFound { upload_id });
+    };
+    ctx.set_user_id(&metadata.user_id);
+    ctx.log(
+        LogLevel::Info,
+        &format!("retention hold updated: {update:?}"),
+    );
+
+    let body = serde_json::json!({
+        "upload_id": metadata.upload_id,
+        "retain_until": metadata.retain_until.map(|until| until.to_rfc3339()),
+        "legal_hold": metadata.legal_hold,
+        "held": metadata.is_held(now),
+    });
+    Response::from_json(&body).map_err(|_| AppError::InternalError {
+        message: "Failed to serialize retention hold".to_string(),
+    })
+}
+
+/// Reads the `retain_until` timestamp of a set-retention body.
+fn parse_retain_until(body: &Value, now: DateTime<Utc>) -> AppResult<DateTime<Utc>> {
+    let value = body
+        .get("retain_until")
+        .ok_or_else(|| AppError::MissingField {
+            field: "retain_until".to_string(),
+        })?;
+    let until = value
+        .as_str()
+        .and_then(|value| DateTime::parse_from_rfc3339(value).ok())
+        .ok_or_else(|| invalid("retain_until", "Must be an RFC 3339 timestamp".to_string()))?
+        .with_timezone(&Utc);
+    if until <= now {
+        return Err(invalid("retain_until", "Must be in the future".to_string()));
+    }
+    Ok(until)
+}
+
+/// How an update combines the request body with the stored configuration.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConfigUpdate {
+    /// `PUT`: the body is the whole KV value; omitted fields take their defaults.
+    Replace,
+    /// `PATCH`: the body is a JSON Merge Patch applied to the stored value.
+    Merge,
+}
+
+/// Show the stored configuration, the values it resolves to once Worker vars
+/// and secrets are layered over it, and what the answering isolate is serving.
+///
+/// `config` is `null` and `problems` lists why when the result would be
+/// rejected. Secrets are redacted.
+pub async fn get_config(
+    _req: Request,
+    env: &Env,
+    _config: &Config,
+    _ctx: &RequestContext,
+) -> AppResult<Response> {
+    let kv = config_kv(env)?;
+    let sources = ConfigSources {
+        kv: read_stored_config(&kv).await?,
+        ..ConfigSources::overlays(env)
+    };
+    let stored = sources
+        .kv
+        .as_deref()
+        .map(serde_json::from_str::<Value>)
+        .transpose()
+        .map_err(|err| AppError::InternalError {
+            message: format!("Stored configuration is not valid JSON: {err}"),
+        })?;
+
+    let (effective, problems) = match LoadedConfig::resolve(&sources) {
+        Ok(loaded) => (Some(loaded.config), Vec::new()),
+        Err(problems) => (None, problems),
+    };
+
+    let body = serde_json::json!({
+        "stored": stored,
+        "config": effective.as_deref(),
+        "problems": problems,
+        "active": config::status(),
+    });
+    Response::from_json(&body).map_err(|_| AppError::InternalError {
+        message: "Failed to serialize configuration".to_string(),
+    })
+}
+
+/// Validate and store a new configuration.
+///
+/// The stored `config_version` is incremented and the previous value kept
+/// under `config:v<previous version>`. A body that carries `config_version`
+/// must match the stored one, so two operators editing the same version cannot
+/// silently overwrite each other; KV has no compare-and-swap, so this narrows
+/// the race rather than closing it. `?dry_run=true` validates and returns the
+/// result without writing.
+pub async fn update_config(
+    mut req: Request,
+    env: &Env,
+    _config: &Config,
+    ctx: &RequestContext,
+    mode: ConfigUpdate,
+) -> AppResult<Response> {
+    let url = req.url().map_err(|err| AppError::InternalError {
+        message: format!("Failed to parse request URL: {err}"),
+    })?;
+    let dry_run = parse_dry_run(url.query_pairs())?;
+    let body: Value = req.json().await.map_err(|_| AppError::ValidationError {
+        message: "Invalid JSON payload".to_string(),
+    })?;
+
+    let kv = config_kv(env)?;
+    let stored = read_stored_config(&kv).await?;
+    let update = next_config(stored.as_deref(), body, mode)?;
+    // The stored value must also survive the vars and secrets layered over it.
+    let sources = ConfigSources {
+        kv: Some(update.value.to_string()),
+        ..ConfigSources::overlays(env)
+    };
+    let layered = LoadedConfig::resolve(&sources).map_err(rejected)?;
+    let backup_key = stored
+        .as_ref()
+        .map(|_| format!("{CONFIG_BACKUP_KV_PREFIX}{}", update.previous_version));
+
+    if !dry_run {
+        if let (Some(previous), Some(backup_key)) = (&stored, &backup_key) {
+            write_kv(&kv, backup_key, previous).await?;
+        }
+        if let Some(json) = &sources.kv {
+            write_kv(&kv, CONFIG_KV_KEY, json).await?;
+        }
+        // Resolved above, so this cannot fail.
+        let _ = config::install(&sources);
+        ctx.log(
+            LogLevel::Info,
+            &format!(
+                "configuration updated from version {} to {}",
+                update.previous_version, update.config.config_version
+            ),
+        );
+    }
+
+    let body = serde_json::json!({
+        "dry_run": dry_run,
+        "config_version": update.config.config_version,
+        "previous_version": update.previous_version,
+        "backup_key": backup_key,
+        "config": *layered.config,
+    });
+    Response::from_json(&body).map_err(|_| AppError::InternalError {
+        message: "Failed to serialize configuration".to_string(),
+    })
+}
+
+/// The KV value an update would store and the configuration it resolves to.
+#[derive(Debug)]
+struct PendingConfig {
+    value: Value,
+    config: Config,
+    previous_version: u64,
+}
+
+/// Combines the stored KV value with an update body, checks the body's
+/// `config_version` against the stored one, bumps it, and validates the result.
+fn next_config(stored: Option<&str>, body: Value, mode: ConfigUpdate) -> AppResult<PendingConfig> {
+    if !body.is_object() {
+        return Err(AppError::ValidationError {
+            message: "Configuration must be a JSON object".to_string(),
+        });
+    }
+
+    let stored: Option<Value> = match stored.map(serde_json::from_str) {
+        None => None,
+        Some(Ok(value)) => Some(value),
+        // A broken value can still be replaced wholesale, but not patched.
+        Some(Err(_)) if mode == ConfigUpdate::Replace => None,
+        Some(Err(err)) => {
+            return Err(AppError::ValidationError {
+                message: format!(
+                    "Stored configuration is not valid JSON ({err}); replace it with PUT"
+                ),
+            })
+        }
+    };
+    let previous_version = stored
+        .as_ref()
+        .and_then(|value| value.get("config_version"))
+        .and_then(Value::as_u64)
+        .unwrap_or(0);
+
+    if let Some(expected) = body.get("config_version") {
+        let expected = expected.as_u64().ok_or_else(|| {
+            invalid(
+                "config_version",
+                "Must be a non-negative integer".to_string(),
+            )
+        })?;
+        if expected != previous_version {
+            return Err(AppError::ConfigVersionConflict {
+                expected,
+                current: previous_version,
+            });
+        }
+    }
+
+    let mut value = match mode {
+        ConfigUpdate::Replace => body,
+        ConfigUpdate::Merge => {
+            let mut value = stored.unwrap_or_else(|| Value::Object(Default::default()));
+            config::merge_patch(&mut value, body);
+            value
+        }
+    };
+    value["config_version"] = Value::from(previous_version + 1);
+
+    let config = Config::from_json(value.clone()).map_err(rejected)?;
+
+    Ok(PendingConfig {
+        value,
+        config,
+        previous_version,
+    })
+}
+
+fn rejected(problems: Vec<String>) -> AppError {
+    AppError::ValidationError {
+        message: format!("Configuration rejected: {}", problems.join("; ")),
+    }
+}
+
+fn config_kv(env: &Env) -> AppResult<KvStore> {
+    env.kv(STORAGE_CONFIG_KV_NAME)
+        .map_err(|err| AppError::InternalError {
+            message: format!("Configuration KV namespace unavailable: {err}"),
+        })
+}
+
+async fn read_stored_config(kv: &KvStore) -> AppResult<Option<String>> {
+    kv.get(CONFIG_KV_KEY)
+        .text()
+        .await
+        .map_err(|err| AppError::InternalError {
+            message: format!("Failed to read configuration from KV: {err}"),
+        })
+}
+
+async fn write_kv(kv: &KvStore, key: &str, value: &str) -> AppResult<()> {
+    let failed = |err: String| AppError::InternalError {
+        message: format!("Failed to write {key} to KV: {err}"),
+    };
+    kv.put(key, value)
+        .map_err(|err| failed(err.to_string()))?
+        .execute()
+        .await
+        .map_err(|err| failed(err.to_string()))
+}
+
+/// Reads the optional `dry_run` flag from the request's query string.
+fn parse_dry_run<'a>(
+    mut pairs: impl Iterator<Item = (std::borrow::Cow<'a, str>, std::borrow::Cow<'a, str>)>,
+) -> AppResult<bool> {
+    match pairs.find(|(name, _)| name == "dry_run") {
+        None => Ok(false),
+        Some((_, value)) => match value.as_ref() {
+            "" | "true" | "1" => Ok(true),
+            "false" | "0" => Ok(false),
+            _ => Err(invalid("dry_run", "Must be true or false".to_string())),
+        },
+    }
+}
+
+/// Reads the optional `baseline` version from the request's query string.
+fn parse_baseline<'a>(
+    mut pairs: impl Iterator<Item = (std::borrow::Cow<'a, str>, std::borrow::Cow<'a, str>)>,
+) -> AppResult<Option<u32>> {
+    pairs
+        .find(|(name, _)| name == "baseline")
+        .map(|(_, value)| {
+            value
+                .parse::<u32>()
+                .map_err(|_| invalid("baseline", "Must be a migration version".to_string()))
+        })
+        .transpose()
+}
+
+/// Builds an [`AuditQuery`] from the request's query string.
+fn parse_audit_query<'a>(
+    pairs: impl Iterator<Item = (std::borrow::Cow<'a, str>, std::borrow::Cow<'a, str>)>,
+) -> AppResult<AuditQuery> {
+    let mut query = AuditQuery {
+        limit: DEFAULT_AUDIT_PAGE_SIZE,
+        ..AuditQuery::default()
+    };
+
+    for (name, value) in pairs {
+        match name.as_ref() {
+            "upload_id" if !value.is_empty() => query.upload_id = Some(value.into_owned()),
+            "user_id" if !value.is_empty() => query.user_id = Some(value.into_owned()),
+            "since" if !value.is_empty() => {
+                let since = DateTime::parse_from_rfc3339(&value)
+                    .map_err(|_| invalid("since", "Must be an RFC 3339 timestamp".to_string()))?;
+                query.since = Some(since.with_timezone(&Utc));
+            }
+            "limit" => {
+                query.limit = value
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|limit| (1..=MAX_AUDIT_PAGE_SIZE).contains(limit))
+                    .ok_or_else(|| {
+                        invalid(
+                            "limit",
+                            format!("Must be an integer from 1 to {MAX_AUDIT_PAGE_SIZE}"),
+                        )
+                    })?;
+            }
+            "cursor" if !value.is_empty() => {
+                let cursor = value.parse::<u64>().map_err(|_| {
+                    invalid(
+                        "cursor",
+                        "Must be a cursor from a previous page".to_string(),
+                    )
+                })?;
+                query.after = Some(cursor);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(query)
+}
+
+fn invalid(field: &str, reason: String) -> AppError {
+    AppError::InvalidField {
+        field: field.to_string(),
+        reason,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(query: &str) -> AppResult<AuditQuery> {
+        let url = Url::parse(&format!("https://example.com/api/admin/audit?{query}")).unwrap();
+        parse_audit_query(url.query_pairs())
+    }
+
+    #[test]
+    fn parse_audit_query_defaults_to_first_page() {
+        assert_eq!(
+            parse("").unwrap(),
+            AuditQuery {
+                limit: DEFAULT_AUDIT_PAGE_SIZE,
+                ..AuditQuery::default()
+            }
+        );
+    }
+
+    #[test]
+    fn parse_audit_query_reads_filters_and_cursor() {
+        let query =
+            parse("upload_id=u1&user_id=alice&since=2025-01-02T03:04:05Z&limit=10&cursor=42")
+                .unwrap();
+        assert_eq!(query.upload_id.as_deref(), Some("u1"));
+        assert_eq!(query.user_id.as_deref(), Some("alice"));
+        assert_eq!(
+            query.since.unwrap().to_rfc3339(),
+            "2025-01-02T03:04:05+00:00"
+        );
+        assert_eq!(query.limit, 10);
+        assert_eq!(query.after, Some(42));
+    }
+
+    #[test]
+    fn parse_baseline_is_optional_and_numeric() {
+        let baseline = |query: &str| {
+            let url = Url::parse(&format!("https://example.com/?{query}")).unwrap();
+            parse_baseline(url.query_pairs())
+        };
+        assert_eq!(baseline("").unwrap(), None);
+        assert_eq!(baseline("baseline=4").unwrap(), Some(4));
+        assert!(matches!(
+            baseline("baseline=latest"),
+            Err(AppError::InvalidField { .. })
+        ));
+    }
+
+    #[test]
+    fn next_config_bumps_version_over_stored_value() {
+        let stored = r#"{"config_version":3,"max_file_size":1000000000}"#;
+
+        let merged = next_config(
+            Some(stored),
+            serde_json::json!({ "auto_migrate": false }),
+            ConfigUpdate::Merge,
+        )
+        .unwrap();
+        assert_eq!(merged.previous_version, 3);
+        assert_eq!(merged.config.config_version, 4);
+        assert_eq!(merged.config.max_file_size, 1_000_000_000);
+        assert!(!merged.config.auto_migrate);
+
+        let replaced = next_config(
+            Some(stored),
+            serde_json::json!({ "config_version": 3, "auto_migrate": false }),
+            ConfigUpdate::Replace,
+        )
+        .unwrap();
+        assert_eq!(replaced.config.config_version, 4);
+        assert_eq!(
+            replaced.config.max_file_size,
+            Config::default().max_file_size
+        );
+        assert_eq!(
+            replaced.value,
+            serde_json::json!({ "config_version": 4, "auto_migrate": false })
+        );
+    }
+
+    #[test]
+    fn next_config_rejects_stale_version_and_invalid_values() {
+        let stored = Some(r#"{"config_version":3}"#);
+        assert!(matches!(
+            next_config(
+                stored,
+                serde_json::json!({ "config_version": 2 }),
+                ConfigUpdate::Merge
+            ),
+            Err(AppError::ConfigVersionConflict {
+                expected: 2,
+                current: 3
+            })
+        ));
+        assert!(matches!(
+            next_config(
+                stored,
+                serde_json::json!({ "chunk_size": 1 }),
+                ConfigUpdate::Merge
+            ),
+            Err(AppError::ValidationError { .. })
+        ));
+        assert!(matches!(
+            next_config(Some("{broken"), serde_json::json!({}), ConfigUpdate::Merge),
+            Err(AppError::ValidationError { .. })
+        ));
+        assert_eq!(
+            next_config(
+                Some("{broken"),
+                serde_json::json!({}),
+                ConfigUpdate::Replace
+            )
+            .unwrap()
+            .config
+            .config_version,
+            1
+        );
+    }
+
+    #[test]
+    fn parse_dry_run_accepts_flags() {
+        let dry_run = |query: &str| {
+            let url = Url::parse(&format!("https://example.com/?{query}")).unwrap();
+            parse_dry_run(url.query_pairs())
+        };
+        assert!(!dry_run("").unwrap());
+        assert!(dry_run("dry_run=true").unwrap());
+        assert!(dry_run("dry_run").unwrap());
+        assert!(!dry_run("dry_run=0").unwrap());
+        assert!(dry_run("dry_run=maybe").is_err());
+    }
+
+    #[test]
+    fn hold_parse_reads_upload_id_and_hold() {
+        assert_eq!(
+            Hold::parse("/api/admin/uploads/abc/retention"),
+            Some(("abc", Hold::Retention))
+        );
+        assert_eq!(
+            Hold::parse("/api/admin/uploads/abc/legal-hold"),
+            Some(("abc", Hold::Legal))
+        );
+        for path in [
+            "/api/admin/uploads//retention",
+            "/api/admin/uploads/a/b/retention",
+            "/api/admin/uploads/abc/hold",
+            "/api/admin/audit",
+        ] {
+            assert_eq!(Hold::parse(path), None, "{path}");
+        }
+    }
+
+    #[test]
+    fn parse_retain_until_requires_a_future_timestamp() {
+        let now = DateTime::parse_from_rfc3339("2026-01-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let until = parse_retain_until(
+            &serde_json::json!({ "retain_until": "2027-06-30T12:00:00+02:00" }),
+            now,
+        )
+        .unwrap();
+        assert_eq!(until.to_rfc3339(), "2027-06-30T10:00:00+00:00");
+
+        assert!(matches!(
+            parse_retain_until(&serde_json::json!({}), now),
+            Err(AppError::MissingField { .. })
+        ));
+        for value in [
+            serde_json::json!("next year"),
+            serde_json::json!(1_800_000_000),
+            serde_json::json!("2025-12-31T23:59:59Z"),
+            serde_json::json!("2026-01-01T00:00:00Z"),
+        ] {
+            assert!(
+                matches!(
+                    parse_retain_until(&serde_json::json!({ "retain_until": value }), now),
+                    Err(AppError::InvalidField { .. })
+                ),
+                "{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_audit_query_rejects_bad_values() {
+        for query in ["since=yesterday", "limit=0", "limit=1001", "cursor=abc"] {
+            assert!(
+                matches!(parse(query), Err(AppError::InvalidField { .. })),
+                "{query}"
+            );
+        }
+    }
+}
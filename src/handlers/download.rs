@@ -0,0 +1,482 @@
+//! # Download Handlers
+//!
+//! Serves completed uploads from R2:
+//!
+//! - `GET /api/upload/{id}/download` streams the upload's object.
+//! - `GET /api/upload/{id}/variants/{name}` streams one of its generated image
+//!   variants.
+//!
+//! A single `Range: bytes=...` is honoured with `206 Partial Content`; other
+//! range forms get the whole object. Encrypted uploads are decrypted as they
+//! stream, reading only the encrypted segments that cover the requested
+//! range, and customer-encrypted ones need the `X-Encryption-Key` they were
+//! uploaded with. [`serve_upload_object`] is shared with version downloads.
+
+use futures_util::{stream, Stream, StreamExt};
+use worker::*;
+
+use crate::config::Config;
+use crate::crypto::{self, Key, RangeDecryptor, StoredChunk};
+use crate::database::{ChunkList, DatabaseService};
+use crate::errors::{AppError, AppResult};
+use crate::logging::RequestContext;
+use crate::middleware::RateLimitMiddleware;
+use crate::models::{UploadMetadata, UploadStatus};
+
+use super::upload::{open_bucket, upload_data_key};
+
+/// Returns the upload ID of a `/api/upload/{id}/download` path.
+pub fn download_path(path: &str) -> Option<&str> {
+    path.strip_prefix("/api/upload/")?
+        .strip_suffix("/download")
+        .filter(|upload_id| !upload_id.is_empty() && !upload_id.contains('/'))
+}
+
+/// Returns the upload ID and variant name of a
+/// `/api/upload/{id}/variants/{name}` path.
+pub fn variant_path(path: &str) -> Option<(&str, &str)> {
+    let (upload_id, variant) = path
+        .strip_prefix("/api/upload/")?
+        .split_once("/variants/")?;
+    let valid = |segment: &str| !segment.is_empty() && !segment.contains('/');
+    (valid(upload_id) && valid(variant)).then_some((upload_id, variant))
+}
+
+/// Stream a completed upload's object.
+pub async fn download_upload(
+    req: Request,
+    env: &Env,
+    config: &Config,
+    ctx: &RequestContext,
+) -> AppResult<Response> {
+    let url = req.url().map_err(|err| AppError::InternalError {
+        message: format!("Failed to parse request URL: {err}"),
+    })?;
+    let upload_id = download_path(url.path()).ok_or_else(|| AppError::ValidationError {
+        message: "Upload ID missing from path".to_string(),
+    })?;
+    ctx.set_upload_id(upload_id);
+
+    let database = DatabaseService::new(env, &config.database_name)?;
+    let Some(metadata) = database.get_upload(upload_id, ChunkList::Skip).await? else {
+        return Err(AppError::UploadNotFound {
+            upload_id: upload_id.to_string(),
+        });
+    };
+    ctx.set_user_id(&metadata.user_id);
+    RateLimitMiddleware::check_user(env, config, ctx, &metadata.user_role, &metadata.user_id)
+        .await?;
+
+    serve_upload_object(&req, env, config, &database, &metadata).await
+}
+
+/// Stream one of a completed upload's generated variants.
+pub async fn download_variant(
+    req: Request,
+    env: &Env,
+    config: &Config,
+    ctx: &RequestContext,
+) -> AppResult<Response> {
+    let url = req.url().map_err(|err| AppError::InternalError {
+        message: format!("Failed to parse request URL: {err}"),
+    })?;
+    let (upload_id, name) = variant_path(url.path()).ok_or_else(|| AppError::ValidationError {
+        message: "Upload ID or variant name missing from path".to_string(),
+    })?;
+    ctx.set_upload_id(upload_id);
+
+    let database = DatabaseService::new(env, &config.database_name)?;
+    let Some(metadata) = database.get_upload(upload_id, ChunkList::Skip).await? else {
+        return Err(AppError::UploadNotFound {
+            upload_id: upload_id.to_string(),
+        });
+    };
+    ctx.set_user_id(&metadata.user_id);
+    RateLimitMiddleware::check_user(env, config, ctx, &metadata.user_role, &metadata.user_id)
+        .await?;
+
+    if metadata.status != UploadStatus::Completed {
+        return Err(AppError::UploadStateConflict {
+            upload_id: metadata.upload_id.clone(),
+            status: metadata.status.as_str().to_string(),
+        });
+    }
+    let variant = database
+        .get_upload_variants(upload_id)
+        .await?
+        .into_iter()
+        .find(|variant| variant.name == name)
+        .ok_or_else(|| AppError::VariantNotFound {
+            upload_id: upload_id.to_string(),
+            variant: name.to_string(),
+        })?;
+
+    // Variants are only generated for unencrypted uploads, so the object is
+    // served as stored, under the variant's own key and content type.
+    let range_header = req
+        .headers()
+        .get("Range")
+        .map_err(|err| AppError::InternalError {
+            message: format!("Failed to read Range header: {err}"),
+        })?;
+    let bucket = open_bucket(env, &metadata.bucket)?;
+    let variant_object = UploadMetadata {
+        r2_key: variant.r2_key,
+        content_type: variant.content_type,
+        ..metadata
+    };
+    serve_plain(&bucket, &variant_object, range_header.as_deref()).await
+}
+
+/// Streams the object of a completed upload, decrypting it if needed and
+/// honouring the request's `Range` header.
+pub(super) async fn serve_upload_object(
+    req: &Request,
+    env: &Env,
+    config: &Config,
+    database: &DatabaseService,
+    metadata: &UploadMetadata,
+) -> AppResult<Response> {
+    if metadata.status != UploadStatus::Completed {
+        return Err(AppError::UploadStateConflict {
+            upload_id: metadata.upload_id.clone(),
+            status: metadata.status.as_str().to_string(),
+        });
+    }
+    let data_key = upload_data_key(req, config, metadata)?;
+    let range_header = req
+        .headers()
+        .get("Range")
+        .map_err(|err| AppError::InternalError {
+            message: format!("Failed to read Range header: {err}"),
+        })?;
+    let bucket = open_bucket(env, &metadata.bucket)?;
+
+    match data_key {
+        None => serve_plain(&bucket, metadata, range_header.as_deref()).await,
+        Some(data_key) => {
+            serve_encrypted(
+                &bucket,
+                database,
+                metadata,
+                data_key,
+                range_header.as_deref(),
+            )
+            .await
+        }
+    }
+}
+
+/// A byte range `start..=end` resolved against an object's size.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct ByteRange {
+    start: u64,
+    end: u64,
+}
+
+impl ByteRange {
+    fn len(&self) -> u64 {
+        self.end - self.start + 1
+    }
+}
+
+/// Resolves a `Range` header against an object of `size` bytes.
+///
+/// Returns `None`, meaning the whole object, for headers that are malformed,
+/// not in bytes, or ask for several ranges, and fails with
+/// [`AppError::RangeNotSatisfiable`] for a range that starts past the end.
+fn parse_range(header: &str, size: u64) -> AppResult<Option<ByteRange>> {
+    let Some((first, last)) = header
+        .trim()
+        .strip_prefix("bytes=")
+        .filter(|spec| !spec.contains(','))
+        .and_then(|spec| spec.split_once('-'))
+    else {
+        return Ok(None);
+    };
+    let (first, last) = (first.trim(), last.trim());
+    let unsatisfiable = || AppError::RangeNotSatisfiable { size };
+
+    if first.is_empty() {
+        let Ok(suffix) = last.parse::<u64>() else {
+            return Ok(None);
+        };
+        if suffix == 0 || size == 0 {
+            return Err(unsatisfiable());
+        }
+        return Ok(Some(ByteRange {
+            start: size.saturating_sub(suffix),
+            end: size - 1,
+        }));
+    }
+
+    let Ok(start) = first.parse::<u64>() else {
+        return Ok(None);
+    };
+    let end = match last {
+        "" => u64::MAX,
+        last => match last.parse::<u64>() {
+            Ok(end) if end >= start => end,
+            _ => return Ok(None),
+        },
+    };
+    if start >= size {
+        return Err(unsatisfiable());
+    }
+    Ok(Some(ByteRange {
+        start,
+        end: end.min(size - 1),
+    }))
+}
+
+async fn serve_plain(
+    bucket: &Bucket,
+    metadata: &UploadMetadata,
+    range_header: Option<&str>,
+) -> AppResult<Response> {
+    let range = match range_header {
+        Some(header) => {
+            let object = bucket
+                .head(metadata.r2_key.clone())
+                .await
+                .map_err(|err| read_error(metadata, err))?
+                .ok_or_else(|| missing_object(metadata))?;
+            parse_range(header, object.size())?
+        }
+        None => None,
+    };
+
+    let mut get = bucket.get(metadata.r2_key.clone());
+    if let Some(range) = range {
+        get = get.range(Range::OffsetWithLength {
+            offset: range.start,
+            length: range.len(),
+        });
+    }
+    let object = get
+        .execute()
+        .await
+        .map_err(|err| read_error(metadata, err))?
+        .ok_or_else(|| missing_object(metadata))?;
+    let body = object
+        .body()
+        .ok_or_else(|| missing_object(metadata))?
+        .response_body()
+        .map_err(|err| read_error(metadata, err))?;
+
+    let response = Response::from_body(body).map_err(|err| AppError::InternalError {
+        message: format!("Failed to build download response: {err}"),
+    })?;
+    with_object_headers(
+        response,
+        metadata,
+        object.size(),
+        range,
+        &object.http_etag(),
+    )
+}
+
+async fn serve_encrypted(
+    bucket: &Bucket,
+    database: &DatabaseService,
+    metadata: &UploadMetadata,
+    data_key: Key,
+    range_header: Option<&str>,
+) -> AppResult<Response> {
+    let chunks = database
+        .get_upload_chunks(&metadata.upload_id)
+        .await?
+        .into_iter()
+        .map(|chunk| match (chunk.stored_size, chunk.nonce.as_deref()) {
+            (Some(stored_size), Some(nonce)) => {
+                StoredChunk::new(chunk.chunk_index, stored_size, nonce)
+            }
+            _ => Err(AppError::DatabaseError {
+                message: format!(
+                    "Chunk {} of encrypted upload {} has no encryption metadata",
+                    chunk.chunk_index, metadata.upload_id
+                ),
+            }),
+        })
+        .collect::<AppResult<Vec<_>>>()?;
+    let size: u64 = chunks.iter().map(|chunk| chunk.plain_size).sum();
+
+    let range = range_header
+        .map(|header| parse_range(header, size))
+        .transpose()?
+        .flatten();
+    let whole = ByteRange {
+        start: 0,
+        end: size.saturating_sub(1),
+    };
+    let requested = range.unwrap_or(whole);
+    let plan = crypto::plan_range(
+        data_key,
+        &metadata.upload_id,
+        chunks,
+        requested.start,
+        requested.end,
+    );
+
+    let object = bucket
+        .get(metadata.r2_key.clone())
+        .range(Range::OffsetWithLength {
+            offset: plan.stored_offset,
+            length: plan.stored_length,
+        })
+        .execute()
+        .await
+        .map_err(|err| read_error(metadata, err))?
+        .ok_or_else(|| missing_object(metadata))?;
+    let body = object
+        .body()
+        .ok_or_else(|| missing_object(metadata))?
+        .stream()
+        .map_err(|err| read_error(metadata, err))?;
+
+    let response = Response::from_stream(decrypting(body, plan.decryptor)).map_err(|err| {
+        AppError::InternalError {
+            message: format!("Failed to build download response: {err}"),
+        }
+    })?;
+    with_object_headers(response, metadata, size, range, &object.http_etag())
+}
+
+/// Decrypts `body` as it streams. A segment that fails authentication, or a
+/// body that ends early, aborts the response.
+fn decrypting(body: ByteStream, decryptor: RangeDecryptor) -> impl Stream<Item = Result<Vec<u8>>> {
+    stream::unfold(Some((body, decryptor)), |state| async move {
+        let (mut body, mut decryptor) = state?;
+        let decrypted = match body.next().await {
+            Some(Ok(bytes)) => decryptor.push(&bytes),
+            Some(Err(err)) => return Some((Err(err), None)),
+            None => {
+                return decryptor
+                    .finish()
+                    .err()
+                    .map(|err| (Err(Error::RustError(err.to_string())), None))
+            }
+        };
+        match decrypted {
+            Ok(plaintext) => Some((Ok(plaintext), Some((body, decryptor)))),
+            Err(err) => Some((Err(Error::RustError(err.to_string())), None)),
+        }
+    })
+}
+
+/// Sets the headers of an object response and, for a range, the `206` status.
+fn with_object_headers(
+    response: Response,
+    metadata: &UploadMetadata,
+    size: u64,
+    range: Option<ByteRange>,
+    etag: &str,
+) -> AppResult<Response> {
+    let headers = Headers::new();
+    let set_headers = || -> Result<()> {
+        headers.set("Content-Type", &metadata.content_type)?;
+        headers.set("Accept-Ranges", "bytes")?;
+        headers.set("ETag", etag)?;
+        match range {
+            Some(range) => {
+                headers.set("Content-Length", &range.len().to_string())?;
+                headers.set(
+                    "Content-Range",
+                    &format!("bytes {}-{}/{size}", range.start, range.end),
+                )
+            }
+            None => headers.set("Content-Length", &size.to_string()),
+        }
+    };
+    set_headers().map_err(|err| AppError::InternalError {
+        message: format!("Failed to build download headers: {err}"),
+    })?;
+
+    let status = if range.is_some() { 206 } else { 200 };
+    Ok(response.with_headers(headers).with_status(status))
+}
+
+fn read_error(metadata: &UploadMetadata, err: Error) -> AppError {
+    AppError::R2Error {
+        message: format!("Failed to read upload {}: {err}", metadata.upload_id),
+    }
+}
+
+fn missing_object(metadata: &UploadMetadata) -> AppError {
+    AppError::R2Error {
+        message: format!("Object for upload {} is missing", metadata.upload_id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u64, end: u64) -> Option<ByteRange> {
+        Some(ByteRange { start, end })
+    }
+
+    #[test]
+    fn parse_range_resolves_each_form() {
+        assert_eq!(parse_range("bytes=0-99", 1000).unwrap(), range(0, 99));
+        assert_eq!(parse_range("bytes=900-", 1000).unwrap(), range(900, 999));
+        assert_eq!(parse_range("bytes=-100", 1000).unwrap(), range(900, 999));
+        assert_eq!(parse_range("bytes=-5000", 1000).unwrap(), range(0, 999));
+        assert_eq!(
+            parse_range("bytes=990-2000", 1000).unwrap(),
+            range(990, 999)
+        );
+    }
+
+    #[test]
+    fn parse_range_ignores_unsupported_headers() {
+        for header in [
+            "items=0-1",
+            "bytes=0-1,5-6",
+            "bytes=a-b",
+            "bytes=5-1",
+            "bytes=-",
+        ] {
+            assert_eq!(parse_range(header, 1000).unwrap(), None, "{header}");
+        }
+    }
+
+    #[test]
+    fn parse_range_rejects_ranges_past_the_end() {
+        for header in ["bytes=1000-", "bytes=1000-1001", "bytes=-0"] {
+            assert!(matches!(
+                parse_range(header, 1000),
+                Err(AppError::RangeNotSatisfiable { size: 1000 })
+            ));
+        }
+    }
+
+    #[test]
+    fn variant_path_requires_single_segments() {
+        assert_eq!(
+            variant_path("/api/upload/abc/variants/w128"),
+            Some(("abc", "w128"))
+        );
+        for path in [
+            "/api/upload//variants/w128",
+            "/api/upload/abc/variants/",
+            "/api/upload/abc/variants/w128/x",
+            "/api/upload/a/b/variants/w128",
+            "/api/upload/abc/download",
+        ] {
+            assert_eq!(variant_path(path), None, "{path}");
+        }
+    }
+
+    #[test]
+    fn download_path_requires_a_single_id_segment() {
+        assert_eq!(download_path("/api/upload/abc/download"), Some("abc"));
+        for path in [
+            "/api/upload//download",
+            "/api/upload/a/b/download",
+            "/api/upload/abc/status",
+        ] {
+            assert_eq!(download_path(path), None, "{path}");
+        }
+    }
+}
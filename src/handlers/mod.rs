@@ -6,53 +6,223 @@
 use std::sync::Arc;
 use worker::*;
 
-use crate::config::Config;
-use crate::utils::cors_headers;
+use crate::audit;
+use crate::config::{self, Config};
+use crate::errors::AppResult;
+use crate::idempotency::run_idempotent;
+use crate::logging::RequestContext;
+use crate::middleware::{AdminAuthMiddleware, CorsMiddleware, RateLimitMiddleware};
+use crate::migrations::ensure_current;
+use admin::{Hold, HoldChange};
+use versions::VersionPath;
 
+pub mod admin;
+pub mod download;
 pub mod upload;
+pub mod versions;
 
 /// Handles all upload-related operations using D1 database and R2 storage.
-pub async fn handle_upload_routes(req: Request, env: Env, config: Arc<Config>) -> Result<Response> {
+///
+/// Handler errors are converted into JSON error bodies carrying the request ID,
+/// and their code is recorded on `ctx` for the completion log line. Every
+/// response carries the CORS headers for the request's `Origin`. The per-IP
+/// rate limit for the route and the D1 schema version are checked before any
+/// handler runs, `init`,
+/// `complete` and `cancel` honour `Idempotency-Key`, and successful lifecycle
+/// requests are appended to the audit log.
+pub async fn handle_upload_routes(
+    req: Request,
+    env: Env,
+    config: Arc<Config>,
+    ctx: &RequestContext,
+) -> Result<Response> {
     use upload::{
-        cancel_upload, complete_upload, get_upload_status, initialize_upload, upload_chunk,
+        cancel_upload, complete_upload, delete_upload, get_upload_status, initialize_upload,
+        upload_chunk,
     };
 
     let method = req.method();
+    let origin = CorsMiddleware::request_origin(&req);
     let url = req.url()?;
     let path = url.path();
 
-    let result = match (method, path) {
-        (Method::Post, "/api/upload/init") => initialize_upload(req, &env, &config).await,
-        (Method::Put, "/api/upload/chunk") => upload_chunk(req, &env, &config).await,
-        (Method::Post, "/api/upload/complete") => complete_upload(req, &env, &config).await,
-        (Method::Post, "/api/upload/cancel") => cancel_upload(req, &env, &config).await,
-        (Method::Get, path) if path.starts_with("/api/upload/") && path.ends_with("/status") => {
-            get_upload_status(req, &env, &config).await
-        }
-        _ => {
-            return Response::error("Not Found", 404);
-        }
+    let ready = match RateLimitMiddleware::check_ip(&req, &env, &config, ctx).await {
+        Ok(()) => ensure_current(&env, &config).await,
+        Err(err) => Err(err),
+    };
+    let result = match ready {
+        Err(err) => Err(err),
+        Ok(()) => match (method, path) {
+            (Method::Post, "/api/upload/init") => {
+                run_idempotent(req, &env, &config, ctx, |req| {
+                    initialize_upload(req, &env, &config, ctx)
+                })
+                .await
+            }
+            (Method::Put, "/api/upload/chunk") => upload_chunk(req, &env, &config, ctx).await,
+            (Method::Post, "/api/upload/complete") => {
+                run_idempotent(req, &env, &config, ctx, |req| {
+                    complete_upload(req, &env, &config, ctx)
+                })
+                .await
+            }
+            (Method::Post, "/api/upload/cancel") => {
+                run_idempotent(req, &env, &config, ctx, |req| {
+                    cancel_upload(req, &env, &config, ctx)
+                })
+                .await
+            }
+            (Method::Delete, path) if upload::upload_path(path).is_some() => {
+                delete_upload(req, &env, &config, ctx).await
+            }
+            (Method::Get, path)
+                if path.starts_with("/api/upload/") && path.ends_with("/status") =>
+            {
+                get_upload_status(req, &env, &config, ctx).await
+            }
+            (Method::Get, path) if download::download_path(path).is_some() => {
+                download::download_upload(req, &env, &config, ctx).await
+            }
+            (Method::Get, path) if download::variant_path(path).is_some() => {
+                download::download_variant(req, &env, &config, ctx).await
+            }
+            (Method::Get, path)
+                if VersionPath::parse(path).is_some_and(|r| r.version.is_none()) =>
+            {
+                versions::list_versions(req, &env, &config, ctx).await
+            }
+            (Method::Get, path) if VersionPath::parse(path).is_some_and(|r| !r.restore) => {
+                versions::download_version(req, &env, &config, ctx).await
+            }
+            (Method::Post, path) if VersionPath::parse(path).is_some_and(|r| r.restore) => {
+                versions::restore_version(req, &env, &config, ctx).await
+            }
+            _ => {
+                return Response::error("Not Found", 404);
+            }
+        },
     };
 
-    match result {
-        Ok(response) => Ok(response.with_headers(cors_headers())),
-        Err(app_error) => match app_error.to_response() {
-            Ok(response) => Ok(response.with_headers(cors_headers())),
-            Err(_) => Response::error("Internal Server Error", 500)
-                .map(|r| r.with_headers(cors_headers())),
+    if let Ok(response) = &result {
+        audit::record(&env, &config, ctx, response).await;
+    }
+
+    finish(result, &config, origin.as_deref(), ctx)
+}
+
+/// Handles operator endpoints under `/api/admin`.
+///
+/// Every request must pass [`AdminAuthMiddleware`]; errors and CORS headers are
+/// handled as in [`handle_upload_routes`]. The migration and configuration
+/// endpoints skip the schema check so an operator can bring the database up
+/// to date or fix a configuration that breaks it. Successful retention hold
+/// changes are appended to the audit log.
+pub async fn handle_admin_routes(
+    req: Request,
+    env: Env,
+    config: Arc<Config>,
+    ctx: &RequestContext,
+) -> Result<Response> {
+    let method = req.method();
+    let origin = CorsMiddleware::request_origin(&req);
+    let url = req.url()?;
+    let path = url.path();
+
+    let result = match AdminAuthMiddleware::authorize(&req, &config) {
+        Err(err) => Err(err),
+        Ok(()) => match (method, path) {
+            (Method::Get, "/api/admin/audit") => match ensure_current(&env, &config).await {
+                Ok(()) => admin::list_audit_log(req, &env, &config, ctx).await,
+                Err(err) => Err(err),
+            },
+            (Method::Get, "/api/admin/migrations") => {
+                admin::migration_status(req, &env, &config, ctx).await
+            }
+            (Method::Post, "/api/admin/migrations") => {
+                admin::run_migrations(req, &env, &config, ctx).await
+            }
+            (Method::Get, "/api/admin/config") => admin::get_config(req, &env, &config, ctx).await,
+            (Method::Put, "/api/admin/config") => {
+                admin::update_config(req, &env, &config, ctx, admin::ConfigUpdate::Replace).await
+            }
+            (Method::Patch, "/api/admin/config") => {
+                admin::update_config(req, &env, &config, ctx, admin::ConfigUpdate::Merge).await
+            }
+            (Method::Put, path) if Hold::parse(path).is_some() => {
+                match ensure_current(&env, &config).await {
+                    Ok(()) => admin::update_hold(req, &env, &config, ctx, HoldChange::Set).await,
+                    Err(err) => Err(err),
+                }
+            }
+            (Method::Delete, path) if Hold::parse(path).is_some() => {
+                match ensure_current(&env, &config).await {
+                    Ok(()) => admin::update_hold(req, &env, &config, ctx, HoldChange::Clear).await,
+                    Err(err) => Err(err),
+                }
+            }
+            _ => {
+                return Response::error("Not Found", 404);
+            }
         },
+    };
+
+    if let Ok(response) = &result {
+        audit::record(&env, &config, ctx, response).await;
+    }
+
+    finish(result, &config, origin.as_deref(), ctx)
+}
+
+/// Converts a handler result into the final response: errors become JSON
+/// bodies carrying the request ID (with their code recorded on `ctx`), and
+/// every response gets the CORS headers for `origin`.
+fn finish(
+    result: AppResult<Response>,
+    config: &Config,
+    origin: Option<&str>,
+    ctx: &RequestContext,
+) -> Result<Response> {
+    let with_cors =
+        |response: Response| CorsMiddleware::apply_headers(response, &config.cors, origin);
+
+    match result {
+        Ok(response) => Ok(with_cors(response)),
+        Err(app_error) => {
+            ctx.set_error_code(app_error.code());
+            match app_error.to_response(ctx.request_id()) {
+                Ok(response) => Ok(with_cors(response)),
+                Err(_) => Response::error("Internal Server Error", 500).map(with_cors),
+            }
+        }
     }
 }
 
 /// Provides a health check endpoint for monitoring and load balancer probes.
+///
+/// `config` reports the version of the configuration this isolate is serving
+/// and, when the last refresh from KV failed, why.
 pub async fn handle_health_check(_req: Request, _env: Env) -> Result<Response> {
     Response::from_json(&serde_json::json!({
         "status": "healthy",
         "service": "memenow-storage-cf-workers",
-        "timestamp": chrono::Utc::now().to_rfc3339()
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "config": config::status(),
     }))
 }
 
+/// Health check served when no configuration could be loaded at all, e.g.
+/// because the KV value was rejected on an isolate's first request.
+pub fn handle_health_check_without_config(error: &str) -> Result<Response> {
+    Ok(Response::from_json(&serde_json::json!({
+        "status": "unhealthy",
+        "service": "memenow-storage-cf-workers",
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "config": null,
+        "config_error": error,
+    }))?
+    .with_status(503))
+}
+
 /// Handles requests to unmatched routes with a 404 Not Found response.
 pub async fn handle_not_found(_req: Request, _env: Env) -> Result<Response> {
     Response::error("Not Found", 404)
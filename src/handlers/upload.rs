@@ -2,20 +2,29 @@
 //!
 //! End-to-end implementation of the upload lifecycle backed by Cloudflare R2 and D1.
 //! The handlers coordinate multipart upload creation, chunk ingestion, completion,
-//! and cancellation while keeping metadata in sync with D1.
+//! cancellation and deletion while keeping metadata in sync with D1.
 
-use chrono::Utc;
+use chrono::{DateTime, Duration, Utc};
 use serde::Deserialize;
 use uuid::Uuid;
 use worker::{HttpMetadata, UploadedPart, *};
 
-use crate::config::Config;
-use crate::constants::STORAGE_BUCKET_NAME;
-use crate::database::{DatabaseService, UploadChunkRecord};
+use crate::config::{Config, EncryptionPolicy, KeyCollisionStrategy, MetadataPolicy};
+use crate::constants::{
+    COMPLETION_CLAIM_LEASE_SECS, ENCRYPTION_MASTER_KEY_SECRET_NAME, HEADER_ENCRYPTION_KEY,
+    MAX_KEY_SUFFIX_ATTEMPTS, MAX_LISTED_ACTIVE_UPLOADS,
+};
+use crate::crypto::{self, Key};
+use crate::database::{ChunkList, ChunkWrite, DatabaseService, NewAuditEntry, UploadChunkRecord};
 use crate::errors::{AppError, AppResult};
-use crate::middleware::ValidationMiddleware;
-use crate::models::{UploadMetadata, UploadStatus, UserRole};
-use crate::utils::generate_r2_key;
+use crate::logging::{LogLevel, RequestContext};
+use crate::media::{metadata, sanitize, variants};
+use crate::metrics::Metric;
+use crate::middleware::{RateLimitMiddleware, ValidationMiddleware};
+use crate::models::{
+    transition, AuditAction, EncryptionMode, UploadEvent, UploadMetadata, UploadStatus, UserRole,
+};
+use crate::utils::{generate_r2_key, with_key_suffix, KeyContext};
 
 /// JSON payload for the upload initialization endpoint.
 #[derive(Debug, Deserialize)]
@@ -26,6 +35,9 @@ struct UploadInitRequest {
     user_role: UserRole,
     #[serde(default = "default_content_type")]
     content_type: String,
+    /// Hex SHA-256 of the whole file, used by `{sha256}` in the key template.
+    #[serde(default)]
+    sha256: Option<String>,
 }
 
 /// JSON payload for complete and cancel endpoints.
@@ -44,34 +56,93 @@ pub async fn initialize_upload(
     mut req: Request,
     env: &Env,
     config: &Config,
+    ctx: &RequestContext,
 ) -> AppResult<Response> {
+    let customer_key = ValidationMiddleware::validate_encryption_headers(&req)?;
     let payload: UploadInitRequest = req.json().await.map_err(|_| AppError::ValidationError {
         message: "Invalid JSON in request body".to_string(),
     })?;
+    ctx.set_user_id(&payload.user_id);
+    RateLimitMiddleware::check_user(env, config, ctx, &payload.user_role, &payload.user_id).await?;
 
     ValidationMiddleware::validate_file_size(payload.total_size, config.max_file_size)?;
     ValidationMiddleware::validate_content_type(&payload.content_type)?;
+    let sha256 = payload
+        .sha256
+        .as_deref()
+        .map(ValidationMiddleware::validate_sha256)
+        .transpose()?;
+
+    // A client key always wins; otherwise the role's policy decides.
+    let (encryption, kek) = match (
+        customer_key,
+        config.encryption_policy_for(&payload.user_role),
+    ) {
+        (Some(key), _) => (EncryptionMode::Customer, Some(key)),
+        (None, EncryptionPolicy::Customer) => {
+            return Err(AppError::MissingField {
+                field: format!("{HEADER_ENCRYPTION_KEY} header"),
+            })
+        }
+        (None, EncryptionPolicy::Managed) => (
+            EncryptionMode::Managed,
+            Some(managed_key(config, &payload.user_id)?),
+        ),
+        (None, EncryptionPolicy::None) => (EncryptionMode::None, None),
+    };
 
-    let bucket = env
-        .bucket(STORAGE_BUCKET_NAME)
-        .map_err(|err| AppError::R2Error {
-            message: format!("Unable to access R2 bucket: {err}"),
-        })?;
+    let bucket_name = config
+        .bucket_routing
+        .bucket_for(&payload.user_role, &payload.content_type);
+    let bucket = open_bucket(env, bucket_name)?;
 
     let database = DatabaseService::new(env, &config.database_name)?;
 
+    if let Some(limit) = config.active_upload_limit(&payload.user_role) {
+        let active = database
+            .list_active_uploads(&payload.user_id, MAX_LISTED_ACTIVE_UPLOADS)
+            .await?;
+        ValidationMiddleware::validate_active_uploads(active, limit)?;
+    }
+
     let upload_id = Uuid::new_v4().to_string();
+    ctx.set_upload_id(&upload_id);
     let r2_key = generate_r2_key(
-        &payload.user_role,
-        &payload.user_id,
-        &payload.file_name,
-        &payload.content_type,
-    );
-
+        &config.r2_key_template,
+        &KeyContext {
+            user_role: &payload.user_role,
+            user_id: &payload.user_id,
+            file_name: &payload.file_name,
+            content_type: &payload.content_type,
+            upload_id: &upload_id,
+            sha256: sha256.as_deref(),
+            now: Utc::now(),
+        },
+    )?;
+    let (r2_key, logical_key) = resolve_key_collision(
+        &database,
+        &bucket,
+        bucket_name,
+        r2_key,
+        &upload_id,
+        config.key_collision,
+    )
+    .await?;
+
+    let wrapped_key = kek
+        .map(|kek| crypto::wrap_key(&kek, &Key::generate()?, &upload_id))
+        .transpose()?;
+
+    // Encrypted objects are ciphertext, so R2 must not label them with the
+    // plaintext type; downloads set the real type after decrypting.
+    let stored_content_type = match encryption {
+        EncryptionMode::None => payload.content_type.clone(),
+        EncryptionMode::Customer | EncryptionMode::Managed => default_content_type(),
+    };
     let multipart = bucket
         .create_multipart_upload(r2_key.clone())
         .http_metadata(HttpMetadata {
-            content_type: Some(payload.content_type.clone()),
+            content_type: Some(stored_content_type),
             ..Default::default()
         })
         .execute()
@@ -94,17 +165,39 @@ pub async fn initialize_upload(
         status: UploadStatus::Initiated,
         chunks: Vec::new(),
         r2_key,
+        bucket: bucket_name.to_string(),
         user_id: payload.user_id,
         r2_upload_id,
+        media_info: None,
+        metadata_stripped: false,
+        logical_key,
+        encryption,
+        wrapped_key,
+        retain_until: None,
+        legal_hold: false,
+        completion_claimed_at: None,
     };
 
-    database.create_upload(&metadata).await?;
+    if !database.create_upload(&metadata).await? {
+        // A concurrent init claimed the key after our check; release the session.
+        if let Err(err) = multipart.abort().await {
+            ctx.log(
+                LogLevel::Warn,
+                &format!("failed to abort multipart upload: {err}"),
+            );
+        }
+        return Err(AppError::R2KeyConflict {
+            r2_key: metadata.r2_key,
+        });
+    }
 
     let body = serde_json::json!({
         "upload_id": metadata.upload_id,
         "chunk_size": config.chunk_size,
         "status": metadata.status.as_str(),
         "r2_key": metadata.r2_key,
+        "logical_key": metadata.logical_key,
+        "encryption": metadata.encryption.as_str(),
     });
 
     Response::from_json(&body).map_err(|_| AppError::InternalError {
@@ -113,11 +206,17 @@ pub async fn initialize_upload(
 }
 
 /// Upload a single chunk and persist chunk metadata.
-pub async fn upload_chunk(mut req: Request, env: &Env, config: &Config) -> AppResult<Response> {
+pub async fn upload_chunk(
+    mut req: Request,
+    env: &Env,
+    config: &Config,
+    ctx: &RequestContext,
+) -> AppResult<Response> {
     let (upload_id, chunk_index) = ValidationMiddleware::validate_upload_headers(&req)?;
+    ctx.set_upload_id(&upload_id);
     ValidationMiddleware::validate_chunk_index(chunk_index)?;
 
-    let chunk_bytes = req.bytes().await.map_err(|err| AppError::ValidationError {
+    let mut chunk_bytes = req.bytes().await.map_err(|err| AppError::ValidationError {
         message: format!("Failed to read chunk body: {err}"),
     })?;
 
@@ -128,23 +227,17 @@ pub async fn upload_chunk(mut req: Request, env: &Env, config: &Config) -> AppRe
     }
 
     let database = DatabaseService::new(env, &config.database_name)?;
-    let Some(metadata) = database.get_upload(&upload_id).await? else {
+    let Some(metadata) = database.get_upload(&upload_id, ChunkList::Skip).await? else {
         return Err(AppError::UploadNotFound { upload_id });
     };
+    ctx.set_user_id(&metadata.user_id);
+    RateLimitMiddleware::check_user(env, config, ctx, &metadata.user_role, &metadata.user_id)
+        .await?;
 
-    if metadata.status == UploadStatus::Completed {
-        return Err(AppError::UploadAlreadyCompleted { upload_id });
-    }
+    let next_status = check_transition(&metadata, UploadEvent::ChunkUploaded)?;
+    let data_key = upload_data_key(&req, config, &metadata)?;
 
-    if metadata.status == UploadStatus::Cancelled {
-        return Err(AppError::UploadCancelled { upload_id });
-    }
-
-    let bucket = env
-        .bucket(STORAGE_BUCKET_NAME)
-        .map_err(|err| AppError::R2Error {
-            message: format!("Unable to access R2 bucket: {err}"),
-        })?;
+    let bucket = open_bucket(env, &metadata.bucket)?;
 
     let multipart = bucket
         .resume_multipart_upload(metadata.r2_key.clone(), metadata.r2_upload_id.clone())
@@ -155,35 +248,91 @@ pub async fn upload_chunk(mut req: Request, env: &Env, config: &Config) -> AppRe
     let part_number = chunk_index + 1;
     let chunk_size = chunk_bytes.len() as u64;
 
+    // Container headers and image metadata live at the start of the file, so only
+    // the first chunk is rewritten and parsed. Stripping runs first so the parsed
+    // metadata describes the stored object. The flag is only set when something
+    // was stripped, and never for a multipart WebP, whose trailing `EXIF` chunk
+    // is out of reach.
+    let metadata_stripped = chunk_index == 0
+        && metadata.content_type.to_lowercase().starts_with("image/")
+        && config.metadata_policy_for(&metadata.user_role) == MetadataPolicy::Strip
+        && sanitize::strip_image_metadata(&mut chunk_bytes).is_some_and(|count| count > 0)
+        && !(sanitize::is_webp(&chunk_bytes) && chunk_size < metadata.total_size);
+
+    let mut media_info =
+        if chunk_index == 0 && metadata::is_supported_content_type(&metadata.content_type) {
+            metadata::extract_media_info(&chunk_bytes, metadata.total_size)
+        } else {
+            None
+        };
+    // D1 is plaintext, so encrypted uploads keep only technical fields; EXIF
+    // and ID3 tags would leak what the encryption protects.
+    if let (Some(info), Some(_)) = (&mut media_info, &data_key) {
+        info.tags.clear();
+    }
+
+    // Encryption runs last so stripping and parsing see the plaintext; the
+    // plaintext buffer is sealed in place rather than copied.
+    let (chunk_bytes, nonce, stored_size) = match &data_key {
+        Some(key) => {
+            let sealed = crypto::encrypt_chunk(key, &metadata.upload_id, chunk_index, chunk_bytes)?;
+            let stored_size = sealed.bytes.len() as u64;
+            (sealed.bytes, Some(sealed.nonce), Some(stored_size))
+        }
+        None => (chunk_bytes, None, None),
+    };
+
+    let part_started_ms = ctx.elapsed_ms();
     let uploaded_part = multipart
         .upload_part(part_number, chunk_bytes)
         .await
         .map_err(|err| AppError::R2Error {
             message: format!("Failed to upload chunk to R2: {err}"),
         })?;
+    ctx.record(Metric::ChunkUploaded {
+        role: metadata.user_role.clone(),
+        bytes: chunk_size,
+        latency_ms: ctx.elapsed_ms().saturating_sub(part_started_ms),
+    });
 
-    database
-        .record_chunk(
-            &metadata.upload_id,
+    // One D1 batch for the chunk row, the upload row and the audit entry. The
+    // status change is conditional so a concurrent completion or cancellation
+    // is never overwritten; losing to another chunk's transition is harmless.
+    // Once a completion or cancellation has claimed the upload nothing is
+    // written, and the request fails with the status it lost to.
+    let etag = uploaded_part.etag();
+    let recorded = database
+        .record_chunk_upload(&ChunkWrite {
+            upload_id: &metadata.upload_id,
             chunk_index,
             chunk_size,
-            Some(&uploaded_part.etag()),
-        )
+            etag: Some(&etag),
+            nonce: nonce.as_deref(),
+            stored_size,
+            media_info: media_info.as_ref(),
+            metadata_stripped,
+            event: (next_status != metadata.status).then_some(UploadEvent::ChunkUploaded),
+            actor: &metadata.user_id,
+            audit: &NewAuditEntry {
+                action: AuditAction::Chunk,
+                upload_id: Some(&metadata.upload_id),
+                actor: Some(&metadata.user_id),
+                ip: ctx.client_ip(),
+                user_agent: ctx.user_agent(),
+                request_id: ctx.request_id(),
+                created_at: Utc::now(),
+            },
+        })
         .await?;
-
-    if metadata.status == UploadStatus::Initiated {
-        database
-            .update_upload_status(&metadata.upload_id, UploadStatus::InProgress)
-            .await?;
-    } else {
-        database.touch_upload(&metadata.upload_id).await?;
+    if !recorded {
+        return Err(lost_transition(&database, &metadata.upload_id).await);
     }
 
     let body = serde_json::json!({
         "upload_id": metadata.upload_id,
         "chunk_index": chunk_index,
-        "etag": uploaded_part.etag(),
-        "status": UploadStatus::InProgress.as_str(),
+        "etag": etag,
+        "status": next_status.as_str(),
     });
 
     Response::from_json(&body).map_err(|_| AppError::InternalError {
@@ -192,30 +341,32 @@ pub async fn upload_chunk(mut req: Request, env: &Env, config: &Config) -> AppRe
 }
 
 /// Complete the multipart upload by stitching R2 parts together.
-pub async fn complete_upload(mut req: Request, env: &Env, config: &Config) -> AppResult<Response> {
+pub async fn complete_upload(
+    mut req: Request,
+    env: &Env,
+    config: &Config,
+    ctx: &RequestContext,
+) -> AppResult<Response> {
     let payload: UploadLifecycleRequest =
         req.json().await.map_err(|_| AppError::ValidationError {
             message: "Invalid JSON in request body".to_string(),
         })?;
+    ctx.set_upload_id(&payload.upload_id);
 
     let database = DatabaseService::new(env, &config.database_name)?;
-    let Some(metadata) = database.get_upload(&payload.upload_id).await? else {
+    let Some(metadata) = database
+        .get_upload(&payload.upload_id, ChunkList::Skip)
+        .await?
+    else {
         return Err(AppError::UploadNotFound {
             upload_id: payload.upload_id,
         });
     };
+    ctx.set_user_id(&metadata.user_id);
+    RateLimitMiddleware::check_user(env, config, ctx, &metadata.user_role, &metadata.user_id)
+        .await?;
 
-    if metadata.status == UploadStatus::Completed {
-        return Err(AppError::UploadAlreadyCompleted {
-            upload_id: metadata.upload_id,
-        });
-    }
-
-    if metadata.status == UploadStatus::Cancelled {
-        return Err(AppError::UploadCancelled {
-            upload_id: metadata.upload_id,
-        });
-    }
+    let claim = completion_claim(&metadata, Utc::now())?;
 
     let chunk_records = database.get_upload_chunks(&metadata.upload_id).await?;
     if chunk_records.is_empty() {
@@ -229,11 +380,7 @@ pub async fn complete_upload(mut req: Request, env: &Env, config: &Config) -> Ap
 
     let uploaded_parts = build_uploaded_parts(&chunk_records)?;
 
-    let bucket = env
-        .bucket(STORAGE_BUCKET_NAME)
-        .map_err(|err| AppError::R2Error {
-            message: format!("Unable to access R2 bucket: {err}"),
-        })?;
+    let bucket = open_bucket(env, &metadata.bucket)?;
 
     let multipart = bucket
         .resume_multipart_upload(metadata.r2_key.clone(), metadata.r2_upload_id.clone())
@@ -241,21 +388,88 @@ pub async fn complete_upload(mut req: Request, env: &Env, config: &Config) -> Ap
             message: format!("Failed to resume multipart upload: {err}"),
         })?;
 
-    multipart
-        .complete(uploaded_parts)
-        .await
-        .map_err(|err| AppError::R2Error {
-            message: format!("Failed to finalize multipart upload: {err}"),
-        })?;
+    // Claim the upload so a concurrent complete or cancel loses deterministically.
+    match claim {
+        CompletionClaim::Fresh => {
+            apply_event(&database, &metadata, UploadEvent::CompletionStarted).await?;
+        }
+        CompletionClaim::TakeOver => {
+            let stale_before = Utc::now() - Duration::seconds(COMPLETION_CLAIM_LEASE_SECS);
+            if !database
+                .take_over_completion(&metadata.upload_id, &metadata.user_id, stale_before)
+                .await?
+            {
+                return Err(lost_transition(&database, &metadata.upload_id).await);
+            }
+        }
+    }
 
-    database
-        .update_upload_status(&metadata.upload_id, UploadStatus::Completed)
-        .await?;
+    if let Err(err) = multipart.complete(uploaded_parts).await {
+        // An earlier completion may have had R2 assemble the object before its
+        // worker died or its response was lost, which ends the multipart session.
+        let session_missing = multipart_session_missing(&err);
+        if !(session_missing && object_assembled(&bucket, &metadata.r2_key, &chunk_records).await?)
+        {
+            // Release the claim so the client can retry completion, unless the
+            // session is gone and the upload can never complete.
+            let event = if session_missing {
+                UploadEvent::Failed
+            } else {
+                UploadEvent::CompletionFailed
+            };
+            if apply_event(&database, &metadata, event).await.is_err() {
+                ctx.log(LogLevel::Warn, "failed to release completing status");
+            }
+            return Err(AppError::R2Error {
+                message: format!("Failed to finalize multipart upload: {err}"),
+            });
+        }
+        ctx.log(
+            LogLevel::Info,
+            "multipart upload was already completed by an earlier request",
+        );
+    }
+
+    let version = match &metadata.logical_key {
+        Some(logical_key) => {
+            if !database
+                .complete_version(&metadata, logical_key, &metadata.user_id)
+                .await?
+            {
+                return Err(lost_transition(&database, &metadata.upload_id).await);
+            }
+            database.version_of_upload(&metadata.upload_id).await?
+        }
+        None => {
+            apply_event(&database, &metadata, UploadEvent::CompletionSucceeded).await?;
+            None
+        }
+    };
+    ctx.record(Metric::UploadCompleted {
+        role: metadata.user_role.clone(),
+        total_size: metadata.total_size,
+    });
+
+    // Variant generation is best-effort: the original object is already durable,
+    // so a decode or storage failure is logged rather than failing completion.
+    let generated_variants = if variants::is_eligible(&config.image_variants, &metadata) {
+        variants::generate_image_variants(&bucket, &database, &config.image_variants, &metadata)
+            .await
+            .unwrap_or_else(|err| {
+                ctx.log(LogLevel::Warn, &format!("variant generation failed: {err}"));
+                Vec::new()
+            })
+    } else {
+        Vec::new()
+    };
 
     let body = serde_json::json!({
         "upload_id": metadata.upload_id,
         "status": UploadStatus::Completed.as_str(),
         "r2_key": metadata.r2_key,
+        "logical_key": metadata.logical_key,
+        "version": version,
+        "variants": generated_variants,
     });
 
     Response::from_json(&body).map_err(|_| AppError::InternalError {
@@ -264,36 +478,34 @@ pub async fn complete_upload(mut req: Request, env: &Env, config: &Config) -> Ap
 }
 
 /// Cancel an in-flight upload and abort the multipart session.
-pub async fn cancel_upload(mut req: Request, env: &Env, config: &Config) -> AppResult<Response> {
+pub async fn cancel_upload(
+    mut req: Request,
+    env: &Env,
+    config: &Config,
+    ctx: &RequestContext,
+) -> AppResult<Response> {
     let payload: UploadLifecycleRequest =
         req.json().await.map_err(|_| AppError::ValidationError {
             message: "Invalid JSON in request body".to_string(),
         })?;
+    ctx.set_upload_id(&payload.upload_id);
 
     let database = DatabaseService::new(env, &config.database_name)?;
-    let Some(metadata) = database.get_upload(&payload.upload_id).await? else {
+    let Some(metadata) = database
+        .get_upload(&payload.upload_id, ChunkList::Skip)
+        .await?
+    else {
         return Err(AppError::UploadNotFound {
             upload_id: payload.upload_id,
         });
     };
+    ctx.set_user_id(&metadata.user_id);
+    RateLimitMiddleware::check_user(env, config, ctx, &metadata.user_role, &metadata.user_id)
+        .await?;
 
-    if metadata.status == UploadStatus::Completed {
-        return Err(AppError::UploadAlreadyCompleted {
-            upload_id: metadata.upload_id,
-        });
-    }
-
-    if metadata.status == UploadStatus::Cancelled {
-        return Err(AppError::UploadCancelled {
-            upload_id: metadata.upload_id,
-        });
-    }
+    check_transition(&metadata, UploadEvent::Cancelled)?;
 
-    let bucket = env
-        .bucket(STORAGE_BUCKET_NAME)
-        .map_err(|err| AppError::R2Error {
-            message: format!("Unable to access R2 bucket: {err}"),
-        })?;
+    let bucket = open_bucket(env, &metadata.bucket)?;
 
     let multipart = bucket
         .resume_multipart_upload(metadata.r2_key.clone(), metadata.r2_upload_id.clone())
@@ -301,13 +513,16 @@ pub async fn cancel_upload(mut req: Request, env: &Env, config: &Config) -> AppR
             message: format!("Failed to resume multipart upload: {err}"),
         })?;
 
-    multipart.abort().await.map_err(|err| AppError::R2Error {
-        message: format!("Failed to abort multipart upload: {err}"),
-    })?;
+    apply_event(&database, &metadata, UploadEvent::Cancelled).await?;
 
-    database
-        .update_upload_status(&metadata.upload_id, UploadStatus::Cancelled)
-        .await?;
+    // The upload is already cancelled; R2 expires unfinished multipart uploads,
+    // so a failed abort only delays cleanup.
+    if let Err(err) = multipart.abort().await {
+        ctx.log(
+            LogLevel::Warn,
+            &format!("failed to abort multipart upload: {err}"),
+        );
+    }
 
     let body = serde_json::json!({
         "upload_id": metadata.upload_id,
@@ -319,8 +534,87 @@ pub async fn cancel_upload(mut req: Request, env: &Env, config: &Config) -> AppR
     })
 }
 
+/// Returns the upload ID of a `/api/upload/{id}` path.
+pub fn upload_path(path: &str) -> Option<&str> {
+    path.strip_prefix("/api/upload/")
+        .filter(|upload_id| !upload_id.is_empty() && !upload_id.contains('/'))
+}
+
+/// Delete a completed upload, its generated variants and their R2 objects.
+///
+/// Uploads under a legal hold or inside their retention period are refused
+/// with [`AppError::UploadRetained`]; the hold is checked again in the D1
+/// update, so a hold placed after the upload was read still blocks the delete.
+pub async fn delete_upload(
+    req: Request,
+    env: &Env,
+    config: &Config,
+    ctx: &RequestContext,
+) -> AppResult<Response> {
+    let url = req.url().map_err(|err| AppError::InternalError {
+        message: format!("Failed to parse request URL: {err}"),
+    })?;
+    let upload_id = upload_path(url.path()).ok_or_else(|| AppError::ValidationError {
+        message: "Upload ID missing from path".to_string(),
+    })?;
+    ctx.set_upload_id(upload_id);
+
+    let database = DatabaseService::new(env, &config.database_name)?;
+    let Some(metadata) = database.get_upload(upload_id, ChunkList::Skip).await? else {
+        return Err(AppError::UploadNotFound {
+            upload_id: upload_id.to_string(),
+        });
+    };
+    ctx.set_user_id(&metadata.user_id);
+    RateLimitMiddleware::check_user(env, config, ctx, &metadata.user_role, &metadata.user_id)
+        .await?;
+
+    check_transition(&metadata, UploadEvent::Deleted)?;
+    check_not_held(&metadata, Utc::now())?;
+    let upload_variants = database.get_upload_variants(upload_id).await?;
+
+    if !database.delete_upload(upload_id, &metadata.user_id).await? {
+        // Either a hold was placed since the read above or another request
+        // deleted the upload first.
+        if let Some(current) = database.get_upload(upload_id, ChunkList::Skip).await? {
+            if current.status == UploadStatus::Completed {
+                check_not_held(&current, Utc::now())?;
+            }
+        }
+        return Err(lost_transition(&database, upload_id).await);
+    }
+
+    // The upload is already deleted in D1; an object left behind by a failed
+    // R2 delete is unreachable through the API and only costs storage.
+    let bucket = open_bucket(env, &metadata.bucket)?;
+    let keys = std::iter::once(metadata.r2_key.clone())
+        .chain(upload_variants.into_iter().map(|variant| variant.r2_key));
+    for key in keys {
+        if let Err(err) = bucket.delete(key.clone()).await {
+            ctx.log(
+                LogLevel::Warn,
+                &format!("failed to delete object {key}: {err}"),
+            );
+        }
+    }
+
+    let body = serde_json::json!({
+        "upload_id": metadata.upload_id,
+        "status": UploadStatus::Deleted.as_str(),
+    });
+
+    Response::from_json(&body).map_err(|_| AppError::InternalError {
+        message: "Failed to serialize deletion response".to_string(),
+    })
+}
+
 /// Fetch the latest upload status and chunk progress.
-pub async fn get_upload_status(req: Request, env: &Env, config: &Config) -> AppResult<Response> {
+pub async fn get_upload_status(
+    req: Request,
+    env: &Env,
+    config: &Config,
+    ctx: &RequestContext,
+) -> AppResult<Response> {
     let url = req.url().map_err(|err| AppError::InternalError {
         message: format!("Failed to parse request URL: {err}"),
     })?;
@@ -333,13 +627,23 @@ pub async fn get_upload_status(req: Request, env: &Env, config: &Config) -> AppR
         .ok_or_else(|| AppError::ValidationError {
             message: "Upload ID missing from path".to_string(),
         })?;
+    ctx.set_upload_id(upload_id);
 
     let database = DatabaseService::new(env, &config.database_name)?;
-    let Some(metadata) = database.get_upload(upload_id).await? else {
+    let Some(metadata) = database.get_upload(upload_id, ChunkList::Include).await? else {
         return Err(AppError::UploadNotFound {
             upload_id: upload_id.to_string(),
         });
     };
+    ctx.set_user_id(&metadata.user_id);
+    RateLimitMiddleware::check_user(env, config, ctx, &metadata.user_role, &metadata.user_id)
+        .await?;
+
+    let upload_variants = if metadata.status == UploadStatus::Completed {
+        database.get_upload_variants(&metadata.upload_id).await?
+    } else {
+        Vec::new()
+    };
 
     let body = serde_json::json!({
         "upload_id": metadata.upload_id,
@@ -348,6 +652,13 @@ pub async fn get_upload_status(req: Request, env: &Env, config: &Config) -> AppR
         "chunks": metadata.chunks,
         "chunk_size": config.chunk_size,
         "r2_key": metadata.r2_key,
+        "logical_key": metadata.logical_key,
+        "encryption": metadata.encryption.as_str(),
+        "variants": upload_variants,
+        "media_info": metadata.media_info,
+        "metadata_stripped": metadata.metadata_stripped,
+        "retain_until": metadata.retain_until.map(|until| until.to_rfc3339()),
+        "legal_hold": metadata.legal_hold,
         "updated_at": metadata.updated_at.to_rfc3339(),
     });
 
@@ -356,6 +667,222 @@ pub async fn get_upload_status(req: Request, env: &Env, config: &Config) -> AppR
     })
 }
 
+/// Picks the R2 key for a new upload from the rendered `r2_key`.
+///
+/// Under `version` the rendered key becomes the upload's logical key and the
+/// object gets a key of its own, so earlier versions are never overwritten.
+/// Otherwise `strategy` applies when the key is already held by an upload or
+/// object in the bucket. Returns the R2 key and, if versioned, the logical key.
+async fn resolve_key_collision(
+    database: &DatabaseService,
+    bucket: &Bucket,
+    bucket_name: &str,
+    r2_key: String,
+    upload_id: &str,
+    strategy: KeyCollisionStrategy,
+) -> AppResult<(String, Option<String>)> {
+    if strategy == KeyCollisionStrategy::Version {
+        return Ok((with_key_suffix(&r2_key, upload_id)?, Some(r2_key)));
+    }
+    if !key_taken(database, bucket, bucket_name, &r2_key).await? {
+        return Ok((r2_key, None));
+    }
+
+    let resolved = match strategy {
+        KeyCollisionStrategy::Reject => return Err(AppError::R2KeyConflict { r2_key }),
+        KeyCollisionStrategy::NumericSuffix => {
+            let mut free = None;
+            for suffix in 1..=MAX_KEY_SUFFIX_ATTEMPTS {
+                let candidate = with_key_suffix(&r2_key, &suffix.to_string())?;
+                if !key_taken(database, bucket, bucket_name, &candidate).await? {
+                    free = Some(candidate);
+                    break;
+                }
+            }
+            match free {
+                Some(candidate) => candidate,
+                None => with_key_suffix(&r2_key, upload_id)?,
+            }
+        }
+        KeyCollisionStrategy::AppendUploadId | KeyCollisionStrategy::Version => {
+            with_key_suffix(&r2_key, upload_id)?
+        }
+    };
+    Ok((resolved, None))
+}
+
+/// Whether D1 tracks an upload holding `r2_key` or R2 already has an object there.
+async fn key_taken(
+    database: &DatabaseService,
+    bucket: &Bucket,
+    bucket_name: &str,
+    r2_key: &str,
+) -> AppResult<bool> {
+    if database.key_in_use(bucket_name, r2_key).await? {
+        return Ok(true);
+    }
+    let object = bucket.head(r2_key).await.map_err(|err| AppError::R2Error {
+        message: format!("Failed to check for an existing object: {err}"),
+    })?;
+    Ok(object.is_some())
+}
+
+/// Opens the R2 bucket bound as `name`.
+pub(super) fn open_bucket(env: &Env, name: &str) -> AppResult<Bucket> {
+    env.bucket(name).map_err(|err| AppError::R2Error {
+        message: format!("Unable to access R2 bucket `{name}`: {err}"),
+    })
+}
+
+/// The managed key-encryption key of `user_id`.
+fn managed_key(config: &Config, user_id: &str) -> AppResult<Key> {
+    config
+        .master_key()
+        .map(|master| master.derive_user_key(user_id))
+        .ok_or_else(|| AppError::InternalError {
+            message: format!("{ENCRYPTION_MASTER_KEY_SECRET_NAME} is not configured"),
+        })
+}
+
+/// Unwraps the data key of an encrypted upload, or `None` for an unencrypted one.
+///
+/// Customer-encrypted uploads need the request to send the same
+/// `X-Encryption-Key` the upload was initialized with.
+pub(super) fn upload_data_key(
+    req: &Request,
+    config: &Config,
+    metadata: &UploadMetadata,
+) -> AppResult<Option<Key>> {
+    let kek = match metadata.encryption {
+        EncryptionMode::None => return Ok(None),
+        EncryptionMode::Customer => ValidationMiddleware::validate_encryption_headers(req)?
+            .ok_or_else(|| AppError::MissingField {
+                field: format!("{HEADER_ENCRYPTION_KEY} header"),
+            })?,
+        EncryptionMode::Managed => managed_key(config, &metadata.user_id)?,
+    };
+    let wrapped = metadata
+        .wrapped_key
+        .as_deref()
+        .ok_or_else(|| AppError::DatabaseError {
+            message: format!("Upload {} has no wrapped key", metadata.upload_id),
+        })?;
+    crypto::unwrap_key(&kek, wrapped, &metadata.upload_id).map(Some)
+}
+
+/// Returns the status `event` leads to, or the `409` for the upload's current status.
+/// How a completion request claims its upload.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum CompletionClaim {
+    /// Move an `initiated` or `in_progress` upload to `completing`.
+    Fresh,
+    /// Take over a `completing` upload whose claim outlived the completion
+    /// lease; the request holding it failed or never finished.
+    TakeOver,
+}
+
+/// Decides how a completion request at `now` claims the upload, failing with
+/// a `409` while another completion holds a fresh claim.
+fn completion_claim(metadata: &UploadMetadata, now: DateTime<Utc>) -> AppResult<CompletionClaim> {
+    if metadata.status == UploadStatus::Completing {
+        let lease = Duration::seconds(COMPLETION_CLAIM_LEASE_SECS);
+        let stale = metadata
+            .completion_claimed_at
+            .is_none_or(|claimed_at| claimed_at + lease <= now);
+        if stale {
+            return Ok(CompletionClaim::TakeOver);
+        }
+    }
+    check_transition(metadata, UploadEvent::CompletionStarted)?;
+    Ok(CompletionClaim::Fresh)
+}
+
+/// Whether R2 rejected a multipart call because the session no longer exists
+/// (`NoSuchUpload`, error 10024): it was completed, aborted or expired.
+fn multipart_session_missing(err: &Error) -> bool {
+    let message = err.to_string();
+    message.contains("10024") || message.contains("NoSuchUpload")
+}
+
+/// Whether R2 already holds the assembled object for `chunks` at `r2_key`.
+async fn object_assembled(
+    bucket: &Bucket,
+    r2_key: &str,
+    chunks: &[UploadChunkRecord],
+) -> AppResult<bool> {
+    let object = bucket.head(r2_key).await.map_err(|err| AppError::R2Error {
+        message: format!("Failed to check for the assembled object: {err}"),
+    })?;
+    Ok(object.is_some_and(|object| object.size() == stored_object_size(chunks)))
+}
+
+/// Size of the object R2 assembles from `chunks`: ciphertext sizes for
+/// encrypted uploads, chunk sizes otherwise.
+fn stored_object_size(chunks: &[UploadChunkRecord]) -> u64 {
+    chunks
+        .iter()
+        .map(|chunk| chunk.stored_size.unwrap_or(chunk.chunk_size))
+        .sum()
+}
+
+fn check_transition(metadata: &UploadMetadata, event: UploadEvent) -> AppResult<UploadStatus> {
+    transition(metadata.status, event)
+        .map_err(|invalid| status_conflict(metadata.upload_id.clone(), invalid.from))
+}
+
+/// Fails with [`AppError::UploadRetained`] while a retention period or legal
+/// hold protects the upload from being deleted.
+fn check_not_held(metadata: &UploadMetadata, now: DateTime<Utc>) -> AppResult<()> {
+    if !metadata.is_held(now) {
+        return Ok(());
+    }
+    Err(AppError::UploadRetained {
+        upload_id: metadata.upload_id.clone(),
+        legal_hold: metadata.legal_hold,
+        retain_until: metadata.retain_until.filter(|until| *until > now),
+    })
+}
+
+/// Applies `event` in D1 on behalf of the upload's owner, failing if another
+/// request changed the status first.
+async fn apply_event(
+    database: &DatabaseService,
+    metadata: &UploadMetadata,
+    event: UploadEvent,
+) -> AppResult<()> {
+    if database
+        .apply_event(&metadata.upload_id, event, &metadata.user_id)
+        .await?
+    {
+        return Ok(());
+    }
+    Err(lost_transition(database, &metadata.upload_id).await)
+}
+
+/// Maps the status blocking an operation to its `409` error.
+fn status_conflict(upload_id: String, status: UploadStatus) -> AppError {
+    match status {
+        UploadStatus::Completed => AppError::UploadAlreadyCompleted { upload_id },
+        UploadStatus::Cancelled => AppError::UploadCancelled { upload_id },
+        status => AppError::UploadStateConflict {
+            upload_id,
+            status: status.as_str().to_string(),
+        },
+    }
+}
+
+/// Error for a request that lost a conditional status transition, based on
+/// the status the winning request left behind.
+async fn lost_transition(database: &DatabaseService, upload_id: &str) -> AppError {
+    match database.current_status(upload_id).await {
+        Ok(Some(status)) => status_conflict(upload_id.to_string(), status),
+        Ok(None) => AppError::UploadNotFound {
+            upload_id: upload_id.to_string(),
+        },
+        Err(err) => err,
+    }
+}
+
 /// Converts chunk records into R2 `UploadedPart` values for multipart completion.
 fn build_uploaded_parts(chunks: &[UploadChunkRecord]) -> AppResult<Vec<UploadedPart>> {
     collect_part_descriptors(chunks).map(|descriptors| {
@@ -449,11 +976,15 @@ mod tests {
             UploadChunkRecord {
                 chunk_index: 1,
                 chunk_size: 1,
+                nonce: None,
+                stored_size: None,
                 etag: Some("etag-two".into()),
             },
             UploadChunkRecord {
                 chunk_index: 0,
                 chunk_size: 1,
+                nonce: None,
+                stored_size: None,
                 etag: Some("etag-one".into()),
             },
         ];
@@ -472,16 +1003,172 @@ mod tests {
             chunk_index: 0,
             chunk_size: 1,
             etag: None,
+            nonce: None,
+            stored_size: None,
         }];
 
         let error = collect_part_descriptors(&chunks).unwrap_err();
         assert!(matches!(error, AppError::ValidationError { .. }));
     }
 
+    fn held_upload(retain_until: Option<&str>, legal_hold: bool) -> UploadMetadata {
+        serde_json::from_value(serde_json::json!({
+            "upload_id": "u1",
+            "file_name": "a.txt",
+            "total_size": 1,
+            "created_at": "2026-01-01T00:00:00Z",
+            "updated_at": "2026-01-01T00:00:00Z",
+            "user_role": "creator",
+            "content_type": "text/plain",
+            "status": "completed",
+            "chunks": [0],
+            "r2_key": "a.txt",
+            "bucket": "STORAGE_BUCKET",
+            "user_id": "alice",
+            "r2_upload_id": "r2",
+            "metadata_stripped": false,
+            "encryption": "none",
+            "retain_until": retain_until,
+            "legal_hold": legal_hold,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn check_not_held_honours_retention_and_legal_hold() {
+        let now = DateTime::parse_from_rfc3339("2026-06-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        assert!(check_not_held(&held_upload(None, false), now).is_ok());
+        assert!(check_not_held(&held_upload(Some("2026-05-31T23:59:59Z"), false), now).is_ok());
+
+        assert!(matches!(
+            check_not_held(&held_upload(Some("2026-06-01T00:00:01Z"), false), now),
+            Err(AppError::UploadRetained {
+                legal_hold: false,
+                retain_until: Some(_),
+                ..
+            })
+        ));
+        // An expired retention period is not reported alongside a legal hold.
+        assert!(matches!(
+            check_not_held(&held_upload(Some("2026-01-01T00:00:00Z"), true), now),
+            Err(AppError::UploadRetained {
+                legal_hold: true,
+                retain_until: None,
+                ..
+            })
+        ));
+    }
+
+    fn claimed_upload(status: &str, completion_claimed_at: Option<&str>) -> UploadMetadata {
+        let mut upload = held_upload(None, false);
+        upload.status = status.parse().unwrap();
+        upload.completion_claimed_at = completion_claimed_at.map(|at| {
+            DateTime::parse_from_rfc3339(at)
+                .unwrap()
+                .with_timezone(&Utc)
+        });
+        upload
+    }
+
+    #[test]
+    fn completion_claim_takes_over_a_stale_completing_upload() {
+        let now = DateTime::parse_from_rfc3339("2026-06-01T00:10:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        let stale = claimed_upload("completing", Some("2026-06-01T00:05:00Z"));
+        assert_eq!(
+            completion_claim(&stale, now).unwrap(),
+            CompletionClaim::TakeOver
+        );
+        // Uploads stuck before claims were stamped can be taken over too.
+        let unstamped = claimed_upload("completing", None);
+        assert_eq!(
+            completion_claim(&unstamped, now).unwrap(),
+            CompletionClaim::TakeOver
+        );
+    }
+
+    #[test]
+    fn completion_claim_respects_a_fresh_claim() {
+        let now = DateTime::parse_from_rfc3339("2026-06-01T00:10:00Z")
+            .unwrap()
+            .with_timezone(&Utc);
+
+        let fresh = claimed_upload("completing", Some("2026-06-01T00:05:01Z"));
+        assert!(matches!(
+            completion_claim(&fresh, now),
+            Err(AppError::UploadStateConflict { status, .. }) if status == "completing"
+        ));
+        assert_eq!(
+            completion_claim(&claimed_upload("in_progress", None), now).unwrap(),
+            CompletionClaim::Fresh
+        );
+        assert!(matches!(
+            completion_claim(&claimed_upload("completed", None), now),
+            Err(AppError::UploadAlreadyCompleted { .. })
+        ));
+    }
+
+    #[test]
+    fn multipart_session_missing_matches_no_such_upload() {
+        assert!(multipart_session_missing(&Error::RustError(
+            "completeMultipartUpload: The specified multipart upload does not exist. (10024)"
+                .to_string()
+        )));
+        assert!(!multipart_session_missing(&Error::RustError(
+            "completeMultipartUpload: We encountered an internal error. (10001)".to_string()
+        )));
+    }
+
+    #[test]
+    fn stored_object_size_prefers_ciphertext_sizes() {
+        let chunk = |chunk_size, stored_size| UploadChunkRecord {
+            chunk_index: 0,
+            chunk_size,
+            etag: None,
+            nonce: None,
+            stored_size,
+        };
+        assert_eq!(stored_object_size(&[chunk(10, None), chunk(5, None)]), 15);
+        assert_eq!(
+            stored_object_size(&[chunk(10, Some(42)), chunk(5, Some(37))]),
+            79
+        );
+    }
+
+    #[test]
+    fn upload_path_requires_a_single_id_segment() {
+        assert_eq!(upload_path("/api/upload/abc"), Some("abc"));
+        for path in ["/api/upload/", "/api/upload/abc/status", "/api/uploads/abc"] {
+            assert_eq!(upload_path(path), None, "{path}");
+        }
+    }
+
+    #[test]
+    fn status_conflict_maps_terminal_and_busy_states() {
+        assert!(matches!(
+            status_conflict("u".into(), UploadStatus::Completed),
+            AppError::UploadAlreadyCompleted { .. }
+        ));
+        assert!(matches!(
+            status_conflict("u".into(), UploadStatus::Cancelled),
+            AppError::UploadCancelled { .. }
+        ));
+        assert!(matches!(
+            status_conflict("u".into(), UploadStatus::Completing),
+            AppError::UploadStateConflict { ref status, .. } if status == "completing"
+        ));
+    }
+
     fn chunk(index: u16, size: u64) -> UploadChunkRecord {
         UploadChunkRecord {
             chunk_index: index,
             chunk_size: size,
+            nonce: None,
+            stored_size: None,
             etag: Some(format!("etag-{index}")),
         }
     }
@@ -0,0 +1,326 @@
+//! # Version Handlers
+//!
+//! Version history for uploads initialized with the `version` key collision
+//! strategy. Every completed upload of the same logical key by the same user is
+//! one version, stored under its own R2 key; any of those uploads addresses
+//! the history:
+//!
+//! - `GET /api/upload/{id}/versions` lists versions, newest first.
+//! - `GET /api/upload/{id}/versions/{version}` downloads one; `current`
+//!   selects the current version.
+//! - `POST /api/upload/{id}/versions/{version}/restore` makes a version current.
+
+use worker::*;
+
+use crate::config::Config;
+use crate::database::{ChunkList, DatabaseService, VersionRef, VersionScope};
+use crate::errors::{AppError, AppResult};
+use crate::logging::RequestContext;
+use crate::middleware::RateLimitMiddleware;
+use crate::models::{FileVersion, UploadMetadata};
+
+use super::download::serve_upload_object;
+
+/// A request path under `/api/upload/{id}/versions`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionPath<'a> {
+    pub upload_id: &'a str,
+    /// The `{version}` segment, absent when listing.
+    pub version: Option<&'a str>,
+    /// Whether the path ends in `/restore`.
+    pub restore: bool,
+}
+
+impl<'a> VersionPath<'a> {
+    /// Splits a version route path, or `None` for any other path.
+    pub fn parse(path: &'a str) -> Option<Self> {
+        let rest = path.strip_prefix("/api/upload/")?;
+        let segments: Vec<&str> = rest.split('/').collect();
+        let (upload_id, version, restore) = match segments.as_slice() {
+            [upload_id, "versions"] => (*upload_id, None, false),
+            [upload_id, "versions", version] => (*upload_id, Some(*version), false),
+            [upload_id, "versions", version, "restore"] => (*upload_id, Some(*version), true),
+            _ => return None,
+        };
+        if upload_id.is_empty() || version.is_some_and(str::is_empty) {
+            return None;
+        }
+        Some(Self {
+            upload_id,
+            version,
+            restore,
+        })
+    }
+}
+
+/// List every version in the upload's history.
+pub async fn list_versions(
+    req: Request,
+    env: &Env,
+    config: &Config,
+    ctx: &RequestContext,
+) -> AppResult<Response> {
+    let url = request_url(&req)?;
+    let path = parse_path(url.path())?;
+    let (database, metadata) = load_versioned(env, config, ctx, &path).await?;
+    let scope = version_scope(&metadata)?;
+
+    let versions = database.list_versions(scope).await?;
+
+    let body = serde_json::json!({
+        "logical_key": scope.logical_key,
+        "versions": versions,
+    });
+    Response::from_json(&body).map_err(|_| AppError::InternalError {
+        message: "Failed to serialize version list".to_string(),
+    })
+}
+
+/// Stream the object holding one version of the upload's logical key,
+/// decrypting it and honouring `Range` as for upload downloads.
+pub async fn download_version(
+    req: Request,
+    env: &Env,
+    config: &Config,
+    ctx: &RequestContext,
+) -> AppResult<Response> {
+    let url = request_url(&req)?;
+    let path = parse_path(url.path())?;
+    let (database, metadata) = load_versioned(env, config, ctx, &path).await?;
+    let version = find_version(&database, version_scope(&metadata)?, &path).await?;
+
+    let Some(upload) = database
+        .get_upload(&version.upload_id, ChunkList::Skip)
+        .await?
+    else {
+        return Err(AppError::UploadNotFound {
+            upload_id: version.upload_id,
+        });
+    };
+
+    let mut response = serve_upload_object(&req, env, config, &database, &upload).await?;
+    response
+        .headers_mut()
+        .set("X-Version", &version.version.to_string())
+        .map_err(|err| AppError::InternalError {
+            message: format!("Failed to build download headers: {err}"),
+        })?;
+    Ok(response)
+}
+
+/// Make a version of the upload's logical key the current one.
+pub async fn restore_version(
+    req: Request,
+    env: &Env,
+    config: &Config,
+    ctx: &RequestContext,
+) -> AppResult<Response> {
+    let url = request_url(&req)?;
+    let path = parse_path(url.path())?;
+    let (database, metadata) = load_versioned(env, config, ctx, &path).await?;
+    let scope = version_scope(&metadata)?;
+    let version = find_version(&database, scope, &path).await?;
+
+    if !database.restore_version(scope, version.version).await? {
+        return Err(AppError::VersionNotFound {
+            logical_key: scope.logical_key.to_string(),
+            version: version.version.to_string(),
+        });
+    }
+
+    let body = serde_json::json!({
+        "logical_key": scope.logical_key,
+        "current_version": version.version,
+        "upload_id": version.upload_id,
+        "r2_key": version.r2_key,
+    });
+    Response::from_json(&body).map_err(|_| AppError::InternalError {
+        message: "Failed to serialize restore response".to_string(),
+    })
+}
+
+fn request_url(req: &Request) -> AppResult<Url> {
+    req.url().map_err(|err| AppError::InternalError {
+        message: format!("Failed to parse request URL: {err}"),
+    })
+}
+
+fn parse_path(path: &str) -> AppResult<VersionPath<'_>> {
+    VersionPath::parse(path).ok_or_else(|| AppError::ValidationError {
+        message: "Upload ID missing from path".to_string(),
+    })
+}
+
+/// Loads the addressed upload and applies the owner's rate limit.
+async fn load_versioned(
+    env: &Env,
+    config: &Config,
+    ctx: &RequestContext,
+    path: &VersionPath<'_>,
+) -> AppResult<(DatabaseService, UploadMetadata)> {
+    ctx.set_upload_id(path.upload_id);
+
+    let database = DatabaseService::new(env, &config.database_name)?;
+    let Some(metadata) = database.get_upload(path.upload_id, ChunkList::Skip).await? else {
+        return Err(AppError::UploadNotFound {
+            upload_id: path.upload_id.to_string(),
+        });
+    };
+    ctx.set_user_id(&metadata.user_id);
+    RateLimitMiddleware::check_user(env, config, ctx, &metadata.user_role, &metadata.user_id)
+        .await?;
+    Ok((database, metadata))
+}
+
+/// The upload owner's history of its logical key, failing for uploads that
+/// were not initialized as versions.
+fn version_scope(metadata: &UploadMetadata) -> AppResult<VersionScope<'_>> {
+    VersionScope::of(metadata).ok_or_else(|| AppError::ValidationError {
+        message: format!(
+            "Upload {} was not initialized with the version key collision strategy",
+            metadata.upload_id
+        ),
+    })
+}
+
+async fn find_version(
+    database: &DatabaseService,
+    scope: VersionScope<'_>,
+    path: &VersionPath<'_>,
+) -> AppResult<FileVersion> {
+    let selector = path.version.unwrap_or("current");
+    let version = parse_version(selector)?;
+    database
+        .get_version(scope, version)
+        .await?
+        .ok_or_else(|| AppError::VersionNotFound {
+            logical_key: scope.logical_key.to_string(),
+            version: selector.to_string(),
+        })
+}
+
+/// Parses a `{version}` path segment: `current` or a number from 1.
+fn parse_version(segment: &str) -> AppResult<VersionRef> {
+    if segment == "current" {
+        return Ok(VersionRef::Current);
+    }
+    segment
+        .parse::<u32>()
+        .ok()
+        .filter(|number| *number > 0)
+        .map(VersionRef::Number)
+        .ok_or_else(|| AppError::InvalidField {
+            field: "version".to_string(),
+            reason: "Must be 'current' or a version number from 1".to_string(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn version_path_parses_each_route() {
+        assert_eq!(
+            VersionPath::parse("/api/upload/abc/versions"),
+            Some(VersionPath {
+                upload_id: "abc",
+                version: None,
+                restore: false,
+            })
+        );
+        assert_eq!(
+            VersionPath::parse("/api/upload/abc/versions/3"),
+            Some(VersionPath {
+                upload_id: "abc",
+                version: Some("3"),
+                restore: false,
+            })
+        );
+        assert_eq!(
+            VersionPath::parse("/api/upload/abc/versions/2/restore"),
+            Some(VersionPath {
+                upload_id: "abc",
+                version: Some("2"),
+                restore: true,
+            })
+        );
+    }
+
+    #[test]
+    fn version_path_rejects_other_paths() {
+        for path in [
+            "/api/upload/abc/status",
+            "/api/upload//versions",
+            "/api/upload/abc/versions/",
+            "/api/upload/abc/versions/2/undo",
+            "/api/admin/abc/versions",
+        ] {
+            assert_eq!(VersionPath::parse(path), None, "{path}");
+        }
+    }
+
+    #[test]
+    fn parse_version_accepts_current_and_positive_numbers() {
+        assert_eq!(parse_version("current").unwrap(), VersionRef::Current);
+        assert_eq!(parse_version("7").unwrap(), VersionRef::Number(7));
+        for bad in ["0", "-1", "latest"] {
+            assert!(matches!(
+                parse_version(bad),
+                Err(AppError::InvalidField { .. })
+            ));
+        }
+    }
+
+    fn versioned_upload(user_id: &str, logical_key: Option<&str>) -> UploadMetadata {
+        serde_json::from_value(serde_json::json!({
+            "upload_id": format!("{user_id}-upload"),
+            "file_name": "photo.jpg",
+            "total_size": 1,
+            "created_at": "2026-01-01T00:00:00Z",
+            "updated_at": "2026-01-01T00:00:00Z",
+            "user_role": "member",
+            "content_type": "image/jpeg",
+            "status": "completed",
+            "chunks": [0],
+            "r2_key": format!("shared/photo.{user_id}-upload.jpg"),
+            "bucket": "STORAGE_BUCKET",
+            "user_id": user_id,
+            "r2_upload_id": "r2",
+            "metadata_stripped": false,
+            "logical_key": logical_key,
+            "encryption": "none",
+            "legal_hold": false,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn version_scope_separates_users_sharing_a_logical_key() {
+        let alice = versioned_upload("alice", Some("shared/photo.jpg"));
+        let bob = versioned_upload("bob", Some("shared/photo.jpg"));
+
+        let scope = version_scope(&alice).unwrap();
+        assert_eq!(
+            scope,
+            VersionScope {
+                bucket: "STORAGE_BUCKET",
+                user_id: "alice",
+                logical_key: "shared/photo.jpg",
+            }
+        );
+        assert_ne!(scope, version_scope(&bob).unwrap());
+        assert_eq!(
+            scope,
+            version_scope(&versioned_upload("alice", Some("shared/photo.jpg"))).unwrap()
+        );
+    }
+
+    #[test]
+    fn version_scope_rejects_unversioned_uploads() {
+        assert!(matches!(
+            version_scope(&versioned_upload("alice", None)),
+            Err(AppError::ValidationError { .. })
+        ));
+    }
+}
@@ -0,0 +1,226 @@
+//! # Idempotent Requests
+//!
+//! Requests to `init`, `complete` and `cancel` may carry an `Idempotency-Key`
+//! header. The first request with a key claims it in the D1 `idempotency_keys`
+//! table (scoped by route) and stores its successful response; retries with
+//! the same key and body replay that response with `Idempotent-Replayed: true`
+//! instead of running the handler again.
+//!
+//! - A retry with a different body gets `409 IDEMPOTENCY_KEY_MISMATCH`.
+//! - A retry while the first request is still running gets `409 IDEMPOTENCY_KEY_IN_USE`.
+//! - Failed requests release the key, so a retry executes from scratch.
+//! - Keys expire after `Config::idempotency_ttl_secs`; a claim whose request
+//!   never finished can be taken over after a short lease.
+
+use std::future::Future;
+
+use chrono::{Duration, Utc};
+use sha2::{Digest, Sha256};
+use worker::{Env, Request, Response};
+
+use crate::config::Config;
+use crate::constants::{
+    HEADER_IDEMPOTENCY_KEY, HEADER_IDEMPOTENT_REPLAYED, IDEMPOTENCY_PENDING_LEASE_SECS,
+    MAX_IDEMPOTENCY_KEY_LENGTH,
+};
+use crate::database::{DatabaseService, IdempotencyRecord};
+use crate::errors::{AppError, AppResult};
+use crate::logging::{LogLevel, RequestContext};
+
+/// What to do with a request whose key is already claimed.
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    /// Replay the stored response.
+    Replay { status_code: u16, body: String },
+    /// The key was used with a different body.
+    Mismatch,
+    /// The original request has not stored a response yet.
+    InProgress,
+}
+
+/// Runs `handler` at most once per `Idempotency-Key` on the current route.
+///
+/// Requests without the header go straight to `handler`.
+pub async fn run_idempotent<F, Fut>(
+    req: Request,
+    env: &Env,
+    config: &Config,
+    ctx: &RequestContext,
+    handler: F,
+) -> AppResult<Response>
+where
+    F: FnOnce(Request) -> Fut,
+    Fut: Future<Output = AppResult<Response>>,
+{
+    let Some(key) = req.headers().get(HEADER_IDEMPOTENCY_KEY).ok().flatten() else {
+        return handler(req).await;
+    };
+    validate_key(&key)?;
+
+    let body = req
+        .clone()
+        .map_err(|err| AppError::InternalError {
+            message: format!("Failed to clone request: {err}"),
+        })?
+        .bytes()
+        .await
+        .map_err(|err| AppError::ValidationError {
+            message: format!("Failed to read request body: {err}"),
+        })?;
+    let request_hash = hash_body(&body);
+
+    let route = ctx.route();
+    let database = DatabaseService::new(env, &config.database_name)?;
+    let now = Utc::now();
+    let ttl = Duration::seconds(i64::try_from(config.idempotency_ttl_secs).unwrap_or(i64::MAX));
+    let claimed = database
+        .claim_idempotency_key(
+            route,
+            &key,
+            &request_hash,
+            now,
+            now.checked_add_signed(ttl).unwrap_or(now),
+            now - Duration::seconds(IDEMPOTENCY_PENDING_LEASE_SECS),
+        )
+        .await?;
+
+    if !claimed {
+        let record = database.get_idempotency_record(route, &key).await?;
+        return match record.map(|record| outcome(record, &request_hash)) {
+            Some(Outcome::Replay { status_code, body }) => replay_response(status_code, body),
+            Some(Outcome::Mismatch) => Err(AppError::IdempotencyKeyMismatch { key }),
+            // The entry was released between the claim and the lookup.
+            Some(Outcome::InProgress) | None => Err(AppError::IdempotencyKeyInUse { key }),
+        };
+    }
+
+    match handler(req).await {
+        Ok(mut response) if (200..300).contains(&response.status_code()) => {
+            let stored = match response.cloned() {
+                Ok(mut copy) => copy.text().await.ok(),
+                Err(_) => None,
+            };
+            let saved = match stored {
+                Some(body) => database
+                    .save_idempotent_response(route, &key, response.status_code(), &body)
+                    .await
+                    .is_ok(),
+                None => false,
+            };
+            if !saved {
+                release(&database, ctx, route, &key).await;
+            }
+            Ok(response)
+        }
+        result => {
+            release(&database, ctx, route, &key).await;
+            result
+        }
+    }
+}
+
+/// Frees the key so a retry runs the handler again; failures only cost the
+/// client a wait until the pending lease runs out.
+async fn release(database: &DatabaseService, ctx: &RequestContext, route: &str, key: &str) {
+    if database.release_idempotency_key(route, key).await.is_err() {
+        ctx.log(LogLevel::Warn, "failed to release idempotency key");
+    }
+}
+
+/// Accepts 1–255 visible ASCII characters.
+fn validate_key(key: &str) -> AppResult<()> {
+    if key.is_empty()
+        || key.len() > MAX_IDEMPOTENCY_KEY_LENGTH
+        || !key.bytes().all(|byte| byte.is_ascii_graphic())
+    {
+        return Err(AppError::InvalidField {
+            field: HEADER_IDEMPOTENCY_KEY.to_string(),
+            reason: format!("Must be 1-{MAX_IDEMPOTENCY_KEY_LENGTH} visible ASCII characters"),
+        });
+    }
+    Ok(())
+}
+
+/// Hex-encoded SHA-256 of the request body.
+fn hash_body(body: &[u8]) -> String {
+    Sha256::digest(body)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn outcome(record: IdempotencyRecord, request_hash: &str) -> Outcome {
+    if record.request_hash != request_hash {
+        return Outcome::Mismatch;
+    }
+    match (record.status_code, record.response_body) {
+        (Some(status_code), Some(body)) => Outcome::Replay { status_code, body },
+        _ => Outcome::InProgress,
+    }
+}
+
+fn replay_response(status_code: u16, body: String) -> AppResult<Response> {
+    let build = || -> worker::Result<Response> {
+        let mut response = Response::from_bytes(body.into_bytes())?.with_status(status_code);
+        response
+            .headers_mut()
+            .set("Content-Type", "application/json")?;
+        response
+            .headers_mut()
+            .set(HEADER_IDEMPOTENT_REPLAYED, "true")?;
+        Ok(response)
+    };
+    build().map_err(|err| AppError::InternalError {
+        message: format!("Failed to build replayed response: {err}"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(hash: &str, status_code: Option<u16>, body: Option<&str>) -> IdempotencyRecord {
+        IdempotencyRecord {
+            request_hash: hash.to_string(),
+            status_code,
+            response_body: body.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn hash_body_is_hex_sha256() {
+        assert_eq!(
+            hash_body(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+
+    #[test]
+    fn validate_key_rejects_empty_long_or_non_printable() {
+        assert!(validate_key("9f1c-retry_01").is_ok());
+        for key in ["", "has space", "tab\t", &"k".repeat(256)] {
+            assert!(validate_key(key).is_err(), "{key:?} should be rejected");
+        }
+    }
+
+    #[test]
+    fn outcome_replays_matching_completed_request() {
+        let outcome = outcome(record("abc", Some(200), Some("{}")), "abc");
+        assert_eq!(
+            outcome,
+            Outcome::Replay {
+                status_code: 200,
+                body: "{}".into()
+            }
+        );
+    }
+
+    #[test]
+    fn outcome_detects_mismatch_before_progress() {
+        assert_eq!(outcome(record("abc", None, None), "def"), Outcome::Mismatch);
+        assert_eq!(
+            outcome(record("abc", None, None), "abc"),
+            Outcome::InProgress
+        );
+    }
+}
@@ -7,13 +7,22 @@
 //! ## Modules
 //!
 //! - `router` — pattern-based HTTP dispatch.
-//! - `middleware` — CORS preflight, request validation.
-//! - `handlers` — upload lifecycle endpoints and health check.
+//! - `logging` — request IDs and structured JSON log lines.
+//! - `metrics` — Analytics Engine metrics behind a pluggable `MetricsSink`.
+//! - `middleware` — CORS preflight, rate limiting, admin auth, request validation.
+//! - `rate_limit` — token buckets in a Durable Object or KV behind a `CounterStore`.
+//! - `handlers` — upload lifecycle endpoints, admin endpoints and health check.
+//! - `audit` — append-only audit log of upload lifecycle actions.
+//! - `idempotency` — `Idempotency-Key` replay for init, complete and cancel.
+//! - `expiry` — scheduled expiry of abandoned uploads.
 //! - `database` — D1-backed persistence for upload and chunk records.
+//! - `migrations` — embedded, versioned D1 schema migrations and the startup schema check.
+//! - `media` — first-chunk metadata extraction and stripping, and image variants after completion.
 //! - `models` — shared types (`UploadMetadata`, `UploadStatus`, `UserRole`).
 //! - `config` — KV-loaded configuration with default fallbacks.
 //! - `errors` — structured `AppError` to HTTP response mapping.
 //! - `utils` — R2 key generation and CORS headers.
+//! - `crypto` — AES-GCM encryption of chunks at rest and ranged decryption.
 //!
 //! ## Routes
 //!
@@ -23,52 +32,123 @@
 //! PUT  /api/upload/chunk            - Upload a chunk
 //! POST /api/upload/complete         - Finalize the multipart upload
 //! POST /api/upload/cancel           - Cancel an in-flight upload
+//! DELETE /api/upload/{id}           - Delete a completed upload (refused while held)
 //! GET  /api/upload/{id}/status      - Get upload status
+//! GET  /api/upload/{id}/download    - Download a completed upload (Range supported)
+//! GET  /api/upload/{id}/variants/{name}             - Download an image variant
+//! GET  /api/upload/{id}/versions    - List versions of a versioned upload's key
+//! GET  /api/upload/{id}/versions/{version}          - Download a version
+//! POST /api/upload/{id}/versions/{version}/restore  - Make a version current
+//! GET  /api/admin/audit             - Query the audit log (admin token)
+//! GET  /api/admin/migrations        - Show schema migration status (admin token)
+//! POST /api/admin/migrations        - Apply pending schema migrations (admin token)
+//! GET  /api/admin/config            - Show the stored configuration (admin token)
+//! PUT  /api/admin/config            - Replace the stored configuration (admin token)
+//! PATCH /api/admin/config           - Merge-patch the stored configuration (admin token)
+//! PUT|DELETE /api/admin/uploads/{id}/retention  - Set or clear a retention period (admin token)
+//! PUT|DELETE /api/admin/uploads/{id}/legal-hold - Set or clear a legal hold (admin token)
 //! ```
 
-use std::sync::{Arc, OnceLock};
+use std::sync::Arc;
 use worker::*;
 
+mod audit;
 mod config;
 mod constants;
+mod crypto;
 mod database;
 mod errors;
+mod expiry;
 mod handlers;
+mod idempotency;
+mod logging;
+mod media;
+mod metrics;
 mod middleware;
+mod migrations;
 mod models;
+mod rate_limit;
 mod router;
 mod utils;
 
 use config::Config;
-use constants::STORAGE_CONFIG_KV_NAME;
-
-static CONFIG_CACHE: OnceLock<Arc<Config>> = OnceLock::new();
+use constants::HEADER_REQUEST_ID;
+use errors::AppError;
+use logging::{LogLevel, RequestContext};
 
 /// Worker fetch entry point.
 ///
-/// Installs the panic hook, resolves a cached `Config`, and hands the request
-/// to `router::handle_request`. Configuration is cached per worker isolate
-/// via `OnceLock`, so the KV round-trip happens at most once per isolate
-/// lifetime — not per request.
+/// Installs the panic hook, resolves the cached `Config`, and hands the request
+/// to `router::handle_request`. Configuration is cached per worker isolate and
+/// re-read from KV every `config_refresh_secs`, not per request. When no valid
+/// configuration can be loaded, `/health` still answers with a 503 naming the
+/// problem.
+///
+/// Every response carries an `X-Request-Id` header and produces exactly one
+/// structured completion log line and `request` metric. Unhandled `worker::Error`s are converted
+/// into a 500 JSON error body so the request ID still reaches the client.
 #[event(fetch)]
 pub async fn main(req: Request, env: Env, _ctx: Context) -> Result<Response> {
     console_error_panic_hook::set_once();
 
-    console_log!("Request: {} {}", req.method(), req.url()?.path());
+    let ctx = RequestContext::from_request(&req).with_metrics(metrics::sink_from_env(&env));
+
+    let result = match load_config(&env).await {
+        Ok(config) => router::handle_request(req, env, config, &ctx).await,
+        Err(err) if req.method() == Method::Get && req.path() == "/health" => {
+            handlers::handle_health_check_without_config(&err.to_string())
+        }
+        Err(err) => Err(err),
+    };
 
-    let config = load_config(&env).await?;
+    let mut response = match result {
+        Ok(response) => response,
+        Err(err) => {
+            let error = AppError::InternalError {
+                message: err.to_string(),
+            };
+            ctx.set_error_code(error.code());
+            error.to_response(ctx.request_id())?
+        }
+    };
 
-    router::handle_request(req, env, config).await
+    let _ = response
+        .headers_mut()
+        .set(HEADER_REQUEST_ID, ctx.request_id());
+    ctx.finish(response.status_code());
+
+    Ok(response)
 }
 
-/// Loads configuration once per isolate and returns the cached `Arc<Config>`.
-async fn load_config(env: &Env) -> Result<Arc<Config>> {
-    if let Some(config) = CONFIG_CACHE.get() {
-        return Ok(config.clone());
+/// Worker scheduled entry point.
+///
+/// The hourly Cron Trigger expires abandoned uploads (see `expiry`). Failures
+/// are logged and left for the next run.
+#[event(scheduled)]
+pub async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    console_error_panic_hook::set_once();
+
+    let result = match load_config(&env).await {
+        Ok(config) => expiry::expire_abandoned_uploads(&env, &config)
+            .await
+            .map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    };
+    match result {
+        Ok(expired) => logging::log(
+            LogLevel::Info,
+            "expired abandoned uploads",
+            serde_json::json!({ "expired": expired }),
+        ),
+        Err(err) => logging::log(
+            LogLevel::Error,
+            &format!("upload expiry failed: {err}"),
+            serde_json::json!({}),
+        ),
     }
+}
 
-    let kv = env.kv(STORAGE_CONFIG_KV_NAME)?;
-    let config = Arc::new(Config::load(&kv).await?);
-    let _ = CONFIG_CACHE.set(config.clone());
-    Ok(config)
+/// Returns the isolate's cached `Arc<Config>`, refreshing it from KV when stale.
+async fn load_config(env: &Env) -> Result<Arc<Config>> {
+    config::current(env).await
 }
@@ -0,0 +1,342 @@
+//! # Structured Logging
+//!
+//! JSON log lines and per-request context for the worker. Every request gets a
+//! [`RequestContext`] carrying its `X-Request-Id` (propagated from the client
+//! when valid, generated otherwise). Handlers enrich the context with the
+//! upload and user they act on, and emit metrics through the context's
+//! [`MetricsSink`]. When the response is ready, the entry point emits a single
+//! completion record (and the matching `request` metric):
+//!
+//! ```json
+//! {
+//!   "level": "info",
+//!   "message": "request completed",
+//!   "request_id": "3f0c0d6e-7d8f-4b59-9a53-0a5d3c1b2e4f",
+//!   "method": "PUT",
+//!   "route": "PUT /api/upload/chunk",
+//!   "status": 200,
+//!   "latency_ms": 412,
+//!   "upload_id": "550e8400-e29b-41d4-a716-446655440000",
+//!   "user_id": "user_12345",
+//!   "error_code": null
+//! }
+//! ```
+
+use std::cell::{Cell, RefCell};
+
+use serde_json::{json, Map, Value};
+use uuid::Uuid;
+#[cfg(target_arch = "wasm32")]
+use worker::{console_error, console_log, console_warn};
+use worker::{Date, Request};
+
+use crate::constants::{HEADER_CONNECTING_IP, HEADER_REQUEST_ID};
+use crate::metrics::{Metric, MetricsSink, NoopSink};
+
+/// Longest client-supplied request ID that is propagated as-is.
+const MAX_REQUEST_ID_LENGTH: usize = 128;
+
+/// Severity of a structured log line.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LogLevel {
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error",
+        }
+    }
+}
+
+/// Writes a JSON log line with `level`, `message`, and any extra `fields`.
+///
+/// `fields` must be a JSON object; other values are ignored. Native (test)
+/// builds have no JS console, so lines go to stderr instead.
+pub fn log(level: LogLevel, message: &str, fields: Value) {
+    let line = build_record(level, message, fields).to_string();
+
+    #[cfg(target_arch = "wasm32")]
+    match level {
+        LogLevel::Info => console_log!("{line}"),
+        LogLevel::Warn => console_warn!("{line}"),
+        LogLevel::Error => console_error!("{line}"),
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    eprintln!("{line}");
+}
+
+fn build_record(level: LogLevel, message: &str, fields: Value) -> Value {
+    let mut record = Map::new();
+    record.insert("level".into(), level.as_str().into());
+    record.insert("message".into(), message.into());
+    if let Value::Object(fields) = fields {
+        record.extend(fields);
+    }
+    Value::Object(record)
+}
+
+/// Per-request context shared by the router and handlers: logging fields and
+/// the metrics sink.
+///
+/// Fields set by handlers use interior mutability so the context can be passed
+/// by shared reference alongside `&Env` and `&Config`.
+pub struct RequestContext {
+    request_id: String,
+    method: String,
+    started_at_ms: u64,
+    client_ip: Option<String>,
+    user_agent: Option<String>,
+    route: Cell<&'static str>,
+    upload_id: RefCell<Option<String>>,
+    user_id: RefCell<Option<String>>,
+    error_code: Cell<Option<&'static str>>,
+    metrics: Box<dyn MetricsSink>,
+}
+
+impl RequestContext {
+    /// Builds a context for `req`, propagating a valid incoming `X-Request-Id`.
+    pub fn from_request(req: &Request) -> Self {
+        let header = |name: &str| req.headers().get(name).ok().flatten();
+        let mut ctx = Self::new(
+            resolve_request_id(header(HEADER_REQUEST_ID).as_deref()),
+            req.method().to_string(),
+            Date::now().as_millis(),
+        );
+        ctx.client_ip = header(HEADER_CONNECTING_IP);
+        ctx.user_agent = header("User-Agent");
+        ctx
+    }
+
+    fn new(request_id: String, method: String, started_at_ms: u64) -> Self {
+        Self {
+            request_id,
+            method,
+            started_at_ms,
+            client_ip: None,
+            user_agent: None,
+            route: Cell::new("unmatched"),
+            upload_id: RefCell::new(None),
+            user_id: RefCell::new(None),
+            error_code: Cell::new(None),
+            metrics: Box::new(NoopSink),
+        }
+    }
+
+    /// Replaces the metrics sink (defaults to [`NoopSink`]).
+    pub fn with_metrics(mut self, metrics: Box<dyn MetricsSink>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Emits a metric through the request's sink.
+    pub fn record(&self, metric: Metric) {
+        self.metrics.record(metric);
+    }
+
+    /// The request ID echoed in the `X-Request-Id` header and error bodies.
+    pub fn request_id(&self) -> &str {
+        &self.request_id
+    }
+
+    /// Client IP from `CF-Connecting-IP`, if present.
+    pub fn client_ip(&self) -> Option<&str> {
+        self.client_ip.as_deref()
+    }
+
+    /// The request's `User-Agent`, if present.
+    pub fn user_agent(&self) -> Option<&str> {
+        self.user_agent.as_deref()
+    }
+
+    /// Records the matched route pattern (e.g. `GET /api/upload/{id}/status`).
+    pub fn set_route(&self, route: &'static str) {
+        self.route.set(route);
+    }
+
+    /// The matched route pattern, `unmatched` until the router sets it.
+    pub fn route(&self) -> &'static str {
+        self.route.get()
+    }
+
+    /// Records the upload the request operates on.
+    pub fn set_upload_id(&self, upload_id: &str) {
+        *self.upload_id.borrow_mut() = Some(upload_id.to_string());
+    }
+
+    /// Records the user the request acts for.
+    pub fn set_user_id(&self, user_id: &str) {
+        *self.user_id.borrow_mut() = Some(user_id.to_string());
+    }
+
+    /// The upload the request operates on, once a handler has recorded it.
+    pub fn upload_id(&self) -> Option<String> {
+        self.upload_id.borrow().clone()
+    }
+
+    /// The user the request acts for, once a handler has recorded it.
+    pub fn user_id(&self) -> Option<String> {
+        self.user_id.borrow().clone()
+    }
+
+    /// Records the `AppError` code returned to the client.
+    pub fn set_error_code(&self, code: &'static str) {
+        self.error_code.set(Some(code));
+    }
+
+    /// Logs a handler-level event tagged with the request, upload, and user IDs.
+    pub fn log(&self, level: LogLevel, message: &str) {
+        log(
+            level,
+            message,
+            json!({
+                "request_id": self.request_id,
+                "upload_id": *self.upload_id.borrow(),
+                "user_id": *self.user_id.borrow(),
+            }),
+        );
+    }
+
+    /// Milliseconds elapsed since the request arrived.
+    pub fn elapsed_ms(&self) -> u64 {
+        Date::now().as_millis().saturating_sub(self.started_at_ms)
+    }
+
+    /// Emits the completion log line and `request` metric for this request.
+    pub fn finish(&self, status: u16) {
+        self.finish_with_latency(status, self.elapsed_ms());
+    }
+
+    fn finish_with_latency(&self, status: u16, latency_ms: u64) {
+        self.record(Metric::Request {
+            route: self.route.get(),
+            status,
+            latency_ms,
+            error_code: self.error_code.get(),
+        });
+
+        let level = match status {
+            500.. => LogLevel::Error,
+            400.. => LogLevel::Warn,
+            _ => LogLevel::Info,
+        };
+        log(
+            level,
+            "request completed",
+            self.completion_fields(status, latency_ms),
+        );
+    }
+
+    fn completion_fields(&self, status: u16, latency_ms: u64) -> Value {
+        json!({
+            "request_id": self.request_id,
+            "method": self.method,
+            "route": self.route.get(),
+            "status": status,
+            "latency_ms": latency_ms,
+            "upload_id": *self.upload_id.borrow(),
+            "user_id": *self.user_id.borrow(),
+            "error_code": self.error_code.get(),
+        })
+    }
+}
+
+/// Returns the incoming request ID when it is safe to propagate, otherwise a new UUID.
+///
+/// Accepted IDs are 1–128 characters of ASCII alphanumerics, `-`, `_`, `.` or `:`,
+/// which keeps log lines and response headers free of injected content.
+fn resolve_request_id(incoming: Option<&str>) -> String {
+    match incoming.map(str::trim) {
+        Some(id)
+            if !id.is_empty()
+                && id.len() <= MAX_REQUEST_ID_LENGTH
+                && id
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':')) =>
+        {
+            id.to_string()
+        }
+        _ => Uuid::new_v4().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metrics::InMemorySink;
+
+    #[test]
+    fn resolve_request_id_propagates_valid_incoming_id() {
+        assert_eq!(resolve_request_id(Some("req-123:abc")), "req-123:abc");
+    }
+
+    #[test]
+    fn resolve_request_id_replaces_unsafe_or_missing_ids() {
+        for incoming in [
+            None,
+            Some(""),
+            Some("bad id\n{}"),
+            Some(&"a".repeat(129)[..]),
+        ] {
+            let id = resolve_request_id(incoming);
+            assert!(
+                Uuid::parse_str(&id).is_ok(),
+                "expected generated UUID, got {id}"
+            );
+        }
+    }
+
+    #[test]
+    fn completion_fields_include_enriched_context() {
+        let ctx = RequestContext::new("req-1".into(), "PUT".into(), 0);
+        ctx.set_route("PUT /api/upload/chunk");
+        ctx.set_upload_id("upload-1");
+        ctx.set_user_id("user-1");
+        ctx.set_error_code("UPLOAD_NOT_FOUND");
+
+        let fields = ctx.completion_fields(404, 12);
+        assert_eq!(fields["request_id"], "req-1");
+        assert_eq!(fields["method"], "PUT");
+        assert_eq!(fields["route"], "PUT /api/upload/chunk");
+        assert_eq!(fields["status"], 404);
+        assert_eq!(fields["latency_ms"], 12);
+        assert_eq!(fields["upload_id"], "upload-1");
+        assert_eq!(fields["user_id"], "user-1");
+        assert_eq!(fields["error_code"], "UPLOAD_NOT_FOUND");
+    }
+
+    #[test]
+    fn finish_records_request_metric() {
+        let sink = InMemorySink::default();
+        let ctx = RequestContext::new("req-1".into(), "POST".into(), 0)
+            .with_metrics(Box::new(sink.clone()));
+        ctx.set_route("POST /api/upload/complete");
+        ctx.set_error_code("UPLOAD_CANCELLED");
+
+        ctx.finish_with_latency(409, 7);
+
+        assert_eq!(
+            sink.events(),
+            vec![Metric::Request {
+                route: "POST /api/upload/complete",
+                status: 409,
+                latency_ms: 7,
+                error_code: Some("UPLOAD_CANCELLED"),
+            }]
+        );
+    }
+
+    #[test]
+    fn build_record_merges_fields_after_level_and_message() {
+        let record = build_record(LogLevel::Warn, "hello", json!({ "request_id": "r" }));
+        assert_eq!(record["level"], "warn");
+        assert_eq!(record["message"], "hello");
+        assert_eq!(record["request_id"], "r");
+    }
+}
@@ -0,0 +1,822 @@
+//! # Media Metadata
+//!
+//! Extracts [`MediaInfo`] from the leading bytes of an upload. Parsing works on
+//! whatever prefix of the file is available (normally the first chunk), so every
+//! parser tolerates truncated input and simply reports fewer fields.
+//!
+//! ## Supported Formats
+//!
+//! - **Images**: PNG (`IHDR`, `eXIf`), JPEG (`SOFn`, `APP1` EXIF), WebP
+//!   (`VP8 `/`VP8L`/`VP8X`, `EXIF`), GIF (logical screen descriptor)
+//! - **Video**: MP4/MOV `moov` box (`mvhd`, `tkhd`, `hdlr`, `stsd`). Files whose
+//!   `moov` box is stored after the media data report container format only.
+//! - **Audio**: WAV `fmt `/`data` chunks, MP3 ID3v2 text frames and the first
+//!   MPEG audio frame header
+
+use std::collections::BTreeMap;
+
+use exif::{In, Reader, Tag, Value};
+
+use crate::models::MediaInfo;
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+
+/// EXIF tags copied into [`MediaInfo::tags`]. GPS tags are excluded on purpose
+/// so location data never reaches API responses.
+const EXIF_TAGS: &[(Tag, &str)] = &[
+    (Tag::Make, "make"),
+    (Tag::Model, "model"),
+    (Tag::Software, "software"),
+    (Tag::DateTimeOriginal, "date_time_original"),
+    (Tag::Orientation, "orientation"),
+    (Tag::ExposureTime, "exposure_time"),
+    (Tag::FNumber, "f_number"),
+    (Tag::PhotographicSensitivity, "iso"),
+    (Tag::FocalLength, "focal_length"),
+    (Tag::LensModel, "lens_model"),
+];
+
+/// ID3v2 text frames copied into [`MediaInfo::tags`].
+const ID3_FRAMES: &[(&[u8; 4], &str)] = &[
+    (b"TIT2", "title"),
+    (b"TPE1", "artist"),
+    (b"TALB", "album"),
+    (b"TDRC", "year"),
+    (b"TYER", "year"),
+    (b"TCON", "genre"),
+];
+
+/// Returns true for content types whose headers are worth parsing.
+pub fn is_supported_content_type(content_type: &str) -> bool {
+    let content_type = content_type.to_lowercase();
+    content_type.starts_with("image/")
+        || content_type.starts_with("video/")
+        || content_type.starts_with("audio/")
+}
+
+/// Parses media metadata from the leading bytes of a file.
+///
+/// `total_size` is the declared size of the whole upload; it is used to
+/// estimate durations for formats that only carry a bitrate or data length.
+/// Returns `None` when the format is not recognised.
+pub fn extract_media_info(bytes: &[u8], total_size: u64) -> Option<MediaInfo> {
+    let riff_form = match (bytes.get(0..4), bytes.get(8..12)) {
+        (Some(b"RIFF"), Some(form)) => Some(form),
+        _ => None,
+    };
+
+    if bytes.starts_with(PNG_SIGNATURE) {
+        parse_png(bytes)
+    } else if bytes.starts_with(&[0xFF, 0xD8]) {
+        Some(parse_jpeg(bytes))
+    } else if riff_form == Some(b"WEBP") {
+        Some(parse_webp(bytes))
+    } else if riff_form == Some(b"WAVE") {
+        Some(parse_wav(bytes))
+    } else if bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a") {
+        parse_gif(bytes)
+    } else if bytes.get(4..8) == Some(b"ftyp") {
+        Some(parse_mp4(bytes))
+    } else if bytes.starts_with(b"ID3") || is_mpeg_frame_sync(bytes) {
+        parse_mp3(bytes, total_size)
+    } else {
+        None
+    }
+}
+
+fn parse_png(bytes: &[u8]) -> Option<MediaInfo> {
+    // IHDR is always the first chunk: signature(8) length(4) type(4) data.
+    if bytes.get(12..16)? != b"IHDR" {
+        return None;
+    }
+
+    let mut info = MediaInfo {
+        format: "png".to_string(),
+        width: Some(be_u32(bytes, 16)?),
+        height: Some(be_u32(bytes, 20)?),
+        ..Default::default()
+    };
+
+    let mut offset = PNG_SIGNATURE.len();
+    while let (Some(length), Some(kind)) =
+        (be_u32(bytes, offset), bytes.get(offset + 4..offset + 8))
+    {
+        let data_start = offset + 8;
+        // Checked so a huge declared length cannot wrap a 32-bit usize.
+        let Some(data_end) = data_start.checked_add(length as usize) else {
+            break;
+        };
+
+        match kind {
+            b"eXIf" => {
+                if let Some(data) = bytes.get(data_start..data_end) {
+                    info.tags = read_exif_tags(data);
+                }
+                break;
+            }
+            // eXIf must precede image data.
+            b"IDAT" | b"IEND" => break,
+            _ => {}
+        }
+
+        // A chunk running past the buffer leaves nothing more to read.
+        match data_end.checked_add(4) {
+            Some(next) if next <= bytes.len() => offset = next,
+            _ => break,
+        }
+    }
+
+    Some(info)
+}
+
+fn parse_jpeg(bytes: &[u8]) -> MediaInfo {
+    let mut info = MediaInfo {
+        format: "jpeg".to_string(),
+        ..Default::default()
+    };
+
+    let mut offset = 2;
+    while offset + 4 <= bytes.len() && bytes[offset] == 0xFF {
+        let marker = bytes[offset + 1];
+
+        // Fill bytes and standalone markers carry no length.
+        if marker == 0xFF {
+            offset += 1;
+            continue;
+        }
+        if marker == 0x01 || (0xD0..=0xD8).contains(&marker) {
+            offset += 2;
+            continue;
+        }
+        // Start of scan or end of image: no more header segments.
+        if marker == 0xDA || marker == 0xD9 {
+            break;
+        }
+
+        let Some(length) = be_u16(bytes, offset + 2).map(usize::from) else {
+            break;
+        };
+        if length < 2 {
+            break;
+        }
+        let segment = bytes.get(offset + 4..offset + 2 + length);
+
+        match (marker, segment) {
+            (0xE1, Some(segment)) if info.tags.is_empty() => {
+                if let Some(tiff) = segment.strip_prefix(EXIF_HEADER) {
+                    info.tags = read_exif_tags(tiff);
+                }
+            }
+            // SOF0..SOF15, excluding DHT (C4), JPG (C8) and DAC (CC).
+            (0xC0..=0xCF, Some(segment)) if !matches!(marker, 0xC4 | 0xC8 | 0xCC) => {
+                info.height = be_u16(segment, 1).map(u32::from);
+                info.width = be_u16(segment, 3).map(u32::from);
+            }
+            _ => {}
+        }
+
+        offset += 2 + length;
+    }
+
+    info
+}
+
+fn parse_webp(bytes: &[u8]) -> MediaInfo {
+    let mut info = MediaInfo {
+        format: "webp".to_string(),
+        ..Default::default()
+    };
+
+    for chunk in riff_chunks(bytes) {
+        match &chunk.id {
+            b"VP8X" => {
+                info.width = le_u24(chunk.data, 4).map(|value| value + 1);
+                info.height = le_u24(chunk.data, 7).map(|value| value + 1);
+            }
+            b"VP8L" if info.width.is_none() && chunk.data.first() == Some(&0x2F) => {
+                if let Some(bits) = le_u32(chunk.data, 1) {
+                    info.width = Some((bits & 0x3FFF) + 1);
+                    info.height = Some(((bits >> 14) & 0x3FFF) + 1);
+                }
+            }
+            b"VP8 "
+                if info.width.is_none() && chunk.data.get(3..6) == Some(&[0x9D, 0x01, 0x2A]) =>
+            {
+                info.width = le_u16(chunk.data, 6).map(|value| u32::from(value & 0x3FFF));
+                info.height = le_u16(chunk.data, 8).map(|value| u32::from(value & 0x3FFF));
+            }
+            b"EXIF" if chunk.is_complete() => {
+                let tiff = chunk.data.strip_prefix(EXIF_HEADER).unwrap_or(chunk.data);
+                info.tags = read_exif_tags(tiff);
+            }
+            _ => {}
+        }
+    }
+
+    info
+}
+
+fn parse_gif(bytes: &[u8]) -> Option<MediaInfo> {
+    Some(MediaInfo {
+        format: "gif".to_string(),
+        width: Some(u32::from(le_u16(bytes, 6)?)),
+        height: Some(u32::from(le_u16(bytes, 8)?)),
+        ..Default::default()
+    })
+}
+
+fn parse_wav(bytes: &[u8]) -> MediaInfo {
+    let mut info = MediaInfo {
+        format: "wav".to_string(),
+        ..Default::default()
+    };
+    let mut byte_rate = None;
+
+    for chunk in riff_chunks(bytes) {
+        match &chunk.id {
+            b"fmt " => {
+                info.audio_codec = le_u16(chunk.data, 0).map(|tag| match tag {
+                    0x0001 => "pcm".to_string(),
+                    0x0003 => "pcm_float".to_string(),
+                    0xFFFE => "extensible".to_string(),
+                    other => format!("0x{other:04x}"),
+                });
+                info.channels = le_u16(chunk.data, 2);
+                info.sample_rate = le_u32(chunk.data, 4);
+                byte_rate = le_u32(chunk.data, 8).filter(|rate| *rate > 0);
+            }
+            b"data" => {
+                if let Some(rate) = byte_rate {
+                    info.duration_seconds = Some(f64::from(chunk.size) / f64::from(rate));
+                }
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    info
+}
+
+fn parse_mp4(bytes: &[u8]) -> MediaInfo {
+    let major_brand = bytes.get(8..12);
+    let mut info = MediaInfo {
+        format: if major_brand == Some(b"qt  ") {
+            "mov"
+        } else {
+            "mp4"
+        }
+        .to_string(),
+        ..Default::default()
+    };
+
+    let Some(moov) = mp4_boxes(bytes).find(|b| &b.kind == b"moov") else {
+        return info;
+    };
+
+    for child in mp4_boxes(moov.payload) {
+        match &child.kind {
+            b"mvhd" => info.duration_seconds = parse_mvhd_duration(child.payload),
+            b"trak" => parse_trak(child.payload, &mut info),
+            _ => {}
+        }
+    }
+
+    info
+}
+
+fn parse_mvhd_duration(payload: &[u8]) -> Option<f64> {
+    let (timescale, duration) = match payload.first()? {
+        1 => (be_u32(payload, 20)?, be_u64(payload, 24)?),
+        _ => (be_u32(payload, 12)?, u64::from(be_u32(payload, 16)?)),
+    };
+
+    (timescale > 0).then(|| duration as f64 / f64::from(timescale))
+}
+
+fn parse_trak(payload: &[u8], info: &mut MediaInfo) {
+    let mut dimensions = None;
+    let mut handler = None;
+    let mut sample_entry = None;
+
+    for child in mp4_boxes(payload) {
+        match &child.kind {
+            b"tkhd" => {
+                let offset = if child.payload.first() == Some(&1) {
+                    88
+                } else {
+                    76
+                };
+                // Width and height are 16.16 fixed-point.
+                dimensions = be_u32(child.payload, offset)
+                    .zip(be_u32(child.payload, offset + 4))
+                    .map(|(width, height)| (width >> 16, height >> 16));
+            }
+            b"mdia" => {
+                for mdia_child in mp4_boxes(child.payload) {
+                    match &mdia_child.kind {
+                        b"hdlr" => handler = mdia_child.payload.get(8..12),
+                        b"minf" => sample_entry = find_sample_entry(mdia_child.payload),
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    match handler {
+        Some(b"vide") if info.video_codec.is_none() => {
+            if let Some((width, height)) = dimensions {
+                info.width = Some(width);
+                info.height = Some(height);
+            }
+            info.video_codec = sample_entry.map(|entry| fourcc(&entry.kind));
+        }
+        Some(b"soun") if info.audio_codec.is_none() => {
+            if let Some(entry) = sample_entry {
+                info.audio_codec = Some(fourcc(&entry.kind));
+                // AudioSampleEntry: reserved(6) data_ref(2) reserved(8) channels(2)
+                // sample_size(2) pre_defined(2) reserved(2) sample_rate(16.16).
+                info.channels = be_u16(entry.payload, 16);
+                info.sample_rate = be_u32(entry.payload, 24).map(|rate| rate >> 16);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Walks `minf/stbl/stsd` and returns the first sample entry box.
+fn find_sample_entry(minf: &[u8]) -> Option<Mp4Box<'_>> {
+    let stbl = mp4_boxes(minf).find(|b| &b.kind == b"stbl")?;
+    let stsd = mp4_boxes(stbl.payload).find(|b| &b.kind == b"stsd")?;
+    // Full box header (4) + entry_count (4) precede the entries.
+    mp4_boxes(stsd.payload.get(8..)?).next()
+}
+
+fn parse_mp3(bytes: &[u8], total_size: u64) -> Option<MediaInfo> {
+    let mut info = MediaInfo {
+        format: "mp3".to_string(),
+        ..Default::default()
+    };
+
+    let mut audio_start = 0usize;
+    if bytes.starts_with(b"ID3") {
+        let tag_size = syncsafe_u32(bytes.get(6..10)?)? as usize;
+        let has_footer = bytes.get(5).is_some_and(|flags| flags & 0x10 != 0);
+        audio_start = 10 + tag_size + if has_footer { 10 } else { 0 };
+
+        let version = *bytes.get(3)?;
+        let frames = bytes.get(10..audio_start.min(bytes.len()))?;
+        parse_id3_frames(frames, version, &mut info);
+    }
+
+    if let Some(header) = bytes.get(audio_start..).and_then(parse_mpeg_audio_header) {
+        info.audio_codec = Some("mp3".to_string());
+        info.sample_rate = Some(header.sample_rate);
+        info.channels = Some(header.channels);
+
+        if info.duration_seconds.is_none() && header.bitrate_kbps > 0 {
+            let audio_bytes = total_size.saturating_sub(audio_start as u64);
+            info.duration_seconds =
+                Some(audio_bytes as f64 * 8.0 / (f64::from(header.bitrate_kbps) * 1000.0));
+        }
+    }
+
+    Some(info)
+}
+
+fn parse_id3_frames(frames: &[u8], version: u8, info: &mut MediaInfo) {
+    let mut offset = 0;
+    while let (Some(id), Some(raw_size)) = (
+        frames.get(offset..offset + 4),
+        frames.get(offset + 4..offset + 8),
+    ) {
+        // Padding starts with a zero byte.
+        if id[0] == 0 {
+            break;
+        }
+
+        let size = if version >= 4 {
+            syncsafe_u32(raw_size)
+        } else {
+            be_u32(raw_size, 0)
+        };
+        let Some(size) = size.map(|size| size as usize) else {
+            break;
+        };
+
+        let body_start = offset + 10;
+        let Some(body) = frames.get(body_start..body_start + size) else {
+            break;
+        };
+
+        if id == b"TLEN" {
+            info.duration_seconds = decode_id3_text(body)
+                .and_then(|millis| millis.trim().parse::<f64>().ok())
+                .map(|millis| millis / 1000.0);
+        } else if let Some((_, name)) = ID3_FRAMES.iter().find(|(frame, _)| frame[..] == *id) {
+            if let Some(text) = decode_id3_text(body).filter(|text| !text.is_empty()) {
+                info.tags.insert((*name).to_string(), text);
+            }
+        }
+
+        offset = body_start + size;
+    }
+}
+
+/// Decodes an ID3v2 text frame body (encoding byte followed by text).
+fn decode_id3_text(body: &[u8]) -> Option<String> {
+    let (encoding, text) = body.split_first()?;
+
+    let decoded = match encoding {
+        0 => text.iter().map(|&byte| char::from(byte)).collect(),
+        1 | 2 => {
+            let big_endian = *encoding == 2 || text.starts_with(&[0xFE, 0xFF]);
+            let text = match text.get(..2) {
+                Some([0xFF, 0xFE]) | Some([0xFE, 0xFF]) => &text[2..],
+                _ => text,
+            };
+            let units: Vec<u16> = text
+                .chunks_exact(2)
+                .map(|pair| {
+                    if big_endian {
+                        u16::from_be_bytes([pair[0], pair[1]])
+                    } else {
+                        u16::from_le_bytes([pair[0], pair[1]])
+                    }
+                })
+                .collect();
+            String::from_utf16_lossy(&units)
+        }
+        _ => String::from_utf8_lossy(text).into_owned(),
+    };
+
+    Some(decoded.trim_end_matches('\0').to_string())
+}
+
+/// Fields of an MPEG audio Layer III frame header.
+#[derive(Debug, PartialEq)]
+struct MpegAudioHeader {
+    bitrate_kbps: u32,
+    sample_rate: u32,
+    channels: u16,
+}
+
+fn is_mpeg_frame_sync(bytes: &[u8]) -> bool {
+    matches!(bytes, [0xFF, second, ..] if second & 0xE0 == 0xE0)
+}
+
+fn parse_mpeg_audio_header(bytes: &[u8]) -> Option<MpegAudioHeader> {
+    const MPEG1_L3_BITRATES: [u32; 15] = [
+        0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+    ];
+    const MPEG2_L3_BITRATES: [u32; 15] =
+        [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+    const MPEG1_SAMPLE_RATES: [u32; 3] = [44_100, 48_000, 32_000];
+
+    if !is_mpeg_frame_sync(bytes) {
+        return None;
+    }
+    let header = bytes.get(1..4)?;
+
+    let version = (header[0] >> 3) & 0b11;
+    let layer = (header[0] >> 1) & 0b11;
+    // Only Layer III (`01`) is reported; version `01` is reserved.
+    if layer != 0b01 || version == 0b01 {
+        return None;
+    }
+
+    let bitrate_index = usize::from(header[1] >> 4);
+    let sample_rate_index = usize::from((header[1] >> 2) & 0b11);
+    let sample_rate = *MPEG1_SAMPLE_RATES.get(sample_rate_index)?;
+
+    let (bitrates, sample_rate) = match version {
+        0b11 => (&MPEG1_L3_BITRATES, sample_rate),
+        0b10 => (&MPEG2_L3_BITRATES, sample_rate / 2),
+        _ => (&MPEG2_L3_BITRATES, sample_rate / 4),
+    };
+
+    Some(MpegAudioHeader {
+        bitrate_kbps: *bitrates.get(bitrate_index)?,
+        sample_rate,
+        channels: if header[2] >> 6 == 0b11 { 1 } else { 2 },
+    })
+}
+
+/// Reads the curated EXIF tags from raw TIFF-structured EXIF data.
+fn read_exif_tags(tiff: &[u8]) -> BTreeMap<String, String> {
+    let Ok(exif) = Reader::new().read_raw(tiff.to_vec()) else {
+        return BTreeMap::new();
+    };
+
+    EXIF_TAGS
+        .iter()
+        .filter_map(|(tag, name)| {
+            let field = exif.get_field(*tag, In::PRIMARY)?;
+            let value = match &field.value {
+                Value::Ascii(values) => values.first().map(|value| {
+                    String::from_utf8_lossy(value)
+                        .trim_end_matches('\0')
+                        .trim()
+                        .to_string()
+                })?,
+                _ => field.display_value().with_unit(&exif).to_string(),
+            };
+            (!value.is_empty()).then(|| ((*name).to_string(), value))
+        })
+        .collect()
+}
+
+/// A RIFF chunk whose data may be truncated by the end of the available bytes.
+struct RiffChunk<'a> {
+    id: [u8; 4],
+    size: u32,
+    data: &'a [u8],
+}
+
+impl RiffChunk<'_> {
+    fn is_complete(&self) -> bool {
+        self.data.len() == self.size as usize
+    }
+}
+
+/// Iterates the chunks following a 12-byte RIFF header.
+fn riff_chunks(bytes: &[u8]) -> impl Iterator<Item = RiffChunk<'_>> {
+    let mut offset = 12usize;
+    std::iter::from_fn(move || {
+        let id: [u8; 4] = bytes.get(offset..offset + 4)?.try_into().ok()?;
+        let size = le_u32(bytes, offset + 4)?;
+        let data_start = offset + 8;
+        let data_end = data_start.saturating_add(size as usize);
+        let data = &bytes[data_start.min(bytes.len())..data_end.min(bytes.len())];
+
+        // Chunks are padded to an even length.
+        offset = data_end.saturating_add(size as usize & 1);
+        Some(RiffChunk { id, size, data })
+    })
+}
+
+/// An ISO-BMFF box whose payload may be truncated by the end of the available bytes.
+struct Mp4Box<'a> {
+    kind: [u8; 4],
+    payload: &'a [u8],
+}
+
+/// Iterates sibling ISO-BMFF boxes.
+fn mp4_boxes(bytes: &[u8]) -> impl Iterator<Item = Mp4Box<'_>> {
+    let mut offset = 0usize;
+    std::iter::from_fn(move || {
+        let size = be_u32(bytes, offset)?;
+        let kind: [u8; 4] = bytes.get(offset + 4..offset + 8)?.try_into().ok()?;
+
+        let (header, size) = match size {
+            0 => (8, bytes.len() - offset),
+            1 => (16, usize::try_from(be_u64(bytes, offset + 8)?).ok()?),
+            size => (8, size as usize),
+        };
+        if size < header {
+            return None;
+        }
+
+        let end = offset.saturating_add(size);
+        let payload = &bytes[(offset + header).min(bytes.len())..end.min(bytes.len())];
+        offset = end;
+        Some(Mp4Box { kind, payload })
+    })
+}
+
+fn fourcc(kind: &[u8; 4]) -> String {
+    String::from_utf8_lossy(kind).trim().to_string()
+}
+
+fn syncsafe_u32(bytes: &[u8]) -> Option<u32> {
+    let bytes: [u8; 4] = bytes.get(..4)?.try_into().ok()?;
+    Some(
+        bytes
+            .iter()
+            .fold(0, |acc, byte| (acc << 7) | u32::from(byte & 0x7F)),
+    )
+}
+
+fn be_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn be_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn be_u64(bytes: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(
+        bytes.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+fn le_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        bytes.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn le_u24(bytes: &[u8], offset: usize) -> Option<u32> {
+    let bytes = bytes.get(offset..offset + 3)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]))
+}
+
+fn le_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        bytes.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// Little-endian TIFF with a single `Make = "Canon"` entry in IFD0.
+#[cfg(test)]
+pub(crate) fn tiff_with_make() -> Vec<u8> {
+    let mut tiff = b"II*\0\x08\0\0\0".to_vec();
+    tiff.extend_from_slice(&1u16.to_le_bytes());
+    tiff.extend_from_slice(&0x010Fu16.to_le_bytes());
+    tiff.extend_from_slice(&2u16.to_le_bytes());
+    tiff.extend_from_slice(&6u32.to_le_bytes());
+    tiff.extend_from_slice(&26u32.to_le_bytes());
+    tiff.extend_from_slice(&0u32.to_le_bytes());
+    tiff.extend_from_slice(b"Canon\0");
+    tiff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{DynamicImage, ImageFormat, RgbImage};
+    use std::io::Cursor;
+
+    fn encode_fixture(format: ImageFormat) -> Vec<u8> {
+        let image = DynamicImage::ImageRgb8(RgbImage::new(40, 30));
+        let mut output = Cursor::new(Vec::new());
+        image.write_to(&mut output, format).unwrap();
+        output.into_inner()
+    }
+
+    fn mp4_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut out = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(payload);
+        out
+    }
+
+    #[test]
+    fn extracts_png_dimensions() {
+        let info = extract_media_info(&encode_fixture(ImageFormat::Png), 0).unwrap();
+        assert_eq!(info.format, "png");
+        assert_eq!((info.width, info.height), (Some(40), Some(30)));
+    }
+
+    #[test]
+    fn png_stops_at_a_chunk_with_a_huge_declared_length() {
+        let png = encode_fixture(ImageFormat::Png);
+        // Replace the chunk after IHDR with one claiming ~4 GiB of data.
+        let mut bytes = png[..33].to_vec();
+        bytes.extend_from_slice(&0xFFFF_FFF4u32.to_be_bytes());
+        bytes.extend_from_slice(b"tEXt");
+        bytes.extend_from_slice(&png[33..]);
+
+        let info = extract_media_info(&bytes, 0).unwrap();
+        assert_eq!((info.width, info.height), (Some(40), Some(30)));
+        assert!(info.tags.is_empty());
+    }
+
+    #[test]
+    fn extracts_jpeg_dimensions_and_exif() {
+        let jpeg = encode_fixture(ImageFormat::Jpeg);
+        let tiff = tiff_with_make();
+
+        let mut with_exif = jpeg[..2].to_vec();
+        with_exif.extend_from_slice(&[0xFF, 0xE1]);
+        with_exif.extend_from_slice(&((2 + EXIF_HEADER.len() + tiff.len()) as u16).to_be_bytes());
+        with_exif.extend_from_slice(EXIF_HEADER);
+        with_exif.extend_from_slice(&tiff);
+        with_exif.extend_from_slice(&jpeg[2..]);
+
+        let info = extract_media_info(&with_exif, 0).unwrap();
+        assert_eq!(info.format, "jpeg");
+        assert_eq!((info.width, info.height), (Some(40), Some(30)));
+        assert_eq!(info.tags.get("make").map(String::as_str), Some("Canon"));
+    }
+
+    #[test]
+    fn extracts_webp_and_gif_dimensions() {
+        for (format, name) in [(ImageFormat::WebP, "webp"), (ImageFormat::Gif, "gif")] {
+            let info = extract_media_info(&encode_fixture(format), 0).unwrap();
+            assert_eq!(info.format, name);
+            assert_eq!((info.width, info.height), (Some(40), Some(30)), "{name}");
+        }
+    }
+
+    #[test]
+    fn tolerates_truncated_image_headers() {
+        let png = encode_fixture(ImageFormat::Png);
+        assert!(extract_media_info(&png[..14], 0).is_none());
+        assert_eq!(extract_media_info(&png[..24], 0).unwrap().width, Some(40));
+    }
+
+    #[test]
+    fn extracts_wav_format_and_duration() {
+        let mut wav = b"RIFF\0\0\0\0WAVE".to_vec();
+        wav.extend_from_slice(b"fmt ");
+        wav.extend_from_slice(&16u32.to_le_bytes());
+        wav.extend_from_slice(&1u16.to_le_bytes());
+        wav.extend_from_slice(&2u16.to_le_bytes());
+        wav.extend_from_slice(&44_100u32.to_le_bytes());
+        wav.extend_from_slice(&176_400u32.to_le_bytes());
+        wav.extend_from_slice(&4u16.to_le_bytes());
+        wav.extend_from_slice(&16u16.to_le_bytes());
+        wav.extend_from_slice(b"data");
+        wav.extend_from_slice(&352_800u32.to_le_bytes());
+
+        let info = extract_media_info(&wav, 0).unwrap();
+        assert_eq!(info.format, "wav");
+        assert_eq!(info.audio_codec.as_deref(), Some("pcm"));
+        assert_eq!(info.channels, Some(2));
+        assert_eq!(info.sample_rate, Some(44_100));
+        assert_eq!(info.duration_seconds, Some(2.0));
+    }
+
+    #[test]
+    fn extracts_mp4_moov_metadata() {
+        let mut mvhd = vec![0u8; 100];
+        mvhd[12..16].copy_from_slice(&1000u32.to_be_bytes());
+        mvhd[16..20].copy_from_slice(&12_500u32.to_be_bytes());
+
+        let mut tkhd = vec![0u8; 84];
+        tkhd[76..80].copy_from_slice(&(1920u32 << 16).to_be_bytes());
+        tkhd[80..84].copy_from_slice(&(1080u32 << 16).to_be_bytes());
+
+        let mut hdlr = vec![0u8; 24];
+        hdlr[8..12].copy_from_slice(b"vide");
+
+        let mut stsd = vec![0u8; 8];
+        stsd.extend(mp4_box(b"avc1", &[0u8; 78]));
+
+        let stbl = mp4_box(b"stbl", &mp4_box(b"stsd", &stsd));
+        let minf = mp4_box(b"minf", &stbl);
+        let mut mdia = mp4_box(b"hdlr", &hdlr);
+        mdia.extend(minf);
+        let mut trak = mp4_box(b"tkhd", &tkhd);
+        trak.extend(mp4_box(b"mdia", &mdia));
+        let mut moov = mp4_box(b"mvhd", &mvhd);
+        moov.extend(mp4_box(b"trak", &trak));
+
+        let mut file = mp4_box(b"ftyp", b"isom\0\0\0\0isom");
+        file.extend(mp4_box(b"moov", &moov));
+
+        let info = extract_media_info(&file, 0).unwrap();
+        assert_eq!(info.format, "mp4");
+        assert_eq!(info.duration_seconds, Some(12.5));
+        assert_eq!((info.width, info.height), (Some(1920), Some(1080)));
+        assert_eq!(info.video_codec.as_deref(), Some("avc1"));
+    }
+
+    #[test]
+    fn mp4_without_moov_reports_container_only() {
+        let mut file = mp4_box(b"ftyp", b"qt  \0\0\0\0qt  ");
+        file.extend(mp4_box(b"mdat", &[0u8; 16]));
+
+        let info = extract_media_info(&file, 0).unwrap();
+        assert_eq!(info.format, "mov");
+        assert!(info.duration_seconds.is_none());
+    }
+
+    #[test]
+    fn extracts_id3_tags_and_mpeg_header() {
+        let mut frame = b"TIT2".to_vec();
+        frame.extend_from_slice(&6u32.to_be_bytes());
+        frame.extend_from_slice(&[0, 0]);
+        frame.extend_from_slice(b"\x03Intro");
+
+        let mut mp3 = b"ID3\x03\x00\x00".to_vec();
+        mp3.extend_from_slice(&[0, 0, 0, frame.len() as u8]);
+        mp3.extend_from_slice(&frame);
+        // MPEG-1 Layer III, 128 kbps, 44.1 kHz, joint stereo.
+        mp3.extend_from_slice(&[0xFF, 0xFB, 0x90, 0x40]);
+
+        let total_size = mp3.len() as u64 - 4 + 160_000;
+        let info = extract_media_info(&mp3, total_size).unwrap();
+        assert_eq!(info.format, "mp3");
+        assert_eq!(info.tags.get("title").map(String::as_str), Some("Intro"));
+        assert_eq!(info.audio_codec.as_deref(), Some("mp3"));
+        assert_eq!(info.sample_rate, Some(44_100));
+        assert_eq!(info.channels, Some(2));
+        let duration = info.duration_seconds.unwrap();
+        assert!((duration - 10.0).abs() < 0.01, "duration {duration}");
+    }
+
+    #[test]
+    fn unknown_bytes_yield_none() {
+        assert!(extract_media_info(b"plain text", 10).is_none());
+    }
+}
@@ -0,0 +1,16 @@
+//! # Media Processing
+//!
+//! Processing for media objects stored in R2. Metadata is stripped from and
+//! parsed out of the first chunk as it streams through; variants are produced after the multipart upload
+//! has been finalized. Failures here never invalidate the upload itself; callers
+//! log and continue.
+//!
+//! ## Submodules
+//!
+//! - `metadata` — header parsing for image, video, and audio metadata.
+//! - `sanitize` — in-place EXIF/XMP/GPS stripping for JPEG, PNG, and WebP.
+//! - `variants` — thumbnail generation for `image/*` uploads.
+
+pub mod metadata;
+pub mod sanitize;
+pub mod variants;
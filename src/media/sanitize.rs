@@ -0,0 +1,302 @@
+//! # Image Metadata Stripping
+//!
+//! Neutralises EXIF, XMP, and other descriptive metadata (including GPS) in
+//! JPEG, PNG, and WebP data before it is forwarded to R2.
+//!
+//! R2 multipart uploads require every part except the last to have the same
+//! size, and chunk sizes are verified against the declared total at completion.
+//! Metadata is therefore blanked *in place* rather than removed: each segment
+//! keeps its length but is rewritten into an inert form that decoders skip.
+//!
+//! - **JPEG**: `APP1` (EXIF/XMP) and `APP13` (IPTC) become zero-filled `COM` segments.
+//! - **PNG**: `eXIf`, `tEXt`, `zTXt` and `iTXt` chunks become zero-filled private
+//!   ancillary chunks with a recomputed CRC.
+//! - **WebP**: `EXIF` and `XMP ` chunks become zero-filled `JUNK` chunks and the
+//!   matching `VP8X` feature flags are cleared.
+//!
+//! Only the bytes passed in are rewritten. For multipart uploads this is the
+//! first part, which holds JPEG and PNG metadata; WebP files may place `EXIF`
+//! after the image data, in which case it is only reached for single-part uploads.
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Private, ancillary, safe-to-copy PNG chunk type that decoders ignore.
+const PNG_INERT_CHUNK: &[u8; 4] = b"nuLl";
+
+/// JPEG comment marker used to replace metadata segments.
+const JPEG_COM: u8 = 0xFE;
+
+/// VP8X feature flag bits for EXIF and XMP metadata.
+const VP8X_EXIF_FLAG: u8 = 0x08;
+const VP8X_XMP_FLAG: u8 = 0x04;
+
+/// Strips metadata from `bytes` in place.
+///
+/// Returns the number of segments neutralised, or `None` when the data is not
+/// a JPEG, PNG, or WebP image and nothing was inspected.
+pub fn strip_image_metadata(bytes: &mut [u8]) -> Option<usize> {
+    if bytes.starts_with(PNG_SIGNATURE) {
+        Some(strip_png(bytes))
+    } else if bytes.starts_with(&[0xFF, 0xD8]) {
+        Some(strip_jpeg(bytes))
+    } else if is_webp(bytes) {
+        Some(strip_webp(bytes))
+    } else {
+        None
+    }
+}
+
+/// Whether `bytes` start a WebP file, whose `EXIF` chunk may follow the image
+/// data and so lie beyond the bytes that were stripped.
+pub fn is_webp(bytes: &[u8]) -> bool {
+    bytes.get(0..4) == Some(b"RIFF") && bytes.get(8..12) == Some(b"WEBP")
+}
+
+fn strip_jpeg(bytes: &mut [u8]) -> usize {
+    let mut stripped = 0;
+    let mut offset = 2;
+
+    while offset + 4 <= bytes.len() && bytes[offset] == 0xFF {
+        let marker = bytes[offset + 1];
+
+        if marker == 0xFF {
+            offset += 1;
+            continue;
+        }
+        if marker == 0x01 || (0xD0..=0xD8).contains(&marker) {
+            offset += 2;
+            continue;
+        }
+        if marker == 0xDA || marker == 0xD9 {
+            break;
+        }
+
+        let length = usize::from(u16::from_be_bytes([bytes[offset + 2], bytes[offset + 3]]));
+        if length < 2 {
+            break;
+        }
+
+        if marker == 0xE1 || marker == 0xED {
+            bytes[offset + 1] = JPEG_COM;
+            let end = (offset + 2 + length).min(bytes.len());
+            bytes[offset + 4..end].fill(0);
+            stripped += 1;
+        }
+
+        offset += 2 + length;
+    }
+
+    stripped
+}
+
+fn strip_png(bytes: &mut [u8]) -> usize {
+    let mut stripped = 0;
+    let mut offset = PNG_SIGNATURE.len();
+
+    while offset + 8 <= bytes.len() {
+        let length = u32::from_be_bytes([
+            bytes[offset],
+            bytes[offset + 1],
+            bytes[offset + 2],
+            bytes[offset + 3],
+        ]) as usize;
+        let kind = [
+            bytes[offset + 4],
+            bytes[offset + 5],
+            bytes[offset + 6],
+            bytes[offset + 7],
+        ];
+        if &kind == b"IEND" {
+            break;
+        }
+        // A chunk running past the buffer ends the walk: nothing after it is
+        // reachable, and its CRC could not be recomputed. Checked so a huge
+        // length cannot wrap a 32-bit usize.
+        let Some(chunk_end) = (offset + 8)
+            .checked_add(length)
+            .and_then(|data_end| data_end.checked_add(4))
+            .filter(|chunk_end| *chunk_end <= bytes.len())
+        else {
+            break;
+        };
+        let data_end = chunk_end - 4;
+
+        if matches!(&kind, b"eXIf" | b"tEXt" | b"zTXt" | b"iTXt") {
+            bytes[offset + 4..offset + 8].copy_from_slice(PNG_INERT_CHUNK);
+            bytes[offset + 8..data_end].fill(0);
+            let crc = crc32(&bytes[offset + 4..data_end]);
+            bytes[data_end..data_end + 4].copy_from_slice(&crc.to_be_bytes());
+            stripped += 1;
+        }
+
+        offset = chunk_end;
+    }
+
+    stripped
+}
+
+fn strip_webp(bytes: &mut [u8]) -> usize {
+    let mut stripped = 0;
+    let mut vp8x_flags = None;
+    let mut offset = 12;
+
+    while offset + 8 <= bytes.len() {
+        let size = u32::from_le_bytes([
+            bytes[offset + 4],
+            bytes[offset + 5],
+            bytes[offset + 6],
+            bytes[offset + 7],
+        ]) as usize;
+        let data_start = offset + 8;
+        // A metadata chunk cut off by the end of the buffer is blanked as far
+        // as it goes.
+        let data_end = data_start.saturating_add(size).min(bytes.len());
+
+        match &bytes[offset..offset + 4] {
+            b"VP8X" if data_start < bytes.len() => vp8x_flags = Some(data_start),
+            b"EXIF" | b"XMP " => {
+                bytes[offset..offset + 4].copy_from_slice(b"JUNK");
+                bytes[data_start..data_end].fill(0);
+                stripped += 1;
+            }
+            _ => {}
+        }
+
+        match data_start
+            .checked_add(size)
+            .and_then(|end| end.checked_add(size & 1))
+        {
+            Some(next) => offset = next,
+            None => break,
+        }
+    }
+
+    if let Some(flags) = vp8x_flags {
+        bytes[flags] &= !(VP8X_EXIF_FLAG | VP8X_XMP_FLAG);
+    }
+
+    stripped
+}
+
+/// CRC-32 (ISO-HDLC) as used by PNG chunk checksums.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::metadata::{extract_media_info, tiff_with_make};
+    use image::{DynamicImage, ImageFormat, RgbImage};
+    use std::io::Cursor;
+
+    fn encode_fixture(format: ImageFormat) -> Vec<u8> {
+        let image = DynamicImage::ImageRgb8(RgbImage::new(8, 8));
+        let mut output = Cursor::new(Vec::new());
+        image.write_to(&mut output, format).unwrap();
+        output.into_inner()
+    }
+
+    fn png_chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(data);
+        chunk.extend_from_slice(&crc32(&chunk[4..]).to_be_bytes());
+        chunk
+    }
+
+    #[test]
+    fn crc32_matches_reference_value() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+    }
+
+    #[test]
+    fn strips_jpeg_exif_without_changing_length() {
+        let jpeg = encode_fixture(ImageFormat::Jpeg);
+        let tiff = tiff_with_make();
+
+        let mut bytes = jpeg[..2].to_vec();
+        bytes.extend_from_slice(&[0xFF, 0xE1]);
+        bytes.extend_from_slice(&((2 + 6 + tiff.len()) as u16).to_be_bytes());
+        bytes.extend_from_slice(b"Exif\0\0");
+        bytes.extend_from_slice(&tiff);
+        bytes.extend_from_slice(&jpeg[2..]);
+        let original_len = bytes.len();
+
+        assert_eq!(strip_image_metadata(&mut bytes), Some(1));
+        assert_eq!(bytes.len(), original_len);
+        assert!(extract_media_info(&bytes, 0).unwrap().tags.is_empty());
+        image::load_from_memory(&bytes).expect("stripped JPEG still decodes");
+    }
+
+    #[test]
+    fn strips_png_exif_and_keeps_image_decodable() {
+        let png = encode_fixture(ImageFormat::Png);
+        // Insert eXIf directly after IHDR (signature 8 + IHDR chunk 25 bytes).
+        let mut bytes = png[..33].to_vec();
+        bytes.extend(png_chunk(b"eXIf", &tiff_with_make()));
+        bytes.extend_from_slice(&png[33..]);
+        assert!(!extract_media_info(&bytes, 0).unwrap().tags.is_empty());
+
+        assert_eq!(strip_image_metadata(&mut bytes), Some(1));
+        assert!(extract_media_info(&bytes, 0).unwrap().tags.is_empty());
+        image::load_from_memory(&bytes).expect("stripped PNG still decodes");
+    }
+
+    #[test]
+    fn strips_webp_exif_chunk_and_clears_flags() {
+        let mut bytes = b"RIFF\0\0\0\0WEBPVP8X\x0a\0\0\0".to_vec();
+        bytes.extend_from_slice(&[VP8X_EXIF_FLAG | 0x10, 0, 0, 0, 7, 0, 0, 7, 0, 0]);
+        let tiff = tiff_with_make();
+        bytes.extend_from_slice(b"EXIF");
+        bytes.extend_from_slice(&(tiff.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&tiff);
+
+        assert_eq!(strip_image_metadata(&mut bytes), Some(1));
+        assert_eq!(bytes[20], 0x10, "alpha flag kept, EXIF flag cleared");
+        assert!(bytes.windows(4).all(|window| window != b"EXIF"));
+        assert!(bytes.windows(5).all(|window| window != b"Canon"));
+    }
+
+    #[test]
+    fn stops_at_chunks_with_huge_declared_lengths() {
+        let png = encode_fixture(ImageFormat::Png);
+        for kind in [b"eXIf", b"tEXt"] {
+            let mut bytes = png[..33].to_vec();
+            bytes.extend_from_slice(&0xFFFF_FFF4u32.to_be_bytes());
+            bytes.extend_from_slice(kind);
+            bytes.extend_from_slice(&png[33..]);
+            let original = bytes.clone();
+
+            assert_eq!(strip_image_metadata(&mut bytes), Some(0));
+            assert_eq!(bytes, original);
+        }
+
+        // A truncated EXIF chunk is blanked as far as the buffer goes.
+        for (kind, stripped) in [(b"EXIF", 1), (b"ALPH", 0)] {
+            let mut bytes = b"RIFF\0\0\0\0WEBP".to_vec();
+            bytes.extend_from_slice(kind);
+            bytes.extend_from_slice(&0xFFFF_FFF8u32.to_le_bytes());
+            bytes.extend_from_slice(b"Canon");
+
+            assert_eq!(strip_image_metadata(&mut bytes), Some(stripped));
+            let kept = bytes.windows(5).any(|window| window == b"Canon");
+            assert_eq!(kept, stripped == 0);
+        }
+    }
+
+    #[test]
+    fn ignores_non_image_data() {
+        let mut bytes = b"plain text".to_vec();
+        assert_eq!(strip_image_metadata(&mut bytes), None);
+        assert_eq!(bytes, b"plain text");
+    }
+}
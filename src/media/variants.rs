@@ -0,0 +1,279 @@
+//! # Image Variants
+//!
+//! Decodes a completed `image/*` upload inside the worker using the pure-Rust
+//! `image` crate, renders every configured [`ImageVariantSpec`], writes the
+//! results next to the original object in R2, and records them in D1.
+//! Variant keys carry the upload ID and are only written when free, so a
+//! variant never overwrites another object.
+
+use std::io::Cursor;
+
+use image::{DynamicImage, ImageFormat, ImageReader, Limits};
+use worker::{Bucket, Conditional, HttpMetadata};
+
+use crate::config::{ImageVariantConfig, ImageVariantSpec, VariantFormat};
+use crate::database::DatabaseService;
+use crate::errors::{AppError, AppResult};
+use crate::models::{EncryptionMode, UploadMetadata, UploadVariant};
+use crate::utils::generate_variant_key;
+
+/// Upper bound on decoder allocations, leaving headroom in the 128 MB isolate.
+const MAX_DECODE_ALLOCATION: u64 = 96 * 1024 * 1024;
+
+/// A variant rendered in memory and ready to be written to R2.
+#[derive(Debug)]
+pub struct RenderedVariant {
+    pub name: String,
+    pub format: VariantFormat,
+    pub width: u32,
+    pub height: u32,
+    pub bytes: Vec<u8>,
+}
+
+/// Returns true when variants should be generated for the given upload.
+///
+/// Encrypted uploads never qualify: the stored object is ciphertext, and a
+/// variant would keep a plaintext rendition of it.
+pub fn is_eligible(config: &ImageVariantConfig, metadata: &UploadMetadata) -> bool {
+    config.enabled
+        && !config.variants.is_empty()
+        && metadata.content_type.to_lowercase().starts_with("image/")
+        && metadata.total_size <= config.max_source_size
+        && metadata.encryption == EncryptionMode::None
+}
+
+/// Generates, stores, and records all configured variants for a completed upload.
+///
+/// Returns the variants that were written. The original object is read back from
+/// R2 in full, so callers must check [`is_eligible`] first.
+pub async fn generate_image_variants(
+    bucket: &Bucket,
+    database: &DatabaseService,
+    config: &ImageVariantConfig,
+    metadata: &UploadMetadata,
+) -> AppResult<Vec<UploadVariant>> {
+    let object = bucket
+        .get(metadata.r2_key.clone())
+        .execute()
+        .await
+        .map_err(|err| AppError::R2Error {
+            message: format!("Failed to read object for variant generation: {err}"),
+        })?
+        .ok_or_else(|| AppError::R2Error {
+            message: format!("Object {} missing after completion", metadata.r2_key),
+        })?;
+
+    let source = object
+        .body()
+        .ok_or_else(|| AppError::R2Error {
+            message: format!("Object {} has no body", metadata.r2_key),
+        })?
+        .bytes()
+        .await
+        .map_err(|err| AppError::R2Error {
+            message: format!("Failed to read object body: {err}"),
+        })?;
+
+    let rendered = render_variants(&source, &config.variants)?;
+    let mut stored = Vec::with_capacity(rendered.len());
+
+    for variant in rendered {
+        let r2_key = generate_variant_key(
+            &metadata.r2_key,
+            &metadata.upload_id,
+            &variant.name,
+            variant.format.extension(),
+        )?;
+        let size = variant.bytes.len() as u64;
+
+        // Only write where nothing exists yet: a variant never replaces another
+        // object, including one under a retention hold.
+        let written = bucket
+            .put(r2_key.clone(), variant.bytes)
+            .http_metadata(HttpMetadata {
+                content_type: Some(variant.format.content_type().to_string()),
+                ..Default::default()
+            })
+            .only_if(Conditional {
+                etag_does_not_match: Some("*".to_string()),
+                ..Default::default()
+            })
+            .execute()
+            .await
+            .map_err(|err| AppError::R2Error {
+                message: format!("Failed to store variant {}: {err}", variant.name),
+            })?;
+        if written.is_none() {
+            return Err(AppError::R2KeyConflict { r2_key });
+        }
+
+        let record = UploadVariant {
+            name: variant.name,
+            r2_key,
+            content_type: variant.format.content_type().to_string(),
+            width: variant.width,
+            height: variant.height,
+            size,
+        };
+        database
+            .record_variant(&metadata.upload_id, &record)
+            .await?;
+        stored.push(record);
+    }
+
+    Ok(stored)
+}
+
+/// Decodes `source` once and renders every spec from the decoded image.
+pub fn render_variants(
+    source: &[u8],
+    specs: &[ImageVariantSpec],
+) -> AppResult<Vec<RenderedVariant>> {
+    let image = decode(source)?;
+
+    specs
+        .iter()
+        .map(|spec| {
+            let (width, height) = fit_within(image.width(), image.height(), spec.max_dimension);
+            let resized = if (width, height) == (image.width(), image.height()) {
+                image.clone()
+            } else {
+                image.resize_exact(width, height, image::imageops::FilterType::Lanczos3)
+            };
+
+            Ok(RenderedVariant {
+                name: spec.name.clone(),
+                format: spec.format,
+                width,
+                height,
+                bytes: encode(resized, spec.format)?,
+            })
+        })
+        .collect()
+}
+
+/// Computes dimensions whose longest edge is at most `max_dimension`.
+///
+/// Preserves aspect ratio and never upscales; each edge is at least one pixel.
+fn fit_within(width: u32, height: u32, max_dimension: u32) -> (u32, u32) {
+    let longest = width.max(height);
+    if longest <= max_dimension || longest == 0 {
+        return (width, height);
+    }
+
+    let scale = |edge: u32| {
+        let scaled = (edge as u64 * max_dimension as u64 + longest as u64 / 2) / longest as u64;
+        (scaled as u32).max(1)
+    };
+
+    (scale(width), scale(height))
+}
+
+fn decode(source: &[u8]) -> AppResult<DynamicImage> {
+    let mut limits = Limits::default();
+    limits.max_alloc = Some(MAX_DECODE_ALLOCATION);
+
+    let mut reader = ImageReader::new(Cursor::new(source))
+        .with_guessed_format()
+        .map_err(|err| AppError::InternalError {
+            message: format!("Failed to detect image format: {err}"),
+        })?;
+    reader.limits(limits);
+
+    reader.decode().map_err(|err| AppError::InternalError {
+        message: format!("Failed to decode image: {err}"),
+    })
+}
+
+fn encode(image: DynamicImage, format: VariantFormat) -> AppResult<Vec<u8>> {
+    // WebP encoding in `image` is lossless RGB(A)8 only; JPEG has no alpha channel.
+    let (image, image_format) = match format {
+        VariantFormat::Webp => (
+            DynamicImage::ImageRgba8(image.into_rgba8()),
+            ImageFormat::WebP,
+        ),
+        VariantFormat::Jpeg => (
+            DynamicImage::ImageRgb8(image.into_rgb8()),
+            ImageFormat::Jpeg,
+        ),
+    };
+
+    let mut output = Cursor::new(Vec::new());
+    image
+        .write_to(&mut output, image_format)
+        .map_err(|err| AppError::InternalError {
+            message: format!("Failed to encode {} variant: {err}", format.extension()),
+        })?;
+
+    Ok(output.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::RgbaImage;
+
+    fn png_fixture(width: u32, height: u32) -> Vec<u8> {
+        let image = DynamicImage::ImageRgba8(RgbaImage::from_pixel(
+            width,
+            height,
+            image::Rgba([200, 10, 10, 255]),
+        ));
+        let mut output = Cursor::new(Vec::new());
+        image.write_to(&mut output, ImageFormat::Png).unwrap();
+        output.into_inner()
+    }
+
+    fn spec(name: &str, max_dimension: u32, format: VariantFormat) -> ImageVariantSpec {
+        ImageVariantSpec {
+            name: name.to_string(),
+            max_dimension,
+            format,
+        }
+    }
+
+    #[test]
+    fn fit_within_preserves_aspect_ratio() {
+        assert_eq!(fit_within(2000, 1000, 512), (512, 256));
+        assert_eq!(fit_within(1000, 2000, 128), (64, 128));
+    }
+
+    #[test]
+    fn fit_within_never_upscales() {
+        assert_eq!(fit_within(100, 50, 512), (100, 50));
+    }
+
+    #[test]
+    fn fit_within_keeps_at_least_one_pixel() {
+        assert_eq!(fit_within(10_000, 1, 100), (100, 1));
+    }
+
+    #[test]
+    fn render_variants_produces_each_spec() {
+        let source = png_fixture(400, 200);
+        let specs = [
+            spec("w128", 128, VariantFormat::Webp),
+            spec("w1024", 1024, VariantFormat::Jpeg),
+        ];
+
+        let rendered = render_variants(&source, &specs).unwrap();
+        assert_eq!(rendered.len(), 2);
+        assert_eq!((rendered[0].width, rendered[0].height), (128, 64));
+        assert_eq!(
+            image::guess_format(&rendered[0].bytes).unwrap(),
+            ImageFormat::WebP
+        );
+        assert_eq!((rendered[1].width, rendered[1].height), (400, 200));
+        assert_eq!(
+            image::guess_format(&rendered[1].bytes).unwrap(),
+            ImageFormat::Jpeg
+        );
+    }
+
+    #[test]
+    fn render_variants_rejects_non_image_bytes() {
+        let error = render_variants(b"not an image", &[spec("w128", 128, VariantFormat::Webp)])
+            .unwrap_err();
+        assert!(matches!(error, AppError::InternalError { .. }));
+    }
+}
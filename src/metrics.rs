@@ -0,0 +1,230 @@
+//! # Metrics
+//!
+//! Operational metrics written to a Workers Analytics Engine dataset. Metrics
+//! are emitted through the [`MetricsSink`] trait so the transport can be swapped:
+//! production uses [`AnalyticsEngineSink`], deployments without the binding use
+//! [`NoopSink`], and tests assert on an in-memory sink.
+//!
+//! ## Data Point Layout
+//!
+//! Every data point is indexed by its event name, which is also `blob1`:
+//!
+//! | Event | blob2 | blob3 | double1 | double2 |
+//! |-------|-------|-------|---------|---------|
+//! | `request` | route pattern | error code (empty on success) | HTTP status | latency (ms) |
+//! | `chunk_uploaded` | user role | — | chunk bytes | R2 part upload latency (ms) |
+//! | `upload_completed` | user role | — | total bytes | — |
+//!
+//! Bytes ingested is `SUM(double1)` over `chunk_uploaded`; error rates come from
+//! `request` points grouped by `blob3`.
+
+use worker::{AnalyticsEngineDataPointBuilder, AnalyticsEngineDataset, Env};
+
+use crate::constants::METRICS_DATASET_NAME;
+use crate::models::UserRole;
+
+/// A single metric event.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Metric {
+    /// One per request, emitted when the response is ready.
+    Request {
+        route: &'static str,
+        status: u16,
+        latency_ms: u64,
+        error_code: Option<&'static str>,
+    },
+    /// A chunk was stored in R2.
+    ChunkUploaded {
+        role: UserRole,
+        bytes: u64,
+        latency_ms: u64,
+    },
+    /// A multipart upload was finalized.
+    UploadCompleted { role: UserRole, total_size: u64 },
+}
+
+impl Metric {
+    /// Event name used as the Analytics Engine index and `blob1`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Metric::Request { .. } => "request",
+            Metric::ChunkUploaded { .. } => "chunk_uploaded",
+            Metric::UploadCompleted { .. } => "upload_completed",
+        }
+    }
+
+    /// Blob and double columns for this event, following the layout in the module docs.
+    fn columns(&self) -> (Vec<String>, Vec<f64>) {
+        let name = self.name().to_string();
+        match self {
+            Metric::Request {
+                route,
+                status,
+                latency_ms,
+                error_code,
+            } => (
+                vec![
+                    name,
+                    (*route).to_string(),
+                    error_code.unwrap_or_default().to_string(),
+                ],
+                vec![f64::from(*status), *latency_ms as f64],
+            ),
+            Metric::ChunkUploaded {
+                role,
+                bytes,
+                latency_ms,
+            } => (
+                vec![name, role.as_str().to_string()],
+                vec![*bytes as f64, *latency_ms as f64],
+            ),
+            Metric::UploadCompleted { role, total_size } => (
+                vec![name, role.as_str().to_string()],
+                vec![*total_size as f64],
+            ),
+        }
+    }
+}
+
+/// Destination for metric events.
+///
+/// Recording is fire-and-forget: sinks must never fail the request.
+pub trait MetricsSink {
+    fn record(&self, metric: Metric);
+}
+
+/// Writes metrics to a Workers Analytics Engine dataset.
+pub struct AnalyticsEngineSink {
+    dataset: AnalyticsEngineDataset,
+}
+
+impl MetricsSink for AnalyticsEngineSink {
+    fn record(&self, metric: Metric) {
+        let (blobs, doubles) = metric.columns();
+        let point = AnalyticsEngineDataPointBuilder::new()
+            .indexes([metric.name()])
+            .blobs(blobs)
+            .doubles(doubles)
+            .build();
+
+        // Analytics Engine writes are best-effort; dropping a point is preferable
+        // to failing the request that produced it.
+        let _ = self.dataset.write_data_point(&point);
+    }
+}
+
+/// Discards all metrics. Used when the dataset binding is not configured.
+pub struct NoopSink;
+
+impl MetricsSink for NoopSink {
+    fn record(&self, _metric: Metric) {}
+}
+
+/// Returns the Analytics Engine sink when the dataset binding exists, otherwise [`NoopSink`].
+pub fn sink_from_env(env: &Env) -> Box<dyn MetricsSink> {
+    match env.analytics_engine(METRICS_DATASET_NAME) {
+        Ok(dataset) => Box::new(AnalyticsEngineSink { dataset }),
+        Err(_) => Box::new(NoopSink),
+    }
+}
+
+/// Collects metrics in memory so tests can assert on what was emitted.
+#[cfg(test)]
+#[derive(Clone, Default)]
+pub struct InMemorySink {
+    events: std::rc::Rc<std::cell::RefCell<Vec<Metric>>>,
+}
+
+#[cfg(test)]
+impl InMemorySink {
+    pub fn events(&self) -> Vec<Metric> {
+        self.events.borrow().clone()
+    }
+}
+
+#[cfg(test)]
+impl MetricsSink for InMemorySink {
+    fn record(&self, metric: Metric) {
+        self.events.borrow_mut().push(metric);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn request_columns_follow_documented_layout() {
+        let metric = Metric::Request {
+            route: "PUT /api/upload/chunk",
+            status: 404,
+            latency_ms: 25,
+            error_code: Some("UPLOAD_NOT_FOUND"),
+        };
+
+        let (blobs, doubles) = metric.columns();
+        assert_eq!(
+            blobs,
+            vec!["request", "PUT /api/upload/chunk", "UPLOAD_NOT_FOUND"]
+        );
+        assert_eq!(doubles, vec![404.0, 25.0]);
+    }
+
+    #[test]
+    fn successful_request_has_empty_error_blob() {
+        let metric = Metric::Request {
+            route: "GET /health",
+            status: 200,
+            latency_ms: 1,
+            error_code: None,
+        };
+        assert_eq!(metric.columns().0[2], "");
+    }
+
+    #[test]
+    fn chunk_and_completion_columns_carry_role_and_bytes() {
+        let chunk = Metric::ChunkUploaded {
+            role: UserRole::Member,
+            bytes: 1024,
+            latency_ms: 80,
+        };
+        assert_eq!(
+            chunk.columns(),
+            (
+                vec!["chunk_uploaded".into(), "member".into()],
+                vec![1024.0, 80.0]
+            )
+        );
+
+        let completed = Metric::UploadCompleted {
+            role: UserRole::Creator,
+            total_size: 2048,
+        };
+        assert_eq!(
+            completed.columns(),
+            (
+                vec!["upload_completed".into(), "creator".into()],
+                vec![2048.0]
+            )
+        );
+    }
+
+    #[test]
+    fn in_memory_sink_records_in_order() {
+        let sink = InMemorySink::default();
+        let handle = sink.clone();
+
+        sink.record(Metric::UploadCompleted {
+            role: UserRole::Creator,
+            total_size: 1,
+        });
+        sink.record(Metric::ChunkUploaded {
+            role: UserRole::Creator,
+            bytes: 1,
+            latency_ms: 0,
+        });
+
+        let names: Vec<_> = handle.events().iter().map(Metric::name).collect();
+        assert_eq!(names, vec!["upload_completed", "chunk_uploaded"]);
+    }
+}
@@ -7,6 +7,8 @@
 //! ## Middleware Types
 //!
 //! - **CORS Middleware**: Handles cross-origin request support
+//! - **Rate Limit Middleware**: Per-IP and per-user token buckets
+//! - **Admin Auth Middleware**: Bearer token check for `/api/admin` endpoints
 //! - **Validation Middleware**: Validates request headers and parameters
 //!
 //! ## Design Patterns
@@ -20,20 +22,30 @@
 //!
 //! ```rust
 //! // Apply CORS headers to response
-//! let response = CorsMiddleware::apply_headers(response);
+//! let origin = CorsMiddleware::request_origin(&req);
+//! let response = CorsMiddleware::apply_headers(response, &config.cors, origin.as_deref());
 //!
 //! // Handle CORS preflight
 //! if req.method() == Method::Options {
-//!     return CorsMiddleware::handle_preflight();
+//!     return CorsMiddleware::handle_preflight(&req, &config.cors);
 //! }
 //!
 //! // Validate upload headers
 //! let (upload_id, chunk_index) = ValidationMiddleware::validate_upload_headers(&req)?;
 //! ```
 
-use crate::constants::{HEADER_CHUNK_INDEX, HEADER_UPLOAD_ID, MAX_PART_NUMBER};
+use crate::config::{Config, CorsConfig, TokenBucketSpec};
+use crate::constants::{
+    HEADER_CHUNK_INDEX, HEADER_CONNECTING_IP, HEADER_ENCRYPTION_KEY, HEADER_ENCRYPTION_KEY_MD5,
+    HEADER_UPLOAD_ID, MAX_PART_NUMBER,
+};
+use crate::crypto::Key;
+use crate::database::ActiveUploads;
 use crate::errors::{AppError, AppResult};
-use crate::utils::cors_preflight_headers;
+use crate::logging::RequestContext;
+use crate::models::UserRole;
+use crate::rate_limit::{store_from_env, RateLimiter};
+use crate::utils::{cors_header_values, cors_preflight_header_values, to_headers};
 use worker::*;
 
 /// Middleware for handling Cross-Origin Resource Sharing (CORS) requests.
@@ -44,34 +56,32 @@ use worker::*;
 ///
 /// # CORS Support
 ///
-/// - **Preflight Requests**: Handles OPTIONS requests with proper headers
-/// - **Header Application**: Adds CORS headers to all responses
-/// - **Broad Compatibility**: Configured for maximum client compatibility
-///
-/// # Security Considerations
-///
-/// The current implementation allows all origins (`*`) for maximum compatibility.
-/// For production environments with sensitive data, consider restricting origins
-/// to specific trusted domains.
+/// - **Preflight Requests**: Answers OPTIONS requests from allowed origins and
+///   rejects the rest with `403`
+/// - **Header Application**: Adds CORS headers for the request origin to responses
+/// - **Configurable Policy**: Origins, methods, headers, and credentials come from
+///   [`CorsConfig`]
 pub struct CorsMiddleware;
 
 impl CorsMiddleware {
+    /// Returns the request's `Origin` header, if any.
+    pub fn request_origin(req: &Request) -> Option<String> {
+        req.headers().get("Origin").ok().flatten()
+    }
+
     /// Handles CORS preflight requests (OPTIONS method).
     ///
     /// Preflight requests are sent by browsers before making cross-origin
-    /// requests with certain characteristics. This method returns an empty
-    /// response with appropriate CORS headers to indicate that the actual
-    /// request is allowed.
-    ///
-    /// # Returns
-    ///
-    /// Returns a `Result<Response>` containing an empty response with CORS headers.
+    /// requests with certain characteristics. Allowed origins receive an empty
+    /// response with the CORS headers of their matching rule. Unknown origins,
+    /// and methods not permitted for the origin, receive `403` without any
+    /// `Access-Control-*` headers.
     ///
     /// # Example
     ///
     /// ```rust
     /// if req.method() == Method::Options {
-    ///     return CorsMiddleware::handle_preflight();
+    ///     return CorsMiddleware::handle_preflight(&req, &config.cors);
     /// }
     /// ```
     ///
@@ -81,11 +91,144 @@ impl CorsMiddleware {
     /// - Non-simple HTTP methods (PUT, DELETE, etc.)
     /// - Custom headers (X-Upload-Id, X-Chunk-Index)
     /// - Non-simple content types
-    pub fn handle_preflight() -> Result<Response> {
-        Ok(Response::empty()?.with_headers(cors_preflight_headers()))
+    pub fn handle_preflight(req: &Request, cors: &CorsConfig) -> Result<Response> {
+        let origin = Self::request_origin(req);
+        let requested_method = req
+            .headers()
+            .get("Access-Control-Request-Method")
+            .ok()
+            .flatten();
+
+        match cors_preflight_header_values(cors, origin.as_deref(), requested_method.as_deref()) {
+            Some(values) => Ok(Response::empty()?.with_headers(to_headers(&values))),
+            None => Ok(Response::error("CORS origin not allowed", 403)?
+                .with_headers(to_headers(&[("Vary", "Origin".to_string())]))),
+        }
+    }
+
+    /// Adds the CORS headers for `origin` to the response, keeping its own headers
+    /// (such as `Retry-After`).
+    pub fn apply_headers(
+        mut response: Response,
+        cors: &CorsConfig,
+        origin: Option<&str>,
+    ) -> Response {
+        for (name, value) in cors_header_values(cors, origin) {
+            let _ = response.headers_mut().set(name, &value);
+        }
+        response
+    }
+}
+
+/// Middleware enforcing the per-route rate limits from [`Config::rate_limits`].
+///
+/// Limits are looked up by the route pattern recorded on the [`RequestContext`].
+/// The per-IP check runs before the handler; the per-user check runs inside
+/// handlers once the user (and their role) is known. Both are no-ops when the
+/// route has no limit or neither the `RATE_LIMITER` Durable Object nor the
+/// `RATE_LIMIT` KV binding is present.
+pub struct RateLimitMiddleware;
+
+impl RateLimitMiddleware {
+    /// Takes a token from the bucket for the request's `CF-Connecting-IP`.
+    ///
+    /// # Errors
+    ///
+    /// - `RateLimited`: When the IP has exhausted its bucket for this route.
+    pub async fn check_ip(
+        req: &Request,
+        env: &Env,
+        config: &Config,
+        ctx: &RequestContext,
+    ) -> AppResult<()> {
+        let Some(spec) = config.rate_limits.ip_limit(ctx.route()) else {
+            return Ok(());
+        };
+        let Some(ip) = req.headers().get(HEADER_CONNECTING_IP).ok().flatten() else {
+            return Ok(());
+        };
+        Self::take(env, ctx.route(), "ip", &ip, spec).await
+    }
+
+    /// Takes a token from the bucket for `user_id`, sized by `role`.
+    ///
+    /// # Errors
+    ///
+    /// - `RateLimited`: When the user has exhausted their bucket for this route.
+    pub async fn check_user(
+        env: &Env,
+        config: &Config,
+        ctx: &RequestContext,
+        role: &UserRole,
+        user_id: &str,
+    ) -> AppResult<()> {
+        match config.rate_limits.user_limit(ctx.route(), role) {
+            Some(spec) => Self::take(env, ctx.route(), "user", user_id, spec).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn take(
+        env: &Env,
+        route: &str,
+        scope: &'static str,
+        subject: &str,
+        spec: &TokenBucketSpec,
+    ) -> AppResult<()> {
+        let Some(store) = store_from_env(env) else {
+            return Ok(());
+        };
+        RateLimiter::new(store)
+            .check(route, scope, subject, spec, Date::now().as_millis())
+            .await
+    }
+}
+
+/// Middleware guarding `/api/admin` endpoints with the `ADMIN_TOKEN` secret.
+///
+/// Requests must send `Authorization: Bearer <token>`. Without the secret the
+/// admin API rejects every request.
+pub struct AdminAuthMiddleware;
+
+impl AdminAuthMiddleware {
+    /// Checks the request's bearer token against `Config::admin_token`, which
+    /// is filled from the `ADMIN_TOKEN` secret.
+    ///
+    /// # Errors
+    ///
+    /// - `Unauthorized`: When the secret is not configured or the token is missing or wrong.
+    pub fn authorize(req: &Request, config: &Config) -> AppResult<()> {
+        let expected = config
+            .admin_token
+            .as_ref()
+            .ok_or_else(|| AppError::Unauthorized {
+                message: "Admin API is not configured".to_string(),
+            })?;
+        let provided = req.headers().get("Authorization").ok().flatten();
+        Self::check_token(provided.as_deref(), expected.expose())
+    }
+
+    fn check_token(authorization: Option<&str>, expected: &str) -> AppResult<()> {
+        let token = authorization.and_then(|value| value.strip_prefix("Bearer "));
+        match token {
+            Some(token) if !expected.is_empty() && constant_time_eq(token, expected) => Ok(()),
+            _ => Err(AppError::Unauthorized {
+                message: "Missing or invalid admin token".to_string(),
+            }),
+        }
     }
 }
 
+/// Compares two strings without short-circuiting on the first differing byte.
+fn constant_time_eq(left: &str, right: &str) -> bool {
+    left.len() == right.len()
+        && left
+            .bytes()
+            .zip(right.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
 /// Middleware for validating request parameters and headers.
 ///
 /// This middleware provides validation functions for various aspects of
@@ -203,6 +346,24 @@ impl ValidationMiddleware {
         Ok(())
     }
 
+    /// Validates that the user has room for another in-flight upload.
+    ///
+    /// # Errors
+    ///
+    /// - `TooManyActiveUploads`: When `active.count` has reached `limit`; the
+    ///   error lists the oldest active upload IDs so the client can resume or
+    ///   cancel them.
+    pub fn validate_active_uploads(active: ActiveUploads, limit: u32) -> AppResult<()> {
+        if active.count >= u64::from(limit) {
+            return Err(AppError::TooManyActiveUploads {
+                active: active.count,
+                limit,
+                oldest_upload_ids: active.oldest_upload_ids,
+            });
+        }
+        Ok(())
+    }
+
     /// Validates a 0-based chunk index against the R2 multipart part-number ceiling.
     ///
     /// R2 caps multipart uploads at `MAX_PART_NUMBER` parts. Indexes are 0-based and
@@ -284,12 +445,67 @@ impl ValidationMiddleware {
 
         Ok(())
     }
+
+    /// Validates a client-declared SHA-256 digest and returns it lowercased.
+    ///
+    /// # Errors
+    ///
+    /// - `InvalidField`: If the value is not 64 hexadecimal characters
+    pub fn validate_sha256(sha256: &str) -> AppResult<String> {
+        if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(AppError::InvalidField {
+                field: "sha256".to_string(),
+                reason: "Must be 64 hexadecimal characters".to_string(),
+            });
+        }
+        Ok(sha256.to_ascii_lowercase())
+    }
+
+    /// Reads a client-provided encryption key from `X-Encryption-Key`,
+    /// checked against `X-Encryption-Key-MD5`.
+    ///
+    /// # Returns
+    ///
+    /// The key, or `None` when the request does not send one.
+    ///
+    /// # Errors
+    ///
+    /// - `InvalidField`: If the key is not a base64 256-bit key or the digest does not match
+    /// - `MissingField`: If the key is sent without its digest
+    pub fn validate_encryption_headers(req: &Request) -> AppResult<Option<Key>> {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .map_err(|err| AppError::InternalError {
+                    message: format!("Failed to read {name} header: {err}"),
+                })
+        };
+        let Some(key) = header(HEADER_ENCRYPTION_KEY)? else {
+            return Ok(None);
+        };
+        Key::from_customer_headers(&key, header(HEADER_ENCRYPTION_KEY_MD5)?.as_deref()).map(Some)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn admin_token_requires_exact_bearer_match() {
+        assert!(AdminAuthMiddleware::check_token(Some("Bearer s3cret"), "s3cret").is_ok());
+        for header in [
+            None,
+            Some("s3cret"),
+            Some("Bearer s3cre"),
+            Some("Bearer s3cret2"),
+        ] {
+            let err = AdminAuthMiddleware::check_token(header, "s3cret").unwrap_err();
+            assert!(matches!(err, AppError::Unauthorized { .. }), "{header:?}");
+        }
+        assert!(AdminAuthMiddleware::check_token(Some("Bearer "), "").is_err());
+    }
+
     #[test]
     fn validate_file_size_allows_within_limit() {
         assert!(ValidationMiddleware::validate_file_size(1_048_576, 10_485_760).is_ok());
@@ -301,6 +517,29 @@ mod tests {
         assert!(matches!(err, AppError::FileSizeExceeded { .. }));
     }
 
+    #[test]
+    fn validate_active_uploads_allows_below_limit() {
+        let active = ActiveUploads {
+            count: 4,
+            oldest_upload_ids: vec!["a".into()],
+        };
+        assert!(ValidationMiddleware::validate_active_uploads(active, 5).is_ok());
+    }
+
+    #[test]
+    fn validate_active_uploads_rejects_at_limit_with_oldest_ids() {
+        let active = ActiveUploads {
+            count: 5,
+            oldest_upload_ids: vec!["a".into(), "b".into()],
+        };
+        let err = ValidationMiddleware::validate_active_uploads(active, 5).unwrap_err();
+        assert!(matches!(
+            err,
+            AppError::TooManyActiveUploads { active: 5, limit: 5, ref oldest_upload_ids }
+                if oldest_upload_ids == &["a", "b"]
+        ));
+    }
+
     #[test]
     fn validate_chunk_index_accepts_zero() {
         assert!(ValidationMiddleware::validate_chunk_index(0).is_ok());
@@ -339,4 +578,17 @@ mod tests {
             ValidationMiddleware::validate_content_type("application/x-msdownload").unwrap_err();
         assert!(matches!(err, AppError::InvalidField { .. }));
     }
+
+    #[test]
+    fn validate_sha256_lowercases_hex_digest() {
+        let digest = "9F86D081884C7D659A2FEAA0C55AD015A3BF4F1B2B0B822CD15D6C15B0F00A08";
+        assert_eq!(
+            ValidationMiddleware::validate_sha256(digest).unwrap(),
+            digest.to_ascii_lowercase()
+        );
+        for bad in ["abc", &"g".repeat(64)] {
+            let err = ValidationMiddleware::validate_sha256(bad).unwrap_err();
+            assert!(matches!(err, AppError::InvalidField { .. }));
+        }
+    }
 }
@@ -0,0 +1,375 @@
+//! # Schema Migrations
+//!
+//! The D1 schema is versioned by the numbered SQL files in `migrations/`,
+//! embedded in the binary by [`MIGRATIONS`]. Applied versions are recorded in
+//! the `schema_migrations` table; `DatabaseService::run_migrations` applies
+//! pending ones in order, each in a single D1 batch (one transaction) together
+//! with its `schema_migrations` row.
+//!
+//! The first API request in each isolate checks the schema through
+//! [`ensure_current`], which applies pending migrations when
+//! `Config::auto_migrate` is set. Requests are refused with
+//! `503 SCHEMA_MISMATCH` while the database is:
+//!
+//! - ahead of this build (a newer worker migrated it),
+//! - behind it with `auto_migrate` off (run `POST /api/admin/migrations`), or
+//! - populated by hand without a `schema_migrations` history (baseline it with
+//!   `POST /api/admin/migrations?baseline=N`).
+//!
+//! `schema.sql` is the full current schema and records every migration as
+//! applied, so a database created from it starts up to date.
+
+use std::sync::OnceLock;
+
+use serde::Serialize;
+use serde_json::json;
+use worker::Env;
+
+use crate::config::Config;
+use crate::database::DatabaseService;
+use crate::errors::{AppError, AppResult};
+use crate::logging::{log, LogLevel};
+
+/// A numbered schema change from `migrations/`.
+#[derive(Debug)]
+pub struct Migration {
+    pub version: u32,
+    /// File name without the `.sql` extension.
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+macro_rules! migration {
+    ($version:expr, $name:literal) => {
+        Migration {
+            version: $version,
+            name: $name,
+            sql: include_str!(concat!("../migrations/", $name, ".sql")),
+        }
+    };
+}
+
+/// Every migration, in the order it must be applied.
+pub const MIGRATIONS: &[Migration] = &[
+    migration!(0, "000_initial_schema"),
+    migration!(1, "001_add_upload_variants"),
+    migration!(2, "002_add_media_info"),
+    migration!(3, "003_add_metadata_stripped"),
+    migration!(4, "004_add_user_status_index"),
+    migration!(5, "005_add_idempotency_keys"),
+    migration!(6, "006_add_completing_status"),
+    migration!(7, "007_add_upload_events"),
+    migration!(8, "008_add_audit_log"),
+    migration!(9, "009_add_upload_bucket"),
+    migration!(10, "010_add_upload_key_index"),
+    migration!(11, "011_add_file_versions"),
+    migration!(12, "012_add_encryption"),
+    migration!(13, "013_add_retention"),
+    migration!(14, "014_scope_file_versions_to_user"),
+    migration!(15, "015_add_completion_claim"),
+];
+
+/// Schema version this build expects.
+pub fn latest_version() -> u32 {
+    MIGRATIONS.last().map_or(0, |migration| migration.version)
+}
+
+/// Where a database stands relative to [`MIGRATIONS`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct MigrationStatus {
+    /// Highest applied version; `None` for an empty database.
+    pub database_version: Option<u32>,
+    /// Version this build expects.
+    pub code_version: u32,
+    /// Migrations applied by this call.
+    pub applied: Vec<&'static str>,
+    /// Migrations still to apply.
+    pub pending: Vec<&'static str>,
+}
+
+impl MigrationStatus {
+    pub fn new(
+        database_version: Option<u32>,
+        applied: Vec<&'static str>,
+        pending: &[Migration],
+    ) -> Self {
+        Self {
+            database_version,
+            code_version: latest_version(),
+            applied,
+            pending: pending.iter().map(|migration| migration.name).collect(),
+        }
+    }
+}
+
+/// Set once the schema has been confirmed current in this isolate.
+static SCHEMA_READY: OnceLock<()> = OnceLock::new();
+
+/// Confirms the database schema matches this build, migrating it first when
+/// `auto_migrate` is on. Only the first successful check per isolate touches D1.
+///
+/// # Errors
+///
+/// - `SchemaMismatch`: When the database is ahead, behind without `auto_migrate`,
+///   or has no migration history.
+pub async fn ensure_current(env: &Env, config: &Config) -> AppResult<()> {
+    if SCHEMA_READY.get().is_some() {
+        return Ok(());
+    }
+
+    let database = DatabaseService::new(env, &config.database_name)?;
+    let status = if config.auto_migrate {
+        database.run_migrations(None).await?
+    } else {
+        database.migration_status().await?
+    };
+
+    if !status.applied.is_empty() {
+        log(
+            LogLevel::Info,
+            "applied schema migrations",
+            json!({ "applied": status.applied, "database_version": status.database_version }),
+        );
+    }
+    if !status.pending.is_empty() {
+        return Err(AppError::SchemaMismatch {
+            message: format!(
+                "Database schema is behind this build; pending migrations: {}. \
+                 Run POST /api/admin/migrations",
+                status.pending.join(", ")
+            ),
+        });
+    }
+
+    let _ = SCHEMA_READY.set(());
+    Ok(())
+}
+
+/// Decides which version a run starts from.
+///
+/// `baseline` declares that a database without migration history already has
+/// every migration up to that version. A database with tables but no history
+/// must be baselined before anything is applied to it.
+pub fn resolve_start(
+    recorded: Option<u32>,
+    has_tables: bool,
+    baseline: Option<u32>,
+) -> AppResult<Option<u32>> {
+    match (recorded, baseline) {
+        (Some(_), Some(_)) => Err(AppError::ValidationError {
+            message: "Database already has a migration history; baseline is only for \
+                      databases created by hand"
+                .to_string(),
+        }),
+        (None, Some(baseline)) if baseline > latest_version() => Err(AppError::InvalidField {
+            field: "baseline".to_string(),
+            reason: format!("Must be at most {}", latest_version()),
+        }),
+        (None, Some(baseline)) => Ok(Some(baseline)),
+        (None, None) if has_tables => Err(AppError::SchemaMismatch {
+            message: "Database has tables but no schema_migrations history; record the \
+                      migrations it already has with POST /api/admin/migrations?baseline=N"
+                .to_string(),
+        }),
+        (recorded, None) => Ok(recorded),
+    }
+}
+
+/// Migrations after `version`, or every migration for an empty database.
+///
+/// # Errors
+///
+/// - `SchemaMismatch`: When the database is ahead of this build.
+pub fn pending_after(version: Option<u32>) -> AppResult<&'static [Migration]> {
+    if let Some(version) = version.filter(|version| *version > latest_version()) {
+        return Err(AppError::SchemaMismatch {
+            message: format!(
+                "Database schema version {version} is ahead of this build ({}); \
+                 deploy a newer worker",
+                latest_version()
+            ),
+        });
+    }
+    let start = MIGRATIONS.partition_point(|migration| Some(migration.version) <= version);
+    Ok(&MIGRATIONS[start..])
+}
+
+/// Splits a migration file into individual statements for a D1 batch.
+///
+/// Comments are dropped; semicolons inside quotes and inside
+/// `CREATE TRIGGER ... END` bodies do not end a statement.
+pub fn split_statements(sql: &str) -> Vec<String> {
+    let mut statements = Vec::new();
+    let mut current = String::new();
+    let mut chars = sql.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '-' if chars.peek() == Some(&'-') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        current.push('\n');
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = '\0';
+                for c in chars.by_ref() {
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+                current.push(' ');
+            }
+            '\'' | '"' => {
+                current.push(c);
+                for inner in chars.by_ref() {
+                    current.push(inner);
+                    if inner == c {
+                        break;
+                    }
+                }
+            }
+            ';' if is_open_trigger(&current) => current.push(c),
+            ';' => push_statement(&mut statements, &mut current),
+            c => current.push(c),
+        }
+    }
+    push_statement(&mut statements, &mut current);
+
+    statements
+}
+
+fn push_statement(statements: &mut Vec<String>, current: &mut String) {
+    let statement = current.trim();
+    if !statement.is_empty() {
+        statements.push(statement.to_string());
+    }
+    current.clear();
+}
+
+/// Whether `statement` is a trigger definition whose `END` has not been reached.
+fn is_open_trigger(statement: &str) -> bool {
+    let mut words = statement.split_whitespace().map(str::to_ascii_uppercase);
+    let is_trigger = words.next().as_deref() == Some("CREATE")
+        && match words.next().as_deref() {
+            Some("TEMP" | "TEMPORARY") => words.next().as_deref() == Some("TRIGGER"),
+            Some("TRIGGER") => true,
+            _ => false,
+        };
+    is_trigger
+        && statement
+            .split_whitespace()
+            .last()
+            .is_none_or(|word| !word.eq_ignore_ascii_case("END"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrations_are_numbered_contiguously_from_zero() {
+        for (index, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version as usize, index);
+            assert!(
+                migration.name.starts_with(&format!("{index:03}_")),
+                "{}",
+                migration.name
+            );
+        }
+    }
+
+    #[test]
+    fn every_migration_file_is_embedded() {
+        let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/migrations");
+        let mut files: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .filter_map(|entry| entry.unwrap().file_name().into_string().ok())
+            .filter_map(|name| name.strip_suffix(".sql").map(str::to_string))
+            .collect();
+        files.sort();
+        let embedded: Vec<&str> = MIGRATIONS.iter().map(|migration| migration.name).collect();
+        assert_eq!(files, embedded);
+    }
+
+    #[test]
+    fn schema_sql_records_every_migration() {
+        let schema = include_str!("../schema.sql");
+        for migration in MIGRATIONS {
+            let record = format!("({}, '{}'", migration.version, migration.name);
+            assert!(schema.contains(&record), "schema.sql lacks {record}");
+        }
+    }
+
+    #[test]
+    fn pending_after_returns_everything_newer() {
+        assert_eq!(pending_after(None).unwrap().len(), MIGRATIONS.len());
+        assert_eq!(pending_after(Some(0)).unwrap()[0].version, 1);
+        assert!(pending_after(Some(latest_version())).unwrap().is_empty());
+    }
+
+    #[test]
+    fn pending_after_refuses_database_ahead_of_code() {
+        let err = pending_after(Some(latest_version() + 1)).unwrap_err();
+        assert!(matches!(err, AppError::SchemaMismatch { .. }));
+    }
+
+    #[test]
+    fn resolve_start_requires_baseline_for_hand_made_databases() {
+        assert_eq!(resolve_start(None, false, None).unwrap(), None);
+        assert_eq!(resolve_start(Some(3), true, None).unwrap(), Some(3));
+        assert_eq!(resolve_start(None, true, Some(4)).unwrap(), Some(4));
+        assert!(matches!(
+            resolve_start(None, true, None),
+            Err(AppError::SchemaMismatch { .. })
+        ));
+        assert!(matches!(
+            resolve_start(Some(3), true, Some(4)),
+            Err(AppError::ValidationError { .. })
+        ));
+        assert!(matches!(
+            resolve_start(None, true, Some(latest_version() + 1)),
+            Err(AppError::InvalidField { .. })
+        ));
+    }
+
+    #[test]
+    fn split_statements_drops_comments_and_keeps_quoted_semicolons() {
+        let sql = "-- header; not a statement\n\
+                   CREATE TABLE t (a TEXT DEFAULT 'x;y'); /* block; */\n\
+                   INSERT INTO t VALUES ('it''s');\n";
+        assert_eq!(
+            split_statements(sql),
+            vec![
+                "CREATE TABLE t (a TEXT DEFAULT 'x;y')",
+                "INSERT INTO t VALUES ('it''s')"
+            ]
+        );
+    }
+
+    #[test]
+    fn split_statements_keeps_trigger_bodies_whole() {
+        let sql = "CREATE TRIGGER no_delete BEFORE DELETE ON t\n\
+                   BEGIN\n    SELECT RAISE(ABORT, 'nope');\nEND;\n\
+                   CREATE INDEX i ON t(a);";
+        let statements = split_statements(sql);
+        assert_eq!(statements.len(), 2);
+        assert!(statements[0].ends_with("END"));
+        assert!(statements[0].contains("RAISE(ABORT, 'nope');"));
+    }
+
+    #[test]
+    fn every_migration_splits_into_statements() {
+        for migration in MIGRATIONS {
+            assert!(
+                !split_statements(migration.sql).is_empty(),
+                "{}",
+                migration.name
+            );
+        }
+    }
+}
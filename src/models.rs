@@ -9,6 +9,10 @@
 //! - `UserRole`: Enumeration of user roles for file organization
 //! - `UploadMetadata`: Complete metadata for an upload session
 //! - `UploadStatus`: State tracking for upload progress
+//! - `UploadEvent` / `transition`: The upload state machine
+//! - `UploadVariant`: Derived image rendition stored alongside a completed upload
+//! - `MediaInfo`: Technical metadata parsed from media file headers
+//! - `AuditAction` / `AuditEntry`: Rows of the append-only audit log
 //!
 //! ## Design Principles
 //!
@@ -17,6 +21,8 @@
 //! - Metadata includes comprehensive tracking information
 //! - UTC timestamps for global consistency
 
+use std::collections::BTreeMap;
+
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 
@@ -143,21 +149,192 @@ pub struct UploadMetadata {
     /// Generated based on user role, ID, date, and content type.
     pub r2_key: String,
 
+    /// R2 bucket binding holding the object, chosen at init by
+    /// `Config::bucket_routing` and used for every later R2 call.
+    pub bucket: String,
+
     /// Identifier of the user performing the upload.
     pub user_id: String,
 
     /// R2 multipart upload identifier.
     /// Required for completing the multipart upload operation.
     pub r2_upload_id: String,
+
+    /// Media metadata parsed from the first chunk of `image/*`, `video/*`
+    /// and `audio/*` uploads, when the format was recognised.
+    pub media_info: Option<MediaInfo>,
+
+    /// Whether EXIF/XMP/GPS metadata was stripped from the stored image.
+    pub metadata_stripped: bool,
+
+    /// Key this upload is a version of, set when it was initialized with the
+    /// `version` collision strategy. `r2_key` is then unique to the upload.
+    pub logical_key: Option<String>,
+
+    /// How the stored object is encrypted at rest.
+    pub encryption: EncryptionMode,
+
+    /// The upload's data key wrapped under the customer or managed key;
+    /// `None` for unencrypted uploads.
+    #[serde(skip)]
+    pub wrapped_key: Option<String>,
+
+    /// The upload cannot be deleted before this time.
+    pub retain_until: Option<DateTime<Utc>>,
+
+    /// Whether a legal hold protects the upload until an operator clears it.
+    pub legal_hold: bool,
+
+    /// When a completion request last claimed the upload; a retried
+    /// completion may take over a claim older than the completion lease.
+    pub completion_claimed_at: Option<DateTime<Utc>>,
 }
 
-/// Upload lifecycle state.
+impl UploadMetadata {
+    /// Whether a legal hold or an unexpired retention period protects the
+    /// upload at `now`.
+    pub fn is_held(&self, now: DateTime<Utc>) -> bool {
+        self.legal_hold || self.retain_until.is_some_and(|until| until > now)
+    }
+}
+
+/// Encryption applied to an upload's chunks before they reach R2.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EncryptionMode {
+    /// Stored as sent.
+    #[default]
+    None,
+    /// Data key wrapped under the client's `X-Encryption-Key`.
+    Customer,
+    /// Data key wrapped under a key derived from `ENCRYPTION_MASTER_KEY`.
+    Managed,
+}
+
+impl EncryptionMode {
+    /// Returns the string stored in the `uploads.encryption` column.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EncryptionMode::None => "none",
+            EncryptionMode::Customer => "customer",
+            EncryptionMode::Managed => "managed",
+        }
+    }
+}
+
+impl std::str::FromStr for EncryptionMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "none" => Ok(EncryptionMode::None),
+            "customer" => Ok(EncryptionMode::Customer),
+            "managed" => Ok(EncryptionMode::Managed),
+            other => Err(format!("Invalid encryption mode: {}", other)),
+        }
+    }
+}
+
+/// Technical metadata extracted from media file headers.
 ///
-/// Transitions:
-/// - `Initiated`   --first chunk-->  `InProgress`
-/// - `InProgress`  --complete-->     `Completed`
-/// - `Initiated` | `InProgress` --cancel--> `Cancelled`
+/// Populated from the leading bytes of an upload so clients can read
+/// dimensions, duration, and codec details without downloading the file.
+/// Fields that the container does not expose (or that live beyond the
+/// parsed bytes) are left as `None`.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct MediaInfo {
+    /// Detected container format (`png`, `jpeg`, `webp`, `gif`, `mp4`, `wav`, `mp3`).
+    pub format: String,
+
+    /// Pixel width for images and video.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+
+    /// Pixel height for images and video.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+
+    /// Playback duration in seconds for audio and video.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duration_seconds: Option<f64>,
+
+    /// Video codec identifier (e.g. `avc1`, `hvc1`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub video_codec: Option<String>,
+
+    /// Audio codec identifier (e.g. `mp4a`, `pcm`, `mp3`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub audio_codec: Option<String>,
+
+    /// Audio sample rate in Hz.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sample_rate: Option<u32>,
+
+    /// Audio channel count.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channels: Option<u16>,
+
+    /// Descriptive fields: EXIF for images, ID3 text frames for audio.
+    /// GPS tags are deliberately never recorded.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+}
+
+/// Derived rendition of a completed image upload (e.g. a thumbnail).
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct UploadVariant {
+    /// Configured variant name, unique per upload.
+    pub name: String,
+
+    /// R2 key of the variant object, a sibling of the original upload key.
+    pub r2_key: String,
+
+    /// MIME type of the encoded variant.
+    pub content_type: String,
+
+    /// Pixel width of the variant.
+    pub width: u32,
+
+    /// Pixel height of the variant.
+    pub height: u32,
+
+    /// Encoded size in bytes.
+    pub size: u64,
+}
+
+/// One completed upload of a versioned logical key.
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FileVersion {
+    /// Version number, starting at 1 and increasing with each completion.
+    pub version: u32,
+
+    /// Upload that produced this version.
+    pub upload_id: String,
+
+    /// Original filename of that upload.
+    pub file_name: String,
+
+    /// R2 key of the object holding this version.
+    pub r2_key: String,
+
+    /// Object size in bytes.
+    pub size: u64,
+
+    /// MIME type of the object.
+    pub content_type: String,
+
+    /// Whether this is the version served as current.
+    pub is_current: bool,
+
+    /// When the version was completed.
+    pub created_at: DateTime<Utc>,
+}
+
+/// Upload lifecycle state.
+///
+/// Every change of state goes through [`transition`], which validates it
+/// against [`TRANSITIONS`].
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum UploadStatus {
     /// Upload session has been created but no chunks have been uploaded yet.
@@ -166,21 +343,50 @@ pub enum UploadStatus {
     /// Upload is in progress with one or more chunks successfully uploaded.
     InProgress,
 
+    /// A completion request has claimed the upload and is finalizing it in R2.
+    Completing,
+
     /// Upload has been completed successfully and file is available in R2.
     Completed,
 
     /// Upload has been cancelled and any uploaded chunks have been cleaned up.
     Cancelled,
+
+    /// Upload was abandoned and expired by the scheduled sweep.
+    Expired,
+
+    /// Completion found the multipart session gone from R2 without an
+    /// assembled object, so the upload can never complete.
+    Failed,
+
+    /// Completed object has been removed from R2.
+    Deleted,
 }
 
 impl UploadStatus {
+    /// Every status, in lifecycle order.
+    pub const ALL: [UploadStatus; 8] = [
+        UploadStatus::Initiated,
+        UploadStatus::InProgress,
+        UploadStatus::Completing,
+        UploadStatus::Completed,
+        UploadStatus::Cancelled,
+        UploadStatus::Expired,
+        UploadStatus::Failed,
+        UploadStatus::Deleted,
+    ];
+
     /// Returns the lowercase string representation for storage and API responses.
     pub fn as_str(&self) -> &'static str {
         match self {
             UploadStatus::Initiated => "initiated",
             UploadStatus::InProgress => "in_progress",
+            UploadStatus::Completing => "completing",
             UploadStatus::Completed => "completed",
             UploadStatus::Cancelled => "cancelled",
+            UploadStatus::Expired => "expired",
+            UploadStatus::Failed => "failed",
+            UploadStatus::Deleted => "deleted",
         }
     }
 }
@@ -192,13 +398,197 @@ impl std::str::FromStr for UploadStatus {
         match value.to_lowercase().as_str() {
             "initiated" => Ok(UploadStatus::Initiated),
             "in_progress" => Ok(UploadStatus::InProgress),
+            "completing" => Ok(UploadStatus::Completing),
             "completed" => Ok(UploadStatus::Completed),
             "cancelled" => Ok(UploadStatus::Cancelled),
+            "expired" => Ok(UploadStatus::Expired),
+            "failed" => Ok(UploadStatus::Failed),
+            "deleted" => Ok(UploadStatus::Deleted),
             other => Err(format!("Invalid upload status: {}", other)),
         }
     }
 }
 
+/// Something that happens to an upload and may change its status.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UploadEvent {
+    /// A chunk was stored in R2.
+    ChunkUploaded,
+    /// A completion request claimed the upload.
+    CompletionStarted,
+    /// R2 assembled the final object.
+    CompletionSucceeded,
+    /// R2 rejected completion; the upload can be completed again.
+    CompletionFailed,
+    /// The client cancelled the upload.
+    Cancelled,
+    /// The expiry sweep found the open upload idle past its multipart lifetime.
+    Expired,
+    /// Completion found the multipart session gone without an assembled object.
+    Failed,
+    /// The completed object was removed.
+    Deleted,
+}
+
+impl UploadEvent {
+    /// Returns the string stored in the `upload_events` history table.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            UploadEvent::ChunkUploaded => "chunk_uploaded",
+            UploadEvent::CompletionStarted => "completion_started",
+            UploadEvent::CompletionSucceeded => "completion_succeeded",
+            UploadEvent::CompletionFailed => "completion_failed",
+            UploadEvent::Cancelled => "cancelled",
+            UploadEvent::Expired => "expired",
+            UploadEvent::Failed => "failed",
+            UploadEvent::Deleted => "deleted",
+        }
+    }
+}
+
+/// Allowed transitions: each event, the statuses it applies to, and the single
+/// status it leads to. Statuses absent from every source list are terminal.
+pub const TRANSITIONS: &[(UploadEvent, &[UploadStatus], UploadStatus)] = &[
+    (
+        UploadEvent::ChunkUploaded,
+        &[UploadStatus::Initiated, UploadStatus::InProgress],
+        UploadStatus::InProgress,
+    ),
+    (
+        UploadEvent::CompletionStarted,
+        &[UploadStatus::Initiated, UploadStatus::InProgress],
+        UploadStatus::Completing,
+    ),
+    (
+        UploadEvent::CompletionSucceeded,
+        &[UploadStatus::Completing],
+        UploadStatus::Completed,
+    ),
+    (
+        UploadEvent::CompletionFailed,
+        &[UploadStatus::Completing],
+        UploadStatus::InProgress,
+    ),
+    (
+        UploadEvent::Cancelled,
+        &[UploadStatus::Initiated, UploadStatus::InProgress],
+        UploadStatus::Cancelled,
+    ),
+    (
+        UploadEvent::Expired,
+        &[UploadStatus::Initiated, UploadStatus::InProgress],
+        UploadStatus::Expired,
+    ),
+    (
+        UploadEvent::Failed,
+        &[UploadStatus::Completing],
+        UploadStatus::Failed,
+    ),
+    (
+        UploadEvent::Deleted,
+        &[UploadStatus::Completed],
+        UploadStatus::Deleted,
+    ),
+];
+
+/// An event that does not apply to the upload's current status.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InvalidTransition {
+    pub from: UploadStatus,
+    pub event: UploadEvent,
+}
+
+/// Returns the status `event` leads to from `from`.
+pub fn transition(
+    from: UploadStatus,
+    event: UploadEvent,
+) -> Result<UploadStatus, InvalidTransition> {
+    TRANSITIONS
+        .iter()
+        .find(|(candidate, sources, _)| *candidate == event && sources.contains(&from))
+        .map(|(_, _, target)| *target)
+        .ok_or(InvalidTransition { from, event })
+}
+
+/// Lifecycle action recorded in the audit log.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    /// Upload session created.
+    Init,
+    /// Chunk stored.
+    Chunk,
+    /// Upload completed.
+    Complete,
+    /// Upload cancelled.
+    Cancel,
+    /// Object downloaded.
+    Download,
+    /// Older version made current again.
+    Restore,
+    /// Object deleted.
+    Delete,
+    /// Object shared.
+    Share,
+    /// Retention period or legal hold set.
+    Hold,
+    /// Retention period or legal hold cleared.
+    Release,
+}
+
+impl AuditAction {
+    /// Returns the string stored in the `audit_log` table.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::Init => "init",
+            AuditAction::Chunk => "chunk",
+            AuditAction::Complete => "complete",
+            AuditAction::Cancel => "cancel",
+            AuditAction::Download => "download",
+            AuditAction::Restore => "restore",
+            AuditAction::Delete => "delete",
+            AuditAction::Share => "share",
+            AuditAction::Hold => "hold",
+            AuditAction::Release => "release",
+        }
+    }
+}
+
+impl std::str::FromStr for AuditAction {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "init" => Ok(AuditAction::Init),
+            "chunk" => Ok(AuditAction::Chunk),
+            "complete" => Ok(AuditAction::Complete),
+            "cancel" => Ok(AuditAction::Cancel),
+            "download" => Ok(AuditAction::Download),
+            "restore" => Ok(AuditAction::Restore),
+            "delete" => Ok(AuditAction::Delete),
+            "share" => Ok(AuditAction::Share),
+            "hold" => Ok(AuditAction::Hold),
+            "release" => Ok(AuditAction::Release),
+            other => Err(format!("Invalid audit action: {}", other)),
+        }
+    }
+}
+
+/// One row of the audit log: who did what to which upload, and from where.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AuditEntry {
+    /// Monotonic entry identifier, also used as the pagination cursor.
+    pub audit_id: u64,
+    pub action: AuditAction,
+    pub upload_id: Option<String>,
+    /// User ID the action was performed for.
+    pub actor: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub request_id: String,
+    pub created_at: DateTime<Utc>,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -224,12 +614,7 @@ mod tests {
 
     #[test]
     fn upload_status_roundtrip() {
-        for status in [
-            UploadStatus::Initiated,
-            UploadStatus::InProgress,
-            UploadStatus::Completed,
-            UploadStatus::Cancelled,
-        ] {
+        for status in UploadStatus::ALL {
             let as_str = status.as_str();
             let parsed = UploadStatus::from_str(as_str).unwrap();
             assert_eq!(status, parsed);
@@ -244,4 +629,111 @@ mod tests {
         );
         assert!(UploadStatus::from_str("done").is_err());
     }
+
+    #[test]
+    fn audit_action_roundtrip() {
+        for action in [
+            AuditAction::Init,
+            AuditAction::Chunk,
+            AuditAction::Complete,
+            AuditAction::Cancel,
+            AuditAction::Download,
+            AuditAction::Restore,
+            AuditAction::Delete,
+            AuditAction::Share,
+            AuditAction::Hold,
+            AuditAction::Release,
+        ] {
+            assert_eq!(AuditAction::from_str(action.as_str()), Ok(action));
+        }
+    }
+
+    #[test]
+    fn encryption_mode_roundtrip() {
+        for mode in [
+            EncryptionMode::None,
+            EncryptionMode::Customer,
+            EncryptionMode::Managed,
+        ] {
+            assert_eq!(EncryptionMode::from_str(mode.as_str()), Ok(mode));
+        }
+    }
+
+    const EVENTS: [UploadEvent; 8] = [
+        UploadEvent::ChunkUploaded,
+        UploadEvent::CompletionStarted,
+        UploadEvent::CompletionSucceeded,
+        UploadEvent::CompletionFailed,
+        UploadEvent::Cancelled,
+        UploadEvent::Expired,
+        UploadEvent::Failed,
+        UploadEvent::Deleted,
+    ];
+
+    #[test]
+    fn every_event_has_exactly_one_rule() {
+        for event in EVENTS {
+            let rules = TRANSITIONS.iter().filter(|(e, _, _)| *e == event).count();
+            assert_eq!(rules, 1, "{event:?}");
+        }
+    }
+
+    #[test]
+    fn transition_follows_the_happy_path() {
+        let status = transition(UploadStatus::Initiated, UploadEvent::ChunkUploaded).unwrap();
+        assert_eq!(status, UploadStatus::InProgress);
+        let status = transition(status, UploadEvent::ChunkUploaded).unwrap();
+        assert_eq!(status, UploadStatus::InProgress);
+        let status = transition(status, UploadEvent::CompletionStarted).unwrap();
+        assert_eq!(status, UploadStatus::Completing);
+        let status = transition(status, UploadEvent::CompletionSucceeded).unwrap();
+        assert_eq!(status, UploadStatus::Completed);
+        assert_eq!(
+            transition(status, UploadEvent::Deleted),
+            Ok(UploadStatus::Deleted)
+        );
+    }
+
+    #[test]
+    fn terminal_statuses_reject_every_event() {
+        for from in [
+            UploadStatus::Cancelled,
+            UploadStatus::Expired,
+            UploadStatus::Failed,
+            UploadStatus::Deleted,
+        ] {
+            for event in EVENTS {
+                assert_eq!(
+                    transition(from, event),
+                    Err(InvalidTransition { from, event })
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn completing_blocks_chunks_and_cancellation() {
+        for event in [UploadEvent::ChunkUploaded, UploadEvent::Cancelled] {
+            assert!(transition(UploadStatus::Completing, event).is_err());
+        }
+        assert_eq!(
+            transition(UploadStatus::Completing, UploadEvent::CompletionFailed),
+            Ok(UploadStatus::InProgress)
+        );
+        assert_eq!(
+            transition(UploadStatus::Completing, UploadEvent::Failed),
+            Ok(UploadStatus::Failed)
+        );
+    }
+
+    #[test]
+    fn only_open_uploads_expire() {
+        for from in [UploadStatus::Initiated, UploadStatus::InProgress] {
+            assert_eq!(
+                transition(from, UploadEvent::Expired),
+                Ok(UploadStatus::Expired)
+            );
+        }
+        assert!(transition(UploadStatus::Completing, UploadEvent::Expired).is_err());
+    }
 }
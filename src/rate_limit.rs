@@ -0,0 +1,404 @@
+//! # Rate Limiting
+//!
+//! Token-bucket rate limiting keyed by route and either client IP
+//! (`CF-Connecting-IP`) or user ID. Bucket sizes come from
+//! [`RateLimitConfig`](crate::config::RateLimitConfig); bucket state lives in a
+//! pluggable [`CounterStore`].
+//!
+//! Production uses [`DurableObjectCounterStore`] on the optional
+//! `RATE_LIMITER` Durable Object binding: each bucket is a [`RateLimitBucket`]
+//! object that takes tokens one request at a time, so limits are exact at any
+//! rate. Without it, [`KvCounterStore`] on the optional `RATE_LIMIT` KV binding
+//! is used. KV is eventually consistent and accepts about one write per second
+//! per key, so it cannot enforce a sustained rate above 1 request per second on
+//! a bucket, and concurrent requests may take the same token. Without either
+//! binding, requests are not limited.
+//!
+//! Store failures never fail a request: the limiter logs a warning and lets the
+//! request through.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use worker::kv::KvStore;
+use worker::{
+    durable_object, wasm_bindgen, DurableObject, Env, Method, ObjectNamespace, Request,
+    RequestInit, Response, Result, State,
+};
+
+use crate::config::TokenBucketSpec;
+use crate::constants::{RATE_LIMIT_DO_NAME, RATE_LIMIT_KEY_PREFIX, RATE_LIMIT_KV_NAME};
+use crate::errors::{AppError, AppResult};
+use crate::logging::{log, LogLevel};
+
+/// Shortest expiration KV accepts for a key, in seconds.
+const MIN_KV_TTL_SECS: u64 = 60;
+
+/// Upper bound for `Retry-After` and stored bucket lifetime (one day), so a
+/// bucket with no refill rate resets daily instead of never.
+const MAX_BUCKET_WAIT_SECS: u64 = 86_400;
+
+/// Persisted state of a single token bucket.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct BucketState {
+    /// Tokens available at `updated_at_ms`.
+    pub tokens: f64,
+    /// Unix time in milliseconds of the last refill.
+    pub updated_at_ms: u64,
+}
+
+/// Refills the bucket up to `now_ms` and tries to take one token.
+///
+/// Returns the new state and, when no token was available, the number of whole
+/// seconds until one will be. A missing state starts as a full bucket.
+pub fn take_token(
+    state: Option<BucketState>,
+    spec: &TokenBucketSpec,
+    now_ms: u64,
+) -> (BucketState, Option<u64>) {
+    let capacity = f64::from(spec.capacity);
+    let tokens = match state {
+        Some(state) => {
+            let elapsed_secs = now_ms.saturating_sub(state.updated_at_ms) as f64 / 1000.0;
+            (state.tokens + elapsed_secs * spec.refill_per_second).min(capacity)
+        }
+        None => capacity,
+    };
+
+    if tokens >= 1.0 {
+        let state = BucketState {
+            tokens: tokens - 1.0,
+            updated_at_ms: now_ms,
+        };
+        return (state, None);
+    }
+
+    let retry_after_secs = seconds_to_refill(1.0 - tokens, spec).max(1);
+    let state = BucketState {
+        tokens,
+        updated_at_ms: now_ms,
+    };
+    (state, Some(retry_after_secs))
+}
+
+/// Storage for token bucket state.
+pub trait CounterStore {
+    /// Takes a token from the bucket stored under `key` as [`take_token`]
+    /// does, returning the seconds until one is available when it is empty.
+    async fn take(&self, key: &str, spec: &TokenBucketSpec, now_ms: u64) -> Result<Option<u64>>;
+}
+
+/// Bucket state in a KV namespace, expiring once a bucket would be full again.
+///
+/// Each take reads and then rewrites the bucket, so the limit is approximate.
+pub struct KvCounterStore {
+    kv: KvStore,
+}
+
+impl CounterStore for KvCounterStore {
+    async fn take(&self, key: &str, spec: &TokenBucketSpec, now_ms: u64) -> Result<Option<u64>> {
+        let state = self.kv.get(key).json().await?;
+        let (state, retry_after_secs) = take_token(state, spec, now_ms);
+
+        let saved = self
+            .kv
+            .put(key, state)?
+            .expiration_ttl(refill_ttl_secs(spec).max(MIN_KV_TTL_SECS))
+            .execute()
+            .await;
+        if let Err(err) = saved {
+            warn_store_failure("save", key, &err.into());
+        }
+        Ok(retry_after_secs)
+    }
+}
+
+/// Body of a take request sent to a [`RateLimitBucket`].
+#[derive(Debug, Serialize, Deserialize)]
+struct TakeRequest {
+    spec: TokenBucketSpec,
+    now_ms: u64,
+}
+
+/// Reply of a [`RateLimitBucket`] to a take request.
+#[derive(Debug, Serialize, Deserialize)]
+struct TakeResponse {
+    retry_after_secs: Option<u64>,
+}
+
+/// Buckets as [`RateLimitBucket`] Durable Objects, one object per key.
+pub struct DurableObjectCounterStore {
+    namespace: ObjectNamespace,
+}
+
+impl CounterStore for DurableObjectCounterStore {
+    async fn take(&self, key: &str, spec: &TokenBucketSpec, now_ms: u64) -> Result<Option<u64>> {
+        let body = serde_json::to_string(&TakeRequest {
+            spec: *spec,
+            now_ms,
+        })?;
+        let mut init = RequestInit::new();
+        init.with_method(Method::Post).with_body(Some(body.into()));
+        let request = Request::new_with_init("https://rate-limit/take", &init)?;
+
+        let mut response = self
+            .namespace
+            .get_by_name(key)?
+            .fetch_with_request(request)
+            .await?;
+        if response.status_code() != 200 {
+            return Err(worker::Error::RustError(format!(
+                "rate limit bucket answered {}",
+                response.status_code()
+            )));
+        }
+        let reply: TakeResponse = response.json().await?;
+        Ok(reply.retry_after_secs)
+    }
+}
+
+/// Durable Object holding a single token bucket.
+///
+/// The runtime delivers one request at a time to an object and holds further
+/// ones while it reads and writes storage, so every take sees the previous
+/// one's result. An alarm clears the state once the bucket would be full again.
+#[durable_object]
+pub struct RateLimitBucket {
+    state: State,
+}
+
+/// Storage key of the bucket within its object.
+const BUCKET_STATE_KEY: &str = "bucket";
+
+impl DurableObject for RateLimitBucket {
+    fn new(state: State, _env: Env) -> Self {
+        Self { state }
+    }
+
+    async fn fetch(&self, mut req: Request) -> Result<Response> {
+        let take: TakeRequest = req.json().await?;
+        let storage = self.state.storage();
+
+        let state = storage.get(BUCKET_STATE_KEY).await?;
+        let (state, retry_after_secs) = take_token(state, &take.spec, take.now_ms);
+        storage.put(BUCKET_STATE_KEY, state).await?;
+        storage
+            .set_alarm(Duration::from_secs(refill_ttl_secs(&take.spec)))
+            .await?;
+
+        Response::from_json(&TakeResponse { retry_after_secs })
+    }
+
+    async fn alarm(&self) -> Result<Response> {
+        self.state.storage().delete_all().await?;
+        Response::empty()
+    }
+}
+
+/// The counter store selected by the bindings present in the environment.
+pub enum EnvCounterStore {
+    DurableObject(DurableObjectCounterStore),
+    Kv(KvCounterStore),
+}
+
+impl CounterStore for EnvCounterStore {
+    async fn take(&self, key: &str, spec: &TokenBucketSpec, now_ms: u64) -> Result<Option<u64>> {
+        match self {
+            Self::DurableObject(store) => store.take(key, spec, now_ms).await,
+            Self::Kv(store) => store.take(key, spec, now_ms).await,
+        }
+    }
+}
+
+/// Returns the Durable Object store when the `RATE_LIMITER` binding exists,
+/// else the KV store when the `RATE_LIMIT` binding does.
+pub fn store_from_env(env: &Env) -> Option<EnvCounterStore> {
+    if let Ok(namespace) = env.durable_object(RATE_LIMIT_DO_NAME) {
+        return Some(EnvCounterStore::DurableObject(DurableObjectCounterStore {
+            namespace,
+        }));
+    }
+    env.kv(RATE_LIMIT_KV_NAME)
+        .ok()
+        .map(|kv| EnvCounterStore::Kv(KvCounterStore { kv }))
+}
+
+/// Applies token buckets from a [`CounterStore`].
+pub struct RateLimiter<S> {
+    store: S,
+}
+
+impl<S: CounterStore> RateLimiter<S> {
+    pub fn new(store: S) -> Self {
+        Self { store }
+    }
+
+    /// Takes a token from the bucket identified by `route`, `scope` and `subject`.
+    ///
+    /// `scope` is `"ip"` or `"user"` and is reported back in the error.
+    ///
+    /// # Errors
+    ///
+    /// - `RateLimited`: When the bucket is empty.
+    pub async fn check(
+        &self,
+        route: &str,
+        scope: &'static str,
+        subject: &str,
+        spec: &TokenBucketSpec,
+        now_ms: u64,
+    ) -> AppResult<()> {
+        let key = format!("{RATE_LIMIT_KEY_PREFIX}{route}:{scope}:{subject}");
+
+        let retry_after_secs = match self.store.take(&key, spec, now_ms).await {
+            Ok(retry_after_secs) => retry_after_secs,
+            Err(err) => {
+                warn_store_failure("take", &key, &err);
+                return Ok(());
+            }
+        };
+
+        match retry_after_secs {
+            Some(retry_after_secs) => Err(AppError::RateLimited {
+                scope: scope.to_string(),
+                retry_after_secs,
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Whole seconds until `tokens` more are available, capped at [`MAX_BUCKET_WAIT_SECS`].
+fn seconds_to_refill(tokens: f64, spec: &TokenBucketSpec) -> u64 {
+    if spec.refill_per_second > 0.0 {
+        ((tokens / spec.refill_per_second).ceil() as u64).min(MAX_BUCKET_WAIT_SECS)
+    } else {
+        MAX_BUCKET_WAIT_SECS
+    }
+}
+
+/// Seconds for an empty bucket to refill completely; after that the stored state is redundant.
+fn refill_ttl_secs(spec: &TokenBucketSpec) -> u64 {
+    seconds_to_refill(f64::from(spec.capacity), spec)
+}
+
+fn warn_store_failure(operation: &str, key: &str, err: &worker::Error) {
+    log(
+        LogLevel::Warn,
+        "rate limit store unavailable, allowing request",
+        json!({ "operation": operation, "key": key, "error": err.to_string() }),
+    );
+}
+
+/// Keeps bucket state in memory so tests can drive the limiter.
+#[cfg(test)]
+#[derive(Default)]
+pub struct InMemoryCounterStore {
+    buckets: std::cell::RefCell<std::collections::HashMap<String, BucketState>>,
+}
+
+#[cfg(test)]
+impl CounterStore for InMemoryCounterStore {
+    async fn take(&self, key: &str, spec: &TokenBucketSpec, now_ms: u64) -> Result<Option<u64>> {
+        let mut buckets = self.buckets.borrow_mut();
+        let (state, retry_after_secs) = take_token(buckets.get(key).copied(), spec, now_ms);
+        buckets.insert(key.to_string(), state);
+        Ok(retry_after_secs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Poll, RawWaker, RawWakerVTable, Waker};
+
+    /// Polls a future that never suspends, as is the case with the in-memory store.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        const VTABLE: RawWakerVTable = RawWakerVTable::new(
+            |_| RawWaker::new(std::ptr::null(), &VTABLE),
+            |_| {},
+            |_| {},
+            |_| {},
+        );
+        let waker = unsafe { Waker::from_raw(RawWaker::new(std::ptr::null(), &VTABLE)) };
+        match pin!(future).poll(&mut Context::from_waker(&waker)) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("in-memory store futures complete immediately"),
+        }
+    }
+
+    fn spec(capacity: u32, refill_per_second: f64) -> TokenBucketSpec {
+        TokenBucketSpec {
+            capacity,
+            refill_per_second,
+        }
+    }
+
+    #[test]
+    fn take_token_starts_full_and_drains() {
+        let spec = spec(2, 1.0);
+        let (state, limited) = take_token(None, &spec, 0);
+        assert_eq!(limited, None);
+        let (state, limited) = take_token(Some(state), &spec, 0);
+        assert_eq!(limited, None);
+        let (_, limited) = take_token(Some(state), &spec, 0);
+        assert_eq!(limited, Some(1));
+    }
+
+    #[test]
+    fn take_token_refills_over_time_up_to_capacity() {
+        let spec = spec(3, 0.5);
+        let empty = BucketState {
+            tokens: 0.0,
+            updated_at_ms: 0,
+        };
+
+        assert_eq!(take_token(Some(empty), &spec, 1_000).1, Some(1));
+        assert_eq!(take_token(Some(empty), &spec, 2_000).1, None);
+
+        let (state, _) = take_token(Some(empty), &spec, 3_600_000);
+        assert_eq!(state.tokens, 2.0, "refill is capped at capacity");
+    }
+
+    #[test]
+    fn retry_after_reflects_refill_rate() {
+        let spec = spec(1, 0.1);
+        let empty = BucketState {
+            tokens: 0.0,
+            updated_at_ms: 0,
+        };
+        assert_eq!(take_token(Some(empty), &spec, 0).1, Some(10));
+    }
+
+    #[test]
+    fn take_request_round_trips_between_store_and_bucket() {
+        let body = serde_json::to_string(&TakeRequest {
+            spec: spec(5, 0.5),
+            now_ms: 1_234,
+        })
+        .unwrap();
+        let take: TakeRequest = serde_json::from_str(&body).unwrap();
+        assert_eq!(take.spec, spec(5, 0.5));
+        assert_eq!(take.now_ms, 1_234);
+    }
+
+    #[test]
+    fn limiter_keys_buckets_by_route_scope_and_subject() {
+        let limiter = RateLimiter::new(InMemoryCounterStore::default());
+        let spec = spec(1, 0.01);
+        let route = "POST /api/upload/init";
+
+        assert!(block_on(limiter.check(route, "user", "alice", &spec, 0)).is_ok());
+        assert!(block_on(limiter.check(route, "user", "bob", &spec, 0)).is_ok());
+        assert!(block_on(limiter.check(route, "ip", "alice", &spec, 0)).is_ok());
+
+        let err = block_on(limiter.check(route, "user", "alice", &spec, 0)).unwrap_err();
+        assert!(matches!(
+            err,
+            AppError::RateLimited { ref scope, retry_after_secs: 100 } if scope == "user"
+        ));
+    }
+}
@@ -11,45 +11,194 @@
 //! - `PUT  /api/upload/chunk` — upload a chunk
 //! - `POST /api/upload/complete` — finalize the multipart upload
 //! - `POST /api/upload/cancel` — cancel an upload
+//! - `DELETE /api/upload/{id}` — delete a completed upload (refused while held)
 //! - `GET  /api/upload/{id}/status` — get upload status
-//! - `OPTIONS *` — CORS preflight
+//! - `GET  /api/upload/{id}/download` — download a completed upload (`Range` supported)
+//! - `GET  /api/upload/{id}/variants/{name}` — download a generated image variant
+//! - `GET  /api/upload/{id}/versions` — list versions of a versioned upload's key
+//! - `GET  /api/upload/{id}/versions/{version}` — download a version (`current` allowed)
+//! - `POST /api/upload/{id}/versions/{version}/restore` — make a version current
+//! - `GET  /api/admin/audit` — query the audit log (admin token required)
+//! - `GET  /api/admin/migrations` — schema migration status (admin token required)
+//! - `POST /api/admin/migrations` — apply pending schema migrations (admin token required)
+//! - `GET|PUT|PATCH /api/admin/config` — read or update the KV configuration (admin token required)
+//! - `PUT|DELETE /api/admin/uploads/{id}/retention` — set or clear a retention period (admin token required)
+//! - `PUT|DELETE /api/admin/uploads/{id}/legal-hold` — set or clear a legal hold (admin token required)
+//! - `OPTIONS *` — CORS preflight (unknown origins receive 403)
 
 use std::sync::Arc;
 use worker::*;
 
 use crate::config::Config;
-use crate::handlers::{handle_health_check, handle_not_found, handle_upload_routes};
+use crate::handlers::admin::Hold;
+use crate::handlers::download::{download_path, variant_path};
+use crate::handlers::upload::upload_path;
+use crate::handlers::versions::VersionPath;
+use crate::handlers::{
+    handle_admin_routes, handle_health_check, handle_not_found, handle_upload_routes,
+};
+use crate::logging::RequestContext;
 use crate::middleware::CorsMiddleware;
 
 /// Dispatches an incoming request to the appropriate handler.
 ///
 /// CORS preflight is short-circuited before any path matching. Anything under
-/// `/api/upload` is delegated to [`handle_upload_routes`]; unmatched routes
-/// return 404 via [`handle_not_found`].
-pub async fn handle_request(req: Request, env: Env, config: Arc<Config>) -> Result<Response> {
-    if req.method() == Method::Options {
-        return CorsMiddleware::handle_preflight();
-    }
-
+/// `/api/upload` is delegated to [`handle_upload_routes`] and anything under
+/// `/api/admin` to [`handle_admin_routes`]; unmatched routes
+/// return 404 via [`handle_not_found`]. The matched route pattern is recorded
+/// on `ctx` for the completion log line.
+pub async fn handle_request(
+    req: Request,
+    env: Env,
+    config: Arc<Config>,
+    ctx: &RequestContext,
+) -> Result<Response> {
     let url = req.url()?;
     let path = url.path();
     let method = req.method();
 
-    console_log!("Routing request: {} {}", method, path);
+    ctx.set_route(route_label(&method, path));
+
+    if method == Method::Options {
+        return CorsMiddleware::handle_preflight(&req, &config.cors);
+    }
 
     match (method, path) {
         (Method::Get, "/health") => handle_health_check(req, env).await,
 
         (Method::Post, path) if path.starts_with("/api/upload") => {
-            handle_upload_routes(req, env, config).await
+            handle_upload_routes(req, env, config, ctx).await
         }
         (Method::Put, path) if path.starts_with("/api/upload") => {
-            handle_upload_routes(req, env, config).await
+            handle_upload_routes(req, env, config, ctx).await
         }
         (Method::Get, path) if path.starts_with("/api/upload") => {
-            handle_upload_routes(req, env, config).await
+            handle_upload_routes(req, env, config, ctx).await
+        }
+        (Method::Delete, path) if path.starts_with("/api/upload") => {
+            handle_upload_routes(req, env, config, ctx).await
+        }
+        (_, path) if path.starts_with("/api/admin/") => {
+            handle_admin_routes(req, env, config, ctx).await
         }
 
         _ => handle_not_found(req, env).await,
     }
 }
+
+/// Maps a request to its route pattern, keeping path parameters out of log labels.
+fn route_label(method: &Method, path: &str) -> &'static str {
+    match (method, path) {
+        (Method::Options, _) => "OPTIONS *",
+        (Method::Get, "/health") => "GET /health",
+        (Method::Post, "/api/upload/init") => "POST /api/upload/init",
+        (Method::Put, "/api/upload/chunk") => "PUT /api/upload/chunk",
+        (Method::Post, "/api/upload/complete") => "POST /api/upload/complete",
+        (Method::Post, "/api/upload/cancel") => "POST /api/upload/cancel",
+        (Method::Get, path) if path.starts_with("/api/upload/") && path.ends_with("/status") => {
+            "GET /api/upload/{id}/status"
+        }
+        (Method::Get, path) if download_path(path).is_some() => "GET /api/upload/{id}/download",
+        (Method::Get, path) if variant_path(path).is_some() => {
+            "GET /api/upload/{id}/variants/{name}"
+        }
+        (Method::Get, path) if VersionPath::parse(path).is_some_and(|r| r.version.is_none()) => {
+            "GET /api/upload/{id}/versions"
+        }
+        (Method::Get, path) if VersionPath::parse(path).is_some_and(|r| !r.restore) => {
+            "GET /api/upload/{id}/versions/{version}"
+        }
+        (Method::Post, path) if VersionPath::parse(path).is_some_and(|r| r.restore) => {
+            "POST /api/upload/{id}/versions/{version}/restore"
+        }
+        (Method::Get, "/api/admin/audit") => "GET /api/admin/audit",
+        (Method::Get, "/api/admin/migrations") => "GET /api/admin/migrations",
+        (Method::Post, "/api/admin/migrations") => "POST /api/admin/migrations",
+        (Method::Get, "/api/admin/config") => "GET /api/admin/config",
+        (Method::Put, "/api/admin/config") => "PUT /api/admin/config",
+        (Method::Patch, "/api/admin/config") => "PATCH /api/admin/config",
+        (Method::Put, path) => match Hold::parse(path) {
+            Some((_, Hold::Retention)) => "PUT /api/admin/uploads/{id}/retention",
+            Some((_, Hold::Legal)) => "PUT /api/admin/uploads/{id}/legal-hold",
+            None => "unmatched",
+        },
+        (Method::Delete, path) if upload_path(path).is_some() => "DELETE /api/upload/{id}",
+        (Method::Delete, path) => match Hold::parse(path) {
+            Some((_, Hold::Retention)) => "DELETE /api/admin/uploads/{id}/retention",
+            Some((_, Hold::Legal)) => "DELETE /api/admin/uploads/{id}/legal-hold",
+            None => "unmatched",
+        },
+        _ => "unmatched",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_label_hides_path_parameters() {
+        assert_eq!(
+            route_label(&Method::Get, "/api/upload/abc-123/status"),
+            "GET /api/upload/{id}/status"
+        );
+        assert_eq!(
+            route_label(&Method::Get, "/api/upload/abc-123/download"),
+            "GET /api/upload/{id}/download"
+        );
+        assert_eq!(
+            route_label(&Method::Get, "/api/upload/abc-123/variants/w128"),
+            "GET /api/upload/{id}/variants/{name}"
+        );
+    }
+
+    #[test]
+    fn route_label_distinguishes_methods() {
+        assert_eq!(
+            route_label(&Method::Put, "/api/upload/chunk"),
+            "PUT /api/upload/chunk"
+        );
+        assert_eq!(route_label(&Method::Get, "/api/upload/chunk"), "unmatched");
+        assert_eq!(route_label(&Method::Options, "/anything"), "OPTIONS *");
+    }
+
+    #[test]
+    fn route_label_covers_version_routes() {
+        assert_eq!(
+            route_label(&Method::Get, "/api/upload/abc/versions"),
+            "GET /api/upload/{id}/versions"
+        );
+        assert_eq!(
+            route_label(&Method::Get, "/api/upload/abc/versions/current"),
+            "GET /api/upload/{id}/versions/{version}"
+        );
+        assert_eq!(
+            route_label(&Method::Post, "/api/upload/abc/versions/2/restore"),
+            "POST /api/upload/{id}/versions/{version}/restore"
+        );
+        assert_eq!(
+            route_label(&Method::Post, "/api/upload/abc/versions/2"),
+            "unmatched"
+        );
+    }
+
+    #[test]
+    fn route_label_covers_hold_routes() {
+        assert_eq!(
+            route_label(&Method::Put, "/api/admin/uploads/abc/retention"),
+            "PUT /api/admin/uploads/{id}/retention"
+        );
+        assert_eq!(
+            route_label(&Method::Delete, "/api/admin/uploads/abc/legal-hold"),
+            "DELETE /api/admin/uploads/{id}/legal-hold"
+        );
+        assert_eq!(
+            route_label(&Method::Delete, "/api/admin/uploads/abc"),
+            "unmatched"
+        );
+        assert_eq!(
+            route_label(&Method::Delete, "/api/upload/abc"),
+            "DELETE /api/upload/{id}"
+        );
+    }
+}
@@ -4,95 +4,352 @@
 //!
 //! ## Core Utilities
 //!
-//! - **R2 Key Generation**: Creates hierarchical storage paths based on user context
-//! - **CORS Headers**: Provides consistent cross-origin request support
+//! - **R2 Key Generation**: Renders the configured key template from user context
+//! - **Variant Keys**: Derives sibling keys for generated image variants
+//! - **CORS Headers**: Applies the configured cross-origin policy to responses
 //!
 //! ## File Organization Strategy
 //!
-//! Files are organized using a hierarchical structure that facilitates:
+//! By default files are organized by role, user, date and content category
+//! (`creator/user123/20240115/video/video.mp4`), which facilitates:
 //! - Easy browsing by user role and date
 //! - Content type categorization
 //! - Scalable storage organization
 //! - Future access control implementation
 //!
-//! ## Example
-//!
-//! ```rust
-//! let key = generate_r2_key(&UserRole::Creator, "user123", "video.mp4", "video/mp4");
-//! // Result: "creator/user123/20240115/video/video.mp4"
-//! ```
+//! `Config::r2_key_template` can change the layout, e.g. to shard by hash
+//! prefix or include the upload ID; see [`KeyTemplate`].
 
-use crate::constants::{CORS_ALLOW_HEADERS, CORS_ALLOW_METHODS, CORS_ALLOW_ORIGIN, CORS_MAX_AGE};
-use chrono::Utc;
+use crate::config::{CorsConfig, CorsOriginRule};
+use crate::constants::MAX_R2_KEY_LENGTH;
+use crate::errors::{AppError, AppResult};
+use crate::models::UserRole;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
 use worker::Headers;
 
-/// Generates an R2 storage key based on user context and file metadata.
-///
-/// This function creates a hierarchical storage path that organizes files by:
-/// 1. User role (creator/member/subscriber)
-/// 2. User ID for individual user separation
-/// 3. Date (YYYYMMDD format) for chronological organization
-/// 4. Content category based on MIME type
-/// 5. Original filename (sanitized for security)
+/// A placeholder an R2 key template may use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum KeyField {
+    Role,
+    UserId,
+    Year,
+    Month,
+    Day,
+    Category,
+    UploadId,
+    Uuid,
+    Ext,
+    Sha256,
+    Filename,
+}
+
+impl KeyField {
+    fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "role" => Self::Role,
+            "user_id" => Self::UserId,
+            "yyyy" => Self::Year,
+            "mm" => Self::Month,
+            "dd" => Self::Day,
+            "category" => Self::Category,
+            "upload_id" => Self::UploadId,
+            "uuid" => Self::Uuid,
+            "ext" => Self::Ext,
+            "sha256" => Self::Sha256,
+            "filename" => Self::Filename,
+            _ => return None,
+        })
+    }
+
+    /// Whether the value differs between files, keeping keys apart.
+    fn is_distinguishing(self) -> bool {
+        matches!(
+            self,
+            Self::UploadId | Self::Uuid | Self::Sha256 | Self::Filename
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum KeyPart {
+    Literal(String),
+    /// A placeholder, cut to `max_len` characters when given as `{name:N}`.
+    Field {
+        field: KeyField,
+        max_len: Option<usize>,
+    },
+}
+
+/// A parsed `Config::r2_key_template`.
+///
+/// Placeholders are written `{name}`, or `{name:N}` to keep only the first `N`
+/// characters (e.g. `{sha256:2}` to shard by hash prefix):
+///
+/// - `{role}`, `{user_id}`, `{category}` — sanitized path components
+/// - `{yyyy}`, `{mm}`, `{dd}` — UTC date of upload init
+/// - `{upload_id}` — the upload's ID; `{uuid}` — a fresh random UUID
+/// - `{filename}` — sanitized original file name; `{ext}` — its lowercase
+///   extension without the dot, or `bin` when it has none
+/// - `{sha256}` — the content hash the client declared at init
+///
+/// Literal text may use ASCII letters, digits, `-`, `_`, `.` and `/`. The
+/// template must not produce empty, `.` or `..` path segments, and must
+/// include `{filename}`, `{upload_id}`, `{uuid}` or `{sha256}` so that keys
+/// differ between files.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct KeyTemplate {
+    parts: Vec<KeyPart>,
+}
+
+/// What an upload's R2 key is rendered from.
+#[derive(Clone, Debug)]
+pub struct KeyContext<'a> {
+    pub user_role: &'a UserRole,
+    pub user_id: &'a str,
+    pub file_name: &'a str,
+    pub content_type: &'a str,
+    pub upload_id: &'a str,
+    /// Lowercase hex SHA-256 declared by the client, if any.
+    pub sha256: Option<&'a str>,
+    pub now: DateTime<Utc>,
+}
+
+impl KeyTemplate {
+    /// Parses and checks a template, describing the first problem found.
+    pub fn parse(template: &str) -> Result<Self, String> {
+        let mut parts = Vec::new();
+        let mut rest = template;
+        while let Some(open) = rest.find('{') {
+            if open > 0 {
+                parts.push(KeyPart::Literal(rest[..open].to_string()));
+            }
+            let after = &rest[open + 1..];
+            let close = after.find('}').ok_or_else(|| "unclosed '{'".to_string())?;
+            let (name, max_len) = match after[..close].split_once(':') {
+                Some((name, len)) => {
+                    let len = len
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|len| (1..=64).contains(len))
+                        .ok_or_else(|| format!("{{{name}:{len}}} needs a length from 1 to 64"))?;
+                    (name, Some(len))
+                }
+                None => (&after[..close], None),
+            };
+            let field = KeyField::from_name(name)
+                .ok_or_else(|| format!("unknown placeholder {{{name}}}"))?;
+            parts.push(KeyPart::Field { field, max_len });
+            rest = &after[close + 1..];
+        }
+        if !rest.is_empty() {
+            parts.push(KeyPart::Literal(rest.to_string()));
+        }
+
+        for part in &parts {
+            if let KeyPart::Literal(text) = part {
+                if let Some(c) = text
+                    .chars()
+                    .find(|c| !(c.is_ascii_alphanumeric() || "-_./".contains(*c)))
+                {
+                    return Err(format!("'{c}' is not allowed outside placeholders"));
+                }
+            }
+        }
+        // Placeholders never render '/', so the literal layout decides the segments.
+        let layout: String = parts
+            .iter()
+            .map(|part| match part {
+                KeyPart::Literal(text) => text.as_str(),
+                KeyPart::Field { .. } => "x",
+            })
+            .collect();
+        if layout
+            .split('/')
+            .any(|segment| segment.is_empty() || segment == "." || segment == "..")
+        {
+            return Err("must not contain empty, '.' or '..' path segments".to_string());
+        }
+        if !parts
+            .iter()
+            .any(|part| matches!(part, KeyPart::Field { field, .. } if field.is_distinguishing()))
+        {
+            return Err(
+                "must include {filename}, {upload_id}, {uuid} or {sha256} so keys differ between files"
+                    .to_string(),
+            );
+        }
+
+        Ok(Self { parts })
+    }
+
+    /// Renders the key for one upload, sanitizing every value.
+    ///
+    /// # Errors
+    ///
+    /// - `MissingField`: When the template uses `{sha256}` and none was declared.
+    /// - `ValidationError`: When the key would exceed R2's 1024-byte limit.
+    pub fn render(&self, context: &KeyContext) -> AppResult<String> {
+        let file_name = sanitize_filename(context.file_name);
+        let mut key = String::new();
+        for part in &self.parts {
+            let (field, max_len) = match part {
+                KeyPart::Literal(text) => {
+                    key.push_str(text);
+                    continue;
+                }
+                KeyPart::Field { field, max_len } => (*field, *max_len),
+            };
+            let value = match field {
+                KeyField::Role => sanitize_path_component(context.user_role.as_str()),
+                KeyField::UserId => sanitize_path_component(context.user_id),
+                KeyField::Year => context.now.format("%Y").to_string(),
+                KeyField::Month => context.now.format("%m").to_string(),
+                KeyField::Day => context.now.format("%d").to_string(),
+                KeyField::Category => categorize_content_type(context.content_type).to_string(),
+                KeyField::UploadId => sanitize_path_component(context.upload_id),
+                KeyField::Uuid => Uuid::new_v4().to_string(),
+                KeyField::Ext => file_extension(&file_name),
+                KeyField::Sha256 => {
+                    context.sha256.map(sanitize_path_component).ok_or_else(|| {
+                        AppError::MissingField {
+                            field: "sha256".to_string(),
+                        }
+                    })?
+                }
+                KeyField::Filename => file_name.clone(),
+            };
+            match max_len {
+                Some(max_len) => key.extend(value.chars().take(max_len)),
+                None => key.push_str(&value),
+            }
+        }
+
+        check_key_length(key)
+    }
+}
+
+fn check_key_length(key: String) -> AppResult<String> {
+    if key.len() > MAX_R2_KEY_LENGTH {
+        return Err(AppError::ValidationError {
+            message: format!(
+                "Generated R2 key is {} bytes, over the {MAX_R2_KEY_LENGTH}-byte limit; \
+                 use a shorter file name",
+                key.len()
+            ),
+        });
+    }
+    Ok(key)
+}
+
+/// Generates an upload's R2 key from the configured template.
 ///
-/// # Arguments
+/// With the default template the key is
+/// `{role}/{user_id}/{YYYYMMDD}/{category}/{filename}`, e.g.
+/// `creator/user123/20240115/image/profile.jpg`. See [`KeyTemplate`] for the
+/// placeholders.
 ///
-/// * `user_role` - User role for file organization
-/// * `user_id` - User identifier
-/// * `file_name` - Original filename
-/// * `content_type` - MIME type of the file
+/// # Security Features
 ///
-/// # Returns
+/// - Sanitizes file names to prevent path traversal attacks
+/// - Limits field lengths to prevent excessive storage paths
+/// - Removes dangerous characters from all components
 ///
-/// Returns a string representing the R2 storage key path.
+/// # Errors
+///
+/// - `InternalError`: When `template` does not parse; configuration validation
+///   rejects such templates before they are used.
+/// - See [`KeyTemplate::render`].
+pub fn generate_r2_key(template: &str, context: &KeyContext) -> AppResult<String> {
+    KeyTemplate::parse(template)
+        .map_err(|reason| AppError::InternalError {
+            message: format!("Invalid r2_key_template: {reason}"),
+        })?
+        .render(context)
+}
+
+/// Lowercase extension of a sanitized file name, or `bin` when it has none.
+fn file_extension(file_name: &str) -> String {
+    file_name
+        .rsplit_once('.')
+        .map(|(_, ext)| sanitize_path_component(ext))
+        .filter(|ext| !ext.is_empty())
+        .unwrap_or_else(|| "bin".to_string())
+}
+
+/// Inserts `-{suffix}` before the extension of the key's last segment, to move
+/// an upload off a key that is already taken.
 ///
-/// # Path Structure
+/// # Example
 ///
-/// ```text
-/// {userRole}/{userId}/{date}/{category}/{fileName}
+/// ```rust
+/// let key = with_key_suffix("creator/user123/20240115/video/video.mp4", "2")?;
+/// // Returns: "creator/user123/20240115/video/video-2.mp4"
 /// ```
 ///
-/// # Content Categories
+/// # Errors
+///
+/// - `ValidationError`: When the key would exceed R2's 1024-byte limit.
+pub fn with_key_suffix(r2_key: &str, suffix: &str) -> AppResult<String> {
+    let (prefix, file_name) = match r2_key.rsplit_once('/') {
+        Some((directory, file_name)) => (&r2_key[..=directory.len()], file_name),
+        None => ("", r2_key),
+    };
+    let suffix = sanitize_path_component(suffix);
+    let key = match file_name.rsplit_once('.') {
+        Some((stem, extension)) if !stem.is_empty() => {
+            format!("{prefix}{stem}-{suffix}.{extension}")
+        }
+        _ => format!("{prefix}{file_name}-{suffix}"),
+    };
+    check_key_length(key)
+}
+
+/// Derives the R2 key for a generated variant of an upload.
 ///
-/// - `image/` - Image files (image/*)
-/// - `video/` - Video files (video/*)
-/// - `audio/` - Audio files (audio/*)
-/// - `document/` - Text and JSON files (text/*, */json)
-/// - `other/` - All other file types
+/// Variants live next to the original object produced by [`generate_r2_key`].
+/// The upload ID and variant name are inserted between the file stem and the
+/// new extension, so uploads whose keys differ only in their extension, or that
+/// reuse a key another upload released, never share a variant key.
 ///
 /// # Example
 ///
 /// ```rust
-/// use crate::models::UserRole;
-///
-/// let key = generate_r2_key(&UserRole::Creator, "user123", "profile.jpg", "image/jpeg");
-/// // Returns: "creator/user123/20240115/image/profile.jpg"
+/// let key = generate_variant_key(
+///     "creator/user123/20240115/image/profile.jpg",
+///     "4f1c2a9e-0b7d-4e52-9a61-3d8e5c7b2f10",
+///     "w128",
+///     "webp",
+/// )?;
+/// // Returns: "creator/user123/20240115/image/profile.4f1c2a9e-0b7d-4e52-9a61-3d8e5c7b2f10.w128.webp"
 /// ```
 ///
-/// # Security Features
-///
-/// - Sanitizes file names to prevent path traversal attacks
-/// - Validates user role against allowed values
-/// - Limits field lengths to prevent excessive storage paths
-/// - Removes dangerous characters from all components
-pub fn generate_r2_key(
-    user_role: &crate::models::UserRole,
-    user_id: &str,
-    file_name: &str,
-    content_type: &str,
-) -> String {
-    let role_str = sanitize_path_component(user_role.as_str());
-    let user_id_safe = sanitize_path_component(user_id);
-    let file_name_safe = sanitize_filename(file_name);
-    let date = Utc::now().format("%Y%m%d").to_string();
-
-    // Determine content category based on MIME type
-    let category = categorize_content_type(content_type);
-
-    format!(
-        "{}/{}/{}/{}/{}",
-        role_str, user_id_safe, date, category, file_name_safe
-    )
+/// # Errors
+///
+/// - `ValidationError`: When the key would exceed R2's 1024-byte limit.
+pub fn generate_variant_key(
+    r2_key: &str,
+    upload_id: &str,
+    variant_name: &str,
+    extension: &str,
+) -> AppResult<String> {
+    let (directory, file_name) = match r2_key.rsplit_once('/') {
+        Some((directory, file_name)) => (Some(directory), file_name),
+        None => (None, r2_key),
+    };
+
+    let stem = match file_name.rsplit_once('.') {
+        Some((stem, _)) if !stem.is_empty() => stem,
+        _ => file_name,
+    };
+    let upload_id = sanitize_path_component(upload_id);
+    let variant_name = sanitize_path_component(variant_name);
+
+    let key = match directory {
+        Some(directory) => format!("{directory}/{stem}.{upload_id}.{variant_name}.{extension}"),
+        None => format!("{stem}.{upload_id}.{variant_name}.{extension}"),
+    };
+    check_key_length(key)
 }
 
 /// Sanitizes a path component to prevent security issues.
@@ -150,6 +407,9 @@ fn sanitize_filename(filename: &str) -> String {
     }
 }
 
+/// Every category [`categorize_content_type`] can return.
+pub const CONTENT_CATEGORIES: &[&str] = &["image", "video", "audio", "document", "other"];
+
 /// Categorizes content type into storage directories.
 ///
 /// # Arguments
@@ -158,8 +418,9 @@ fn sanitize_filename(filename: &str) -> String {
 ///
 /// # Returns
 ///
-/// Returns a category string for directory organization.
-fn categorize_content_type(content_type: &str) -> &'static str {
+/// Returns one of [`CONTENT_CATEGORIES`], used for directory organization and
+/// bucket routing.
+pub fn categorize_content_type(content_type: &str) -> &'static str {
     let content_type = content_type.to_lowercase();
 
     if content_type.starts_with("image/") {
@@ -175,97 +436,300 @@ fn categorize_content_type(content_type: &str) -> &'static str {
     }
 }
 
-/// Creates HTTP headers for Cross-Origin Resource Sharing (CORS) support.
-///
-/// This function creates CORS headers optimized for the upload API.
-/// The headers are configured to allow broad access while supporting the
-/// necessary HTTP methods and custom headers used by the upload API.
-///
-/// # Returns
-///
-/// Returns a `Headers` object containing the CORS configuration.
-///
-/// # CORS Configuration
-///
-/// - **Access-Control-Allow-Origin**: `*` (allows all origins)
-/// - **Access-Control-Allow-Methods**: `GET, POST, PUT, DELETE, OPTIONS`
-/// - **Access-Control-Allow-Headers**: `Content-Type, X-Upload-Id, X-Chunk-Index`
-///
-/// # Security Note
-///
-/// The current configuration allows all origins (`*`) for maximum compatibility.
-/// In production environments, consider restricting this to specific trusted domains
-/// by modifying the `Access-Control-Allow-Origin` header.
-///
-/// # Example
-///
-/// ```rust
-/// let headers = cors_headers();
-/// let response = Response::empty()?.with_headers(headers);
-/// ```
-///
-/// # Supported Headers
-///
-/// The configuration specifically allows the custom headers used by the upload API:
-/// - `X-Upload-Id`: Required for chunk upload and status operations
-/// - `X-Chunk-Index`: Required for chunk upload operations
-/// - `Content-Type`: Standard header for request payload type
-pub fn cors_headers() -> Headers {
-    let headers = Headers::new();
-    // Note: These values are known to be valid
-    let _ = headers.set("Access-Control-Allow-Origin", CORS_ALLOW_ORIGIN);
-    let _ = headers.set("Access-Control-Allow-Methods", CORS_ALLOW_METHODS);
-    let _ = headers.set("Access-Control-Allow-Headers", CORS_ALLOW_HEADERS);
-    headers
+/// A CORS response header name and value.
+pub type CorsHeader = (&'static str, String);
+
+/// Resolves the `Access-Control-Allow-Origin` value and matching rule for `origin`.
+///
+/// A `*` rule answers with `*` unless credentials are enabled, in which case
+/// the request origin is echoed as the CORS spec requires. Requests without an
+/// `Origin` header (non-browser clients) only resolve under a `*` rule without
+/// credentials.
+fn resolve_allowed_origin<'a>(
+    cors: &'a CorsConfig,
+    origin: Option<&str>,
+) -> Option<(String, &'a CorsOriginRule)> {
+    match origin {
+        Some(origin) => cors.rule_for(origin).map(|rule| {
+            if rule.origin == "*" && !cors.allow_credentials {
+                ("*".to_string(), rule)
+            } else {
+                (origin.to_string(), rule)
+            }
+        }),
+        None if !cors.allow_credentials => cors
+            .allowed_origins
+            .iter()
+            .find(|rule| rule.origin == "*")
+            .map(|rule| ("*".to_string(), rule)),
+        None => None,
+    }
 }
 
-/// Builds CORS headers tailored for preflight (OPTIONS) responses.
+/// Computes the CORS headers for a non-preflight response to `origin`.
 ///
-/// Adds `Access-Control-Max-Age` on top of the standard CORS headers so the
-/// browser can cache the preflight result. The header is meaningless on
-/// non-preflight responses and is therefore omitted from [`cors_headers`].
-pub fn cors_preflight_headers() -> Headers {
-    let headers = cors_headers();
-    let _ = headers.set("Access-Control-Max-Age", CORS_MAX_AGE);
+/// `Vary: Origin` is always present because the answer depends on the request
+/// origin. The `Access-Control-*` headers are omitted entirely for origins that
+/// are not allowed, which makes the browser block the response.
+pub fn cors_header_values(cors: &CorsConfig, origin: Option<&str>) -> Vec<CorsHeader> {
+    let mut values = vec![("Vary", "Origin".to_string())];
+
+    if let Some((allow_origin, rule)) = resolve_allowed_origin(cors, origin) {
+        values.push(("Access-Control-Allow-Origin", allow_origin));
+        values.push((
+            "Access-Control-Allow-Methods",
+            rule.allowed_methods.join(", "),
+        ));
+        values.push((
+            "Access-Control-Allow-Headers",
+            rule.allowed_headers.join(", "),
+        ));
+        if cors.allow_credentials {
+            values.push(("Access-Control-Allow-Credentials", "true".to_string()));
+        }
+        if !cors.expose_headers.is_empty() {
+            values.push((
+                "Access-Control-Expose-Headers",
+                cors.expose_headers.join(", "),
+            ));
+        }
+    }
+
+    values
+}
+
+/// Computes the CORS headers for a preflight (OPTIONS) response.
+///
+/// Returns `None` when the origin is not allowed or the requested method
+/// (`Access-Control-Request-Method`) is not permitted for it. Otherwise adds
+/// `Access-Control-Max-Age` on top of [`cors_header_values`] so the browser can
+/// cache the result.
+pub fn cors_preflight_header_values(
+    cors: &CorsConfig,
+    origin: Option<&str>,
+    requested_method: Option<&str>,
+) -> Option<Vec<CorsHeader>> {
+    let (_, rule) = resolve_allowed_origin(cors, origin)?;
+    if requested_method.is_some_and(|method| !rule.allows_method(method)) {
+        return None;
+    }
+
+    let mut values = cors_header_values(cors, origin);
+    values.push(("Access-Control-Max-Age", cors.max_age.to_string()));
+    Some(values)
+}
+
+/// Converts computed header values into `Headers`.
+pub fn to_headers(values: &[CorsHeader]) -> Headers {
+    let headers = Headers::new();
+    for (name, value) in values {
+        // Names are static and values come from validated configuration.
+        let _ = headers.set(name, value);
+    }
     headers
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::models::UserRole;
+    use crate::constants::DEFAULT_R2_KEY_TEMPLATE;
+
+    fn context<'a>(file_name: &'a str, content_type: &'a str) -> KeyContext<'a> {
+        KeyContext {
+            user_role: &UserRole::Creator,
+            user_id: "User_123",
+            file_name,
+            content_type,
+            upload_id: "0f8fad5b-d9cb-469f-a165-70867728950e",
+            sha256: Some("9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"),
+            now: DateTime::parse_from_rfc3339("2024-01-15T10:30:00Z")
+                .unwrap()
+                .with_timezone(&Utc),
+        }
+    }
+
+    fn render(template: &str, context: &KeyContext) -> AppResult<String> {
+        generate_r2_key(template, context)
+    }
+
+    #[test]
+    fn default_template_structures_path() {
+        let key = render(
+            DEFAULT_R2_KEY_TEMPLATE,
+            &context("../payload.mp4", "video/mp4"),
+        )
+        .unwrap();
+        assert_eq!(key, "creator/user_123/20240115/video/payload.mp4");
+
+        let key = render(
+            DEFAULT_R2_KEY_TEMPLATE,
+            &context("report.bin", "application/octet-stream"),
+        )
+        .unwrap();
+        assert_eq!(key.split('/').nth(3), Some("other"));
+    }
+
+    #[test]
+    fn template_shards_by_hash_prefix_and_truncates() {
+        let key = render(
+            "{sha256:2}/{sha256:4}/{upload_id}.{ext}",
+            &context("Clip.MP4", "video/mp4"),
+        )
+        .unwrap();
+        assert_eq!(key, "9f/9f86/0f8fad5b-d9cb-469f-a165-70867728950e.mp4");
+
+        let key = render(
+            "{yyyy}/{mm}/{dd}/{uuid}.{ext}",
+            &context("noext", "text/plain"),
+        )
+        .unwrap();
+        assert!(key.starts_with("2024/01/15/"));
+        assert!(key.ends_with(".bin"));
+    }
+
+    #[test]
+    fn template_requires_declared_sha256() {
+        let template = KeyTemplate::parse("{sha256}/{filename}").unwrap();
+        let context = KeyContext {
+            sha256: None,
+            ..context("a.png", "image/png")
+        };
+        assert!(matches!(
+            template.render(&context),
+            Err(AppError::MissingField { .. })
+        ));
+    }
+
+    #[test]
+    fn template_parse_rejects_bad_templates() {
+        for template in [
+            "{role}/{unknown}",
+            "{filename",
+            "{sha256:0}/{filename}",
+            "{role}//{filename}",
+            "/{filename}",
+            "{role}/../{filename}",
+            "{role} {filename}",
+            "{role}/{yyyy}",
+        ] {
+            assert!(KeyTemplate::parse(template).is_err(), "{template}");
+        }
+    }
 
     #[test]
-    fn generate_r2_key_structures_path() {
-        let key = generate_r2_key(
-            &UserRole::Creator,
-            "User_123",
-            "../payload.mp4",
-            "video/mp4",
+    fn with_key_suffix_goes_before_the_extension() {
+        assert_eq!(
+            with_key_suffix("creator/u/20240115/video/video.mp4", "2").unwrap(),
+            "creator/u/20240115/video/video-2.mp4"
+        );
+        assert_eq!(
+            with_key_suffix("a.b/archive.tar.gz", "UPLOAD_1").unwrap(),
+            "a.b/archive.tar-upload_1.gz"
+        );
+        assert_eq!(with_key_suffix("v1/readme", "3").unwrap(), "v1/readme-3");
+        assert!(with_key_suffix(&"a".repeat(MAX_R2_KEY_LENGTH), "1").is_err());
+    }
+
+    #[test]
+    fn generate_variant_key_replaces_extension_next_to_original() {
+        let key =
+            generate_variant_key("creator/u/20240115/image/photo.jpg", "up-1", "w128", "webp")
+                .unwrap();
+        assert_eq!(key, "creator/u/20240115/image/photo.up-1.w128.webp");
+    }
+
+    #[test]
+    fn generate_variant_key_handles_missing_extension() {
+        let key =
+            generate_variant_key("member/u/20240115/image/photo", "up-1", "w512", "jpg").unwrap();
+        assert_eq!(key, "member/u/20240115/image/photo.up-1.w512.jpg");
+    }
+
+    #[test]
+    fn generate_variant_key_differs_between_uploads_of_the_same_stem() {
+        let jpg = generate_variant_key("a/photo.jpg", "up-1", "w128", "webp").unwrap();
+        let png = generate_variant_key("a/photo.png", "up-2", "w128", "webp").unwrap();
+        assert_ne!(jpg, png);
+        assert!(
+            generate_variant_key(&"a".repeat(MAX_R2_KEY_LENGTH), "up-1", "w128", "webp").is_err()
+        );
+    }
+
+    fn credentialed_cors() -> CorsConfig {
+        CorsConfig {
+            allowed_origins: vec![CorsOriginRule {
+                origin: "https://*.example.com".to_string(),
+                allowed_methods: vec!["GET".to_string(), "PUT".to_string()],
+                allowed_headers: vec!["Content-Type".to_string()],
+            }],
+            allow_credentials: true,
+            ..CorsConfig::default()
+        }
+    }
+
+    fn header<'a>(values: &'a [CorsHeader], name: &str) -> Option<&'a str> {
+        values
+            .iter()
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn default_cors_allows_any_origin_with_wildcard() {
+        let values = cors_header_values(&CorsConfig::default(), Some("https://x.test"));
+        assert_eq!(header(&values, "Access-Control-Allow-Origin"), Some("*"));
+        assert_eq!(header(&values, "Access-Control-Allow-Credentials"), None);
+        assert_eq!(header(&values, "Vary"), Some("Origin"));
+        assert_eq!(
+            header(&values, "Access-Control-Expose-Headers"),
+            Some(
+                "ETag, X-Upload-Id, X-Request-Id, Retry-After, Idempotent-Replayed, \
+                 Content-Range, X-Version"
+            )
         );
+    }
 
-        let segments: Vec<&str> = key.split('/').collect();
+    #[test]
+    fn credentialed_cors_echoes_matching_origin() {
+        let values = cors_header_values(&credentialed_cors(), Some("https://app.example.com"));
         assert_eq!(
-            segments.len(),
-            5,
-            "expected role/user/date/category/filename"
+            header(&values, "Access-Control-Allow-Origin"),
+            Some("https://app.example.com")
+        );
+        assert_eq!(
+            header(&values, "Access-Control-Allow-Credentials"),
+            Some("true")
+        );
+        assert_eq!(
+            header(&values, "Access-Control-Allow-Methods"),
+            Some("GET, PUT")
         );
-        assert_eq!(segments[0], "creator");
-        assert_eq!(segments[1], "user_123");
-        assert_eq!(segments[3], "video");
-        assert_eq!(segments[4], "payload.mp4");
     }
 
     #[test]
-    fn generate_r2_key_defaults_to_other_category() {
-        let key = generate_r2_key(
-            &UserRole::Member,
-            "abc",
-            "report.bin",
-            "application/octet-stream",
+    fn unknown_origin_gets_only_vary() {
+        let values = cors_header_values(&credentialed_cors(), Some("https://evil.test"));
+        assert_eq!(values, vec![("Vary", "Origin".to_string())]);
+    }
+
+    #[test]
+    fn preflight_rejects_unknown_origin_and_disallowed_method() {
+        let cors = credentialed_cors();
+        assert!(
+            cors_preflight_header_values(&cors, Some("https://evil.test"), Some("PUT")).is_none()
         );
-        let segments: Vec<&str> = key.split('/').collect();
-        assert_eq!(segments[3], "other");
+        assert!(cors_preflight_header_values(&cors, None, Some("PUT")).is_none());
+        assert!(cors_preflight_header_values(
+            &cors,
+            Some("https://app.example.com"),
+            Some("DELETE")
+        )
+        .is_none());
+
+        let values =
+            cors_preflight_header_values(&cors, Some("https://app.example.com"), Some("PUT"))
+                .unwrap();
+        assert_eq!(header(&values, "Access-Control-Max-Age"), Some("86400"));
     }
 
     #[test]